@@ -0,0 +1,199 @@
+//! `qsub2 accounting`: core-hours, GPU-hours and job counts per project and
+//! month, for reports to a PI or a grant.
+//!
+//! The jobs come from the history, and their usage from `qstat -fx` or the
+//! accounting records (see [`report::usage_attributes`]). The project is the
+//! job's `project` (`qsub -P`) or `Account_Name` (`qsub -A`), or else the
+//! `#PBS -P` or `-A` line of its script. A job counts in the month it ended.
+
+use crate::cost;
+use crate::history::SubmissionRecord;
+use crate::pbs::{self, JobAttributes};
+use crate::report;
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use std::fs;
+
+/// What PBS Pro calls jobs submitted without `-P`.
+const DEFAULT_PROJECT: &str = "_pbs_project_default";
+
+/// The project of jobs that have none.
+const NO_PROJECT: &str = "-";
+
+/// One finished job, as accounted.
+#[derive(Debug, PartialEq)]
+pub struct Charge {
+    pub project: String,
+    /// `2025-06`
+    pub month: String,
+    pub failed: bool,
+    pub core_hours: f64,
+    pub gpu_hours: f64,
+}
+
+/// Projects, months and their sums.
+#[derive(Debug, Default, PartialEq)]
+pub struct Total {
+    pub project: String,
+    pub month: String,
+    pub jobs: usize,
+    pub failed: usize,
+    pub core_hours: f64,
+    pub gpu_hours: f64,
+}
+
+/// `2025-06` as itself, checked.
+pub fn parse_month(s: &str) -> Result<String, String> {
+    NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+        .map(|date| format!("{}-{:02}", date.year(), date.month()))
+        .map_err(|_| format!("'{}' is not a month such as 2025-06", s))
+}
+
+fn month_of(time: DateTime<Local>) -> String {
+    time.format("%Y-%m").to_string()
+}
+
+/// The `-P` or `-A` value of the `#PBS` lines of `script`.
+fn script_project(script: &str) -> Option<String> {
+    script
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#PBS"))
+        .find_map(|directive| {
+            let mut words = directive.split_whitespace();
+            match words.next()? {
+                "-P" | "-A" => words.next().map(String::from),
+                _ => None,
+            }
+        })
+}
+
+impl Charge {
+    /// `None` for a job that has not finished or never ran.
+    pub fn from_attributes(attributes: &JobAttributes, record: &SubmissionRecord) -> Option<Self> {
+        let get = |key: &str| attributes.get(key).map(String::as_str);
+        let walltime = get("resources_used.walltime").and_then(pbs::parse_walltime)?;
+        if !matches!(get("job_state"), None | Some("F" | "C")) {
+            return None;
+        }
+        let (ncpus, ngpus) = cost::resources(&record.spec).unwrap_or((record.spec.ncpus as u64, 0));
+        let requested =
+            |key: &str, default| get(key).and_then(|n| n.parse().ok()).unwrap_or(default);
+        let ended = get("obittime")
+            .or(get("comp_time"))
+            .and_then(pbs::parse_time)
+            .unwrap_or(record.submitted_at);
+        let project = get("project")
+            .filter(|&p| p != DEFAULT_PROJECT)
+            .or(get("Account_Name"))
+            .map(String::from)
+            .or_else(|| {
+                let script = record
+                    .content
+                    .clone()
+                    .or_else(|| fs::read_to_string(&record.script).ok())?;
+                script_project(&script)
+            });
+        let hours = walltime as f64 / 3600.0;
+        Some(Charge {
+            project: project.unwrap_or_else(|| NO_PROJECT.to_string()),
+            month: month_of(ended),
+            failed: get("Exit_status").is_some_and(|s| s != "0"),
+            core_hours: hours * requested("Resource_List.ncpus", ncpus) as f64,
+            gpu_hours: hours * requested("Resource_List.ngpus", ngpus) as f64,
+        })
+    }
+}
+
+/// Sum `charges` by project and month, in that order.
+pub fn totals(charges: &[Charge]) -> Vec<Total> {
+    let mut totals: Vec<Total> = Vec::new();
+    for charge in charges {
+        let index = match totals
+            .iter()
+            .position(|t| t.project == charge.project && t.month == charge.month)
+        {
+            Some(index) => index,
+            None => {
+                totals.push(Total {
+                    project: charge.project.clone(),
+                    month: charge.month.clone(),
+                    ..Total::default()
+                });
+                totals.len() - 1
+            }
+        };
+        let total = &mut totals[index];
+        total.jobs += 1;
+        total.failed += usize::from(charge.failed);
+        total.core_hours += charge.core_hours;
+        total.gpu_hours += charge.gpu_hours;
+    }
+    totals.sort_by(|a, b| (&a.project, &a.month).cmp(&(&b.project, &b.month)));
+    totals
+}
+
+/// The usage of a submission, or `None` when it has not finished or the
+/// scheduler has no record of it.
+pub fn lookup(record: &SubmissionRecord) -> Option<Charge> {
+    let attributes = report::usage_attributes(&record.job_id).ok()?;
+    Charge::from_attributes(&attributes, record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::JobSpec;
+
+    #[test]
+    fn test_charges_and_totals() {
+        assert_eq!(parse_month("2025-6"), Ok("2025-06".to_string()));
+        assert!(parse_month("June").is_err());
+        assert_eq!(
+            script_project("#!/bin/bash\n#PBS -N x\n#PBS -P P123\n"),
+            Some("P123".to_string())
+        );
+
+        let record = SubmissionRecord {
+            job_id: "1.s".into(),
+            script: "/nonexistent/run.sh".into(),
+            spec: JobSpec {
+                ncpus: 8,
+                ..JobSpec::default()
+            },
+            generated_at: Local::now(),
+            submitted_at: Local::now(),
+            fingerprint: None,
+            content: Some("#PBS -A G42\n".into()),
+            invocation: None,
+            workdir: None,
+            user: None,
+        };
+        let mut attributes = JobAttributes::new();
+        attributes.insert("resources_used.walltime".into(), "02:00:00".into());
+        attributes.insert("Resource_List.ngpus".into(), "1".into());
+        attributes.insert("obittime".into(), "Mon Jun  2 10:00:00 2025".into());
+        attributes.insert("Exit_status".into(), "1".into());
+        let charge = Charge::from_attributes(&attributes, &record).unwrap();
+        assert_eq!(
+            charge,
+            Charge {
+                project: "G42".into(),
+                month: "2025-06".into(),
+                failed: true,
+                core_hours: 16.0,
+                gpu_hours: 2.0,
+            }
+        );
+        attributes.insert("project".into(), "P123".into());
+        let other = Charge::from_attributes(&attributes, &record).unwrap();
+        let totals = totals(&[other, charge]);
+        assert_eq!(totals.len(), 2);
+        assert_eq!(
+            (
+                totals[0].project.as_str(),
+                totals[0].jobs,
+                totals[0].core_hours
+            ),
+            ("G42", 1, 16.0)
+        );
+    }
+}
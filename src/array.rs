@@ -0,0 +1,307 @@
+//! `--array`: one array job over the input files instead of a job for each.
+//!
+//! The files go in a manifest next to the script, one absolute path per
+//! line, and each subjob reads the line of its `$PBS_ARRAY_INDEX` into
+//! `$FILE`. The manifest path is kept in the job spec, so the history says
+//! which file a failed subjob had.
+//!
+//! With `--param-table`, subjob N instead (or also) reads row N of a
+//! tab-separated table into variables named by its header, which the command
+//! uses as `{sample}` or `$sample`. The table is read again when the subjob
+//! starts, so it must stay where it is until the array has run.
+
+use crate::shell_quote;
+use crate::spec::{Array, Params};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The command's file placeholders and the variables the lookup sets for them.
+const PLACEHOLDERS: [(&str, &str); 6] = [
+    ("{file}", "\"$FILE\""),
+    ("{abs}", "\"$FILE\""),
+    ("{basename}", "\"$FILE_BASENAME\""),
+    ("{stem}", "\"$FILE_STEM\""),
+    ("{ext}", "\"$FILE_EXT\""),
+    ("{dir}", "\"$FILE_DIR\""),
+];
+
+/// Variables the lookup or the shell already uses, which a column of the
+/// parameter table would overwrite; `PBS_` names are the scheduler's.
+const RESERVED: [&str; 9] = [
+    "FILE",
+    "FILE_BASENAME",
+    "FILE_DIR",
+    "FILE_EXT",
+    "FILE_OUTPUT",
+    "FILE_STEM",
+    "PARAM_ROW",
+    "PATH",
+    "HOME",
+];
+
+/// `run.sh` lists its files in `run.files.txt`.
+pub fn manifest_path(script: &Path) -> PathBuf {
+    script.with_extension("files.txt")
+}
+
+/// The columns of the parameter table `content` and how many rows it has.
+/// Empty lines are skipped, here and by the lookup.
+fn parse_table(content: &str) -> Result<(Vec<String>, usize), String> {
+    if content.contains('\r') {
+        return Err("it has Windows line endings; convert it with dos2unix".to_string());
+    }
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+    let columns: Vec<String> = lines
+        .next()
+        .map(|(_, header)| header)
+        .filter(|header| !header.trim().is_empty())
+        .ok_or("the first line must name the columns")?
+        .split('\t')
+        .map(String::from)
+        .collect();
+    for (i, column) in columns.iter().enumerate() {
+        let identifier = column.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && column
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier {
+            return Err(format!("column '{}' is not a shell variable name", column));
+        }
+        let placeholder = format!("{{{}}}", column);
+        if placeholder == "{output}" || PLACEHOLDERS.iter().any(|(p, _)| *p == placeholder) {
+            return Err(format!("column '{}' is a file placeholder", column));
+        }
+        if RESERVED.contains(&column.as_str()) || column.starts_with("PBS_") {
+            return Err(format!(
+                "column '{}' would overwrite a variable the job uses",
+                column
+            ));
+        }
+        if columns[..i].contains(column) {
+            return Err(format!("column '{}' appears twice", column));
+        }
+    }
+    let mut rows = 0;
+    for (i, line) in lines {
+        let fields = line.split('\t').count();
+        if fields != columns.len() {
+            return Err(format!(
+                "line {} has {} fields, not {}",
+                i + 1,
+                fields,
+                columns.len()
+            ));
+        }
+        rows += 1;
+    }
+    if rows == 0 {
+        return Err("it has no rows".to_string());
+    }
+    Ok((columns, rows))
+}
+
+/// The array for `files`, listed in `manifest` once [`write_manifest`] has run,
+/// with `$FILE_OUTPUT` from `--output-pattern` and the rows of `params`.
+pub fn plan(
+    manifest: &Path,
+    files: &[PathBuf],
+    output: Option<&str>,
+    params: Option<&Path>,
+) -> Result<Array, String> {
+    if let Some(file) = files.iter().find(|f| f.to_string_lossy().contains('\n')) {
+        return Err(format!(
+            "{:?} has a newline, which a line of the file list cannot hold",
+            file
+        ));
+    }
+    let manifest = match files.is_empty() {
+        true => None,
+        false => Some(std::path::absolute(manifest).map_err(|e| e.to_string())?),
+    };
+    let mut size = files.len();
+    let params = match params {
+        Some(table) => {
+            let content = fs::read_to_string(table)
+                .map_err(|e| format!("reading {}: {}", table.display(), e))?;
+            let (columns, rows) =
+                parse_table(&content).map_err(|e| format!("{}: {}", table.display(), e))?;
+            if manifest.is_some() && rows != files.len() {
+                return Err(format!(
+                    "{} has {} rows for {} input files",
+                    table.display(),
+                    rows,
+                    files.len()
+                ));
+            }
+            size = rows;
+            let table = std::path::absolute(table).map_err(|e| e.to_string())?;
+            Some(Params { table, columns })
+        }
+        None => None,
+    };
+    Ok(Array {
+        size,
+        manifest,
+        output: output.map(String::from),
+        params,
+    })
+}
+
+/// Write the absolute paths of `files` to the manifest, one per line.
+pub fn write_manifest(array: &Array, files: &[PathBuf]) -> io::Result<()> {
+    let Some(ref manifest) = array.manifest else {
+        return Ok(());
+    };
+    let mut content = String::new();
+    for file in files {
+        content.push_str(&std::path::absolute(file)?.display().to_string());
+        content.push('\n');
+    }
+    fs::write(manifest, content)
+}
+
+/// The command with its placeholders as the subjob's variables; with no file
+/// placeholder, `"$FILE"` is appended as `--per-file` appends the file, unless
+/// the array is over a parameter table alone.
+pub fn command(command: &str, array: &Array) -> Result<String, String> {
+    if array.manifest.is_some() && !PLACEHOLDERS.iter().any(|(p, _)| command.contains(p)) {
+        return fill_in(&format!("{} {{file}}", command), array);
+    }
+    fill_in(command, array)
+}
+
+/// [`command`] for a `--then` step, which gets nothing appended.
+pub fn fill_in(step: &str, array: &Array) -> Result<String, String> {
+    let mut step = step.replace("{output}", "\"$FILE_OUTPUT\"");
+    for column in array.params.iter().flat_map(|p| &p.columns) {
+        step = step.replace(&format!("{{{}}}", column), &format!("\"${}\"", column));
+    }
+    for (placeholder, variable) in PLACEHOLDERS {
+        if step.contains(placeholder) && array.manifest.is_none() {
+            return Err(format!("{} needs input files", placeholder));
+        }
+        step = step.replace(placeholder, variable);
+    }
+    Ok(step)
+}
+
+/// The lines that give a subjob its file and its row of the parameter table.
+pub fn lookup(array: &Array) -> String {
+    let mut lines = String::new();
+    if let Some(ref manifest) = array.manifest {
+        lines.push_str(&format!(
+            "# Subjob N takes line N of the file list; 1 outside an array, e.g. with --local.\n\
+             FILE=$(sed -n \"${{PBS_ARRAY_INDEX:-1}}p\" {})\n\
+             FILE_BASENAME=${{FILE##*/}} FILE_DIR=${{FILE%/*}}\n\
+             FILE_STEM=${{FILE_BASENAME%.*}} FILE_EXT=\n\
+             case $FILE_BASENAME in *.*) FILE_EXT=${{FILE_BASENAME##*.}} ;; esac\n",
+            shell_quote(&manifest.display().to_string()),
+        ));
+    }
+    if let Some(ref params) = array.params {
+        let table = shell_quote(&params.table.display().to_string());
+        lines.push_str(
+            "# Subjob N takes row N of the parameter table, not counting the header\n\
+             # or empty lines.\n\
+             PARAM_ROW=$((${PBS_ARRAY_INDEX:-1} + 1))\n",
+        );
+        for (i, column) in params.columns.iter().enumerate() {
+            lines.push_str(&format!(
+                "{}=$(awk -F '\\t' -v row=\"$PARAM_ROW\" 'NF && ++n == row {{ print ${} }}' {})\n",
+                column,
+                i + 1,
+                table
+            ));
+        }
+        lines.push_str(&format!("export {}\n", params.columns.join(" ")));
+    }
+    lines.push_str(
+        &array.output.as_deref().map_or(String::new(), |pattern| format!(
+            "FILE_OUTPUT={}\n\
+             case $FILE_OUTPUT in /*) ;; *) FILE_OUTPUT=${{PBS_O_WORKDIR:-$PWD}}/$FILE_OUTPUT ;; esac\n",
+            fill_in(pattern, array).unwrap_or_else(|_| pattern.to_string())
+        )),
+    );
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let array = Array {
+            size: 1,
+            manifest: Some("/jobs/run.files.txt".into()),
+            output: None,
+            params: None,
+        };
+        let command = |c: &str| command(c, &array).unwrap();
+        assert_eq!(command("gzip -k"), "gzip -k \"$FILE\"");
+        assert_eq!(
+            command("samtools sort -o {output}"),
+            "samtools sort -o \"$FILE_OUTPUT\" \"$FILE\""
+        );
+        assert_eq!(
+            command("bwa mem ref.fa {file} > {stem}.sam"),
+            "bwa mem ref.fa \"$FILE\" > \"$FILE_STEM\".sam"
+        );
+        assert_eq!(
+            manifest_path(Path::new("jobs/run.sh")),
+            Path::new("jobs/run.files.txt")
+        );
+    }
+
+    #[test]
+    fn test_param_table() {
+        assert_eq!(
+            parse_table("sample\tthreads\nA\t4\nB\t\n"),
+            Ok((vec!["sample".to_string(), "threads".to_string()], 2))
+        );
+        assert!(parse_table("sample\n").is_err());
+        assert!(parse_table("1st\nA\n").is_err());
+        assert!(parse_table("stem\nA\n").is_err());
+        assert!(parse_table("a\tb\nA\n").is_err());
+        assert_eq!(
+            parse_table("sample\n\nA\n\nB\n\n"),
+            Ok((vec!["sample".to_string()], 2))
+        );
+        assert_eq!(
+            parse_table("a\tb\n\nA\n"),
+            Err("line 3 has 1 fields, not 2".to_string())
+        );
+        for column in [
+            "FILE",
+            "FILE_OUTPUT",
+            "PARAM_ROW",
+            "PBS_ARRAY_INDEX",
+            "PATH",
+            "HOME",
+        ] {
+            assert!(parse_table(&format!("{}\nA\n", column)).is_err());
+        }
+
+        let array = Array {
+            size: 2,
+            manifest: None,
+            output: None,
+            params: Some(Params {
+                table: "/data/params.tsv".into(),
+                columns: vec!["sample".into(), "threads".into()],
+            }),
+        };
+        assert_eq!(
+            command("align {sample} -t {threads}", &array),
+            Ok("align \"$sample\" -t \"$threads\"".to_string())
+        );
+        assert!(command("gzip {file}", &array).is_err());
+        assert!(lookup(&array).contains(
+            "threads=$(awk -F '\\t' -v row=\"$PARAM_ROW\" 'NF && ++n == row { print $2 }' '/data/params.tsv')\n"
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+//! An append-only record of submissions, for sites where qsub2 is the way
+//! jobs are submitted and each one has to be traced to a person: who
+//! submitted what, with which resources, from which directory and host.
+//!
+//! `$QSUB2_AUDIT_LOG`, which a site's environment module can set for all of
+//! its users, or else `audit_log` in the configuration names a file to add a
+//! JSON line to, or `syslog` to send the line to the system log through
+//! `logger`. The file is only ever opened for appending, so it can be made
+//! append-only (`chattr +a`), but it has to be writable by everyone who
+//! submits, so anyone can also add lines of their own to it; the system
+//! log, which stamps each line with the sender, is the one to trust.
+//!
+//! Each line names the account by its uid, the owner of `/proc/self`, and
+//! the name `/etc/passwd` gives it, never by `$USER`, which the caller sets.
+//! With `audit_required` in the configuration or `$QSUB2_AUDIT_REQUIRED`,
+//! nothing is submitted unless the log can be written, and a job whose line
+//! could not be added after all is cancelled again. What runs as the user
+//! can still leave qsub2 out and call `qsub` itself: a log that has to be
+//! complete belongs in the server's own hooks.
+
+use crate::config::Config;
+use crate::history::SubmissionRecord;
+use serde::Serialize;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    File(PathBuf),
+    Syslog,
+}
+
+/// Where submissions are recorded, if anywhere.
+pub fn target(config: &Config) -> Option<Target> {
+    let path = env::var_os("QSUB2_AUDIT_LOG")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| config.audit_log())?;
+    Some(match path.as_os_str() == "syslog" {
+        true => Target::Syslog,
+        false => Target::File(path),
+    })
+}
+
+/// Whether a submission is refused when it cannot be recorded.
+pub fn required(config: &Config) -> bool {
+    config.audit_required
+        || env::var("QSUB2_AUDIT_REQUIRED").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Fail now if `target` cannot take a line; the file is created if missing.
+pub fn check(target: &Target) -> io::Result<()> {
+    match target {
+        Target::File(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map(drop)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        Target::Syslog => Ok(()),
+    }
+}
+
+/// The account qsub2 runs as: its uid and, if `/etc/passwd` has it, its name.
+#[derive(Debug, Default, PartialEq)]
+pub struct Account {
+    pub uid: Option<u32>,
+    pub name: Option<String>,
+}
+
+pub fn account() -> Account {
+    #[cfg(unix)]
+    let uid = {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata("/proc/self").ok().map(|m| m.uid())
+    };
+    #[cfg(not(unix))]
+    let uid = None;
+    let name = uid.and_then(|uid| {
+        let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+        passwd_name(&passwd, uid)
+    });
+    Account { uid, name }
+}
+
+/// The name of `uid` in a passwd file.
+fn passwd_name(passwd: &str, uid: u32) -> Option<String> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        (fields.nth(1)?.parse() == Ok(uid)).then(|| name.to_string())
+    })
+}
+
+/// The machine qsub2 runs on.
+fn host() -> String {
+    Command::new("hostname")
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The line recorded for a submission, in the order it is read: who, where,
+/// then what.
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    time: String,
+    uid: Option<u32>,
+    user: Option<&'a str>,
+    host: &'a str,
+    workdir: Option<&'a Path>,
+    job_id: &'a str,
+    name: &'a str,
+    queue: &'a str,
+    ncpus: u32,
+    mem: Option<&'a str>,
+    walltime: &'a str,
+    select: Option<&'a str>,
+    script: &'a Path,
+    fingerprint: Option<&'a str>,
+    invocation: Option<&'a [String]>,
+}
+
+pub fn entry<'a>(record: &'a SubmissionRecord, account: &'a Account, host: &'a str) -> Entry<'a> {
+    let spec = &record.spec;
+    Entry {
+        time: record.submitted_at.to_rfc3339(),
+        uid: account.uid,
+        user: account.name.as_deref(),
+        host,
+        workdir: record.workdir.as_deref(),
+        job_id: &record.job_id,
+        name: &spec.name,
+        queue: &spec.queue,
+        ncpus: spec.ncpus,
+        mem: spec.mem.as_deref(),
+        walltime: &spec.walltime,
+        select: spec.select.as_deref(),
+        script: &record.script,
+        fingerprint: record.fingerprint.as_deref(),
+        invocation: record.invocation.as_deref(),
+    }
+}
+
+/// Add the submission to the audit log.
+pub fn record(target: &Target, record: &SubmissionRecord) -> io::Result<()> {
+    let (account, host) = (account(), host());
+    let line = serde_json::to_string(&entry(record, &account, &host))?;
+    match target {
+        Target::File(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            // one write, so that lines from concurrent submissions do not interleave
+            .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+        Target::Syslog => {
+            let status = Command::new("logger")
+                .args(["-t", "qsub2", "-p", "user.notice", "--", &line])
+                .status()?;
+            match status.success() {
+                true => Ok(()),
+                false => Err(io::Error::other(format!("logger exited with {}", status))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::JobSpec;
+    use crate::Cli;
+    use chrono::Local;
+    use clap::Parser;
+
+    #[test]
+    fn test_entry() {
+        let cli = Cli::try_parse_from(["qsub2", "-@", "4", "-m", "8gb", "make"]).unwrap();
+        let record = SubmissionRecord {
+            job_id: "42.server".into(),
+            script: PathBuf::from("run.sh"),
+            spec: JobSpec::from_cli(&cli),
+            generated_at: Local::now(),
+            submitted_at: Local::now(),
+            fingerprint: None,
+            content: None,
+            invocation: Some(vec!["qsub2".into(), "make".into()]),
+            workdir: Some(PathBuf::from("/home/ana/x")),
+            user: Some("ana".into()),
+        };
+        let account = Account {
+            uid: Some(1000),
+            name: Some("ana".into()),
+        };
+        let entry = serde_json::to_value(entry(&record, &account, "login1")).unwrap();
+        assert_eq!(entry["uid"], 1000);
+        assert_eq!(entry["user"], "ana");
+        assert_eq!(entry["host"], "login1");
+        assert_eq!(entry["job_id"], "42.server");
+        assert_eq!(entry["ncpus"], 4);
+        assert_eq!(entry["mem"], "8gb");
+        assert_eq!(entry["workdir"], "/home/ana/x");
+        assert_eq!(entry["invocation"][1], "make");
+
+        let path = env::temp_dir().join(format!("qsub2-audit-test-{}", std::process::id()));
+        let target = Target::File(path.clone());
+        super::record(&target, &record).unwrap();
+        super::record(&target, &record).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nana:x:1000:1000::/home/ana:/bin/bash\n";
+        assert_eq!(passwd_name(passwd, 1000).as_deref(), Some("ana"));
+        assert_eq!(passwd_name(passwd, 1001), None);
+    }
+}
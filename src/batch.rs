@@ -0,0 +1,568 @@
+//! One job per input file (`--per-file`), or per pair of them (`--paired`).
+
+use crate::naming;
+use crate::pbs;
+use crate::spec::JobSpec;
+use crate::Dependency;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The longest `--max-queued` waits before asking the server again; it
+/// starts with a second, as a job often leaves the queue soon after.
+const THROTTLE_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct Job {
+    /// What the job is named after: its input file, or with `--paired` the
+    /// first one without its marker, e.g. `s1.fq` for `s1_R1.fq`.
+    pub file: PathBuf,
+    /// The input files of the job, `file` itself unless paired.
+    pub files: Vec<PathBuf>,
+    pub spec: JobSpec,
+    pub outfile: PathBuf,
+}
+
+/// A job for each input, with `{file}`, `{file1}`, `{file2}`... and `{stem}`
+/// in the command filled in. When the command has none of them the files are
+/// appended as its last arguments. `{output}` is `output_pattern` filled in
+/// for the input, as an absolute path.
+/// Scripts are named after `script_base` and the file, e.g. `run_sample1.sh`,
+/// unless `script_base` has placeholders such as `{stem}` of its own; so can the job name.
+pub fn per_file(
+    spec: &JobSpec,
+    inputs: &[(PathBuf, Vec<PathBuf>)],
+    script_base: &Path,
+    output_pattern: Option<&str>,
+) -> Result<Vec<Job>, String> {
+    inputs
+        .iter()
+        .enumerate()
+        .map(|(i, (file, files))| {
+            let output = output_pattern
+                .map(|pattern| shell_word(&output_path(pattern, file).display().to_string()));
+            let with_output = |command: &str| match output {
+                Some(ref output) => command.replace("{output}", output),
+                None => command.to_string(),
+            };
+            Ok(Job {
+                file: file.clone(),
+                files: files.clone(),
+                spec: JobSpec {
+                    name: naming::fill_in(&spec.name, Some(file), i + 1)?,
+                    command: substitute(&with_output(&spec.command), file, files),
+                    then: spec
+                        .then
+                        .iter()
+                        .map(|step| fill_in_command(&with_output(step), file, files))
+                        .collect(),
+                    ..spec.clone()
+                },
+                outfile: if naming::has_placeholders(&script_base.to_string_lossy()) {
+                    naming::fill_in_path(script_base, Some(file), i + 1)?
+                } else {
+                    script_path(script_base, file)
+                },
+            })
+        })
+        .collect()
+}
+
+/// `sample1.fq.gz` -> `sample1.fq`
+pub fn stem(file: &Path) -> String {
+    file.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Resources of one row in a `--resources` table; empty cells keep the defaults.
+#[derive(Debug, Default, PartialEq)]
+pub struct Override {
+    ncpus: Option<u32>,
+    mem: Option<String>,
+    walltime: Option<String>,
+    queue: Option<String>,
+}
+
+impl Override {
+    pub fn apply(&self, spec: &mut JobSpec) {
+        if let Some(ncpus) = self.ncpus {
+            spec.ncpus = ncpus;
+        }
+        if let Some(ref mem) = self.mem {
+            spec.mem = Some(mem.clone());
+        }
+        if let Some(ref walltime) = self.walltime {
+            spec.walltime = walltime.clone();
+        }
+        if let Some(ref queue) = self.queue {
+            spec.queue = queue.clone();
+        }
+    }
+}
+
+/// Rows keyed by the `file` column, which may hold the path or just the stem.
+pub struct Overrides(HashMap<String, Override>);
+
+impl Overrides {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// A header line naming the columns (`file`, and any of `ncpus`, `mem`,
+    /// `walltime`, `queue`), then one line per file. Cells are separated by tabs or commas.
+    fn parse(content: &str) -> Result<Self, String> {
+        let split = |line: &str| -> Vec<String> {
+            line.split(['\t', ','])
+                .map(|c| c.trim().to_string())
+                .collect()
+        };
+        let mut lines = content
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+        let header = split(lines.next().ok_or("empty resource table")?);
+        for column in &header {
+            if !["file", "ncpus", "mem", "walltime", "queue"].contains(&column.as_str()) {
+                return Err(format!("unknown column '{}'", column));
+            }
+        }
+        if !header.iter().any(|c| c == "file") {
+            return Err("missing 'file' column".to_string());
+        }
+
+        let mut rows = HashMap::new();
+        for line in lines {
+            let cells = split(line);
+            let cell = |name: &str| {
+                header
+                    .iter()
+                    .position(|c| c == name)
+                    .and_then(|i| cells.get(i))
+                    .filter(|c| !c.is_empty())
+                    .cloned()
+            };
+            let ncpus = match cell("ncpus") {
+                Some(n) => Some(n.parse().map_err(|_| format!("invalid ncpus '{}'", n))?),
+                None => None,
+            };
+            let row = Override {
+                ncpus,
+                mem: cell("mem"),
+                walltime: cell("walltime"),
+                queue: cell("queue"),
+            };
+            if let Some(file) = cell("file") {
+                rows.insert(file, row);
+            }
+        }
+        Ok(Overrides(rows))
+    }
+
+    pub fn get(&self, file: &Path) -> Option<&Override> {
+        self.0
+            .get(&file.display().to_string())
+            .or_else(|| self.0.get(&stem(file)))
+    }
+}
+
+/// `--paired _R1/_R2`: the markers that tell the files of a pair apart.
+pub fn parse_markers(s: &str) -> Result<Vec<String>, String> {
+    let markers: Vec<String> = s.split('/').map(String::from).collect();
+    if markers.len() < 2 || markers.iter().any(String::is_empty) {
+        return Err(format!("expected markers such as _R1/_R2, got {}", s));
+    }
+    if (1..markers.len()).any(|i| markers[..i].contains(&markers[i])) {
+        return Err(format!("{} repeats a marker", s));
+    }
+    Ok(markers)
+}
+
+/// Group `files` by `markers`: each file with the first marker in its name
+/// goes with the files named the same but with the other markers instead.
+/// Every file must end up in exactly one group.
+pub fn pair(files: &[PathBuf], markers: &[String]) -> Result<Vec<(PathBuf, Vec<PathBuf>)>, String> {
+    // the marker in the file name, rather than in a directory
+    let swap = |file: &Path, marker: &str, with: &str| {
+        let name = file.file_name()?.to_string_lossy();
+        let at = name.rfind(marker)?;
+        let name = format!("{}{}{}", &name[..at], with, &name[at + marker.len()..]);
+        Some(file.with_file_name(name))
+    };
+    let mut groups = Vec::new();
+    let mut paired = std::collections::HashSet::new();
+    let mut problems = Vec::new();
+    for file in files {
+        let Some(named) = swap(file, &markers[0], "") else {
+            continue;
+        };
+        let group: Vec<PathBuf> = markers
+            .iter()
+            .filter_map(|marker| swap(file, &markers[0], marker))
+            .collect();
+        match group.iter().find(|mate| !files.contains(mate)) {
+            Some(missing) => {
+                problems.push(format!(
+                    "{} has no mate {}",
+                    file.display(),
+                    missing.display()
+                ));
+                paired.insert(file.clone());
+            }
+            None => {
+                paired.extend(group.iter().cloned());
+                groups.push((named, group));
+            }
+        }
+    }
+    for file in files.iter().filter(|f| !paired.contains(*f)) {
+        problems.push(format!(
+            "{} is not part of a {} group",
+            file.display(),
+            markers.join("/")
+        ));
+    }
+    match problems.is_empty() {
+        true => Ok(groups),
+        false => Err(problems.join("; ")),
+    }
+}
+
+fn substitute(command: &str, file: &Path, files: &[PathBuf]) -> String {
+    let placeholders = naming::file_placeholders(file);
+    if !command.contains("{file") && !placeholders.iter().any(|(p, _)| command.contains(p)) {
+        let files: Vec<String> = files
+            .iter()
+            .map(|f| shell_word(&f.display().to_string()))
+            .collect();
+        return format!("{} {}", command, files.join(" "));
+    }
+    fill_in_command(command, file, files)
+}
+
+/// A file name as one shell word: quoted only when it has spaces, newlines
+/// or other characters the shell would act on.
+pub fn shell_word(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+./,:=@%^".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        return s.to_string();
+    }
+    crate::shell_quote(s)
+}
+
+/// `--output-pattern` filled in for `file`, as an absolute path.
+pub fn output_path(pattern: &str, file: &Path) -> PathBuf {
+    let output = PathBuf::from(fill_in(pattern, file));
+    std::path::absolute(&output).unwrap_or(output)
+}
+
+/// The placeholders of a command, with the file names as shell words.
+/// `{stem}`, `{dir}` and the others describe `file`, the name of the job;
+/// `{dir}` is absolute like the file names.
+fn fill_in_command(command: &str, file: &Path, files: &[PathBuf]) -> String {
+    let word = |f: &Path| shell_word(&f.display().to_string());
+    let mut command = command.to_string();
+    for (i, f) in files.iter().enumerate() {
+        command = command.replace(&format!("{{file{}}}", i + 1), &word(f));
+    }
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+    for (placeholder, value) in naming::file_placeholders(&file) {
+        command = command.replace(placeholder, &shell_word(&value));
+    }
+    command.replace("{file}", &word(&files[0]))
+}
+
+fn fill_in(pattern: &str, file: &Path) -> String {
+    let mut pattern = pattern.replace("{file}", &file.display().to_string());
+    for (placeholder, value) in naming::file_placeholders(file) {
+        pattern = pattern.replace(placeholder, &value);
+    }
+    pattern
+}
+
+/// The output `pattern` (e.g. `{stem}.bam`) names for `file`, if it exists and
+/// is newer than the input `files`, so the job can be skipped.
+pub fn up_to_date_output(pattern: &str, file: &Path, files: &[PathBuf]) -> Option<PathBuf> {
+    let output = PathBuf::from(fill_in(pattern, file));
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let newest_input = files
+        .iter()
+        .map(|f| modified(f))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|times| times.into_iter().max());
+    match (modified(&output), newest_input) {
+        (Some(output_time), Some(input_time)) if output_time >= input_time => Some(output),
+        (Some(_), None) => Some(output),
+        _ => None,
+    }
+}
+
+fn script_path(base: &Path, file: &Path) -> PathBuf {
+    let extension = base
+        .extension()
+        .map_or("sh".to_string(), |e| e.to_string_lossy().into_owned());
+    base.with_file_name(format!("{}_{}.{}", stem(base), stem(file), extension))
+}
+
+/// Parse rates such as `10/min`, `2/s` or `100/h` into the interval between submissions.
+pub fn parse_rate(s: &str) -> Result<Duration, String> {
+    let (count, unit) = s
+        .split_once('/')
+        .ok_or_else(|| format!("invalid rate '{}', expected e.g. 10/min", s))?;
+    let count: u32 = count
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid rate '{}'", s))?;
+    let period = match unit.trim() {
+        "s" | "sec" => Duration::from_secs(1),
+        "m" | "min" => Duration::from_secs(60),
+        "h" | "hour" => Duration::from_secs(60 * 60),
+        _ => return Err(format!("invalid rate unit '{}' in '{}'", unit, s)),
+    };
+    Ok(period / count)
+}
+
+/// A progress bar on stderr; hidden when stderr is not a terminal.
+pub fn progress_bar(len: usize, message: &'static str) -> ProgressBar {
+    let style = ProgressStyle::with_template("{bar:40} {pos}/{len} {msg}")
+        .expect("valid progress template");
+    ProgressBar::new(len as u64)
+        .with_style(style)
+        .with_message(message)
+}
+
+/// Run `submit` on each item using up to `workers` threads and return the results
+/// in item order. `before_each` runs under a lock before every item is handed
+/// out, so throttling and pacing apply to the pool as a whole; once it returns
+/// `false` no further items are started and their results stay `None`.
+pub fn submit_all<T: Sync, R: Send>(
+    items: &[T],
+    workers: u32,
+    before_each: impl FnMut() -> bool + Send,
+    submit: impl Fn(&T) -> R + Sync,
+) -> Vec<Option<R>> {
+    struct Dispatch<F> {
+        next: usize,
+        stopped: bool,
+        before_each: F,
+    }
+    let dispatch = Mutex::new(Dispatch {
+        next: 0,
+        stopped: false,
+        before_each,
+    });
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| loop {
+                let index = {
+                    let mut dispatch = dispatch.lock().unwrap();
+                    if dispatch.stopped || dispatch.next >= items.len() {
+                        return;
+                    }
+                    if !(dispatch.before_each)() {
+                        dispatch.stopped = true;
+                        return;
+                    }
+                    dispatch.next += 1;
+                    dispatch.next - 1
+                };
+                let result = submit(&items[index]);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+/// The most job IDs put in the dependency list of one job.
+pub const DEPEND_CHUNK: usize = 100;
+
+/// A small job that runs once `waiting_on` have ended, counts how many of
+/// `job_ids` exited zero, and mails the owner one summary for the whole batch.
+pub fn summary_job(spec: &JobSpec, job_ids: &[String], waiting_on: Vec<String>) -> JobSpec {
+    let command = format!(
+        r#"succeeded=0
+failed=0
+for job in {jobs}; do
+    status=$(qstat -fx "$job" 2>/dev/null | sed -n 's/^ *Exit_status = //p')
+    if [ "$status" = 0 ]; then succeeded=$((succeeded + 1)); else failed=$((failed + 1)); fi
+done
+echo "$succeeded succeeded, $failed failed of {count} jobs: {jobs}" |
+    mail -s "Batch {name} finished: $failed failed" "$PBS_O_LOGNAME""#,
+        jobs = job_ids.join(" "),
+        count = job_ids.len(),
+        name = spec.name
+    );
+    JobSpec {
+        name: format!("{}-summary", spec.name),
+        ncpus: 1,
+        queue: spec.queue.clone(),
+        walltime: "00:10:00".to_string(),
+        command,
+        depend: after_all(waiting_on),
+        group: spec.group.clone(),
+        raw_command: true,
+        ..Default::default()
+    }
+}
+
+/// The `index`th job that does nothing but end after `waiting_on`, so that
+/// the summary job can wait for more jobs than one dependency list holds.
+pub fn gate_job(spec: &JobSpec, index: usize, waiting_on: Vec<String>) -> JobSpec {
+    JobSpec {
+        name: format!("{}-wait{}", spec.name, index),
+        ncpus: 1,
+        queue: spec.queue.clone(),
+        walltime: "00:01:00".to_string(),
+        command: "true".to_string(),
+        depend: after_all(waiting_on),
+        group: spec.group.clone(),
+        raw_command: true,
+        ..Default::default()
+    }
+}
+
+fn after_all(targets: Vec<String>) -> Vec<Dependency> {
+    match targets.is_empty() {
+        true => Vec::new(),
+        false => vec![Dependency {
+            kind: "afterany".to_string(),
+            targets,
+        }],
+    }
+}
+
+/// Block until `user` has fewer than `max_queued` jobs queued or running,
+/// telling `waiting` how many there are when it has to wait.
+pub fn wait_for_room(user: &str, max_queued: usize, waiting: impl FnOnce(usize)) -> io::Result<()> {
+    let mut waiting = Some(waiting);
+    let mut interval = Duration::from_secs(1);
+    loop {
+        let active = pbs::user_jobs(user)?
+            .iter()
+            .filter(|(_, attributes)| !pbs::is_finished(attributes))
+            .count();
+        if active < max_queued {
+            return Ok(());
+        }
+        if let Some(waiting) = waiting.take() {
+            waiting(active);
+        }
+        thread::sleep(interval);
+        interval = (interval * 2).min(THROTTLE_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_and_script_path() {
+        let file = Path::new("data/s1.fq");
+        let files = [file.to_path_buf()];
+        assert_eq!(
+            substitute("bwa mem ref.fa {file} > {stem}.sam", file, &files),
+            "bwa mem ref.fa data/s1.fq > s1.sam"
+        );
+        assert_eq!(substitute("gzip", file, &files), "gzip data/s1.fq");
+        let odd = Path::new("my data/it's\n1.fq");
+        assert_eq!(
+            substitute("zcat {file} > {stem}.txt", odd, &[odd.to_path_buf()]),
+            "zcat 'my data/it'\\''s\n1.fq' > 'it'\\''s\n1'.txt"
+        );
+        assert_eq!(
+            script_path(Path::new("jobs/align.sh"), file),
+            PathBuf::from("jobs/align_s1.sh")
+        );
+    }
+
+    #[test]
+    fn test_pair() {
+        let files: Vec<PathBuf> = ["d_R1/s1_R1.fq", "d_R1/s1_R2.fq", "s2_R2.fq", "s2_R1.fq"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let markers = parse_markers("_R1/_R2").unwrap();
+        let groups = pair(&files, &markers).unwrap();
+        assert_eq!(groups[0].0, Path::new("d_R1/s1.fq"));
+        assert_eq!(groups[1].1, [files[3].clone(), files[2].clone()]);
+        assert_eq!(
+            substitute(
+                "bwa mem ref.fa {file1} {file2} > {stem}.sam",
+                &groups[1].0,
+                &groups[1].1
+            ),
+            "bwa mem ref.fa s2_R1.fq s2_R2.fq > s2.sam"
+        );
+        assert_eq!(
+            pair(&files[..3], &markers).unwrap_err(),
+            "s2_R2.fq is not part of a _R1/_R2 group"
+        );
+        assert!(parse_markers("_R1").is_err());
+    }
+
+    #[test]
+    fn test_submit_all_keeps_order_and_stops() {
+        let items: Vec<u32> = (0..20).collect();
+        let results = submit_all(&items, 4, || true, |n| n * 2);
+        assert_eq!(
+            results,
+            items.iter().map(|n| Some(n * 2)).collect::<Vec<_>>()
+        );
+
+        let mut started = 0;
+        let results = submit_all(
+            &items,
+            4,
+            || {
+                started += 1;
+                started <= 2
+            },
+            |n| *n,
+        );
+        assert_eq!(results.iter().filter(|r| r.is_some()).count(), 2);
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("10/min"), Ok(Duration::from_secs(6)));
+        assert_eq!(parse_rate("4/s"), Ok(Duration::from_millis(250)));
+        assert!(parse_rate("0/min").is_err());
+        assert!(parse_rate("10").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides =
+            Overrides::parse("file\tmem\twalltime\ns1\t64gb\t\ndata/s2.fq\t\t48:00:00\n").unwrap();
+        assert_eq!(
+            overrides
+                .get(Path::new("data/s1.fq"))
+                .unwrap()
+                .mem
+                .as_deref(),
+            Some("64gb")
+        );
+        let s2 = overrides.get(Path::new("data/s2.fq")).unwrap();
+        assert_eq!(
+            (s2.mem.as_deref(), s2.walltime.as_deref()),
+            (None, Some("48:00:00"))
+        );
+        assert!(overrides.get(Path::new("s3.fq")).is_none());
+        assert!(Overrides::parse("sample,mem\n").is_err());
+    }
+}
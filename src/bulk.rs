@@ -0,0 +1,149 @@
+//! `qsub2 select`: act on every live job that matches a name pattern, job
+//! states, a queue and an owner, whether or not qsub2 submitted it.
+//!
+//! `qselect` narrows the jobs on the server. A name with wildcards, which
+//! qselect only takes exactly, is matched here, and so is everything else
+//! when qselect is missing and the jobs come from `qstat -f` instead. The
+//! matching jobs are listed, and nothing is done to them until the list is
+//! confirmed or `--yes` is given.
+
+use crate::history;
+use crate::inputs;
+use crate::pbs::{self, JobAttributes};
+use crate::scheduler;
+use std::io;
+
+/// What a job has to match; `None` matches anything.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// A job name, with `*`, `?` and `[...]` wildcards.
+    pub name: Option<String>,
+    /// State letters, e.g. `QH`.
+    pub states: Option<String>,
+    pub queue: Option<String>,
+    pub user: Option<String>,
+}
+
+impl Filter {
+    fn qselect_args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        if let Some(ref user) = self.user {
+            args.extend(["-u", user.as_str()]);
+        }
+        if let Some(ref states) = self.states {
+            args.extend(["-s", states.as_str()]);
+        }
+        if let Some(ref queue) = self.queue {
+            args.extend(["-q", queue.as_str()]);
+        }
+        if let Some(name) = self.name.as_ref().filter(|n| !n.contains(['*', '?', '['])) {
+            args.extend(["-N", name.as_str()]);
+        }
+        args
+    }
+
+    /// Whether a live job with `attributes` matches.
+    pub fn matches(&self, attributes: &JobAttributes) -> bool {
+        let get = |key: &str| attributes.get(key).map_or("", String::as_str);
+        let state = get("job_state");
+        // qstat -f also reports jobs that have finished
+        if pbs::is_finished(attributes) || state.is_empty() {
+            return false;
+        }
+        let owner = get("Job_Owner").split('@').next().unwrap_or_default();
+        self.name
+            .as_ref()
+            .is_none_or(|pattern| inputs::matches_wildcard(pattern, get("Job_Name")))
+            && self.states.as_ref().is_none_or(|s| s.contains(state))
+            && self.queue.as_ref().is_none_or(|q| q == get("queue"))
+            && self.user.as_ref().is_none_or(|u| u == owner)
+    }
+}
+
+/// The live jobs that match `filter`, by job ID.
+pub fn live_jobs(filter: &Filter) -> io::Result<Vec<(String, JobAttributes)>> {
+    let mut jobs = match pbs::selected_jobs(&filter.qselect_args()) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            log::debug!("{}; reading all jobs from qstat -f", e);
+            pbs::all_jobs()?
+        }
+        jobs => jobs?,
+    };
+    jobs.retain(|(_, attributes)| filter.matches(attributes));
+    Ok(jobs)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Cancel,
+    Hold,
+    Release,
+}
+
+impl Action {
+    pub fn verb(self) -> &'static str {
+        match self {
+            Action::Cancel => "Cancel",
+            Action::Hold => "Hold",
+            Action::Release => "Release",
+        }
+    }
+
+    pub fn done(self) -> &'static str {
+        match self {
+            Action::Cancel => "Cancelled",
+            Action::Hold => "Held",
+            Action::Release => "Released",
+        }
+    }
+
+    pub fn apply(self, job_id: &str) -> io::Result<()> {
+        match self {
+            Action::Cancel => {
+                if let Err(e) = history::mark_cancelled(job_id) {
+                    log::warn!("{} may be requeued as preempted: {}", job_id, e);
+                }
+                scheduler::cancel(job_id)
+            }
+            Action::Hold => pbs::hold(job_id),
+            Action::Release => pbs::release(job_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let job = |name: &str, state: &str| -> JobAttributes {
+            [
+                ("Job_Name", name),
+                ("job_state", state),
+                ("queue", "batch"),
+                ("Job_Owner", "ana@login1"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+        };
+        let filter = Filter {
+            name: Some("align_*".into()),
+            states: Some("QH".into()),
+            user: Some("ana".into()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&job("align_s1", "Q")));
+        assert!(filter.matches(&job("align_s2", "H")));
+        assert!(!filter.matches(&job("align_s3", "R")));
+        assert!(!filter.matches(&job("qc_s1", "Q")));
+        assert!(!Filter::default().matches(&job("align_s1", "F")));
+        assert_eq!(filter.qselect_args(), ["-u", "ana", "-s", "QH"]);
+        let exact = Filter {
+            name: Some("nightly".into()),
+            ..Default::default()
+        };
+        assert_eq!(exact.qselect_args(), ["-N", "nightly"]);
+    }
+}
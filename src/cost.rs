@@ -0,0 +1,130 @@
+//! `--cost` and `--max-cost`: what a job can cost at most, from the rates in
+//! the `[cost]` table of the configuration.
+//!
+//! The estimate assumes the job runs for its whole walltime on every core
+//! (and GPU) it asks for, which is what most allocations charge against.
+
+use crate::pbs;
+use crate::select;
+use crate::spec::JobSpec;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rates {
+    /// Price of one core for one hour.
+    pub core_hour: Option<f64>,
+    /// Price of one GPU for one hour.
+    pub gpu_hour: Option<f64>,
+    /// Printed after amounts, e.g. `EUR` or `SU`.
+    pub currency: Option<String>,
+    /// Refuse jobs that can cost more than this, as with `--max-cost`.
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    pub cores: u64,
+    pub gpus: u64,
+    pub hours: f64,
+    pub cost: f64,
+}
+
+/// Total of a numeric resource over the chunks: `2:ncpus=8+1:ncpus=4` has 20 ncpus.
+fn total(chunks: &[select::SelectChunk], key: &str) -> u64 {
+    chunks
+        .iter()
+        .map(|c| c.count() as u64 * c.get(key).and_then(|v| v.parse().ok()).unwrap_or(0))
+        .sum()
+}
+
+/// The cores and GPUs `spec` asks for, from `--select` or `--ncpus` and an
+/// `ngpus=` constraint.
+pub fn resources(spec: &JobSpec) -> Result<(u64, u64), String> {
+    match spec.select {
+        Some(ref statement) => {
+            let chunks = select::parse(statement)?;
+            Ok((total(&chunks, "ncpus"), total(&chunks, "ngpus")))
+        }
+        None => {
+            let gpus = spec
+                .constraints
+                .iter()
+                .filter_map(|c| c.strip_prefix("ngpus="))
+                .filter_map(|n| n.parse::<u64>().ok())
+                .sum();
+            Ok((spec.ncpus as u64, gpus))
+        }
+    }
+}
+
+pub fn estimate(spec: &JobSpec, rates: &Rates) -> Result<Estimate, String> {
+    if rates.core_hour.is_none() && rates.gpu_hour.is_none() {
+        return Err("no rates are configured; set cost.core_hour or cost.gpu_hour".to_string());
+    }
+    let (cores, gpus) = resources(spec)?;
+    let hours = pbs::parse_walltime(&spec.walltime)
+        .ok_or_else(|| format!("walltime '{}' is not [[DD:]HH:]MM:SS", spec.walltime))?
+        as f64
+        / 3600.0;
+    let cost = hours
+        * (cores as f64 * rates.core_hour.unwrap_or(0.0)
+            + gpus as f64 * rates.gpu_hour.unwrap_or(0.0));
+    Ok(Estimate {
+        cores,
+        gpus,
+        hours,
+        cost,
+    })
+}
+
+/// `12.50 EUR`, or just `12.50` without a currency.
+pub fn amount(cost: f64, rates: &Rates) -> String {
+    match rates.currency {
+        Some(ref currency) => format!("{:.2} {}", cost, currency),
+        None => format!("{:.2}", cost),
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: u64| if n == 1 { "" } else { "s" };
+        write!(f, "{} core{}", self.cores, plural(self.cores))?;
+        if self.gpus > 0 {
+            write!(f, " and {} GPU{}", self.gpus, plural(self.gpus))?;
+        }
+        write!(f, " for {:.1} h", self.hours)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let rates = Rates {
+            core_hour: Some(0.05),
+            gpu_hour: Some(1.0),
+            ..Rates::default()
+        };
+        let spec = JobSpec {
+            ncpus: 4,
+            walltime: "10:00:00".into(),
+            constraints: vec!["ngpus=2".into()],
+            ..Default::default()
+        };
+        let estimate = estimate(&spec, &rates).unwrap();
+        assert_eq!((estimate.cores, estimate.gpus), (4, 2));
+        assert!((estimate.cost - 22.0).abs() < 1e-9);
+
+        let spec = JobSpec {
+            select: Some("2:ncpus=8:ngpus=1+1:ncpus=4".into()),
+            ..spec
+        };
+        let estimate = super::estimate(&spec, &rates).unwrap();
+        assert_eq!((estimate.cores, estimate.gpus), (20, 2));
+        assert!(super::estimate(&spec, &Rates::default()).is_err());
+    }
+}
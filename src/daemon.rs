@@ -0,0 +1,110 @@
+//! Watch a directory and submit a job for each file that appears (`qsub2 daemon`).
+//!
+//! The directory is polled rather than watched with inotify so that it also
+//! works on the network file systems instruments usually write to. A file is
+//! only handed on once its size has stopped changing between two polls.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Files seen so far and the size each new one had at the last poll.
+#[derive(Default)]
+pub struct Tracker {
+    done: HashSet<PathBuf>,
+    pending: HashMap<PathBuf, u64>,
+}
+
+impl Tracker {
+    /// Start out treating `existing` files as already handled.
+    pub fn new(existing: impl IntoIterator<Item = PathBuf>) -> Self {
+        Tracker {
+            done: existing.into_iter().collect(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Given the current `(file, size)` listing, return the new files whose
+    /// size is the same as at the previous poll.
+    pub fn poll(&mut self, listing: Vec<(PathBuf, u64)>) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        let mut pending = HashMap::new();
+        for (file, size) in listing {
+            if self.done.contains(&file) {
+                continue;
+            }
+            if self.pending.get(&file) == Some(&size) {
+                self.done.insert(file.clone());
+                ready.push(file);
+            } else {
+                pending.insert(file, size);
+            }
+        }
+        self.pending = pending;
+        ready.sort();
+        ready
+    }
+}
+
+/// Regular, non-hidden files directly in `dir` with their sizes.
+pub fn list(dir: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((entry.path(), metadata.len()));
+        }
+    }
+    Ok(files)
+}
+
+/// Poll `dir` forever, calling `submit` with each group of files that are ready.
+pub fn watch(
+    dir: &Path,
+    interval: Duration,
+    include_existing: bool,
+    mut submit: impl FnMut(Vec<PathBuf>),
+) -> io::Result<()> {
+    let mut tracker = if include_existing {
+        Tracker::default()
+    } else {
+        Tracker::new(list(dir)?.into_iter().map(|(file, _)| file))
+    };
+    eprintln!("Watching {} for new files", dir.display());
+    loop {
+        match list(dir) {
+            Ok(listing) => {
+                let ready = tracker.poll(listing);
+                if !ready.is_empty() {
+                    submit(ready);
+                }
+            }
+            Err(e) => log::error!("reading {}: {}", dir.display(), e),
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_waits_for_files_to_settle() {
+        let file = |name: &str| PathBuf::from(name);
+        let mut tracker = Tracker::new([file("old.fq")]);
+        assert!(tracker
+            .poll(vec![(file("old.fq"), 10), (file("a.fq"), 100)])
+            .is_empty());
+        assert!(tracker.poll(vec![(file("a.fq"), 200)]).is_empty());
+        assert_eq!(tracker.poll(vec![(file("a.fq"), 200)]), [file("a.fq")]);
+        assert!(tracker.poll(vec![(file("a.fq"), 200)]).is_empty());
+    }
+}
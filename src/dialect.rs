@@ -0,0 +1,203 @@
+//! `--script-dialect`: the built-in template and the lines qsub2 adds to a
+//! script (exports, the epilogue, restart traps, the exit status) written
+//! for csh or fish instead of bash, for sites whose login shell is tcsh and
+//! whose module setup only works there.
+//!
+//! Only what a dialect can say is translated. csh has no functions and no
+//! traps, so there the epilogue and the failure mail run after the command
+//! rather than on exit, and `--requeue-on-preempt` is refused. Neither
+//! dialect gets the `--array` lookups, DMTCP checkpoints or
+//! `--walltime-warning`, which stay bash only.
+
+use crate::spec::JobSpec;
+use crate::{shell_quote, FAILURE_MAIL};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    #[default]
+    Bash,
+    Csh,
+    Fish,
+}
+
+impl Dialect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Bash => "bash",
+            Dialect::Csh => "csh",
+            Dialect::Fish => "fish",
+        }
+    }
+
+    /// The built-in template, used when no `--template` is given.
+    pub fn template(self) -> &'static str {
+        match self {
+            Dialect::Bash => include_str!("../templates/default_template.sh"),
+            Dialect::Csh => include_str!("../templates/default_template.csh"),
+            Dialect::Fish => include_str!("../templates/default_template.fish"),
+        }
+    }
+
+    /// The first thing in `spec` this dialect cannot express.
+    pub fn unsupported(self, spec: &JobSpec) -> Option<&'static str> {
+        if self == Dialect::Bash {
+            return None;
+        }
+        if spec.array.is_some() {
+            return Some("--array");
+        }
+        if spec.checkpoint.as_ref().is_some_and(|c| c.dmtcp) {
+            return Some("--checkpoint with dmtcp");
+        }
+        if spec.walltime_warning.is_some() {
+            return Some("--walltime-warning");
+        }
+        if self == Dialect::Csh && spec.requeue.is_some() {
+            return Some("--requeue-on-preempt");
+        }
+        None
+    }
+
+    /// `s` as a single word.
+    pub fn quote(self, s: &str) -> String {
+        match self {
+            // a newline inside quotes ends the command unless escaped
+            Dialect::Csh => shell_quote(s).replace('\n', "\\\n"),
+            // fish reads \\ and \' as escapes even inside single quotes
+            Dialect::Fish => shell_quote(&s.replace('\\', "\\\\")),
+            Dialect::Bash => shell_quote(s),
+        }
+    }
+
+    /// Lines exporting each of `vars`, whose values are already quoted.
+    pub fn export(self, vars: &[(&str, String)]) -> String {
+        match self {
+            Dialect::Bash => {
+                let assignments: Vec<String> = vars
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                format!("export {}\n", assignments.join(" "))
+            }
+            Dialect::Csh => vars
+                .iter()
+                .map(|(name, value)| format!("setenv {} {}\n", name, value))
+                .collect(),
+            Dialect::Fish => vars
+                .iter()
+                .map(|(name, value)| format!("set -gx {} {}\n", name, value))
+                .collect(),
+        }
+    }
+
+    /// What joins steps that stop at the first failure.
+    pub fn and(self) -> &'static str {
+        match self {
+            Dialect::Bash | Dialect::Fish => " &&\n",
+            Dialect::Csh => " && \\\n",
+        }
+    }
+
+    /// The variable that holds the status the job exits with.
+    fn status_variable(self) -> &'static str {
+        match self {
+            Dialect::Bash => "status",
+            Dialect::Csh | Dialect::Fish => "qsub2_status",
+        }
+    }
+
+    /// A line setting the job's status to `value`.
+    pub fn set_status(self, value: &str) -> String {
+        match self {
+            Dialect::Bash => format!("status={}", value),
+            Dialect::Csh => format!("set qsub2_status = {}", value),
+            Dialect::Fish => format!("set qsub2_status {}", value),
+        }
+    }
+
+    /// A line keeping the status of the command before it.
+    pub fn save_status(self) -> String {
+        match self {
+            Dialect::Bash => self.set_status("$?"),
+            Dialect::Csh | Dialect::Fish => self.set_status("$status"),
+        }
+    }
+
+    /// `step`, then keeping its status if it failed.
+    pub fn keep_failure(self, step: &str) -> String {
+        match self {
+            Dialect::Bash => format!("{} || status=$?\n", step),
+            Dialect::Csh => format!("{}\nif ($status != 0) set qsub2_status = $status\n", step),
+            Dialect::Fish => format!("{}\nor set qsub2_status $status\n", step),
+        }
+    }
+
+    /// The last line, exiting with the kept status.
+    pub fn exit(self) -> String {
+        format!("exit ${}", self.status_variable())
+    }
+
+    /// Mail the owner when the kept status is not zero.
+    pub fn failure_mail(self) -> String {
+        let mail = r#"echo "Job $PBS_JOBID ($PBS_JOBNAME) exited with status $qsub2_status" | mail -s "PBS job $PBS_JOBID failed" "$PBS_O_LOGNAME""#;
+        match self {
+            Dialect::Bash => FAILURE_MAIL.to_string(),
+            Dialect::Csh => format!("if ($qsub2_status != 0) then\n    {}\nendif", mail),
+            Dialect::Fish => format!("if test $qsub2_status -ne 0\n    {}\nend", mail),
+        }
+    }
+
+    /// Run `program requeue` for the job when PBS sends TERM to preempt it.
+    pub fn requeue_trap(self, program: &str) -> String {
+        match self {
+            Dialect::Fish => format!(
+                "function qsub2_requeue --on-signal TERM\n    {} requeue \"$PBS_JOBID\"\n    exit 143\nend\n",
+                program
+            ),
+            // csh is refused by `unsupported`
+            Dialect::Bash | Dialect::Csh => format!(
+                "qsub2_requeue() {{ {} requeue \"$PBS_JOBID\"; exit 143; }}\ntrap qsub2_requeue TERM\n",
+                program
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialects() {
+        let vars = [
+            ("QSUB2_SEGMENT", "2".to_string()),
+            ("QSUB2_SEGMENTS", "4".to_string()),
+        ];
+        assert_eq!(
+            Dialect::Bash.export(&vars),
+            "export QSUB2_SEGMENT=2 QSUB2_SEGMENTS=4\n"
+        );
+        assert_eq!(
+            Dialect::Csh.export(&vars),
+            "setenv QSUB2_SEGMENT 2\nsetenv QSUB2_SEGMENTS 4\n"
+        );
+        assert_eq!(
+            Dialect::Fish.export(&vars[..1]),
+            "set -gx QSUB2_SEGMENT 2\n"
+        );
+        assert_eq!(Dialect::Csh.quote("a\nb"), "'a\\\nb'");
+        assert_eq!(
+            Dialect::Fish.quote(r"printf 'a\\nb'"),
+            r"'printf '\''a\\\\nb'\'''"
+        );
+        assert_eq!(Dialect::Bash.quote(r"a\b"), r"'a\b'");
+        assert_eq!(Dialect::Fish.save_status(), "set qsub2_status $status");
+        assert_eq!(Dialect::Bash.exit(), "exit $status");
+        for dialect in [Dialect::Bash, Dialect::Csh, Dialect::Fish] {
+            assert!(dialect.template().contains("{command}"));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Unified diffs between an existing script and a newly rendered one (`--diff-against`).
+
+use crate::style;
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The lines of `old` and `new` in order, from their longest common subsequence.
+fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // common[i][j]: length of the common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A unified diff with three lines of context, or `None` when nothing changed.
+pub fn unified(old_name: &str, old: &str, new_name: &str, new: &str) -> Option<String> {
+    let lines = lines(old, new);
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], Line::Same(_)))
+        .collect();
+    if changed.is_empty() {
+        return None;
+    }
+
+    // Neighbouring changes share a hunk when their context would overlap.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let before = &lines[..start];
+        let old_start = before
+            .iter()
+            .filter(|l| !matches!(l, Line::Added(_)))
+            .count();
+        let new_start = before
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_len = hunk
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        // an empty range starts at the line before it
+        let position = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            position(old_start, old_len),
+            old_len,
+            position(new_start, new_len),
+            new_len
+        ));
+        for line in hunk {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            diff.push_str(&format!("{}{}\n", prefix, text));
+        }
+    }
+    Some(diff)
+}
+
+/// Print `diff` with removed lines in red and added lines in green.
+pub fn print(diff: &str) {
+    for line in diff.lines() {
+        let color = match line.chars().next() {
+            _ if line.starts_with("---") || line.starts_with("+++") => style::LABEL,
+            Some('-') => style::REMOVED,
+            Some('+') => style::ADDED,
+            Some('@') => style::DIRECTIVE,
+            _ => anstyle::Style::new(),
+        };
+        anstream::println!("{}{}{}", color.render(), line, color.render_reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified() {
+        let old = "#!/bin/bash\n#PBS -N a\n#PBS -q batch\n\ncd x\none\ntwo\nthree\nfour\necho a\n";
+        let new =
+            "#!/bin/bash\n#PBS -N a\n#PBS -q long\n\ncd x\none\ntwo\nthree\nfour\necho a\necho b\n";
+        assert_eq!(
+            unified("old.sh", old, "new", new).unwrap(),
+            "--- old.sh\n+++ new\n\
+             @@ -1,6 +1,6 @@\n #!/bin/bash\n #PBS -N a\n-#PBS -q batch\n+#PBS -q long\n \n cd x\n one\n\
+             @@ -8,3 +8,4 @@\n three\n four\n echo a\n+echo b\n"
+        );
+        assert_eq!(unified("a", old, "b", old), None);
+    }
+}
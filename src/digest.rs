@@ -0,0 +1,188 @@
+//! `qsub2 digest`: one message about the jobs that finished in a window, in
+//! place of a PBS mail for each of them.
+//!
+//! The jobs come from the history. A job counts if its end time
+//! (`obittime`, or Torque's `comp_time`) is in the window; without one, if it
+//! was submitted in the window. Only submissions up to [`LOOKBACK`] before
+//! the window are looked up, so a job that waited longer is missed.
+
+use crate::history::SubmissionRecord;
+use crate::pbs::{self, JobAttributes};
+use crate::report;
+use crate::suggest::short_duration;
+use chrono::{DateTime, Local};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+pub const LOOKBACK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, PartialEq)]
+pub struct Finished {
+    pub job_id: String,
+    pub name: String,
+    pub exit_status: Option<String>,
+    /// Seconds it ran.
+    pub runtime: Option<u64>,
+    pub ended: Option<DateTime<Local>>,
+    /// Percent of the requested cpus and memory that were used.
+    pub cpu_efficiency: Option<f64>,
+    pub mem_efficiency: Option<f64>,
+}
+
+impl Finished {
+    /// `None` while the job is queued or running.
+    pub fn from_attributes(attributes: &JobAttributes, record: &SubmissionRecord) -> Option<Self> {
+        let get = |key: &str| attributes.get(key).map(String::as_str);
+        let state = get("job_state").unwrap_or("F");
+        if !matches!(state, "F" | "C") && get("Exit_status").is_none() {
+            return None;
+        }
+        let efficiency = |resource: &str| {
+            report::lines(attributes)
+                .into_iter()
+                .find(|line| line.resource == resource)
+                .and_then(|line| line.efficiency)
+        };
+        Some(Finished {
+            job_id: record.job_id.clone(),
+            name: get("Job_Name").unwrap_or(&record.spec.name).to_string(),
+            exit_status: get("Exit_status").map(String::from),
+            runtime: get("resources_used.walltime").and_then(pbs::parse_walltime),
+            ended: get("obittime")
+                .or(get("comp_time"))
+                .and_then(pbs::parse_time),
+            cpu_efficiency: efficiency("cpus"),
+            mem_efficiency: efficiency("mem"),
+        })
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_status.as_deref().is_some_and(|s| s != "0")
+    }
+}
+
+/// `qsub2 digest: 12 jobs finished, 2 failed`
+pub fn subject(jobs: &[Finished]) -> String {
+    let failed = jobs.iter().filter(|j| j.failed()).count();
+    format!(
+        "qsub2 digest: {} {} finished, {} failed",
+        jobs.len(),
+        if jobs.len() == 1 { "job" } else { "jobs" },
+        failed
+    )
+}
+
+/// The failed jobs first, then the others by when they ended, and the median
+/// efficiencies.
+pub fn body(jobs: &mut [Finished], since: Duration) -> String {
+    jobs.sort_by_key(|j| (!j.failed(), j.ended));
+    let percent = |p: Option<f64>| p.map_or("-".to_string(), |p| format!("{:.0}%", p));
+    let mut text = format!(
+        "Jobs that finished in the last {}:\n\n{:<20} {:<16} {:>4} {:>8} {:>5} {:>5}\n",
+        short_duration(since.as_secs()),
+        "NAME",
+        "JOB",
+        "EXIT",
+        "RUNTIME",
+        "CPU",
+        "MEM"
+    );
+    for job in jobs.iter() {
+        text.push_str(&format!(
+            "{:<20} {:<16} {:>4} {:>8} {:>5} {:>5}\n",
+            job.name,
+            job.job_id,
+            job.exit_status.as_deref().unwrap_or("-"),
+            job.runtime.map_or("-".to_string(), short_duration),
+            percent(job.cpu_efficiency),
+            percent(job.mem_efficiency),
+        ));
+    }
+    let median = |mut values: Vec<f64>| {
+        values.sort_by(|a, b| a.total_cmp(b));
+        values.get(values.len() / 2).copied()
+    };
+    text.push_str(&format!(
+        "\nMedian efficiency: cpus {}, memory {}\n",
+        percent(median(
+            jobs.iter().filter_map(|j| j.cpu_efficiency).collect()
+        )),
+        percent(median(
+            jobs.iter().filter_map(|j| j.mem_efficiency).collect()
+        )),
+    ));
+    text
+}
+
+/// Send `body` to `to` with `mail`.
+pub fn mail(to: &str, subject: &str, body: &str) -> io::Result<()> {
+    let mut child = Command::new("mail")
+        .args(["-s", subject, to])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run mail: {}", e)))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(body.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("mail failed: {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::JobSpec;
+
+    #[test]
+    fn test_digest() {
+        let record = |job_id: &str| SubmissionRecord {
+            job_id: job_id.into(),
+            script: "run.sh".into(),
+            spec: JobSpec {
+                name: "align".into(),
+                ..JobSpec::default()
+            },
+            generated_at: Local::now(),
+            submitted_at: Local::now(),
+            fingerprint: None,
+            content: None,
+            invocation: None,
+            workdir: None,
+            user: None,
+        };
+        let attributes = |state: &str, exit: Option<&str>| {
+            let mut attributes = JobAttributes::new();
+            attributes.insert("job_state".into(), state.into());
+            attributes.insert("Resource_List.ncpus".into(), "4".into());
+            attributes.insert("resources_used.walltime".into(), "02:00:00".into());
+            attributes.insert("resources_used.cput".into(), "04:00:00".into());
+            if let Some(exit) = exit {
+                attributes.insert("Exit_status".into(), exit.into());
+            }
+            attributes
+        };
+        assert_eq!(
+            Finished::from_attributes(&attributes("R", None), &record("1.s")),
+            None
+        );
+        let mut jobs: Vec<Finished> = [("1.s", "0"), ("2.s", "1")]
+            .iter()
+            .map(|&(id, exit)| {
+                Finished::from_attributes(&attributes("F", Some(exit)), &record(id)).unwrap()
+            })
+            .collect();
+        assert_eq!(jobs[0].cpu_efficiency, Some(50.0));
+        assert_eq!(subject(&jobs), "qsub2 digest: 2 jobs finished, 1 failed");
+        let body = body(&mut jobs, Duration::from_secs(24 * 3600));
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines[0], "Jobs that finished in the last 1d:");
+        assert!(lines[3].starts_with("align                2.s"));
+        assert_eq!(lines[6], "Median efficiency: cpus 50%, memory -");
+    }
+}
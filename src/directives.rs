@@ -0,0 +1,133 @@
+//! Duplicate `#PBS` directives between a template and what qsub2 adds.
+//!
+//! A template may hard-code `#PBS -q long` while `--queue` or another option
+//! produces a directive for the same setting. The directive qsub2 generated
+//! wins and the template's line is dropped; identical lines are kept once.
+
+use std::collections::HashMap;
+
+/// What a directive sets: `-q`, or the resource name for `-l` (`-l walltime`)
+/// and the attribute for `-W` (`-W depend`). A `-l` line can set several.
+fn keys(directive: &str) -> Vec<String> {
+    let Some(rest) = directive.strip_prefix("#PBS").map(str::trim_start) else {
+        return Vec::new();
+    };
+    let (flag, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    match flag {
+        "-l" | "-W" => value
+            .trim()
+            .split(',')
+            .filter_map(|setting| setting.split('=').next())
+            .map(|name| format!("{} {}", flag, name.trim()))
+            .collect(),
+        _ => vec![flag.to_string()],
+    }
+}
+
+/// Merge the directives of `script`, where `template_lines` are the lines
+/// the template contained word for word. Fails when the template itself sets
+/// the same thing twice, or when an overridden template line also sets something else.
+pub fn merge(script: &str, template_lines: &[&str]) -> Result<String, String> {
+    let lines: Vec<&str> = script.split_inclusive('\n').collect();
+    // PBS stops reading directives at the first command.
+    let header = lines
+        .iter()
+        .position(|l| {
+            let l = l.trim();
+            !l.is_empty() && !l.starts_with('#') && !l.starts_with("{command}")
+        })
+        .unwrap_or(lines.len());
+
+    let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, line) in lines[..header].iter().enumerate() {
+        for key in keys(line.trim_end()) {
+            by_key.entry(key).or_default().push(i);
+        }
+    }
+
+    let is_template = |i: usize| template_lines.contains(&lines[i].trim_end());
+    let mut drop = vec![false; lines.len()];
+    let mut duplicated: Vec<_> = by_key.into_iter().filter(|(_, at)| at.len() > 1).collect();
+    duplicated.sort();
+    for (key, at) in duplicated {
+        let generated: Vec<usize> = at.iter().copied().filter(|&i| !is_template(i)).collect();
+        let keep = match generated.last() {
+            Some(&keep) => keep,
+            None if at
+                .iter()
+                .all(|&i| lines[i].trim_end() == lines[at[0]].trim_end()) =>
+            {
+                at[0]
+            }
+            None => {
+                return Err(format!(
+                    "the template sets {} more than once: {}",
+                    key,
+                    at.iter()
+                        .map(|&i| format!("'{}'", lines[i].trim_end()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        };
+        for &i in at.iter().filter(|&&i| i != keep) {
+            if drop[i] {
+                continue;
+            }
+            let line = lines[i].trim_end();
+            if lines[i].trim_end() != lines[keep].trim_end() {
+                if keys(line).len() > 1 {
+                    return Err(format!(
+                        "'{}' also sets other resources; edit the template instead of overriding {}",
+                        line, key
+                    ));
+                }
+                log::warn!(
+                    "replacing the template's '{}' with '{}'",
+                    line,
+                    lines[keep].trim_end()
+                );
+            }
+            drop[i] = true;
+        }
+    }
+    Ok(lines
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !drop[i])
+        .map(|(_, line)| *line)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(keys("#PBS -q long"), ["-q"]);
+        assert_eq!(
+            keys("#PBS -l nodes=1:ppn=4,walltime=1:00:00"),
+            ["-l nodes", "-l walltime"]
+        );
+        assert_eq!(keys("#PBS -W depend=afterok:1"), ["-W depend"]);
+        assert!(keys("echo #PBS").is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let template = ["#!/bin/bash", "#PBS -q long", "#PBS -N fixed"];
+        let script = "#!/bin/bash\n#PBS -q long\n#PBS -N fixed\n#PBS -q short\n#PBS -N fixed\n\necho hi\n#PBS -q not-a-directive\n";
+        assert_eq!(
+            merge(script, &template).unwrap(),
+            "#!/bin/bash\n#PBS -N fixed\n#PBS -q short\n\necho hi\n#PBS -q not-a-directive\n"
+        );
+
+        let twice = "#!/bin/bash\n#PBS -q long\n#PBS -q short\n";
+        let template = ["#!/bin/bash", "#PBS -q long", "#PBS -q short"];
+        assert!(merge(twice, &template).is_err());
+
+        let combined = "#PBS -l nodes=1,walltime=1:00:00\n#PBS -l walltime=2:00:00\n";
+        assert!(merge(combined, &["#PBS -l nodes=1,walltime=1:00:00"]).is_err());
+    }
+}
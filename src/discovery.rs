@@ -0,0 +1,347 @@
+//! `qsub2 cluster discover`: what the cluster offers, its queues with their
+//! limits and its kinds of node, kept in a cache so that `--queue auto`,
+//! `--segments` and the checks before a submission do not ask the server
+//! every time.
+//!
+//! The cache is `cluster.json` in `$XDG_CACHE_HOME/qsub2` (`~/.cache/qsub2`).
+//! It is used until it is `cluster_cache_ttl` old, five minutes unless
+//! configured, and then read again from `qstat -Qf` and `pbsnodes -a`.
+
+use crate::cluster::{self, Node, Queue};
+use crate::history;
+use crate::pbs;
+use crate::select;
+use crate::spec::JobSpec;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Local>,
+    pub queues: Vec<Queue>,
+    pub nodes: Vec<Node>,
+}
+
+/// Nodes alike in cpus, memory and GPUs, and the queue they are tied to.
+#[derive(Debug, PartialEq)]
+pub struct NodeType {
+    pub ncpus: u64,
+    pub mem: u64,
+    pub ngpus: u64,
+    pub queue: Option<String>,
+    pub count: usize,
+}
+
+/// `$XDG_CACHE_HOME/qsub2/cluster.json`, or `~/.cache/qsub2/cluster.json`.
+pub fn path() -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => history::home_dir()?.join(".cache"),
+    };
+    Some(cache_home.join("qsub2").join("cluster.json"))
+}
+
+/// Ask the server, and keep the answer in the cache.
+pub fn discover() -> io::Result<Snapshot> {
+    let queues = cluster::queues()?;
+    let nodes = cluster::nodes().unwrap_or_else(|e| {
+        log::warn!("cannot read the nodes ({}); only the queues are known", e);
+        Vec::new()
+    });
+    let snapshot = Snapshot {
+        taken_at: Local::now(),
+        queues,
+        nodes,
+    };
+    if let Some(path) = path() {
+        let written = fs::create_dir_all(path.parent().unwrap_or(&path))
+            .and_then(|()| fs::write(&path, serde_json::to_string(&snapshot).unwrap_or_default()));
+        if let Err(e) = written {
+            log::warn!("could not cache the cluster at {}: {}", path.display(), e);
+        }
+    }
+    Ok(snapshot)
+}
+
+/// The cached snapshot if it is younger than `ttl`.
+pub fn cached(ttl: Duration) -> Option<Snapshot> {
+    let content = fs::read_to_string(path()?).ok()?;
+    let snapshot: Snapshot = serde_json::from_str(&content).ok()?;
+    let age = (Local::now() - snapshot.taken_at).to_std().ok()?;
+    (age < ttl).then_some(snapshot)
+}
+
+/// The cached snapshot, or a new one once it is `ttl` old.
+pub fn get(ttl: Duration) -> io::Result<Snapshot> {
+    match cached(ttl) {
+        Some(snapshot) => {
+            log::debug!("Cluster from the cache of {}", snapshot.taken_at);
+            Ok(snapshot)
+        }
+        None => discover(),
+    }
+}
+
+impl Snapshot {
+    pub fn queue(&self, name: &str) -> Option<&Queue> {
+        self.queues.iter().find(|q| q.name == name)
+    }
+
+    pub fn node_types(&self) -> Vec<NodeType> {
+        cluster::classes(&self.nodes)
+            .into_iter()
+            .map(|class| NodeType {
+                ncpus: class[0].ncpus,
+                mem: class[0].mem,
+                ngpus: class[0].ngpus,
+                queue: class[0].queue.clone(),
+                count: class.len(),
+            })
+            .collect()
+    }
+
+    /// What the server would refuse about `spec`, never find a node for, or
+    /// what looks like a mistake next to the nodes it would run on.
+    pub fn problems(&self, spec: &JobSpec) -> Vec<String> {
+        let Some(queue) = self.queue(&spec.queue) else {
+            return vec![format!("there is no queue {}", spec.queue)];
+        };
+        let mut problems = Vec::new();
+        let walltime = pbs::parse_walltime(&spec.walltime).unwrap_or(0);
+        if let Some(max) = queue.max_walltime.filter(|&max| walltime > max) {
+            problems.push(format!(
+                "walltime {} is over the {} limit of queue {}",
+                spec.walltime,
+                pbs::format_walltime(max),
+                queue.name
+            ));
+        }
+        let (ncpus, ngpus) = crate::cost::resources(spec).unwrap_or((spec.ncpus as u64, 0));
+        if let Some(max) = queue.max_ncpus.filter(|&max| ncpus > max) {
+            problems.push(format!(
+                "{} cpus are over the {} of queue {}",
+                ncpus, max, queue.name
+            ));
+        }
+        let usable: Vec<&Node> = self
+            .nodes
+            .iter()
+            .filter(|n| n.serves(&queue.name))
+            .collect();
+        if usable.is_empty() {
+            return problems;
+        }
+        // what one node must hold: the job, or each chunk of its select statement
+        let chunks: Vec<(u64, u64, u64)> = match spec.select {
+            Some(ref statement) => select::parse(statement)
+                .unwrap_or_default()
+                .iter()
+                .map(|chunk| {
+                    let get = |key| chunk.get(key).and_then(|v| v.parse().ok());
+                    (
+                        get("ncpus").unwrap_or(1),
+                        chunk.get("mem").and_then(pbs::parse_size).unwrap_or(0),
+                        get("ngpus").unwrap_or(0),
+                    )
+                })
+                .collect(),
+            None => vec![(
+                ncpus,
+                spec.mem.as_deref().and_then(pbs::parse_size).unwrap_or(0),
+                ngpus,
+            )],
+        };
+        for (ncpus, mem, ngpus) in chunks {
+            if !usable.iter().any(|n| n.ncpus >= ncpus) {
+                problems.push(format!(
+                    "no node of queue {} has {} cpus",
+                    queue.name, ncpus
+                ));
+            } else if !usable.iter().any(|n| n.mem == 0 || n.mem >= mem) {
+                problems.push(format!(
+                    "no node of queue {} has {} of memory",
+                    queue.name,
+                    pbs::format_size(mem)
+                ));
+            } else if let Some(problem) = mem_per_cpu(&usable, &queue.name, ncpus, mem) {
+                problems.push(problem);
+            }
+            if ngpus > 0 && !usable.iter().any(|n| n.ngpus > 0) {
+                problems.push(format!(
+                    "queue {} has no GPUs; the job asks for {}",
+                    queue.name, ngpus
+                ));
+            } else if ngpus > 0 && !usable.iter().any(|n| n.ngpus >= ngpus) {
+                problems.push(format!(
+                    "no node of queue {} has {} GPUs",
+                    queue.name, ngpus
+                ));
+            }
+        }
+        problems.dedup();
+        problems
+    }
+}
+
+/// How far from the memory per cpu of `nodes` a request may be before it
+/// looks like a mistake.
+const MEM_PER_CPU_FACTOR: u64 = 4;
+
+/// A warning when `mem` over `ncpus` is far more memory per cpu than any of
+/// `nodes` has, which holds cores the job does not use, or far less than all,
+/// which is more often a wrong unit than a small job.
+fn mem_per_cpu(nodes: &[&Node], queue: &str, ncpus: u64, mem: u64) -> Option<String> {
+    let norms: Vec<u64> = nodes
+        .iter()
+        .filter(|n| n.mem > 0 && n.ncpus > 0)
+        .map(|n| n.mem / n.ncpus)
+        .collect();
+    let (&low, &high) = (norms.iter().min()?, norms.iter().max()?);
+    let requested = mem.checked_div(ncpus).filter(|&m| m > 0)?;
+    let (side, norm) = if requested > high * MEM_PER_CPU_FACTOR {
+        ("above", high)
+    } else if requested * MEM_PER_CPU_FACTOR < low {
+        ("below", low)
+    } else {
+        return None;
+    };
+    Some(format!(
+        "{} of memory per cpu is far {} the {} per cpu of the nodes of queue {}",
+        pbs::format_size(requested),
+        side,
+        pbs::format_size(norm),
+        queue
+    ))
+}
+
+/// The queues with their limits, then the node types.
+pub fn describe(snapshot: &Snapshot) -> String {
+    let limit = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let mut text = format!(
+        "{:<16} {:<8} {:>12} {:>8}\n",
+        "QUEUE", "STATE", "MAX WALLTIME", "MAX CPUS"
+    );
+    for queue in &snapshot.queues {
+        text.push_str(&format!(
+            "{:<16} {:<8} {:>12} {:>8}\n",
+            queue.name,
+            match (queue.enabled, queue.started) {
+                (true, true) => "open",
+                _ => "closed",
+            },
+            limit(queue.max_walltime.map(pbs::format_walltime)),
+            limit(queue.max_ncpus.map(|n| n.to_string())),
+        ));
+    }
+    text.push_str(&format!(
+        "\n{:>5} {:>6} {:>10} {:>5}  {}\n",
+        "NODES", "CPUS", "MEM", "GPUS", "QUEUE"
+    ));
+    for node_type in snapshot.node_types() {
+        text.push_str(&format!(
+            "{:>5} {:>6} {:>10} {:>5}  {}\n",
+            node_type.count,
+            node_type.ncpus,
+            limit((node_type.mem > 0).then(|| pbs::format_size(node_type.mem))),
+            node_type.ngpus,
+            node_type.queue.as_deref().unwrap_or("any"),
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problems_and_node_types() {
+        let node = |name: &str, ncpus, ngpus, queue: Option<&str>| Node {
+            name: name.into(),
+            ncpus,
+            ngpus,
+            mem: 256 << 30,
+            queue: queue.map(String::from),
+            ..Node::default()
+        };
+        let snapshot = Snapshot {
+            taken_at: Local::now(),
+            queues: vec![
+                Queue {
+                    name: "short".into(),
+                    max_walltime: Some(3600),
+                    ..Queue::default()
+                },
+                Queue {
+                    name: "gpu".into(),
+                    ..Queue::default()
+                },
+            ],
+            nodes: vec![
+                node("cpu01", 64, 0, None),
+                node("cpu02", 64, 0, None),
+                node("gpu01", 32, 4, Some("gpu")),
+            ],
+        };
+        let types = snapshot.node_types();
+        assert_eq!(types.len(), 2);
+        assert_eq!((types[0].count, types[0].ncpus), (2, 64));
+
+        let spec = |queue: &str, walltime: &str, ncpus| JobSpec {
+            queue: queue.into(),
+            walltime: walltime.into(),
+            ncpus,
+            ..JobSpec::default()
+        };
+        assert!(snapshot.problems(&spec("short", "00:30:00", 8)).is_empty());
+        assert_eq!(
+            snapshot.problems(&spec("short", "02:00:00", 128)),
+            [
+                "walltime 02:00:00 is over the 01:00:00 limit of queue short",
+                "no node of queue short has 128 cpus"
+            ]
+        );
+        let gpus = JobSpec {
+            constraints: vec!["ngpus=8".into()],
+            ..spec("gpu", "01:00:00", 1)
+        };
+        assert_eq!(
+            snapshot.problems(&gpus),
+            ["no node of queue gpu has 8 GPUs"]
+        );
+        let cpu_gpus = JobSpec {
+            constraints: vec!["ngpus=1".into()],
+            mem: Some("1gb".into()),
+            ..spec("short", "00:30:00", 64)
+        };
+        assert_eq!(
+            snapshot.problems(&cpu_gpus),
+            [
+                "16mb of memory per cpu is far below the 4gb per cpu of the nodes of queue short",
+                "queue short has no GPUs; the job asks for 1"
+            ]
+        );
+        let chunks = JobSpec {
+            select: Some("1:ncpus=1:mem=200gb+4:ncpus=128".into()),
+            ..spec("short", "00:30:00", 1)
+        };
+        assert_eq!(
+            snapshot.problems(&chunks),
+            [
+                "200gb of memory per cpu is far above the 4gb per cpu of the nodes of queue short",
+                "no node of queue short has 128 cpus"
+            ]
+        );
+        assert_eq!(
+            snapshot.problems(&spec("long", "01:00:00", 1)),
+            ["there is no queue long"]
+        );
+    }
+}
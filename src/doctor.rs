@@ -0,0 +1,281 @@
+//! `qsub2 doctor`: checks that the scheduler, the configuration and the
+//! templates are usable, for "why doesn't it work" triage.
+
+use crate::audit;
+use crate::config::{self, Config};
+use crate::encoding;
+use crate::style;
+use crate::{cluster, Cli};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn line(&mut self, status: Status, name: &str, detail: &str) {
+        let (label, label_style) = match status {
+            Status::Ok => ("ok", style::JOB_ID),
+            Status::Warn => ("warn", style::WARNING),
+            Status::Fail => {
+                self.failures += 1;
+                ("FAIL", style::ERROR)
+            }
+        };
+        anstream::println!(
+            "{}{:<4}{} {:<10} {}",
+            label_style.render(),
+            label,
+            label_style.render_reset(),
+            name,
+            detail
+        );
+    }
+
+    fn info(&mut self, name: &str, detail: &str) {
+        println!("{:<4} {:<10} {}", "", name, detail);
+    }
+}
+
+/// Run every check and print one line each. Returns the number of failed checks.
+pub fn run(cli: &Cli) -> usize {
+    let mut report = Report { failures: 0 };
+    report.info("qsub2", env!("CARGO_PKG_VERSION"));
+
+    let config = match config::default_path() {
+        Some(path) if path.exists() => match Config::load(&path) {
+            Ok(config) => {
+                report.line(Status::Ok, "config", &path.display().to_string());
+                config
+            }
+            Err(e) => {
+                report.line(Status::Fail, "config", &e.to_string());
+                Config::default()
+            }
+        },
+        Some(path) => {
+            report.info(
+                "config",
+                &format!("{} (not present, using defaults)", path.display()),
+            );
+            Config::default()
+        }
+        None => {
+            report.line(Status::Warn, "config", "cannot locate the home directory");
+            Config::default()
+        }
+    };
+    let project = std::env::current_dir()
+        .ok()
+        .and_then(|dir| config::find_project(&dir));
+    let config = match project {
+        Some(path) => match config.clone().with_project(&path) {
+            Ok(config) => {
+                report.line(Status::Ok, "project", &path.display().to_string());
+                config
+            }
+            Err(e) => {
+                report.line(Status::Fail, "project", &e.to_string());
+                config
+            }
+        },
+        None => config,
+    };
+
+    let plugin = config.scheduler_plugin();
+    if cli.no_scheduler {
+        report.line(
+            Status::Ok,
+            "qsub",
+            "mock scheduler (--no-scheduler / QSUB2_MOCK)",
+        );
+    } else if let Some(ref plugin) = plugin {
+        match find_program(&plugin.program) {
+            Some(path) => report.line(
+                Status::Ok,
+                "scheduler",
+                &format!("plugin {} ({})", plugin.name, path.display()),
+            ),
+            None => report.line(
+                Status::Fail,
+                "scheduler",
+                &format!(
+                    "plugin {}: {} not found; set it in [schedulers]",
+                    plugin.name,
+                    plugin.program.display()
+                ),
+            ),
+        }
+    } else {
+        let qsub = cli
+            .qsub_bin
+            .clone()
+            .or(config.qsub_path.clone())
+            .unwrap_or_else(|| PathBuf::from("qsub"));
+        match find_program(&qsub) {
+            Some(path) => report.line(Status::Ok, "qsub", &path.display().to_string()),
+            None => report.line(
+                Status::Fail,
+                "qsub",
+                &format!("{} not found; set qsub_path or --qsub-bin", qsub.display()),
+            ),
+        }
+    }
+    for (program, required) in [
+        // the plugin answers status queries in its place
+        ("qstat", plugin.is_none()),
+        ("qselect", false),
+        ("qdel", false),
+        ("pbsnodes", false),
+    ] {
+        match find_program(Path::new(program)) {
+            Some(path) => report.line(Status::Ok, program, &path.display().to_string()),
+            None if required => report.line(Status::Fail, program, "not found on PATH"),
+            None => report.line(Status::Warn, program, "not found on PATH"),
+        }
+    }
+
+    if let Some(version) = server_version() {
+        report.info("server", &version);
+    }
+    match cluster::queues() {
+        Ok(queues) if queues.is_empty() => report.line(Status::Warn, "queues", "none reported"),
+        Ok(queues) => {
+            let names: Vec<String> = queues
+                .iter()
+                .map(|q| match (q.enabled, q.started) {
+                    (true, true) => q.name.clone(),
+                    _ => format!("{} (not accepting jobs)", q.name),
+                })
+                .collect();
+            report.line(Status::Ok, "queues", &names.join(", "));
+        }
+        Err(e) if plugin.is_some() => report.line(Status::Warn, "queues", &e.to_string()),
+        Err(e) => report.line(Status::Fail, "queues", &e.to_string()),
+    }
+
+    check_audit_log(&mut report, &config);
+    check_templates(&mut report, &config);
+    report.failures
+}
+
+/// Whether submissions can be recorded where the site wants them, without
+/// creating the file.
+fn check_audit_log(report: &mut Report, config: &Config) {
+    match audit::target(config) {
+        None => {}
+        Some(audit::Target::Syslog) => match find_program(Path::new("logger")) {
+            Some(_) => report.line(Status::Ok, "audit", "syslog"),
+            None => report.line(Status::Fail, "audit", "syslog, but logger is not on PATH"),
+        },
+        Some(audit::Target::File(path)) => {
+            let writable = match path.exists() {
+                true => fs::OpenOptions::new().append(true).open(&path).is_ok(),
+                false => path
+                    .parent()
+                    .is_some_and(|dir| dir.as_os_str().is_empty() || dir.is_dir()),
+            };
+            match writable {
+                true => report.line(Status::Ok, "audit", &path.display().to_string()),
+                false => report.line(
+                    Status::Fail,
+                    "audit",
+                    &format!("cannot append to {}", path.display()),
+                ),
+            }
+        }
+    }
+}
+
+fn check_templates(report: &mut Report, config: &Config) {
+    let Some(dir) = config.template_dir() else {
+        return;
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) if config.template_dir.is_none() => {
+            report.info("templates", &format!("{} (not present)", dir.display()));
+            return;
+        }
+        Err(e) => {
+            report.line(
+                Status::Fail,
+                "templates",
+                &format!("{}: {}", dir.display(), e),
+            );
+            return;
+        }
+    };
+    let mut count = 0;
+    for path in entries.flatten().map(|e| e.path()).filter(|p| p.is_file()) {
+        count += 1;
+        match fs::read(&path).map(|bytes| encoding::decode(&bytes)) {
+            Ok((content, _)) if !content.contains("{command}") => report.line(
+                Status::Warn,
+                "template",
+                &format!("{} has no {{command}} placeholder", path.display()),
+            ),
+            Ok((_, changes)) if !changes.is_empty() => report.line(
+                Status::Warn,
+                "template",
+                &format!(
+                    "{} has {}, which qsub2 fixes in each script it writes",
+                    path.display(),
+                    changes.join(" and ")
+                ),
+            ),
+            Ok(_) => {}
+            Err(e) => report.line(
+                Status::Fail,
+                "template",
+                &format!("{}: {}", path.display(), e),
+            ),
+        }
+    }
+    report.line(
+        Status::Ok,
+        "templates",
+        &format!("{} ({} templates)", dir.display(), count),
+    );
+}
+
+/// An explicit path if it is a file, otherwise the first match on `PATH`.
+fn find_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+    let names: Vec<PathBuf> = if cfg!(windows) {
+        ["exe", "bat", "cmd"]
+            .iter()
+            .map(|extension| program.with_extension(extension))
+            .collect()
+    } else {
+        vec![program.to_path_buf()]
+    };
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| {
+        names
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// The version line of `qstat --version` (`pbs_version = 2022.1.1`, `Version: 6.1.2`).
+fn server_version() -> Option<String> {
+    let output = Command::new("qstat").arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).into_owned()
+        + &String::from_utf8_lossy(&output.stderr);
+    text.lines()
+        .map(str::trim)
+        .find(|l| l.to_lowercase().contains("version"))
+        .map(String::from)
+}
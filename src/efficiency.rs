@@ -0,0 +1,164 @@
+//! `qsub2 efficiency`: requested against used memory and walltime over many
+//! finished jobs, per class of job, with a request that would have fit.
+//!
+//! A job's class is its name, or its executable when it kept the default
+//! name, as with the hints of [`crate::suggest`]. Usage comes from
+//! `qstat -fx`, or `tracejob` once the server has forgotten the job. The
+//! recommendation takes the 95th percentile rather than the peak, so that
+//! one runaway job does not inflate every later request; it adds the same
+//! headroom as the hints.
+
+use crate::history::SubmissionRecord;
+use crate::pbs::{self, JobAttributes};
+use crate::report;
+use crate::spec::JobSpec;
+use crate::suggest::{self, PastUsage};
+
+/// The class a job is reported under.
+pub fn class(spec: &JobSpec) -> String {
+    let executable = spec.command.split_whitespace().next().unwrap_or_default();
+    match spec.name.as_str() {
+        "job" if !executable.is_empty() => executable.rsplit('/').next().unwrap().to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// What one finished job asked for and used, in bytes and seconds.
+#[derive(Debug, Default, PartialEq)]
+pub struct Run {
+    pub requested_mem: Option<u64>,
+    pub used_mem: Option<u64>,
+    pub requested_walltime: Option<u64>,
+    pub used_walltime: Option<u64>,
+}
+
+impl Run {
+    pub fn from_attributes(attributes: &JobAttributes, record: &SubmissionRecord) -> Self {
+        let get = |key: &str| attributes.get(key).map(String::as_str);
+        Run {
+            requested_mem: get("Resource_List.mem")
+                .or(record.spec.mem.as_deref())
+                .and_then(pbs::parse_size),
+            used_mem: get("resources_used.mem").and_then(pbs::parse_size),
+            requested_walltime: get("Resource_List.walltime")
+                .and_then(pbs::parse_walltime)
+                .or_else(|| pbs::parse_walltime(&record.spec.walltime)),
+            used_walltime: get("resources_used.walltime").and_then(pbs::parse_walltime),
+        }
+    }
+}
+
+/// The run of a submission, or `None` when nothing of its usage is recorded.
+pub fn lookup(record: &SubmissionRecord) -> Option<Run> {
+    let attributes = report::usage_attributes(&record.job_id).ok()?;
+    let run = Run::from_attributes(&attributes, record);
+    (run.used_mem.is_some() || run.used_walltime.is_some()).then_some(run)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub runs: usize,
+    /// The largest request, in case it changed between runs.
+    pub requested_mem: Option<u64>,
+    pub p95_mem: Option<u64>,
+    pub peak_mem: Option<u64>,
+    pub requested_walltime: Option<u64>,
+    pub p95_walltime: Option<u64>,
+    pub peak_walltime: Option<u64>,
+}
+
+pub fn summarize(runs: &[Run]) -> Summary {
+    let mut mem: Vec<u64> = runs.iter().filter_map(|r| r.used_mem).collect();
+    let mut walltime: Vec<u64> = runs.iter().filter_map(|r| r.used_walltime).collect();
+    Summary {
+        runs: runs.len(),
+        requested_mem: runs.iter().filter_map(|r| r.requested_mem).max(),
+        p95_mem: percentile(&mut mem, 95),
+        peak_mem: mem.last().copied(),
+        requested_walltime: runs.iter().filter_map(|r| r.requested_walltime).max(),
+        p95_walltime: percentile(&mut walltime, 95),
+        peak_walltime: walltime.last().copied(),
+    }
+}
+
+/// The nearest-rank percentile; sorts `values`.
+fn percentile(values: &mut [u64], percent: usize) -> Option<u64> {
+    values.sort_unstable();
+    let rank = (values.len() * percent).div_ceil(100).max(1);
+    values.get(rank - 1).copied()
+}
+
+impl Summary {
+    /// The `--mem` and `--walltime` that would have fit.
+    pub fn recommended(&self) -> (Option<String>, Option<String>) {
+        suggest::suggested(&PastUsage {
+            runs: self.runs,
+            max_mem: self.p95_mem,
+            max_walltime: self.p95_walltime,
+        })
+    }
+
+    /// What to change, e.g. `p95 usage 11gb — consider --mem 14gb`; nothing
+    /// when the job already requests what is recommended.
+    pub fn advice(&self) -> Vec<String> {
+        let (mem, walltime) = self.recommended();
+        let mut advice = Vec::new();
+        if let (Some(used), Some(mem)) = (self.p95_mem, mem) {
+            if self.requested_mem != pbs::parse_size(&mem) {
+                advice.push(format!(
+                    "p95 usage {} — consider --mem {}",
+                    pbs::format_size(used),
+                    mem
+                ));
+            }
+        }
+        if let (Some(used), Some(walltime)) = (self.p95_walltime, walltime) {
+            if self.requested_walltime != pbs::parse_walltime(&walltime) {
+                advice.push(format!(
+                    "p95 walltime {} — consider --walltime {}",
+                    suggest::short_duration(used),
+                    walltime
+                ));
+            }
+        }
+        advice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        const GB: u64 = 1 << 30;
+        let run = |mem: u64, walltime: u64| Run {
+            requested_mem: Some(64 * GB),
+            used_mem: Some(mem * GB),
+            requested_walltime: Some(24 * 3600),
+            used_walltime: Some(walltime * 3600),
+        };
+        let mut runs: Vec<Run> = (1..=19).map(|i| run(10 + i % 2, 4)).collect();
+        runs.push(run(40, 5));
+        let summary = summarize(&runs);
+        assert_eq!(summary.runs, 20);
+        assert_eq!(summary.p95_mem, Some(11 * GB));
+        assert_eq!(summary.peak_mem, Some(40 * GB));
+        assert_eq!(summary.p95_walltime, Some(4 * 3600));
+        assert_eq!(
+            summary.advice(),
+            [
+                "p95 usage 11gb — consider --mem 14gb",
+                "p95 walltime 4h — consider --walltime 06:00:00"
+            ]
+        );
+
+        let spec = |name: &str, command: &str| JobSpec {
+            name: name.into(),
+            command: command.into(),
+            ..Default::default()
+        };
+        assert_eq!(class(&spec("job", "/opt/bin/bwa mem ref.fa")), "bwa");
+        assert_eq!(class(&spec("align", "bwa mem ref.fa")), "align");
+    }
+}
@@ -0,0 +1,102 @@
+//! Templates and script files as edited on Windows: a byte order mark, CRLF
+//! line endings, UTF-16 or a legacy 8-bit encoding. A `\r` left at the end
+//! of the shebang makes the node look for `/bin/bash\r`, which fails with
+//! "bad interpreter", so scripts are written with LF only.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Files already warned about, so that a batch warns once for its template.
+static WARNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Read `path` with [`decode`], warning about what was changed.
+pub fn read(path: &Path) -> io::Result<String> {
+    let (text, changes) = decode(&fs::read(path)?);
+    if !changes.is_empty() {
+        let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+        if !warned.iter().any(|p| p == path) {
+            log::warn!(
+                "{} has {}; the script gets UTF-8 with LF line endings",
+                path.display(),
+                changes.join(" and ")
+            );
+            warned.push(path.to_path_buf());
+        }
+    }
+    Ok(text)
+}
+
+/// The text of `bytes` with LF line endings, and what had to be changed.
+pub fn decode(bytes: &[u8]) -> (String, Vec<&'static str>) {
+    let mut changes = Vec::new();
+    let text = if let Some(rest) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        changes.push("a byte order mark");
+        utf8_or_latin1(rest, &mut changes)
+    } else if let Some(text) = utf16(bytes) {
+        changes.push("UTF-16");
+        text
+    } else {
+        utf8_or_latin1(bytes, &mut changes)
+    };
+    if !text.contains('\r') {
+        return (text, changes);
+    }
+    changes.push(match text.contains("\r\n") {
+        true => "CRLF line endings",
+        false => "CR line endings",
+    });
+    (text.replace("\r\n", "\n").replace('\r', "\n"), changes)
+}
+
+fn utf8_or_latin1(bytes: &[u8], changes: &mut Vec<&'static str>) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => {
+            changes.push("text that is not UTF-8 (read as Latin-1)");
+            bytes.iter().map(|&b| char::from(b)).collect()
+        }
+    }
+}
+
+/// `bytes` decoded as UTF-16 when they start with its byte order mark.
+fn utf16(bytes: &[u8]) -> Option<String> {
+    let little = match bytes.get(..2)? {
+        b"\xff\xfe" => true,
+        b"\xfe\xff" => false,
+        _ => return None,
+    };
+    let units: Vec<u16> = bytes[2..]
+        .chunks_exact(2)
+        .map(|pair| match little {
+            true => u16::from_le_bytes([pair[0], pair[1]]),
+            false => u16::from_be_bytes([pair[0], pair[1]]),
+        })
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let (text, changes) = decode(b"#!/bin/bash\n{command}\n");
+        assert_eq!(text, "#!/bin/bash\n{command}\n");
+        assert!(changes.is_empty());
+
+        let (text, changes) = decode(b"\xef\xbb\xbf#!/bin/bash\r\n{command}\r\n");
+        assert_eq!(text, "#!/bin/bash\n{command}\n");
+        assert_eq!(changes, ["a byte order mark", "CRLF line endings"]);
+
+        let (text, changes) = decode(b"# caf\xe9\n");
+        assert_eq!(text, "# caf\u{e9}\n");
+        assert_eq!(changes, ["text that is not UTF-8 (read as Latin-1)"]);
+
+        let (text, changes) = decode(b"\xff\xfe#\x00!\x00\r\x00\n\x00");
+        assert_eq!(text, "#!\n");
+        assert_eq!(changes, ["UTF-16", "CRLF line endings"]);
+    }
+}
@@ -0,0 +1,133 @@
+//! `--env-file .env.cluster`: variables a project keeps in a dotenv file,
+//! exported at the top of the job so that each template does not have to
+//! set them again.
+//!
+//! Lines are `NAME=value`, optionally after `export`, with `#` comments.
+//! A value may be single-quoted, taken as it is, or double-quoted, where
+//! `\n`, `\t`, `\"` and `\\` are escapes; `$` is never expanded. Names the
+//! `[env_file]` table of the configuration denies, by default those that
+//! look like credentials, are left out: the script is a file on shared
+//! storage. They are exported rather than passed with `qsub -v`, which
+//! would also show them to anyone running `qstat -f`.
+
+use crate::snapshot::Filter;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The variables of a dotenv file, in order.
+pub fn parse(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected NAME=value", number + 1));
+        };
+        let name = name.trim();
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!(
+                "line {}: '{}' is not a variable name",
+                number + 1,
+                name
+            ));
+        }
+        let value = unquote(value.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+/// The text of a value as written after the `=`.
+fn unquote(value: &str) -> Result<String, String> {
+    let quote = value.chars().next().filter(|c| *c == '\'' || *c == '"');
+    let Some(quote) = quote else {
+        // an unquoted value ends at a comment
+        let end = value.find(" #").unwrap_or(value.len());
+        return Ok(value[..end].trim_end().to_string());
+    };
+    let mut text = String::new();
+    let mut chars = value[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == quote => {
+                let rest = chars.as_str().trim();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(format!("unexpected text after the closing {}", quote));
+                }
+                return Ok(text);
+            }
+            '\\' if quote == '"' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(other) => text.push(other),
+                None => break,
+            },
+            c => text.push(c),
+        }
+    }
+    Err(format!("the {} is not closed", quote))
+}
+
+/// The variables of the file at `path` that `filter` keeps, warning about the others.
+pub fn read(path: &Path, filter: &Filter) -> io::Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let mut variables = parse(&content).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })?;
+    variables.retain(|(name, _)| {
+        let keep = filter.keeps(name);
+        if !keep {
+            log::warn!(
+                "{} is left out of the job: it looks like a secret; allow it in [env_file]",
+                name
+            );
+        }
+        keep
+    });
+    Ok(variables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = "# cluster settings\n\
+                       OMP_NUM_THREADS=8\n\
+                       export REF=/data/ref/hg38.fa # the reference\n\
+                       GREETING='it is $HOME'\n\
+                       LINES=\"one\\ntwo \\\"quoted\\\"\"\n\
+                       \n\
+                       EMPTY=\n";
+        assert_eq!(
+            parse(content).unwrap(),
+            [
+                ("OMP_NUM_THREADS".to_string(), "8".to_string()),
+                ("REF".to_string(), "/data/ref/hg38.fa".to_string()),
+                ("GREETING".to_string(), "it is $HOME".to_string()),
+                ("LINES".to_string(), "one\ntwo \"quoted\"".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(
+            parse("A=1\n2B=x\n").unwrap_err(),
+            "line 2: '2B' is not a variable name"
+        );
+        assert_eq!(
+            parse("A='open\n").unwrap_err(),
+            "line 1: the ' is not closed"
+        );
+        assert!(parse("just words\n").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Errors and the exit status each one maps to.
+//!
+//! | status | meaning |
+//! |-------:|---------|
+//! | 0 | success |
+//! | 1 | any other error (scheduler queries, history, I/O, failed `doctor` checks) |
+//! | 2 | invalid command line |
+//! | 3 | the template cannot be read |
+//! | 4 | the job script cannot be written |
+//! | 5 | `qsub` or the scheduler plugin is not installed or not on `PATH` |
+//! | 6 | `qsub` or the scheduler plugin rejected the job |
+//! | 7 | invalid resource request |
+//! | 8 | refused to submit a duplicate job |
+//! | 9 | timed out waiting for jobs |
+//! | 10 | some jobs of a per-file batch failed |
+//! | 11 | the configuration file is invalid |
+//! | 12 | the `pre_submit` hook refused the job |
+//! | 13 | `--lint` found errors in the script |
+//! | 14 | `--require-clean-git` found uncommitted changes |
+//! | 15 | the job can cost more than `--max-cost` |
+//! | 16 | `--preflight` found a directory the job cannot write to, or too little free space |
+//! | 17 | a `{{ ... }}` expression of the template cannot be evaluated |
+//! | 130 | Ctrl-C while `qsub2 run` waited on its job |
+//!
+//! `qsub2 run` and `--local` exit with the job's own exit status instead.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Template(PathBuf, io::Error),
+    Render(PathBuf, io::Error),
+    QsubMissing(io::Error),
+    QsubRejected(String),
+    InvalidResources(String),
+    Duplicate(String),
+    Timeout,
+    BatchFailed(usize),
+    ChecksFailed(usize),
+    Config(PathBuf, String),
+    HookRefused(String),
+    LintFailed(usize),
+    UncleanGit(String),
+    TooExpensive(String),
+    Preflight(String),
+    /// A template expression that failed, with where it is.
+    Expression(String),
+    /// Ctrl-C while `qsub2 run` waited, with what became of the job.
+    Interrupted(String),
+    /// The exit status of a job followed by `qsub2 run` or run by `--local`.
+    JobFailed(i32),
+    /// Anything else, with what was being done at the time.
+    Io(&'static str, io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(..) | Error::ChecksFailed(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Template(..) => 3,
+            Error::Render(..) => 4,
+            Error::QsubMissing(_) => 5,
+            Error::QsubRejected(_) => 6,
+            Error::InvalidResources(_) => 7,
+            Error::Duplicate(_) => 8,
+            Error::Timeout => 9,
+            Error::BatchFailed(_) => 10,
+            Error::Config(..) => 11,
+            Error::HookRefused(_) => 12,
+            Error::LintFailed(_) => 13,
+            Error::UncleanGit(_) => 14,
+            Error::TooExpensive(_) => 15,
+            Error::Preflight(_) => 16,
+            Error::Expression(_) => 17,
+            Error::Interrupted(_) => crate::interrupt::EXIT_CODE,
+            // PBS reports a job killed by signal N as 256 + N (Torque: 128 + N)
+            Error::JobFailed(status) if *status > 256 => 128 + (status - 256),
+            Error::JobFailed(status) if (1..=255).contains(status) => *status,
+            Error::JobFailed(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Template(path, e) => {
+                write!(f, "cannot read template {}: {}", path.display(), e)
+            }
+            Error::Render(path, e) => {
+                write!(f, "cannot write job script {}: {}", path.display(), e)
+            }
+            Error::QsubMissing(e) => write!(f, "cannot run qsub: {}", e),
+            Error::QsubRejected(message) => write!(f, "qsub rejected the job: {}", message),
+            Error::InvalidResources(message) => write!(f, "invalid resources: {}", message),
+            Error::Duplicate(message) => write!(f, "{}", message),
+            Error::Timeout => write!(f, "timed out waiting for jobs"),
+            Error::BatchFailed(count) => write!(f, "{} files of the batch failed", count),
+            Error::Config(path, message) => {
+                write!(f, "invalid configuration {}: {}", path.display(), message)
+            }
+            Error::HookRefused(status) => {
+                write!(f, "the pre_submit hook refused the job ({})", status)
+            }
+            Error::ChecksFailed(count) => write!(f, "{} checks failed", count),
+            Error::LintFailed(count) => write!(f, "the script has {} lint errors", count),
+            Error::UncleanGit(message) => write!(f, "{}", message),
+            Error::TooExpensive(message) => write!(f, "{}", message),
+            Error::Preflight(message) => write!(f, "preflight checks failed:\n{}", message),
+            Error::Expression(message) => write!(f, "{}", message),
+            Error::Interrupted(message) => write!(f, "interrupted; {}", message),
+            Error::JobFailed(status) => write!(f, "the job exited with status {}", status),
+            Error::Io(doing, e) => write!(f, "{}: {}", doing, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,134 @@
+//! `--estimate-start`: a rough guess of when a job could start, from the
+//! cluster snapshot's free resources and queue backlog, and smaller shapes
+//! of the job that would likely start sooner.
+//!
+//! qsub2 does not know when the running jobs end, so it takes their ends to
+//! be spread evenly over the queue's walltime limit (a day without one): a
+//! node whose busy cpus must half free up is expected in half of that. Each
+//! job queued ahead per running job adds one such turnover. The guess is
+//! only good for comparing shapes and queues.
+
+use crate::cluster::{Node, Queue};
+use crate::discovery::Snapshot;
+use crate::suggest::short_duration;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// The seconds until `ncpus` cpus and `mem` bytes could be free on one node
+/// of `queue`, or `None` if no node of it is big enough.
+pub fn start_delay(
+    snapshot: &Snapshot,
+    queue: &Queue,
+    ncpus: u64,
+    mem: Option<u64>,
+) -> Option<u64> {
+    let turnover = queue.max_walltime.unwrap_or(DAY);
+    let nodes: Vec<&Node> = snapshot
+        .nodes
+        .iter()
+        .filter(|n| n.serves(&queue.name) && !n.is_unavailable())
+        .collect();
+    let node_delay = |node: &&Node| -> Option<u64> {
+        let mem_fits = mem.is_none_or(|m| node.mem == 0 || node.mem >= m);
+        if node.ncpus < ncpus || !mem_fits {
+            return None;
+        }
+        let share = |needed: u64, free: u64, total: u64| match total.saturating_sub(free) {
+            0 => 0.0,
+            busy => needed.saturating_sub(free) as f64 / busy as f64,
+        };
+        let cpus = share(ncpus, node.free_ncpus(), node.ncpus);
+        let memory = match (mem, node.mem) {
+            (Some(m), total) if total > 0 => share(m, node.free_mem(), total),
+            _ => 0.0,
+        };
+        Some((turnover as f64 * cpus.max(memory)) as u64)
+    };
+    let delay = if nodes.is_empty() {
+        // without node information, only the backlog counts
+        Some(0)
+    } else {
+        nodes.iter().filter_map(node_delay).min()
+    }?;
+    let backlog = queue.queued as f64 / queue.running.max(1) as f64;
+    Some(delay + (turnover as f64 * backlog) as u64)
+}
+
+/// `now`, `in about 40m` or `in about 6h`
+pub fn describe(delay: u64) -> String {
+    match delay {
+        0..600 => "now".to_string(),
+        d if d < 2 * 3600 => format!("in about {}", short_duration(d / 600 * 600)),
+        d => format!("in about {}", short_duration(d.div_ceil(3600) * 3600)),
+    }
+}
+
+/// Smaller cpu counts, with memory cut alike, that would start at least
+/// half an hour sooner than `ncpus`: `(ncpus, mem, seconds sooner)`.
+pub fn alternatives(
+    snapshot: &Snapshot,
+    queue: &Queue,
+    ncpus: u64,
+    mem: Option<u64>,
+) -> Vec<(u64, Option<u64>, u64)> {
+    let Some(delay) = start_delay(snapshot, queue, ncpus, mem) else {
+        return Vec::new();
+    };
+    let mut shapes: Vec<u64> = [3, 2, 1]
+        .iter()
+        .map(|quarters| ncpus * quarters / 4)
+        .filter(|&n| n > 0 && n < ncpus)
+        .collect();
+    shapes.dedup();
+    shapes
+        .into_iter()
+        .filter_map(|n| {
+            let mem = mem.map(|m| m / ncpus * n);
+            let sooner = delay.saturating_sub(start_delay(snapshot, queue, n, mem)?);
+            (sooner >= 1800).then_some((n, mem, sooner))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    #[test]
+    fn test_start_delay() {
+        let queue = Queue {
+            name: "batch".into(),
+            queued: 0,
+            running: 4,
+            enabled: true,
+            started: true,
+            max_walltime: Some(DAY),
+            ..Queue::default()
+        };
+        let snapshot = Snapshot {
+            taken_at: Local::now(),
+            queues: vec![queue.clone()],
+            nodes: vec![Node {
+                name: "cpu01".into(),
+                state: "job-busy".into(),
+                ncpus: 64,
+                assigned_ncpus: 48,
+                ..Node::default()
+            }],
+        };
+        assert_eq!(start_delay(&snapshot, &queue, 16, None), Some(0));
+        // 32 more of the 48 busy cpus: two thirds of a day
+        assert_eq!(start_delay(&snapshot, &queue, 48, None), Some(DAY * 2 / 3));
+        assert_eq!(start_delay(&snapshot, &queue, 128, None), None);
+        assert_eq!(
+            alternatives(&snapshot, &queue, 64, None)
+                .iter()
+                .map(|a| a.0)
+                .collect::<Vec<_>>(),
+            [48, 32, 16]
+        );
+        assert_eq!(describe(DAY * 2 / 3), "in about 16h");
+        assert_eq!(describe(4000), "in about 1h");
+    }
+}
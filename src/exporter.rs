@@ -0,0 +1,121 @@
+//! Prometheus metrics endpoint (`qsub2 exporter`).
+
+use crate::pbs::{self, JobAttributes};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+pub fn serve(listen: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    eprintln!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        if let Err(e) = handle(stream?) {
+            log::error!("serving metrics: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, body) = if path == "/metrics" {
+        match pbs::all_jobs() {
+            Ok(jobs) => ("200 OK", render(&jobs, Local::now())),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        }
+    } else {
+        ("404 Not Found", "See /metrics\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Render job counts per user and state, and the waiting time of queued jobs per queue.
+fn render(jobs: &[(String, JobAttributes)], now: DateTime<Local>) -> String {
+    let mut counts: BTreeMap<(String, String), u64> = BTreeMap::new();
+    let mut waits: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+
+    for (_, attributes) in jobs {
+        let get = |key: &str| attributes.get(key).map(String::as_str).unwrap_or("");
+        let user = get("Job_Owner").split('@').next().unwrap_or("").to_string();
+        let state = get("job_state");
+        *counts
+            .entry((user, pbs::state_name(state).to_string()))
+            .or_default() += 1;
+
+        if state == "Q" {
+            if let Some(qtime) = pbs::parse_time(get("qtime")) {
+                let wait = (now - qtime).num_seconds().max(0) as f64;
+                waits
+                    .entry(get("queue").to_string())
+                    .or_default()
+                    .push(wait);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("# HELP qsub2_jobs Jobs known to the server by owner and state.\n");
+    out.push_str("# TYPE qsub2_jobs gauge\n");
+    for ((user, state), count) in &counts {
+        let _ = writeln!(
+            out,
+            "qsub2_jobs{{user=\"{}\",state=\"{}\"}} {}",
+            user, state, count
+        );
+    }
+    out.push_str(
+        "# HELP qsub2_queue_wait_seconds_mean Mean time queued jobs have waited so far.\n",
+    );
+    out.push_str("# TYPE qsub2_queue_wait_seconds_mean gauge\n");
+    for (queue, w) in &waits {
+        let mean = w.iter().sum::<f64>() / w.len() as f64;
+        let _ = writeln!(
+            out,
+            "qsub2_queue_wait_seconds_mean{{queue=\"{}\"}} {}",
+            queue, mean
+        );
+    }
+    out.push_str(
+        "# HELP qsub2_queue_wait_seconds_max Longest time a queued job has waited so far.\n",
+    );
+    out.push_str("# TYPE qsub2_queue_wait_seconds_max gauge\n");
+    for (queue, w) in &waits {
+        let max = w.iter().cloned().fold(0.0, f64::max);
+        let _ = writeln!(
+            out,
+            "qsub2_queue_wait_seconds_max{{queue=\"{}\"}} {}",
+            queue, max
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let output = "Job Id: 1.server\n    Job_Owner = alice@login1\n    job_state = Q\n    queue = batch\n    qtime = Wed Oct 14 04:00:00 2026\n\nJob Id: 2.server\n    Job_Owner = alice@login1\n    job_state = Q\n    queue = batch\n    qtime = Wed Oct 14 04:10:00 2026\n\nJob Id: 3.server\n    Job_Owner = bob@login1\n    job_state = R\n    queue = batch\n";
+        let now = pbs::parse_time("Wed Oct 14 04:20:00 2026").unwrap();
+        let metrics = render(&pbs::parse_full(output), now);
+        assert!(metrics.contains("qsub2_jobs{user=\"alice\",state=\"queued\"} 2\n"));
+        assert!(metrics.contains("qsub2_jobs{user=\"bob\",state=\"running\"} 1\n"));
+        assert!(metrics.contains("qsub2_queue_wait_seconds_mean{queue=\"batch\"} 900\n"));
+        assert!(metrics.contains("qsub2_queue_wait_seconds_max{queue=\"batch\"} 1200\n"));
+    }
+}
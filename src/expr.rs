@@ -0,0 +1,479 @@
+//! `{{ ... }}` expressions, for values that placeholders cannot express:
+//!
+//! ```text
+//! #PBS -l select=1:ncpus={ncpus}:mem={{ ncpus * 4 }}gb
+//! #PBS -N {{ upper(stem) + "_" + index }}
+//! export OMP_NUM_THREADS={{ ngpus > 0 ? 1 : ncpus }}
+//! ```
+//!
+//! Templates see the job's settings (`name`, `queue`, `command`, `ncpus`,
+//! `ngpus`, `mem_gb`, `walltime_hours`), and so do `mem` and `walltime`
+//! wherever they are set; `--name` and `--outfile` see the input file (`file`,
+//! `basename`, `stem`, `ext`, `dir`, `abs`, `index`). An expression is numbers,
+//! `"text"`, those names, `+ - * / %`, comparisons, `and`, `or`, `not`,
+//! `COND ? A : B`, and the functions `min max floor ceil round upper lower
+//! replace(TEXT, FROM, TO) basename stem ext dir`. `+` joins when either side
+//! is text.
+//!
+//! Only `{{` followed by a space or tab starts an expression, so shell such
+//! as `{{a,b},c}` in a template is left alone; a literal `{{ ` is written
+//! `{{ "{{" }} `.
+
+use crate::cost;
+use crate::naming;
+use crate::pbs;
+use crate::spec::JobSpec;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+pub type Vars = HashMap<&'static str, Value>;
+
+impl Value {
+    fn number(&self) -> Result<f64, String> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            Value::Text(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| format!("'{}' is not a number", s)),
+            Value::Bool(b) => Err(format!("{} is not a number", b)),
+        }
+    }
+
+    fn truth(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0,
+            Value::Text(s) => !s.is_empty(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", "?", ":", "!",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_digit() || c == '.' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| format!("bad number '{}'", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            end
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("unclosed {} in {}", c, s))?;
+            tokens.push(Token::Text(rest[1..end + 1].to_string()));
+            end + 2
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            end
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|&&symbol| rest.starts_with(symbol))
+                .ok_or_else(|| format!("unexpected '{}'", c))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent over the tokens, evaluating as it goes.
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    at: usize,
+    vars: &'a Vars,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = match self.peek() {
+            Some(Token::Symbol(s)) => *s == symbol,
+            Some(Token::Name(name)) => name == symbol,
+            _ => false,
+        };
+        self.at += usize::from(matches);
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.eat(symbol) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", symbol)),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Value, String> {
+        let condition = self.or()?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.ternary()?;
+        self.expect(":")?;
+        let otherwise = self.ternary()?;
+        Ok(if condition.truth() { then } else { otherwise })
+    }
+
+    fn or(&mut self) -> Result<Value, String> {
+        let mut value = self.and()?;
+        while self.eat("or") {
+            let right = self.and()?;
+            value = Value::Bool(value.truth() || right.truth());
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Value, String> {
+        let mut value = self.comparison()?;
+        while self.eat("and") {
+            let right = self.comparison()?;
+            value = Value::Bool(value.truth() && right.truth());
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<Value, String> {
+        let left = self.sum()?;
+        let Some(op) = ["==", "!=", "<=", ">=", "<", ">"]
+            .into_iter()
+            .find(|op| self.eat(op))
+        else {
+            return Ok(left);
+        };
+        let right = self.sum()?;
+        let ordering = match (&left, &right) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (a, b) => Some(a.to_string().cmp(&b.to_string())),
+        };
+        let holds = match (op, ordering) {
+            (_, None) => false,
+            ("==", Some(o)) => o.is_eq(),
+            ("!=", Some(o)) => o.is_ne(),
+            ("<=", Some(o)) => o.is_le(),
+            (">=", Some(o)) => o.is_ge(),
+            ("<", Some(o)) => o.is_lt(),
+            (_, Some(o)) => o.is_gt(),
+        };
+        Ok(Value::Bool(holds))
+    }
+
+    fn sum(&mut self) -> Result<Value, String> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat("+") {
+                let right = self.product()?;
+                value = match (value, right) {
+                    (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                    (a, b) => Value::Text(format!("{}{}", a, b)),
+                };
+            } else if self.eat("-") {
+                let right = self.product()?;
+                value = Value::Number(value.number()? - right.number()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = ["*", "/", "%"].into_iter().find(|op| self.eat(op)) {
+            let (a, b) = (value.number()?, self.unary()?.number()?);
+            if op != "*" && b == 0.0 {
+                return Err("division by zero".to_string());
+            }
+            value = Value::Number(match op {
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            });
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat("-") {
+            return Ok(Value::Number(-self.unary()?.number()?));
+        }
+        if self.eat("not") || self.eat("!") {
+            return Ok(Value::Bool(!self.unary()?.truth()));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Value, String> {
+        let token = self.peek().cloned().ok_or("unexpected end")?;
+        self.at += 1;
+        match token {
+            Token::Number(n) => Ok(Value::Number(n)),
+            Token::Text(s) => Ok(Value::Text(s)),
+            Token::Symbol("(") => {
+                let value = self.ternary()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Symbol(s) => Err(format!("unexpected '{}'", s)),
+            Token::Name(name) if self.eat("(") => {
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.ternary()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                call(&name, &args)
+            }
+            Token::Name(name) if name == "true" || name == "false" => {
+                Ok(Value::Bool(name == "true"))
+            }
+            Token::Name(name) => self
+                .vars
+                .get(name.as_str())
+                .cloned()
+                .ok_or_else(|| format!("unknown name '{}'", name)),
+        }
+    }
+}
+
+fn call(function: &str, args: &[Value]) -> Result<Value, String> {
+    let text = |i: usize| args.get(i).map(Value::to_string).unwrap_or_default();
+    let count = match function {
+        "min" | "max" => args.len().max(1),
+        "replace" => 3,
+        _ => 1,
+    };
+    if args.len() != count {
+        return Err(format!("{}() takes {} arguments", function, count));
+    }
+    let numbers = || {
+        args.iter()
+            .map(Value::number)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let placeholder = |name: &str| {
+        naming::file_placeholders(Path::new(&text(0)))
+            .into_iter()
+            .find(|(p, _)| *p == name)
+            .map(|(_, value)| Value::Text(value))
+            .expect("a file placeholder")
+    };
+    Ok(match function {
+        "min" => Value::Number(numbers()?.into_iter().fold(f64::INFINITY, f64::min)),
+        "max" => Value::Number(numbers()?.into_iter().fold(f64::NEG_INFINITY, f64::max)),
+        "floor" => Value::Number(args[0].number()?.floor()),
+        "ceil" => Value::Number(args[0].number()?.ceil()),
+        "round" => Value::Number(args[0].number()?.round()),
+        "upper" => Value::Text(text(0).to_uppercase()),
+        "lower" => Value::Text(text(0).to_lowercase()),
+        "replace" => Value::Text(text(0).replace(&text(1), &text(2))),
+        "basename" => placeholder("{basename}"),
+        "stem" => placeholder("{stem}"),
+        "ext" => placeholder("{ext}"),
+        "dir" => placeholder("{dir}"),
+        _ => return Err(format!("unknown function '{}'", function)),
+    })
+}
+
+/// The value of `expression` with `vars`.
+pub fn evaluate(expression: &str, vars: &Vars) -> Result<Value, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        at: 0,
+        vars,
+    };
+    let value = parser.ternary()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(token) => Err(format!("unexpected {:?}", token)),
+    }
+}
+
+/// Where the next expression of `text` starts: a `{{` followed by blank space.
+fn next_expression(text: &str) -> Option<usize> {
+    text.match_indices("{{")
+        .map(|(start, _)| start)
+        .find(|&start| text[start + 2..].starts_with([' ', '\t']))
+}
+
+/// Whether `text` has an expression to render.
+pub fn has_expressions(text: &str) -> bool {
+    next_expression(text).is_some()
+}
+
+/// `text` with each `{{ ... }}` replaced by its value. In text of several
+/// lines, an error says which line the expression is on.
+pub fn render(text: &str, vars: &Vars) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = next_expression(rest) {
+        let failed = |message: String| {
+            let at = text.len() - rest.len() + start;
+            match text.contains('\n') {
+                true => format!("line {}: {}", text[..at].matches('\n').count() + 1, message),
+                false => message,
+            }
+        };
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| failed("'{{' without '}}'".to_string()))?;
+        let expression = &rest[start + 2..start + end];
+        let value = evaluate(expression, vars)
+            .map_err(|e| failed(format!("{{{{{}}}}}: {}", expression, e)))?;
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(&value.to_string());
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// What expressions in templates, `mem` and `walltime` see. Settings that
+/// are still expressions themselves are left out.
+pub fn spec_vars(spec: &JobSpec) -> Vars {
+    let mut vars = Vars::new();
+    let mut text = |name, value: &str| {
+        if !has_expressions(value) {
+            vars.insert(name, Value::Text(value.to_string()));
+        }
+    };
+    text("name", &spec.name);
+    text("queue", &spec.queue);
+    text("command", &spec.command);
+    vars.insert("ncpus", Value::Number(spec.ncpus.into()));
+    let ngpus = cost::resources(spec).map_or(0, |(_, ngpus)| ngpus);
+    vars.insert("ngpus", Value::Number(ngpus as f64));
+    if let Some(bytes) = spec.mem.as_deref().and_then(pbs::parse_size) {
+        vars.insert("mem_gb", Value::Number(bytes as f64 / (1u64 << 30) as f64));
+    }
+    if let Some(seconds) = pbs::parse_walltime(&spec.walltime) {
+        vars.insert("walltime_hours", Value::Number(seconds as f64 / 3600.0));
+    }
+    vars
+}
+
+/// What expressions in `--name` and `--outfile` see.
+pub fn file_vars(file: Option<&Path>, index: usize) -> Vars {
+    let mut vars = Vars::new();
+    vars.insert("index", Value::Number(index as f64));
+    if let Some(file) = file {
+        vars.insert("file", Value::Text(file.display().to_string()));
+        for (placeholder, value) in naming::file_placeholders(file) {
+            vars.insert(placeholder.trim_matches(['{', '}']), Value::Text(value));
+        }
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let spec = JobSpec {
+            ncpus: 8,
+            mem: Some("{{ ncpus * 4 }}gb".into()),
+            walltime: "02:00:00".into(),
+            ..JobSpec::default()
+        };
+        let vars = spec_vars(&spec);
+        assert_eq!(render(spec.mem.as_deref().unwrap(), &vars).unwrap(), "32gb");
+        assert!(!vars.contains_key("mem_gb"));
+        assert_eq!(
+            render("threads={{ ngpus > 0 ? 1 : min(ncpus, 4) }}", &vars).unwrap(),
+            "threads=4"
+        );
+        assert_eq!(
+            render("{{ walltime_hours * 0.75 }}h", &vars).unwrap(),
+            "1.5h"
+        );
+        assert_eq!(
+            render(
+                "{{ 7 / 2 }} {{ ceil(7 / 2) }} {{ -(1 + 2) * 3 % 4 }}",
+                &vars
+            )
+            .unwrap(),
+            "3.5 4 -1"
+        );
+
+        let vars = file_vars(Some(Path::new("data/s1.fq.gz")), 3);
+        assert_eq!(
+            render("{{ upper(stem) + '_' + index }}.sh", &vars).unwrap(),
+            "S1.FQ_3.sh"
+        );
+        assert_eq!(
+            render("{{ replace(basename(stem), '.fq', '') }}", &vars).unwrap(),
+            "s1"
+        );
+        assert_eq!(
+            render("{{ dir == 'data' and not (index < 2) }}", &vars).unwrap(),
+            "true"
+        );
+        assert!(render("{{ nope }}", &vars)
+            .unwrap_err()
+            .contains("unknown name 'nope'"));
+        assert!(render("{{ 1 +", &vars).is_err());
+        assert!(render("{{ 1 / 0 }}", &vars).is_err());
+        assert_eq!(
+            render("#!/bin/bash\necho {{ nope }}\n", &vars),
+            Err("line 2: {{ nope }}: unknown name 'nope'".to_string())
+        );
+        assert_eq!(
+            render("a\nb {{ 1 +\n", &vars),
+            Err("line 2: '{{' without '}}'".to_string())
+        );
+
+        // brace expansion is shell, not an expression
+        let shell = "for f in {{a,b},c}; do echo ${f}; done\nx={{1..3},}\n";
+        assert!(!has_expressions(shell));
+        assert_eq!(render(shell, &vars).unwrap(), shell);
+        assert_eq!(render("{{ \"{{\" }} x }}", &vars).unwrap(), "{{ x }}");
+    }
+}
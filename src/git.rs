@@ -0,0 +1,36 @@
+//! The git repository a job is submitted from (`--require-clean-git`).
+
+use std::io;
+use std::process::Command;
+
+fn git(args: &[&str]) -> io::Result<Option<String>> {
+    let output = Command::new("git").args(args).output()?;
+    // also the answer outside a repository
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+/// The commit checked out in the current directory's repository, or an
+/// error saying why the job should not be submitted from here.
+pub fn clean_head() -> Result<String, String> {
+    let cannot_run = |e: io::Error| format!("cannot run git: {}", e);
+    let head = git(&["rev-parse", "HEAD"])
+        .map_err(cannot_run)?
+        .ok_or("not in a git repository with commits")?;
+    // Untracked files are usually outputs; only changes to tracked files count.
+    let changes = git(&["status", "--porcelain", "--untracked-files=no"])
+        .map_err(cannot_run)?
+        .unwrap_or_default();
+    if !changes.is_empty() {
+        let count = changes.lines().count();
+        return Err(format!(
+            "{} tracked files have uncommitted changes; commit them or leave out --require-clean-git",
+            count
+        ));
+    }
+    Ok(head)
+}
@@ -0,0 +1,436 @@
+//! Records of submitted jobs.
+//!
+//! Every submission is appended to the history file as one JSON line; the
+//! optional submission log uses the same format.
+//!
+//! A lab can share one history by setting `history` in the configuration to
+//! a file everyone can write, e.g. on the group's project space. Writers hold
+//! a lock on the file, and every record says who submitted it; the commands
+//! about your jobs (`list`, `cancel`, `rerun --last`, ...) then only look at
+//! yours, and a job identical to someone else's is caught as a duplicate.
+
+use crate::pbs;
+use crate::scheduler;
+use crate::spec::JobSpec;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubmissionRecord {
+    pub job_id: String,
+    pub script: PathBuf,
+    pub spec: JobSpec,
+    pub generated_at: DateTime<Local>,
+    pub submitted_at: DateTime<Local>,
+    /// See [`fingerprint`]; missing in records written by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// The script itself, for jobs submitted without a script file (`--no-script`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// The qsub2 command line and directory it ran in, for `rerun`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invocation: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<PathBuf>,
+    /// Who submitted the job; missing in records written by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl SubmissionRecord {
+    /// Submitted by the user running qsub2; records without a user are
+    /// taken to be theirs, as they come from a history of their own.
+    pub fn is_mine(&self) -> bool {
+        self.user
+            .as_deref()
+            .is_none_or(|user| user == crate::current_user())
+    }
+}
+
+/// How long an identical earlier submission counts as a likely accident.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Hash of the rendered script and the set of input files (FNV-1a, so it is
+/// stable across builds and can be compared with older history entries).
+pub fn fingerprint(script: &str, files: &[PathBuf]) -> String {
+    let mut files: Vec<PathBuf> = files
+        .iter()
+        .map(|f| fs::canonicalize(f).unwrap_or_else(|_| f.clone()))
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    feed(script.as_bytes());
+    for file in &files {
+        feed(b"\0");
+        feed(file.to_string_lossy().as_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Duplicate {
+    /// Still queued or running.
+    Active(String),
+    /// Finished or gone, but submitted within the last day.
+    Recent(String),
+}
+
+/// The most recent earlier submission of the same job that is still active or recent.
+pub fn find_duplicate(records: &[SubmissionRecord], fingerprint: &str) -> Option<Duplicate> {
+    let now = Local::now();
+    for record in records
+        .iter()
+        .rev()
+        .filter(|r| r.fingerprint.as_deref() == Some(fingerprint))
+    {
+        if let Ok(Some(attributes)) = scheduler::job_status(&record.job_id) {
+            if !pbs::is_finished(&attributes) {
+                return Some(Duplicate::Active(record.job_id.clone()));
+            }
+        }
+        let elapsed = (now - record.submitted_at).to_std().unwrap_or_default();
+        if elapsed < DUPLICATE_WINDOW {
+            return Some(Duplicate::Recent(record.job_id.clone()));
+        }
+    }
+    None
+}
+
+/// Append the record as one JSON line, holding the file's lock so that
+/// writers sharing the history do not mix their lines.
+pub fn append(path: &Path, record: &SubmissionRecord) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.lock()?;
+    file.write_all(line.as_bytes())
+}
+
+/// The `history` of the configuration.
+static CONFIGURED_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `path` as the history unless `$QSUB2_HISTORY` is set.
+pub fn configure_path(path: PathBuf) {
+    let _ = CONFIGURED_PATH.set(path);
+}
+
+/// `$QSUB2_HISTORY`, the `history` of the configuration, or `history.jsonl`
+/// in `$XDG_DATA_HOME/qsub2` (`~/.local/share/qsub2`).
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("QSUB2_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    if let Some(path) = CONFIGURED_PATH.get() {
+        return Some(path.clone());
+    }
+    Some(data_dir()?.join("history.jsonl"))
+}
+
+/// Jobs deleted with `qsub2 cancel`, which must not be requeued as preempted:
+/// both get the same SIGTERM.
+fn cancelled_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("cancelled"))
+}
+
+pub fn mark_cancelled(job_id: &str) -> io::Result<()> {
+    let path =
+        cancelled_path().ok_or_else(|| io::Error::other("cannot locate the data directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(format!("{}\n", job_id).as_bytes())
+}
+
+pub fn was_cancelled(job_id: &str) -> bool {
+    cancelled_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .is_some_and(|content| content.lines().any(|line| line == job_id))
+}
+
+/// `$XDG_DATA_HOME/qsub2`, or `~/.local/share/qsub2`.
+pub fn data_dir() -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => home_dir()?.join(".local").join("share"),
+    };
+    Some(data_home.join("qsub2"))
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// The history as read so far, for a process that reads it again and again.
+#[derive(Default)]
+struct KeptOpen {
+    path: PathBuf,
+    /// Bytes and lines read.
+    offset: u64,
+    lines: usize,
+    records: Vec<SubmissionRecord>,
+}
+
+static KEPT_OPEN: Mutex<Option<KeptOpen>> = Mutex::new(None);
+
+/// Keep the records in memory from now on, and only parse what was appended
+/// since the last [`read`], as `qsub2 serve` does for every submission.
+pub fn keep_open() {
+    let mut kept = KEPT_OPEN.lock().unwrap_or_else(|e| e.into_inner());
+    kept.get_or_insert_with(KeptOpen::default);
+}
+
+/// The records of `reader`, counting lines from `first`, and the bytes and
+/// lines read. With `complete`, a last line without a newline is left for later.
+fn parse_lines(
+    mut reader: impl BufRead,
+    path: &Path,
+    first: usize,
+    complete: bool,
+) -> io::Result<(Vec<SubmissionRecord>, u64, usize)> {
+    let mut records = Vec::new();
+    let (mut bytes, mut lines) = (0, 0);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 || (complete && line.last() != Some(&b'\n')) {
+            break;
+        }
+        bytes += read as u64;
+        lines += 1;
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("skipping {}:{}: {}", path.display(), first + lines, e),
+        }
+    }
+    Ok((records, bytes, lines))
+}
+
+/// Read all records, skipping lines that cannot be parsed. A missing file is an empty history.
+pub fn read(path: &Path) -> io::Result<Vec<SubmissionRecord>> {
+    with_records(path, <[SubmissionRecord]>::to_vec)
+}
+
+/// `look` at all records, as [`read`] would return them, without copying
+/// the ones kept in memory.
+pub fn with_records<T>(path: &Path, look: impl FnOnce(&[SubmissionRecord]) -> T) -> io::Result<T> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(look(&[])),
+        Err(e) => return Err(e),
+    };
+    // a half-written line of another user would be skipped
+    file.lock_shared()?;
+    let mut kept = KEPT_OPEN.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(kept) = kept.as_mut() else {
+        return Ok(look(&parse_lines(BufReader::new(file), path, 0, false)?.0));
+    };
+    if kept.path != path || file.metadata()?.len() < kept.offset {
+        *kept = KeptOpen {
+            path: path.to_path_buf(),
+            ..KeptOpen::default()
+        };
+    }
+    file.seek(SeekFrom::Start(kept.offset))?;
+    let (records, bytes, lines) = parse_lines(BufReader::new(file), path, kept.lines, true)?;
+    kept.records.extend(records);
+    kept.offset += bytes;
+    kept.lines += lines;
+    Ok(look(&kept.records))
+}
+
+/// What the server says about a submitted job now.
+#[derive(Debug, Default, PartialEq)]
+pub struct FinalState {
+    pub state: String,
+    pub exit_status: Option<String>,
+    /// Times the server ran the job again, e.g. after a node failure.
+    pub requeues: u32,
+}
+
+impl FinalState {
+    /// Finished with a non-zero exit status of its own, not one the server
+    /// gives a job it is about to run again.
+    pub fn failed(&self) -> bool {
+        self.exit_status
+            .as_deref()
+            .is_some_and(|s| s != "0" && !pbs::is_requeue_status(s))
+    }
+
+    pub fn requeued(&self) -> bool {
+        self.requeues > 0
+            || self
+                .exit_status
+                .as_deref()
+                .is_some_and(pbs::is_requeue_status)
+    }
+}
+
+/// `finished (exit 1)`, `queued (requeued 1 time)` or `running`.
+impl fmt::Display for FinalState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut notes = Vec::new();
+        match self.exit_status {
+            Some(ref status) if pbs::is_requeue_status(status) => {}
+            Some(ref status) => notes.push(format!("exit {}", status)),
+            None => {}
+        }
+        if self.requeues > 0 {
+            let plural = if self.requeues == 1 { "" } else { "s" };
+            notes.push(format!("requeued {} time{}", self.requeues, plural));
+        } else if self.requeued() {
+            notes.push("requeued by the server".to_string());
+        }
+        write!(f, "{}", self.state)?;
+        if !notes.is_empty() {
+            write!(f, " ({})", notes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The most recent submission of each job in `group`; a job submitted again
+/// by `rerun --group` replaces its earlier attempt.
+pub fn latest_in_group<'a>(
+    records: &'a [SubmissionRecord],
+    group: &str,
+) -> Vec<&'a SubmissionRecord> {
+    let mut latest: Vec<&SubmissionRecord> = Vec::new();
+    for record in records
+        .iter()
+        .filter(|r| r.spec.job_group.as_deref() == Some(group))
+    {
+        let same_job = |r: &&SubmissionRecord| {
+            r.script != Path::new("-") && r.script == record.script && r.workdir == record.workdir
+        };
+        match latest.iter().position(same_job) {
+            Some(i) => latest[i] = record,
+            None => latest.push(record),
+        }
+    }
+    latest
+}
+
+pub fn resolve_state(job_id: &str) -> FinalState {
+    match scheduler::job_status_with_history(job_id) {
+        Ok(Some(attributes)) => FinalState {
+            state: pbs::state_name(attributes.get("job_state").map_or("", String::as_str))
+                .to_string(),
+            exit_status: attributes.get("Exit_status").cloned(),
+            requeues: pbs::requeues(&attributes),
+        },
+        _ => FinalState {
+            state: "unknown".to_string(),
+            ..FinalState::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_failure_is_not_a_failure() {
+        let state = |exit_status: Option<&str>, requeues| FinalState {
+            state: "finished".to_string(),
+            exit_status: exit_status.map(String::from),
+            requeues,
+        };
+        assert!(state(Some("1"), 0).failed());
+        assert!(!state(Some("-11"), 0).failed());
+        assert_eq!(
+            state(Some("-11"), 0).to_string(),
+            "finished (requeued by the server)"
+        );
+        assert_eq!(
+            state(Some("0"), 2).to_string(),
+            "finished (exit 0, requeued 2 times)"
+        );
+        assert_eq!(state(None, 0).to_string(), "finished");
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_file_order() {
+        let a = || PathBuf::from("/data/a.fq");
+        let b = || PathBuf::from("/data/b.fq");
+        let script = "#!/bin/bash\nbwa mem\n";
+        assert_eq!(
+            fingerprint(script, &[a(), b()]),
+            fingerprint(script, &[b(), a()])
+        );
+        assert_ne!(fingerprint(script, &[a()]), fingerprint(script, &[b()]));
+        assert_ne!(fingerprint(script, &[a()]), fingerprint("other", &[a()]));
+    }
+
+    #[test]
+    fn test_shared_history_keeps_the_user() {
+        let path = env::temp_dir().join(format!("qsub2-history-{}.jsonl", std::process::id()));
+        let record = |job_id: &str, user: Option<&str>| SubmissionRecord {
+            job_id: job_id.into(),
+            script: "run.sh".into(),
+            spec: JobSpec::default(),
+            generated_at: Local::now(),
+            submitted_at: Local::now(),
+            fingerprint: None,
+            content: None,
+            invocation: None,
+            workdir: None,
+            user: user.map(String::from),
+        };
+        let me = crate::current_user();
+        for (job_id, user) in [
+            ("1.s", Some(me.as_str())),
+            ("2.s", Some("not-me")),
+            ("3.s", None),
+        ] {
+            append(&path, &record(job_id, user)).unwrap();
+        }
+        let records = read(&path).unwrap();
+        assert_eq!(with_records(&path, |records| records.len()).unwrap(), 3);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(with_records(&path, |records| records.len()).unwrap(), 0);
+        let mine: Vec<&str> = records
+            .iter()
+            .filter(|r| r.is_mine())
+            .map(|r| r.job_id.as_str())
+            .collect();
+        assert_eq!(mine, ["1.s", "3.s"]);
+        assert_eq!(records[1].user.as_deref(), Some("not-me"));
+
+        // what a kept-open history reads of a line still being written
+        let line = serde_json::to_string(&record("4.s", None)).unwrap();
+        let text = format!("{}\n{}", line, &line[..10]);
+        let (records, bytes, lines) = parse_lines(text.as_bytes(), &path, 0, true).unwrap();
+        assert_eq!((records.len(), bytes, lines), (1, line.len() as u64 + 1, 1));
+    }
+}
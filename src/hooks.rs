@@ -0,0 +1,33 @@
+//! `pre_submit` and `post_submit` commands from the configuration, run on the
+//! submitting machine around each submission with JSON on standard input.
+
+use std::io::{self, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Run `hook` through the shell, feeding it `input`. `QSUB2_HOOK` tells a
+/// script shared between both hooks which one it is.
+pub fn run(name: &str, hook: &str, input: &str) -> io::Result<ExitStatus> {
+    log::debug!("{} hook: {}", name, hook);
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+    let mut child = command
+        .env("QSUB2_HOOK", name)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run {} hook: {}", name, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that does not read its input closes the pipe early; that is fine.
+        match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    child.wait()
+}
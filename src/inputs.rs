@@ -0,0 +1,302 @@
+//! Where the input files of a batch come from besides the command line:
+//! `--files0-from`, `--glob` and `--recursive`; and which of them are kept:
+//! `--ext`, `--min-size` and `--newer-than`.
+//!
+//! qsub2 expands the patterns itself, so a directory with a hundred thousand
+//! files does not run into the shell's argument limit, and sorts what it
+//! finds, so the same directory always gives the same jobs in the same order.
+//! As in the shell, hidden files and directories are skipped.
+
+use crate::pbs;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// The file names in `path` (- for stdin), separated by NUL bytes as
+/// `find -print0` writes them, so they may contain spaces and newlines.
+pub fn read_files0(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let content = if path == Path::new("-") {
+        let mut content = Vec::new();
+        io::Read::read_to_end(&mut io::stdin(), &mut content)?;
+        content
+    } else {
+        fs::read(path)?
+    };
+    Ok(content
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStrExt;
+                PathBuf::from(std::ffi::OsStr::from_bytes(name))
+            }
+            #[cfg(not(unix))]
+            {
+                PathBuf::from(String::from_utf8_lossy(name).into_owned())
+            }
+        })
+        .collect())
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// [`wildcard`] on strings.
+pub fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    wildcard(&pattern, &name)
+}
+
+/// Whether `name` matches `pattern`, with `*`, `?` and `[a-z]`/`[!a-z]`.
+fn wildcard(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| wildcard(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && wildcard(rest, &name[1..]),
+        Some(('[', rest)) => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                return name.first() == Some(&'[') && wildcard(rest, &name[1..]);
+            };
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            let (negated, set) = match rest[..end].split_first() {
+                Some(('!' | '^', set)) => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    matched |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= set[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && wildcard(&rest[end + 1..], &name[1..])
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && wildcard(rest, &name[1..]),
+    }
+}
+
+/// The entries of `dir`, without hidden ones unless `hidden`.
+fn entries(dir: &Path, hidden: bool) -> Vec<(String, PathBuf)> {
+    let read = fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    });
+    let Ok(read) = read else {
+        return Vec::new();
+    };
+    read.filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| hidden || !name.starts_with('.'))
+        .map(|name| {
+            let path = dir.join(&name);
+            (name, path)
+        })
+        .collect()
+}
+
+/// A directory to descend into; symbolic links are not, so loops cannot be.
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+fn walk(dir: &Path, parts: &[String], found: &mut Vec<PathBuf>) {
+    let Some((part, rest)) = parts.split_first() else {
+        found.push(dir.to_path_buf());
+        return;
+    };
+    if part == "**" {
+        walk(dir, rest, found);
+        for (_, path) in entries(dir, false) {
+            if is_real_dir(&path) {
+                walk(&path, parts, found);
+            }
+        }
+    } else if !has_wildcards(part) {
+        let path = dir.join(part);
+        if fs::symlink_metadata(&path).is_ok() {
+            walk(&path, rest, found);
+        }
+    } else {
+        let pattern: Vec<char> = part.chars().collect();
+        for (name, path) in entries(dir, part.starts_with('.')) {
+            let name: Vec<char> = name.chars().collect();
+            if wildcard(&pattern, &name) && (rest.is_empty() || path.is_dir()) {
+                walk(&path, rest, found);
+            }
+        }
+    }
+}
+
+/// The files `pattern` matches, sorted. `**` stands for any number of
+/// directories, so `**/*.fastq.gz` finds them at any depth.
+pub fn glob(pattern: &str) -> Vec<PathBuf> {
+    let mut base = PathBuf::new();
+    let mut parts = Vec::new();
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        match component {
+            Component::Normal(_) if !parts.is_empty() || has_wildcards(&part) => {
+                parts.push(part.into_owned())
+            }
+            _ => base.push(component),
+        }
+    }
+    files(&base, &parts)
+}
+
+fn files(base: &Path, parts: &[String]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    walk(base, parts, &mut found);
+    found.retain(|path| path.is_file());
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// The files under `dir`, sorted.
+pub fn recursive(dir: &Path) -> Vec<PathBuf> {
+    files(dir, &["**".to_string(), "*".to_string()])
+}
+
+/// `a.fastq.gz` has the extension `fastq.gz`, and `gz`, but not `q.gz`.
+fn has_extension(path: &Path, extension: &str) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let extension = extension.trim_start_matches('.');
+    name.strip_suffix(extension)
+        .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+}
+
+/// `--min-size 1gb`
+pub fn parse_min_size(s: &str) -> Result<u64, String> {
+    pbs::parse_size(s).ok_or_else(|| format!("invalid size '{}', expected e.g. 500mb", s))
+}
+
+/// `--newer-than`: a time ago such as `1d`, a local date or time such as
+/// `2025-01-31` or `2025-01-31 09:00`, or a file whose modification time to use.
+pub fn parse_newer_than(s: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = crate::parse_duration(s) {
+        return Ok(SystemTime::now() - ago);
+    }
+    let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .ok()
+        .or_else(|| {
+            let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+            date.and_hms_opt(0, 0, 0)
+        });
+    if let Some(time) = time.and_then(|t| Local.from_local_datetime(&t).earliest()) {
+        return Ok(time.into());
+    }
+    fs::metadata(s).and_then(|m| m.modified()).map_err(|_| {
+        format!(
+            "expected a time ago such as 1d, a date such as 2025-01-31, or a file, got {}",
+            s
+        )
+    })
+}
+
+/// Which input files to keep; a file the filter needs to look at but cannot
+/// is left out.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub extensions: Vec<String>,
+    pub min_size: Option<u64>,
+    pub newer_than: Option<SystemTime>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty() && self.min_size.is_none() && self.newer_than.is_none()
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        if !self.extensions.is_empty() && !self.extensions.iter().any(|e| has_extension(path, e)) {
+            return false;
+        }
+        if self.min_size.is_none() && self.newer_than.is_none() {
+            return true;
+        }
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        let big_enough = self.min_size.is_none_or(|min| metadata.len() >= min);
+        let new_enough = self
+            .newer_than
+            .is_none_or(|since| metadata.modified().is_ok_and(|m| m > since));
+        big_enough && new_enough
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_wildcard() {
+        let matches = |p: &str, n: &str| {
+            wildcard(
+                &p.chars().collect::<Vec<_>>(),
+                &n.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert!(matches("*_R[12].fq", "s1_R2.fq"));
+        assert!(!matches("*_R[!12].fq", "s1_R2.fq"));
+        assert!(matches("s?.f*", "s1.fq.gz"));
+        assert!(!matches("*.fq", "s1.fq.gz"));
+        assert!(has_extension(Path::new("d/a.fastq.gz"), ".fastq.gz"));
+        assert!(!has_extension(Path::new("d/a.fastq.gz"), "q.gz"));
+    }
+
+    #[test]
+    fn test_glob_and_recursive() {
+        let dir = env::temp_dir().join(format!("qsub2-inputs-{}", std::process::id()));
+        for file in [
+            "b/2.fq.gz",
+            "a/1.fq.gz",
+            "a/x/3.fq.gz",
+            "a/1.txt",
+            ".hidden/4.fq.gz",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let found = glob(&format!("{}/**/*.fq.gz", dir.display()));
+        let expected: Vec<PathBuf> = ["a/1.fq.gz", "a/x/3.fq.gz", "b/2.fq.gz"]
+            .iter()
+            .map(|f| dir.join(f))
+            .collect();
+        assert_eq!(found, expected);
+        let filter = Filter {
+            extensions: vec!["fq.gz".to_string()],
+            ..Filter::default()
+        };
+        let found: Vec<PathBuf> = recursive(&dir)
+            .into_iter()
+            .filter(|f| filter.matches(f))
+            .collect();
+        assert_eq!(found, expected);
+        let filter = Filter {
+            min_size: Some(1),
+            ..Filter::default()
+        };
+        assert!(!filter.matches(&expected[0]));
+        assert_eq!(glob(&format!("{}/a/*", dir.display())).len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! `qsub2 integrate`: files that let workflow managers submit through qsub2.
+//!
+//! The Snakemake profile hands each job script to qsub2, which takes the
+//! resources from the `# properties = {...}` line Snakemake writes into it,
+//! so the jobs get qsub2's defaults, checks and history. Job states come
+//! from `qsub2 status --quiet`.
+//!
+//! Nextflow submits with its own PBS executor, so it gets a `process` block
+//! with the resources and directives qsub2 would have used instead.
+
+use crate::pbs;
+use crate::spec::JobSpec;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SUBMIT: &str = r##"#!/usr/bin/env python3
+"""Submit a Snakemake job script through qsub2 and print the job ID."""
+import json
+import os
+import subprocess
+import sys
+
+QSUB2 = os.environ.get("QSUB2", @PROGRAM@)
+# the options given after -- to qsub2 integrate snakemake
+EXTRA = @EXTRA@
+
+jobscript = sys.argv[-1]
+properties = {}
+with open(jobscript) as f:
+    for line in f:
+        if line.startswith("# properties = "):
+            properties = json.loads(line[len("# properties = "):])
+            break
+resources = properties.get("resources", {})
+
+args = [QSUB2, "--submit", "--quiet", "--tag", "snakemake"]
+args += ["-n", "smk_" + str(properties.get("rule", "job"))]
+args += ["-@", str(properties.get("threads", 1))]
+if "mem_mb" in resources:
+    args += ["-m", "%dmb" % resources["mem_mb"]]
+if "runtime" in resources:
+    minutes = int(resources["runtime"])
+    args += ["-w", "%d:%02d:00" % (minutes // 60, minutes % 60)]
+args += EXTRA
+args += ["--command-file", jobscript]
+sys.exit(subprocess.call(args))
+"##;
+
+const STATUS: &str = r##"#!/usr/bin/env python3
+"""Print success, failed or running for a job, as Snakemake asks."""
+import os
+import subprocess
+import sys
+
+QSUB2 = os.environ.get("QSUB2", @PROGRAM@)
+WAITING = ("queued", "running", "held", "waiting", "exiting", "moving", "suspended")
+
+output = subprocess.run(
+    [QSUB2, "status", "--quiet", sys.argv[1]], capture_output=True, text=True
+).stdout
+job_id, state, exit_status = (output.strip().split("\t") + ["", "", ""])[:3]
+if state.split(" ")[0] in WAITING:
+    print("running")
+elif exit_status == "0":
+    print("success")
+else:
+    print("failed")
+"##;
+
+const CANCEL: &str = r##"#!/bin/sh
+# Cancel Snakemake's jobs when it is interrupted.
+exec "${QSUB2:-@PROGRAM_SH@}" cancel "$@"
+"##;
+
+/// `'it''s'`, a single-quoted YAML string.
+fn yaml_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn write_executable(path: &Path, content: &str) -> io::Result<()> {
+    fs::write(path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Write `config.yaml`, `submit`, `status` and `cancel` to `dir`, calling
+/// `program` with `extra` options for every job.
+pub fn snakemake(dir: &Path, program: &Path, extra: &[String]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let dir = fs::canonicalize(dir)?;
+    // JSON strings and lists are Python literals too
+    let python_program = serde_json::to_string(&program.display().to_string())?;
+    let python = |template: &str| {
+        template
+            .replace("@PROGRAM@", &python_program)
+            .replace("@EXTRA@", &serde_json::to_string(extra).unwrap_or_default())
+    };
+    write_executable(&dir.join("submit"), &python(SUBMIT))?;
+    write_executable(&dir.join("status"), &python(STATUS))?;
+    let program_sh = program.display().to_string().replace('"', "\\\"");
+    write_executable(
+        &dir.join("cancel"),
+        &CANCEL.replace("@PROGRAM_SH@", &program_sh),
+    )?;
+
+    let script = |name: &str| yaml_quote(&dir.join(name).display().to_string());
+    let config = format!(
+        "# Submit through qsub2: snakemake --profile {dir}\n\
+         # Snakemake 8 and later, with snakemake-executor-plugin-cluster-generic.\n\
+         executor: cluster-generic\n\
+         cluster-generic-submit-cmd: {submit}\n\
+         cluster-generic-status-cmd: {status}\n\
+         cluster-generic-cancel-cmd: {cancel}\n\
+         # Snakemake 7: use these instead of the four lines above.\n\
+         # cluster: {submit}\n\
+         # cluster-status: {status}\n\
+         # cluster-cancel: {cancel}\n\
+         jobs: 100\n\
+         latency-wait: 60\n",
+        dir = dir.display(),
+        submit = script("submit"),
+        status = script("status"),
+        cancel = script("cancel"),
+    );
+    fs::write(dir.join("config.yaml"), config)
+}
+
+/// `'it\'s'`, a single-quoted Groovy string.
+fn groovy_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// `8gb` as Nextflow writes it, `8 GB`.
+fn nextflow_memory(mem: &str) -> Option<String> {
+    let size = pbs::format_size(pbs::parse_size(mem)?);
+    let unit = size.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(format!(
+        "{} {}",
+        &size[..size.len() - unit.len()],
+        unit.to_uppercase()
+    ))
+}
+
+/// A walltime in whole hours, minutes or seconds, e.g. `720h`.
+fn nextflow_time(seconds: u64) -> String {
+    match seconds {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// The `process` and `executor` blocks of a nextflow.config for `spec`.
+/// `submit_args` and the `#PBS` lines qsub2 would add go in clusterOptions;
+/// output paths are left to Nextflow, which keeps its own per task.
+pub fn nextflow(spec: &JobSpec, submit_args: &[String], executor: &str) -> String {
+    let mut options: Vec<String> = submit_args.to_vec();
+    options.extend(
+        crate::extra_directives(spec)
+            .iter()
+            .filter_map(|d| d.strip_prefix("#PBS "))
+            .filter(|d| !d.starts_with("-o ") && !d.starts_with("-e "))
+            .map(String::from),
+    );
+    if !spec.constraints.is_empty() || spec.select.is_some() {
+        log::warn!(
+            "Nextflow writes its own select statement; --select and --constraint are left out"
+        );
+    }
+    let mut process = vec![
+        format!("executor = {}", groovy_quote(executor)),
+        format!("queue = {}", groovy_quote(&spec.queue)),
+        format!("cpus = {}", spec.ncpus),
+    ];
+    if let Some(memory) = spec.mem.as_deref().and_then(nextflow_memory) {
+        process.push(format!("memory = {}", groovy_quote(&memory)));
+    }
+    if let Some(seconds) = pbs::parse_walltime(&spec.walltime) {
+        process.push(format!("time = {}", groovy_quote(&nextflow_time(seconds))));
+    }
+    if !options.is_empty() {
+        process.push(format!(
+            "clusterOptions = {}",
+            groovy_quote(&options.join(" "))
+        ));
+    }
+    let mut config =
+        String::from("// Written by qsub2 integrate nextflow: what qsub2 would request.\n");
+    config.push_str("process {\n");
+    for line in process {
+        config.push_str(&format!("    {}\n", line));
+    }
+    config.push_str("}\n\nexecutor {\n    queueSize = 100\n}\n");
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nextflow() {
+        let spec = JobSpec {
+            queue: "long".into(),
+            ncpus: 4,
+            mem: Some("1536mb".into()),
+            walltime: "30:00:00:00".into(),
+            rerunnable: Some(false),
+            ..JobSpec::default()
+        };
+        let config = nextflow(&spec, &["-P".into(), "lab's".into()], "pbspro");
+        assert!(config.contains("\n    queue = 'long'\n    cpus = 4\n    memory = '1.5 GB'\n"));
+        assert!(config.contains("\n    time = '720h'\n"));
+        assert!(config.contains("\n    clusterOptions = '-P lab\\'s -r n'\n"));
+    }
+
+    #[test]
+    fn test_yaml_quote() {
+        assert_eq!(yaml_quote("/home/me/profile"), "'/home/me/profile'");
+        assert_eq!(yaml_quote("it's"), "'it''s'");
+    }
+}
@@ -0,0 +1,59 @@
+//! Ctrl-C while qsub2 waits on a job it submitted (`qsub2 run`).
+//!
+//! Once [`catch`] has been called, the first Ctrl-C only sets a flag, so the
+//! waiting code can offer to cancel the job before exiting; a second one
+//! exits at once and leaves the job alone.
+
+use signal_hook::consts::SIGINT;
+use signal_hook::flag;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The exit status of a process ended by SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+/// How often [`sleep`] looks at the flag.
+const TICK: Duration = Duration::from_millis(100);
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+pub fn catch() -> io::Result<()> {
+    if INTERRUPTED.get().is_some() {
+        return Ok(());
+    }
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // registered first, so it only sees the flag a previous Ctrl-C set
+    flag::register_conditional_shutdown(SIGINT, EXIT_CODE, Arc::clone(&interrupted))?;
+    flag::register(SIGINT, Arc::clone(&interrupted))?;
+    let _ = INTERRUPTED.set(interrupted);
+    Ok(())
+}
+
+pub fn requested() -> bool {
+    INTERRUPTED
+        .get()
+        .is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// An error for loops to stop with when [`requested`].
+pub fn check() -> io::Result<()> {
+    if requested() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+    }
+    Ok(())
+}
+
+/// Sleep, but wake up early when Ctrl-C is pressed.
+pub fn sleep(duration: Duration) {
+    let start = Instant::now();
+    while !requested() {
+        let left = duration.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            return;
+        }
+        thread::sleep(left.min(TICK));
+    }
+}
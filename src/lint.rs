@@ -0,0 +1,269 @@
+//! `--lint`: check a rendered script before it is written or submitted.
+//!
+//! ShellCheck is used when it is installed. Otherwise a few built-in checks
+//! catch the usual template mistakes: a missing `#!` line, unquoted
+//! variables, unterminated quotes, and `if`/`case`/`do` blocks that are not closed.
+//! Both only know sh and bash, so csh and fish scripts are not checked.
+
+use crate::style;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(line: usize, severity: Severity, message: impl Into<String>) -> Self {
+        Finding {
+            line,
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+/// ShellCheck's findings, or the built-in ones when it is not installed.
+pub fn check(script: &str) -> Vec<Finding> {
+    let shebang = script.lines().next().unwrap_or_default();
+    if ["csh", "fish"].iter().any(|shell| shebang.contains(shell)) {
+        log::warn!(
+            "--lint only checks sh and bash scripts; skipping {}",
+            shebang
+        );
+        return Vec::new();
+    }
+    match shellcheck(script) {
+        Ok(findings) => findings,
+        Err(e) => {
+            log::debug!("shellcheck: {}; using the built-in checks", e);
+            builtin(script)
+        }
+    }
+}
+
+/// Print the findings to stderr and return how many are errors.
+pub fn report(findings: &[Finding], script_name: &str) -> usize {
+    for finding in findings {
+        let (label, color) = match finding.severity {
+            Severity::Error => ("error", style::ERROR),
+            Severity::Warning => ("warning", style::WARNING),
+        };
+        anstream::eprintln!(
+            "{}:{}: {}{}{}: {}",
+            script_name,
+            finding.line,
+            color.render(),
+            label,
+            color.render_reset(),
+            finding.message
+        );
+    }
+    findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count()
+}
+
+/// `-:8:4: warning: Double quote to prevent globbing and word splitting. [SC2086]`
+fn shellcheck(script: &str) -> io::Result<Vec<Finding>> {
+    let mut child = Command::new("shellcheck")
+        .args(["--format", "gcc", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let findings = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ':');
+            let (_, line, _, rest) = (
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+            );
+            let (level, message) = rest.trim().split_once(": ")?;
+            let severity = if level == "error" {
+                Severity::Error
+            } else {
+                Severity::Warning
+            };
+            Some(Finding::new(line.parse().ok()?, severity, message))
+        })
+        .collect();
+    Ok(findings)
+}
+
+fn builtin(script: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if !script.starts_with("#!") {
+        findings.push(Finding::new(
+            1,
+            Severity::Warning,
+            "no #! line; PBS will run the script with your login shell",
+        ));
+    }
+
+    // Blocks still open, with what closes them and the line they started on.
+    let mut blocks: Vec<(&'static str, usize)> = Vec::new();
+    // The quote character and line of a string that is still open.
+    let mut quote: Option<(char, usize)> = None;
+    for (number, line) in script.lines().enumerate() {
+        let number = number + 1;
+        let mut word = String::new();
+        let mut command_position = true;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if let Some((open, _)) = quote {
+                if c == '\\' && open == '"' {
+                    chars.next();
+                } else if c == open {
+                    quote = None;
+                }
+                continue;
+            }
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '\'' | '"' => quote = Some((c, number)),
+                '#' if word.is_empty() => break,
+                '$' if word.is_empty() && !command_position => {
+                    if chars
+                        .peek()
+                        .is_some_and(|&n| n.is_ascii_alphabetic() || n == '_' || n == '{')
+                    {
+                        let name: String = chars
+                            .clone()
+                            .take_while(|&n| n.is_ascii_alphanumeric() || "_{}".contains(n))
+                            .collect();
+                        findings.push(Finding::new(
+                            number,
+                            Severity::Warning,
+                            format!("${} is not quoted; it will be split on spaces", name),
+                        ));
+                    }
+                    word.push(c);
+                }
+                ' ' | '\t' => {
+                    if !word.is_empty() {
+                        command_position =
+                            end_word(&word, command_position, number, &mut blocks, &mut findings);
+                        word.clear();
+                    }
+                }
+                ';' | '|' | '&' | '(' | ')' => {
+                    if !word.is_empty() {
+                        end_word(&word, command_position, number, &mut blocks, &mut findings);
+                        word.clear();
+                    }
+                    command_position = true;
+                }
+                _ => word.push(c),
+            }
+        }
+        if !word.is_empty() {
+            end_word(&word, command_position, number, &mut blocks, &mut findings);
+        }
+    }
+    if let Some((open, line)) = quote {
+        findings.push(Finding::new(
+            line,
+            Severity::Error,
+            format!("the {} quote opened here is never closed", open),
+        ));
+    }
+    for (closing, line) in blocks {
+        findings.push(Finding::new(
+            line,
+            Severity::Error,
+            format!("block opened here has no '{}'", closing),
+        ));
+    }
+    findings.sort_by_key(|f| f.line);
+    findings
+}
+
+/// Track block keywords in command position; returns whether the next word
+/// is in command position too.
+fn end_word(
+    word: &str,
+    command_position: bool,
+    number: usize,
+    blocks: &mut Vec<(&'static str, usize)>,
+    findings: &mut Vec<Finding>,
+) -> bool {
+    if command_position {
+        match word {
+            "if" => blocks.push(("fi", number)),
+            "case" => blocks.push(("esac", number)),
+            "do" => blocks.push(("done", number)),
+            "fi" | "esac" | "done" => match blocks.pop() {
+                Some((expected, _)) if expected == word => {}
+                Some((expected, start)) => findings.push(Finding::new(
+                    number,
+                    Severity::Error,
+                    format!(
+                        "'{}' where '{}' closing line {} was expected",
+                        word, expected, start
+                    ),
+                )),
+                None => findings.push(Finding::new(
+                    number,
+                    Severity::Error,
+                    format!("'{}' without a matching opening", word),
+                )),
+            },
+            _ => {}
+        }
+    }
+    // keywords that are followed by another command
+    matches!(
+        word,
+        "then" | "else" | "elif" | "do" | "if" | "while" | "until" | "!" | "time"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(script: &str) -> Vec<(usize, Severity)> {
+        builtin(script)
+            .into_iter()
+            .map(|f| (f.line, f.severity))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_checks() {
+        let ok = "#!/bin/bash\ncd \"$PBS_O_WORKDIR\"\nif [ -f \"$x\" ]; then\n  echo 'it is # here'\nfi\nfor f in *.fq; do gzip \"$f\"; done\n";
+        assert_eq!(messages(ok), []);
+        assert_eq!(messages("echo hi\n"), [(1, Severity::Warning)]);
+        assert_eq!(
+            messages("#!/bin/bash\nrm -rf $dir/tmp\n"),
+            [(2, Severity::Warning)]
+        );
+        assert_eq!(
+            messages("#!/bin/bash\nif true; then\necho 'done\n"),
+            [(2, Severity::Error), (3, Severity::Error)]
+        );
+        assert_eq!(
+            messages("#!/bin/bash\nwhile true; do\nfi\n"),
+            [(3, Severity::Error)]
+        );
+    }
+}
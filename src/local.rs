@@ -0,0 +1,106 @@
+//! `--local`: run a rendered script on this machine instead of submitting it.
+//!
+//! The `#PBS` lines are comments to the shell, so the script runs as is. The
+//! variables PBS sets for a job are filled in so `cd "$PBS_O_WORKDIR"` and
+//! friends work. With `--local-limits` the job gets its requested CPUs,
+//! memory and walltime through `systemd-run --user --scope` when that is
+//! available, and otherwise only `nice` and `timeout`.
+
+use crate::pbs;
+use crate::spec::JobSpec;
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// The interpreter named by the `#!` line; PBS uses the login shell without one.
+fn interpreter(script: &str) -> Vec<String> {
+    match script.lines().next().and_then(|l| l.strip_prefix("#!")) {
+        Some(line) => line.split_whitespace().map(String::from).collect(),
+        None => vec!["/bin/sh".to_string()],
+    }
+}
+
+/// The command that runs `interpreter` within the job's requested resources.
+fn limited(spec: &JobSpec, interpreter: Vec<String>) -> Vec<String> {
+    let walltime = pbs::parse_walltime(&spec.walltime);
+    // installed is not enough; it also needs a user session to talk to
+    if Command::new("systemd-run")
+        .args(["--user", "--scope", "--quiet", "true"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+    {
+        let mut command: Vec<String> = ["systemd-run", "--user", "--scope", "--quiet"]
+            .map(String::from)
+            .to_vec();
+        command.push(format!("--property=CPUQuota={}%", spec.ncpus * 100));
+        if let Some(mem) = spec.mem.as_deref().and_then(pbs::parse_size) {
+            command.push(format!("--property=MemoryMax={}", mem));
+        }
+        if let Some(walltime) = walltime {
+            command.push(format!("--property=RuntimeMaxSec={}", walltime));
+        }
+        command.push("--".to_string());
+        command.extend(interpreter);
+        return command;
+    }
+    log::warn!("systemd-run is not available; limiting only the walltime and priority");
+    let mut command = vec!["nice".to_string()];
+    if let Some(walltime) = walltime {
+        command.extend(["timeout".to_string(), format!("{}s", walltime)]);
+    }
+    command.extend(interpreter);
+    command
+}
+
+/// Run the script, from `path` when it was written to a file, else on stdin.
+pub fn run(
+    spec: &JobSpec,
+    script: &str,
+    path: Option<&Path>,
+    limits: bool,
+) -> io::Result<ExitStatus> {
+    let mut command = interpreter(script);
+    if limits {
+        command = limited(spec, command);
+    }
+    let workdir = env::current_dir()?;
+    let mut child = Command::new(&command[0]);
+    child
+        .args(&command[1..])
+        .env("PBS_O_WORKDIR", &workdir)
+        .env("PBS_JOBID", "local")
+        .env("PBS_JOBNAME", &spec.name)
+        .env("PBS_QUEUE", &spec.queue)
+        .env("PBS_ENVIRONMENT", "PBS_BATCH")
+        .env("NCPUS", spec.ncpus.to_string())
+        .env("OMP_NUM_THREADS", spec.ncpus.to_string());
+    log::info!("Running locally: {}", command.join(" "));
+    match path {
+        Some(path) => child.arg(path).status(),
+        None => {
+            let mut child = child.stdin(Stdio::piped()).spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(script.as_bytes())?;
+            }
+            child.wait()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpreter() {
+        assert_eq!(interpreter("#!/bin/bash\necho"), ["/bin/bash"]);
+        assert_eq!(
+            interpreter("#!/usr/bin/env bash -l\n"),
+            ["/usr/bin/env", "bash", "-l"]
+        );
+        assert_eq!(interpreter("echo hi\n"), ["/bin/sh"]);
+    }
+}
@@ -0,0 +1,165 @@
+//! Locating and reading the stdout/stderr files of jobs.
+
+use crate::interrupt;
+use crate::scheduler;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+const STATUS_EVERY: u32 = 10;
+
+/// Find the output (or error) file of a job, first from `qstat -f`, then by
+/// looking for `*.o<seq>` / `*.e<seq>` files in the current directory.
+pub fn locate(job_id: &str, stderr: bool) -> io::Result<PathBuf> {
+    if let Some(attributes) = scheduler::job_status_with_history(job_id)? {
+        let joined = attributes.get("Join_Path").map(String::as_str);
+        let key = match (stderr, joined) {
+            (true, Some("oe")) => "Output_Path",
+            (false, Some("eo")) => "Error_Path",
+            (true, _) => "Error_Path",
+            (false, _) => "Output_Path",
+        };
+        if let Some(path) = attributes.get(key) {
+            let path = PathBuf::from(strip_host(path));
+            if path.is_dir() {
+                let suffix = if stderr { "ER" } else { "OU" };
+                return Ok(path.join(format!("{}.{}", job_id, suffix)));
+            }
+            return Ok(path);
+        }
+    }
+
+    let suffix = format!(
+        ".{}{}",
+        if stderr { 'e' } else { 'o' },
+        sequence_number(job_id)
+    );
+    for entry in fs::read_dir(".")? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.ends_with(&suffix))
+        {
+            return Ok(path);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no log file found for job {}", job_id),
+    ))
+}
+
+/// `host:/path/file` -> `/path/file`
+fn strip_host(path: &str) -> &str {
+    match path.split_once(':') {
+        Some((host, rest)) if !host.contains('/') => rest,
+        _ => path,
+    }
+}
+
+/// `1234[].server` -> `1234`
+fn sequence_number(job_id: &str) -> &str {
+    job_id.split(['.', '[']).next().unwrap_or(job_id)
+}
+
+/// Print new content of `path` as it grows until `finished` returns true.
+/// The file does not need to exist yet; PBS often writes it only at the end.
+pub fn follow(path: &Path, finished: impl FnMut() -> io::Result<bool>) -> io::Result<()> {
+    follow_all(&[(path, false)], finished)
+}
+
+/// Like [`follow`] for several files at once; those marked `true` go to stderr.
+pub fn follow_all(
+    paths: &[(&Path, bool)],
+    mut finished: impl FnMut() -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut offsets = vec![0; paths.len()];
+    let mut polls = 0;
+    let mut done = false;
+    loop {
+        interrupt::check()?;
+        if polls % STATUS_EVERY == 0 {
+            done = finished()?;
+        }
+        polls += 1;
+
+        for (&(path, to_stderr), offset) in paths.iter().zip(&mut offsets) {
+            let Ok(mut file) = File::open(path) else {
+                continue;
+            };
+            if file.metadata()?.len() < *offset {
+                *offset = 0;
+            }
+            file.seek(SeekFrom::Start(*offset))?;
+            if to_stderr {
+                let mut stderr = io::stderr().lock();
+                *offset += io::copy(&mut file, &mut stderr)?;
+                stderr.flush()?;
+            } else {
+                let mut stdout = io::stdout().lock();
+                *offset += io::copy(&mut file, &mut stdout)?;
+                stdout.flush()?;
+            }
+        }
+
+        if done {
+            return Ok(());
+        }
+        interrupt::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+/// Like [`follow`], but for output that is only reachable through a command
+/// such as `qpeek`, which prints everything written so far on each call.
+pub fn follow_command(
+    peek: &str,
+    job_id: &str,
+    mut finished: impl FnMut() -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut words = peek.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty peek command"))?;
+    let args: Vec<&str> = words.collect();
+
+    let mut printed = 0;
+    let mut polls = 0;
+    let mut done = false;
+    loop {
+        interrupt::check()?;
+        if polls % STATUS_EVERY == 0 {
+            done = finished()?;
+        }
+        polls += 1;
+
+        let output = Command::new(program).args(&args).arg(job_id).output()?;
+        if output.status.success() && output.stdout.len() > printed {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&output.stdout[printed..])?;
+            stdout.flush()?;
+            printed = output.stdout.len();
+        }
+
+        if done {
+            return Ok(());
+        }
+        interrupt::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_host_and_sequence_number() {
+        assert_eq!(strip_host("node01:/home/u/job.o12"), "/home/u/job.o12");
+        assert_eq!(strip_host("/home/u/a:b"), "/home/u/a:b");
+        assert_eq!(sequence_number("1234[].server"), "1234");
+        assert_eq!(sequence_number("1234.server"), "1234");
+    }
+}
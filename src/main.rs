@@ -1,24 +1,206 @@
+mod accounting;
+mod array;
+mod audit;
+mod batch;
+mod bulk;
+mod checkpoint;
+mod cluster;
+mod config;
+mod cost;
+mod daemon;
+mod dialect;
+mod diff;
+mod digest;
+mod directives;
+mod discovery;
+mod doctor;
+mod efficiency;
+mod encoding;
+mod envfile;
+mod error;
+mod estimate;
+mod exporter;
+mod expr;
+mod git;
+mod history;
+mod hooks;
+mod inputs;
+mod integrate;
+mod interrupt;
+mod lint;
+mod local;
+mod logs;
+mod mock;
+mod monitor;
+mod naming;
+mod nodes;
+mod pbs;
+mod picker;
+mod pipeline;
+mod preflight;
+mod preview;
+mod report;
+mod rerun;
+mod routing;
+mod scaling;
+mod schedule;
+mod scheduler;
+mod schema;
+mod segments;
+mod select;
+mod selfupdate;
+mod serve;
+mod sidecar;
+mod snapshot;
+mod spec;
+mod stats;
+mod style;
+mod suggest;
+mod warning;
+mod wizard;
+
 use chrono::Local;
-use clap::Parser;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use dialect::Dialect;
+use error::Error;
+use scheduler::Scheduler;
+use serde::{Deserialize, Serialize};
+use spec::JobSpec;
+use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Parser)]
-#[command(version, about = "Easily submitting PBS jobs with script template.")]
+#[derive(Parser, Default)]
+#[command(
+    version,
+    about = "Easily submitting PBS jobs with script template.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group = clap::ArgGroup::new("batch").args(["per_file", "array"]),
+    group = clap::ArgGroup::new("inputs").args(["files", "files0_from", "glob", "recursive"]).multiple(true),
+    group = clap::ArgGroup::new("subjobs").args(["files", "files0_from", "glob", "recursive", "param_table"]).multiple(true)
+)]
 struct Cli {
-    #[arg(required = false)]
+    #[arg(skip)]
+    /// Loaded from the configuration file after parsing.
+    config: config::Config,
+
+    #[arg(skip)]
+    /// The command line, kept in the history for `rerun`.
+    invocation: Vec<String>,
+
+    #[arg(skip)]
+    /// Options whose value came from their environment variable, for `--show-defaults -v`.
+    from_env: Vec<String>,
+
+    #[arg(skip)]
+    /// Set by `qsub2 run`, which keeps stdout for the job's own output.
+    attached: bool,
+
+    #[arg(skip)]
+    /// The ID of the job a single submission created.
+    submitted: std::sync::OnceLock<String>,
+
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
+
+    #[arg(required_unless_present_any = ["command_file", "show_defaults"])]
     /// Command to submit
-    command: String,
+    command: Option<String>,
 
     #[arg(required = false)]
     /// Input files
     files: Vec<PathBuf>,
 
+    #[arg(long, value_name = "FILE", requires = "batch")]
+    /// Also read input files from FILE (- for stdin), separated by NUL bytes as `find -print0` writes them
+    files0_from: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATTERN", requires = "batch")]
+    /// Also take the files matching PATTERN, e.g. 'data/**/*.fastq.gz', expanded by qsub2 and sorted
+    glob: Vec<String>,
+
+    #[arg(long, value_name = "DIR", requires = "batch")]
+    /// Also take every file under DIR, sorted
+    recursive: Vec<PathBuf>,
+
+    #[arg(long, value_name = "EXT", requires = "batch")]
+    /// Only the input files ending in .EXT, e.g. fastq.gz; may be repeated
+    ext: Vec<String>,
+
+    #[arg(long, value_name = "SIZE", requires = "batch", value_parser = inputs::parse_min_size)]
+    /// Only the input files of at least SIZE, e.g. 1mb
+    min_size: Option<u64>,
+
+    #[arg(long, value_name = "WHEN", requires = "batch", value_parser = inputs::parse_newer_than)]
+    /// Only the input files modified after WHEN: a time ago (1d), a date (2025-01-31) or a file
+    newer_than: Option<std::time::SystemTime>,
+
+    #[arg(long, requires = "inputs")]
+    /// Generate one job per input file, filling in {file}, {stem}, {ext}, {dir} and {abs} in the command
+    per_file: bool,
+
+    #[arg(long, requires = "subjobs", conflicts_with = "per_file")]
+    /// Submit one array job over the input files; subjob N gets line N of a file list as $FILE
+    array: bool,
+
+    #[arg(long, value_name = "TSV", requires = "array")]
+    /// With --array, subjob N reads row N of this table into variables named by its header, used as {NAME}
+    param_table: Option<PathBuf>,
+
+    #[arg(long, requires = "inputs")]
+    /// Accept input files that do not exist yet, e.g. outputs of jobs this batch depends on
+    allow_missing_inputs: bool,
+
+    #[arg(long, value_name = "MARKERS", requires = "per_file", value_parser = |s: &str| batch::parse_markers(s).map(|_| s.to_string()))]
+    /// One job per group of files named alike but for MARKERS, e.g. _R1/_R2, with {file1}, {file2}...
+    paired: Option<String>,
+
+    #[arg(long, value_name = "TABLE", requires = "per_file")]
+    /// Per-file ncpus/mem/walltime/queue from a table with a `file` column
+    resources: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATTERN", requires = "batch")]
+    /// The output of each job, e.g. '{stem}.sorted.bam', for {output} in the command and --skip-existing
+    output_pattern: Option<String>,
+
+    #[arg(long, value_name = "PATTERN", requires = "per_file")]
+    /// Skip files whose output (e.g. {stem}.bam) exists and is newer than the input
+    skip_existing: Option<String>,
+
+    #[arg(long, value_name = "N", requires = "per_file", value_parser = clap::value_parser!(u32).range(1..))]
+    /// Pause the batch while you have N jobs queued or running
+    max_queued: Option<u32>,
+
+    #[arg(long, requires = "per_file", value_parser = parse_duration)]
+    /// Wait this long between submissions, e.g. 2s
+    delay: Option<Duration>,
+
+    #[arg(long, requires = "per_file", conflicts_with = "delay", value_parser = batch::parse_rate)]
+    /// Submit at most this many jobs per period, e.g. 10/min
+    rate: Option<Duration>,
+
+    #[arg(long, requires = "per_file", requires = "submit")]
+    /// Mail one summary with the succeeded/failed counts once every job of the batch has ended
+    notify_when_done: bool,
+
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "per_file", value_parser = clap::value_parser!(u32).range(1..))]
+    /// Submit up to N jobs at the same time
+    parallel: u32,
+
+    #[arg(long, value_name = "N", requires = "per_file", requires = "submit", conflicts_with_all = ["parallel", "name_from_hash"], value_parser = clap::value_parser!(u32).range(1..))]
+    /// Let at most N jobs of the batch run at once: each waits for the one N places before it to end
+    max_parallel: Option<u32>,
+
     #[arg(short, long)]
-    /// Job name
+    /// Job name; may use {stem}, {ext}, {dir}, {index}, {date}... like --outfile [from the command, e.g. bwa_mem]
     name: Option<String>,
 
     #[arg(short = '@', long)]
@@ -29,115 +211,5259 @@ struct Cli {
     /// Memory [5gb]
     mem: Option<String>,
 
-    #[arg(short, long)]
-    /// Queue [batch]
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "")]
+    /// Queue, or auto for the one expected to start the job first; alone, pick one from a list [batch]
     queue: Option<String>,
 
+    #[arg(long)]
+    /// Rank the queues that can run the job by how soon it would start, and exit
+    recommend_queue: bool,
+
+    #[arg(long, conflicts_with_all = ["per_file", "recommend_queue"])]
+    /// Estimate when the job would start and whether fewer cpus start it sooner, and exit
+    estimate_start: bool,
+
     #[arg(short, long)]
     /// Walltime [30:00:00:00]
     walltime: Option<String>,
 
-    #[arg(short, long)]
-    /// Script template
+    #[arg(long, value_name = "[TYPE:]JOBID", value_delimiter = ',', value_parser = parse_dependency)]
+    /// Job dependencies, e.g. afterany:123,afternotok:456 [afterok]
+    depend: Vec<Dependency>,
+
+    #[arg(long, value_name = "CHUNKS", conflicts_with_all = ["ncpus", "mem", "constraint"], value_parser = parse_select)]
+    /// Whole select statement instead of --ncpus/--mem, e.g. 2:ncpus=64:mem=200gb+1:ncpus=8
+    select: Option<String>,
+
+    #[arg(long, value_name = "CHUNK", conflicts_with_all = ["ncpus", "mem", "constraint", "select"], value_parser = parse_chunk)]
+    /// One kind of chunk of the select statement, e.g. count=4:ncpus=16; repeatable, joined with +
+    chunk: Vec<String>,
+
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_constraint)]
+    /// Node attribute the job needs, e.g. cputype=rome or host=node042; repeatable
+    constraint: Vec<String>,
+
+    #[arg(long, value_enum)]
+    /// Spread chunks over nodes (scatter), keep them on one (pack), or either (free)
+    place: Option<Placement>,
+
+    #[arg(long)]
+    /// Do not share the nodes with other jobs
+    exclusive: bool,
+
+    #[arg(long)]
+    /// Mail only when the job is aborted or exits non-zero
+    notify_on_failure: bool,
+
+    #[arg(long, value_name = "STREAMS", value_parser = parse_keep)]
+    /// Write these streams in place while the job runs instead of at the end (qsub -k), e.g. oed
+    keep: Option<String>,
+
+    #[arg(long, value_name = "GROUP")]
+    /// Run under this group, for accounting and the group of the output files
+    group: Option<String>,
+
+    #[arg(long, value_name = "MODE", value_parser = parse_umask)]
+    /// umask for the job's output and the files it creates, e.g. 0027
+    umask: Option<String>,
+
+    #[arg(long, value_name = "RESID")]
+    /// Run inside this advance reservation
+    reservation: Option<String>,
+
+    #[arg(long)]
+    /// Save the environment next to the script for debugging; the job gets its path as $QSUB2_ENV_SNAPSHOT
+    snapshot_env: bool,
+
+    #[arg(long, value_name = "DIR")]
+    /// Write stdout and stderr to DIR/<name>/<job id>.OU and .ER instead of the working directory
+    log_dir: Option<PathBuf>,
+
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "")]
+    /// Script template; alone, pick one of the template directory from a list
     template: Option<PathBuf>,
 
+    #[arg(long, value_name = "NAME")]
+    /// Use the [preset.NAME] table of the configuration over [default], e.g. one saved by qsub2 efficiency
+    preset: Option<String>,
+
     #[arg(short = 'o', long)]
-    /// Output script
+    /// Output script; may use {basename}, {stem}, {ext}, {dir}, {abs}, {index} and {date} of the input files [named by --name-format]
     outfile: Option<PathBuf>,
 
+    #[arg(long, value_enum, default_value_t = Backup::None, value_name = "MODE")]
+    /// Before overwriting an --outfile that differs, keep the old one as FILE.1, FILE.2, ...
+    backup: Backup,
+
+    #[arg(long, value_name = "FORMAT")]
+    /// Script name without --outfile, from {name}, {id}, {timestamp}, {random} and {hash} [job_{name}_{id}.sh]
+    name_format: Option<String>,
+
+    #[arg(long, conflicts_with_all = ["name_format", "outfile"])]
+    /// Name the script after a hash of its content, so the same job gets the same file: job_{name}_{hash}.sh
+    name_from_hash: bool,
+
     #[arg(short, long)]
     /// Submit the job
     submit: bool,
+
+    #[arg(long, conflicts_with_all = ["per_file", "submit"])]
+    /// Show the script and submit it if you answer yes
+    preview: bool,
+
+    #[arg(long)]
+    /// Check the script with shellcheck, or a few built-in checks without it, and stop on errors
+    lint: bool,
+
+    #[arg(long, conflicts_with_all = ["per_file", "submit", "preview"])]
+    /// Run the script here instead of submitting it, to try it without queueing
+    local: bool,
+
+    #[arg(long, requires = "local")]
+    /// With --local, hold the job to its CPUs, memory and walltime (systemd-run, else nice and timeout)
+    local_limits: bool,
+
+    #[arg(long, value_name = "SCRIPT", conflicts_with_all = ["per_file", "submit", "preview"])]
+    /// Show how the script would differ from SCRIPT, e.g. an earlier version, and exit
+    diff_against: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Print the settings a submission would use and exit; with -v, also where each one came from
+    show_defaults: bool,
+
+    #[arg(long, conflicts_with_all = ["per_file", "segments", "local", "diff_against"])]
+    /// Print the qsub command line that would submit the job, with its flags and script, and exit
+    show_command: bool,
+
+    #[arg(long, requires = "submit", conflicts_with_all = ["outfile", "per_file"])]
+    /// Pipe the script to qsub instead of writing it to a file; the history keeps a copy
+    no_script: bool,
+
+    #[arg(long, conflicts_with = "no_script")]
+    /// Also write SCRIPT.json with the resolved settings, and the job ID once submitted [config sidecar]
+    sidecar: bool,
+
+    #[arg(long, value_name = "RULE", env = "QSUB2_MEM_RULE", value_parser = scaling::parse_mem_rule)]
+    /// Memory from the input size when --mem is not given, e.g. "2gb + 0.5gb/gb"
+    mem_rule: Option<scaling::Rule>,
+
+    #[arg(long, value_name = "RULE", env = "QSUB2_WALLTIME_RULE", value_parser = scaling::parse_walltime_rule)]
+    /// Walltime from the input size when --walltime is not given, e.g. "1h + 20m/gb"
+    walltime_rule: Option<scaling::Rule>,
+
+    #[arg(long, value_name = "PATH")]
+    /// The qsub to run [config qsub_path, or qsub on PATH]
+    qsub_bin: Option<PathBuf>,
+
+    #[arg(long, env = "QSUB2_MOCK", value_parser = clap::builder::BoolishValueParser::new())]
+    /// Record submissions instead of calling qsub, returning job IDs like 1.mock
+    no_scheduler: bool,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "command")]
+    /// Use the contents of FILE (- for stdin) as the job body instead of COMMAND
+    command_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Paste this file's contents before the command, e.g. site setup
+    prologue: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Paste this file's contents after the command; it runs even if the command fails
+    epilogue: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Export the NAME=value lines of this dotenv file in the job, leaving out secrets; repeatable
+    env_file: Vec<PathBuf>,
+
+    #[arg(long, value_name = "FILE|STRING")]
+    /// Put these lines, or this file's contents, right before the command; repeatable
+    prepend: Vec<String>,
+
+    #[arg(long, value_name = "FILE|STRING")]
+    /// Put these lines, or this file's contents, right after the command; the job keeps the command's exit status; repeatable
+    append: Vec<String>,
+
+    #[arg(long, value_name = "COMMAND")]
+    /// Run this after the command if it succeeds; repeatable
+    then: Vec<String>,
+
+    #[arg(long, requires = "then")]
+    /// Run every --then step even if an earlier one fails; the job still fails
+    continue_on_error: bool,
+
+    #[arg(long)]
+    /// Paste the command into the script without shell quoting
+    raw_command: bool,
+
+    #[arg(long, value_name = "SHELL", value_enum)]
+    /// Write the built-in template and the lines qsub2 adds for this shell [default: bash]
+    script_dialect: Option<Dialect>,
+
+    #[arg(long, value_name = "OPTIONS", value_parser = checkpoint::parse)]
+    /// Checkpoint every interval: interval=2h for the server's, add ,dmtcp[,dir=DIR] for DMTCP
+    checkpoint: Option<checkpoint::Checkpoint>,
+
+    #[arg(long, value_name = "TIME", value_parser = parse_duration)]
+    /// Signal the job this long before its walltime ends, e.g. 10m, to checkpoint or flush output
+    walltime_warning: Option<Duration>,
+
+    #[arg(long, value_name = "COMMAND", requires = "walltime_warning")]
+    /// What to run on the --walltime-warning [default: checkpoint a DMTCP job, else sync]
+    on_walltime_warning: Option<String>,
+
+    // the server's SIGTERM before a rerun would also trip the preemption trap
+    #[arg(long, conflicts_with_all = ["not_rerunnable", "requeue_on_preempt"])]
+    /// Let the server run the job again after a node failure (qsub -r y)
+    rerunnable: bool,
+
+    #[arg(long)]
+    /// Never run the job a second time, e.g. when it appends to its outputs (qsub -r n)
+    not_rerunnable: bool,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    /// Submit the job again when it is preempted (killed with SIGTERM), up to N times
+    requeue_on_preempt: Option<u32>,
+
+    #[arg(long, conflicts_with_all = ["per_file", "array", "local", "no_script", "preview"])]
+    /// Split a job longer than the queue's walltime limit into a chain of jobs that each fit it
+    segments: bool,
+
+    #[arg(long, value_name = "CMD", requires = "segments")]
+    /// What the segments after the first run to go on; {segment} is its number, {previous} the one before [config restart_command]
+    restart_command: Option<String>,
+
+    #[arg(long, value_name = "TIME", requires = "segments", value_parser = parse_walltime_arg)]
+    /// The walltime of each segment instead of the queue's limit
+    segment_walltime: Option<String>,
+
+    #[arg(long)]
+    /// Before submitting, check that the job's directories are writable [config preflight]
+    preflight: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = inputs::parse_min_size)]
+    /// Also require this much free space on their filesystems; implies --preflight [config min_free_space]
+    min_free: Option<u64>,
+
+    #[arg(long)]
+    /// Print the most the job can cost, from the rates in the configuration
+    cost: bool,
+
+    #[arg(long, value_name = "AMOUNT")]
+    /// Refuse jobs that can cost more than AMOUNT [cost.max]
+    max_cost: Option<f64>,
+
+    #[arg(long = "tag", value_name = "TAG", value_parser = parse_label)]
+    /// Label the job for list --tag and cancel --tag; can be repeated
+    tags: Vec<String>,
+
+    #[arg(long, value_name = "NAME", value_parser = parse_label)]
+    /// Group the jobs for status, cancel and rerun --group [a new name for each --per-file run]
+    group_name: Option<String>,
+
+    #[arg(long)]
+    /// Refuse to submit with uncommitted changes in the git repository, and note the commit in the script
+    require_clean_git: bool,
+
+    #[arg(long)]
+    /// Submit even if an identical job is queued or was submitted in the last day
+    allow_duplicate: bool,
+
+    #[arg(short = 'y', long)]
+    /// Submit more jobs at once than confirm_over in the configuration [100] without asking
+    yes: bool,
+
+    #[arg(long)]
+    /// Size mem and walltime from earlier runs of similar jobs
+    auto_size: bool,
+
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    /// Show the resolved settings and scheduler commands (-vv for more)
+    verbose: u8,
+
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "verbose",
+        visible_alias = "porcelain"
+    )]
+    /// Print only job IDs on stdout and errors on stderr, e.g. for dep=$(qsub2 ...)
+    quiet: bool,
+
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto, value_name = "WHEN")]
+    /// Color the output; auto honours NO_COLOR and whether it is a terminal
+    color: ColorChoice,
+
+    #[arg(long, value_name = "PATH", env = "QSUB2_SUBMISSION_LOG")]
+    /// Append a JSON line describing each submission to this file
+    submission_log: Option<PathBuf>,
 }
 
-fn generate_job_script(cli: &Cli) -> std::io::Result<()> {
-    let template_content = if let Some(ref template_path) = cli.template {
-        fs::read_to_string(template_path)?
-    } else {
-        include_str!("../templates/default_template.sh").into() // Use a built-in default template as a fallback
-    };
+#[derive(Subcommand)]
+enum Commands {
+    /// Wait for jobs to finish
+    Wait(WaitArgs),
+    /// Show the output of a job
+    Logs(LogsArgs),
+    /// Follow a job's state and, once it runs, its output
+    Watch(WatchArgs),
+    /// Submit a job, stream its output, and exit with its exit status
+    Run(RunArgs),
+    /// Ask for the command, resources, queue and template, then preview and submit the job
+    New,
+    /// Pick a queue or template from a list that narrows as you type, and print it
+    Pick(PickArgs),
+    /// Interactive dashboard of your jobs
+    Monitor(MonitorArgs),
+    /// Queue limits and node types, cached for --queue auto and the checks before submitting
+    #[command(subcommand)]
+    Cluster(ClusterCommand),
+    /// Free cpus, memory and GPUs per node, and which nodes could start a job
+    Nodes(NodesArgs),
+    /// Per-queue overview of jobs and free resources
+    Top,
+    /// Check the scheduler commands, configuration and templates
+    Doctor,
+    /// Replace this executable with the latest release from GitHub
+    SelfUpdate(SelfUpdateArgs),
+    /// Serve job and queue metrics for Prometheus
+    Exporter(ExporterArgs),
+    /// List submitted jobs from the history
+    List(ListArgs),
+    /// Compare requested and used resources of a finished job
+    Report(ReportArgs),
+    /// Summarize your jobs per queue: failures, queue wait, core-hours
+    Stats(StatsArgs),
+    /// Requested against used memory and walltime per class of job, with requests that would fit
+    Efficiency(EfficiencyArgs),
+    /// One message about the jobs that finished recently, in place of a mail for each
+    Digest(DigestArgs),
+    /// Core-hours, GPU-hours and jobs per project and month, for grant reports
+    Accounting(AccountingArgs),
+    /// Start an interactive session with qsub -I
+    Interactive(InteractiveArgs),
+    /// Watch a directory and submit a per-file job for each new file
+    Daemon(DaemonArgs),
+    /// Answer HTTP requests to render, submit, query and cancel jobs
+    Serve(ServeArgs),
+    /// Submit a job on a schedule, or print the crontab line that does
+    Schedule(ScheduleArgs),
+    /// Submit an earlier job again
+    Rerun(RerunArgs),
+    /// Continue a --checkpoint job from its latest checkpoint
+    Restart(RestartArgs),
+    /// Submit a preempted --requeue-on-preempt job again; the job runs this itself
+    Requeue(RestartArgs),
+    /// Delete queued and running jobs with qdel
+    Cancel(CancelArgs),
+    /// Show the state of jobs, or of every job in a group
+    Status(StatusArgs),
+    /// List the live jobs that match, even ones qsub2 did not submit, and cancel, hold or release them
+    Select(SelectArgs),
+    /// Show or change the configuration file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Check job spec files
+    #[command(subcommand)]
+    Spec(SpecCommand),
+    /// Turn a group of jobs and the jobs depending on them into other formats
+    #[command(subcommand)]
+    Pipeline(PipelineCommand),
+    /// Let a workflow manager submit its jobs through qsub2
+    #[command(subcommand)]
+    Integrate(IntegrateCommand),
+}
 
-    let job_script = template_content
-        .replace("{name}", &cli.name.as_deref().unwrap_or("job"))
-        .replace("{ncpus}", &format!(":ncpus={}", cli.ncpus.unwrap_or(1)))
-        .replace(
-            "{mem}",
-            &cli.mem
-                .as_deref()
-                .map_or(String::new(), |m| format!(":mem={}", m)),
-        )
-        .replace("{queue}", cli.queue.as_deref().unwrap_or("batch"))
-        .replace(
-            "{walltime}",
-            cli.walltime.as_deref().unwrap_or("30:00:00:00"),
-        )
-        .replace("{command}", &cli.command);
+#[derive(Subcommand)]
+enum IntegrateCommand {
+    /// Write a Snakemake profile whose jobs are submitted with qsub2
+    Snakemake(SnakemakeArgs),
+    /// Print a nextflow.config process block with the resources qsub2 would request
+    Nextflow(NextflowArgs),
+}
 
-    let output_file_name: PathBuf = cli.outfile.clone().unwrap_or_else(|| {
-        PathBuf::from(format!(
-            "job_script_{}.sh",
-            Local::now().format("%Y%m%d%H%M%S").to_string()
-        ))
-    });
-    let mut file = File::create(&output_file_name)?;
-    file.write_all(job_script.as_bytes())?;
+#[derive(Args)]
+#[command(
+    after_help = "Example:\n    qsub2 integrate nextflow -o nextflow.config -- -q long -m 8gb"
+)]
+struct NextflowArgs {
+    #[arg(short, long, value_name = "FILE")]
+    /// Write to FILE instead of stdout
+    output: Option<PathBuf>,
 
-    println!("Job script generated and saved to: {:?}", output_file_name);
+    #[arg(long, value_parser = ["pbspro", "pbs"], default_value = "pbspro")]
+    /// Nextflow's executor: pbspro, or pbs for Torque
+    executor: String,
 
-    Ok(())
+    #[arg(last = true, value_name = "JOB OPTIONS")]
+    /// qsub2 options to take the resources from, e.g. -q long
+    job: Vec<String>,
 }
 
-fn submit_job(outfile: &PathBuf) -> std::io::Result<()> {
-    let status = Command::new("qsub").arg(outfile.as_path()).status()?;
-    println!("Job submitted with status: {}", status);
-    Ok(())
+#[derive(Args)]
+#[command(
+    after_help = "Example:\n    qsub2 integrate snakemake --out profile -- -q long && snakemake --profile profile"
+)]
+struct SnakemakeArgs {
+    #[arg(long, value_name = "DIR", default_value = "profile")]
+    /// Directory for config.yaml and the submit, status and cancel scripts
+    out: PathBuf,
+
+    #[arg(last = true, value_name = "JOB OPTIONS")]
+    /// qsub2 options for every job, e.g. -q long
+    job: Vec<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum PipelineCommand {
+    /// Write a Makefile whose targets submit the jobs again in dependency order
+    Export(PipelineExportArgs),
+}
 
-    if let Err(e) = generate_job_script(&cli) {
-        eprintln!("Error generating job script: {}", e);
-        return;
-    }
+#[derive(Clone, Copy, ValueEnum)]
+enum PipelineFormat {
+    Makefile,
+}
 
-    if cli.submit {
-        if let Some(ref outfile) = cli.outfile {
-            if let Err(e) = submit_job(outfile) {
-                eprintln!("Error submitting job: {}", e);
-            }
-        } else {
-            eprintln!("Error: Output file not specified. Job submission aborted.");
-        }
-    }
+#[derive(Args)]
+#[command(
+    after_help = "Example:\n    qsub2 pipeline export --group batch-20250101-120000 -o Makefile && make -j"
+)]
+struct PipelineExportArgs {
+    #[arg(long, value_name = "NAME")]
+    /// The group, e.g. one --per-file batch
+    group: String,
+
+    #[arg(long, value_enum, default_value_t = PipelineFormat::Makefile)]
+    format: PipelineFormat,
+
+    #[arg(short, long, value_name = "FILE")]
+    /// Write to FILE instead of stdout
+    output: Option<PathBuf>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Subcommand)]
+enum ClusterCommand {
+    /// Ask the server for its queues and nodes now, cache them, and print them
+    Discover,
+    /// Print the cached queues and nodes, asking the server once the cache is old
+    Show,
+}
 
-    #[test]
-    fn test_generate_job_script_with_defaults() {
-        let cli = Cli {
-            command: "echo Hello, world!".into(),
-            files: vec![],
-            name: None,
-            ncpus: None,
-            mem: None,
-            queue: None,
-            walltime: None,
-            template: None,
-            outfile: Some(PathBuf::from("test_output.sh")),
-            submit: false,
-        };
+#[derive(Subcommand)]
+enum SpecCommand {
+    /// Check specs as rerun --edit shows them, or inside sidecars and history records
+    Validate {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
 
-        let result = generate_job_script(&cli);
-        assert!(result.is_ok());
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration
+    Show,
+    /// Print one setting, e.g. default.queue
+    Get { key: String },
+    /// Change one setting in the configuration file, e.g. default.mem 8gb
+    Set { key: String, value: String },
+    /// Open the configuration file in $EDITOR and check it afterwards
+    Edit,
+    /// Check the configuration file, or FILE, for unknown keys and wrong types
+    Validate { file: Option<PathBuf> },
+}
 
-        let expected_content = include_str!("../test/fixtures/expected_default_script.sh"); // Assume this contains the expected default script
-        let generated_content = fs::read_to_string("test_output.sh").unwrap();
-        assert_eq!(generated_content, expected_content);
+#[derive(Args)]
+struct WaitArgs {
+    #[arg(required = true)]
+    /// Job IDs to wait for
+    job_ids: Vec<String>,
 
-        fs::remove_file("test_output.sh").unwrap();
+    #[arg(long, value_parser = parse_duration)]
+    /// Give up after this long, e.g. 2h
+    timeout: Option<Duration>,
+
+    #[arg(long, conflicts_with = "all")]
+    /// Return as soon as any of the jobs has finished
+    any: bool,
+
+    #[arg(long)]
+    /// Return when all of the jobs have finished [default]
+    all: bool,
+
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    /// Polling interval
+    interval: Duration,
+}
+
+#[derive(Args)]
+struct LogsArgs {
+    /// Job ID
+    job_id: String,
+
+    #[arg(long)]
+    /// Show the standard error file instead of standard output
+    stderr: bool,
+
+    #[arg(short, long)]
+    /// Keep printing new output until the job finishes
+    follow: bool,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Job ID
+    job_id: String,
+
+    #[arg(long, value_name = "COMMAND")]
+    /// Read running output through this command (e.g. qpeek) when it is spooled on the node
+    peek: Option<String>,
+
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    /// Polling interval while the job is queued
+    interval: Duration,
+}
+
+#[derive(Args)]
+#[command(after_help = "Example:\n    qsub2 run -@ 4 -m 8gb 'make test'")]
+struct RunArgs {
+    #[arg(long, value_name = "COMMAND")]
+    /// Read running output through this command (e.g. qpeek) when it is spooled on the node
+    peek: Option<String>,
+
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    /// Polling interval while the job is queued
+    interval: Duration,
+
+    #[arg(long)]
+    /// On Ctrl-C, cancel the job without asking
+    cancel_on_interrupt: bool,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true,
+        value_name = "JOB OPTIONS"
+    )]
+    /// Options and command for the job, as for a --submit run
+    job: Vec<String>,
+}
+
+#[derive(Args)]
+struct MonitorArgs {
+    #[arg(short, long)]
+    /// Show the jobs of this user [current user]
+    user: Option<String>,
+
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    /// Refresh interval
+    interval: Duration,
+}
+
+#[derive(Args)]
+struct ExporterArgs {
+    #[arg(long, default_value = "127.0.0.1:9123", value_name = "ADDRESS")]
+    /// Address to listen on
+    listen: String,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    #[arg(short = 'n', long)]
+    /// Only show the most recent N submissions
+    limit: Option<usize>,
+
+    #[arg(long, value_parser = parse_duration)]
+    /// Only show submissions from this far back, e.g. 7d
+    since: Option<Duration>,
+
+    #[arg(long, value_enum)]
+    /// Print the rows as CSV or TSV instead of a table
+    export: Option<ExportFormat>,
+
+    #[arg(long, value_name = "TAG")]
+    /// Only show jobs submitted with --tag TAG
+    tag: Option<String>,
+
+    #[arg(long)]
+    /// Also the jobs of the others sharing the history
+    all_users: bool,
+}
+
+#[derive(Args)]
+struct CancelArgs {
+    #[arg(required_unless_present_any = ["tag", "group"])]
+    /// Job IDs to delete
+    job_ids: Vec<String>,
+
+    #[arg(long, value_name = "TAG")]
+    /// Also delete the unfinished jobs from the history that have this tag
+    tag: Option<String>,
+
+    #[arg(long, value_name = "NAME")]
+    /// Also delete the unfinished jobs from the history in this group
+    group: Option<String>,
+}
+
+#[derive(Args)]
+#[command(
+    group = clap::ArgGroup::new("action").args(["cancel", "hold", "release"]),
+    after_help = "Example:\n    qsub2 select --name 'align_*' --state Q --hold"
+)]
+struct SelectArgs {
+    #[arg(long, value_name = "PATTERN")]
+    /// Only jobs whose name matches, with * ? and [...] wildcards
+    name: Option<String>,
+
+    #[arg(long, value_name = "STATES")]
+    /// Only jobs in these states, e.g. Q or QH
+    state: Option<String>,
+
+    #[arg(long, short)]
+    /// Only jobs in this queue
+    queue: Option<String>,
+
+    #[arg(long, short, conflicts_with = "all_users")]
+    /// Only this user's jobs [default: yours]
+    user: Option<String>,
+
+    #[arg(long)]
+    /// The jobs of every user
+    all_users: bool,
+
+    #[arg(long)]
+    /// Delete the jobs with qdel
+    cancel: bool,
+
+    #[arg(long)]
+    /// Keep the jobs from starting with qhold
+    hold: bool,
+
+    #[arg(long)]
+    /// Let held jobs start with qrls
+    release: bool,
+
+    #[arg(long, short)]
+    /// Act without asking first
+    yes: bool,
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    #[arg(required_unless_present = "group")]
+    /// Job IDs to show
+    job_ids: Vec<String>,
+
+    #[arg(long, value_name = "NAME")]
+    /// Also show the latest submission of each job in this group
+    group: Option<String>,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// Job ID
+    job_id: String,
+}
+
+#[derive(Args)]
+#[command(after_help = "Example:\n    qsub2 nodes --classes -@ 64 -m 200gb")]
+struct NodesArgs {
+    #[arg(long)]
+    /// One line per class of alike nodes instead of one per node
+    classes: bool,
+
+    #[arg(short = '@', long, value_name = "N")]
+    /// Show which nodes could start a job of N cpus, now or once their jobs end
+    ncpus: Option<u64>,
+
+    #[arg(short, long, value_name = "SIZE", value_parser = inputs::parse_min_size)]
+    /// ... with this much memory
+    mem: Option<u64>,
+
+    #[arg(long, value_name = "N")]
+    /// ... with N GPUs
+    ngpus: Option<u64>,
+
+    #[arg(short, long)]
+    /// Only the nodes that jobs of QUEUE can run on
+    queue: Option<String>,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[arg(long, value_parser = parse_duration)]
+    /// Only count submissions from this far back, e.g. 30d
+    since: Option<Duration>,
+
+    #[arg(long)]
+    /// Also the jobs of the others sharing the history
+    all_users: bool,
+}
+
+#[derive(Args)]
+#[command(after_help = "Example:\n    qsub2 efficiency --since 30d --class align --preset align")]
+struct EfficiencyArgs {
+    #[arg(long)]
+    /// Only the jobs of this group
+    group: Option<String>,
+
+    #[arg(long, value_parser = parse_duration)]
+    /// Only the jobs submitted this far back, e.g. 30d
+    since: Option<Duration>,
+
+    #[arg(long)]
+    /// Only the jobs of this class: their name, or their executable for unnamed jobs
+    class: Option<String>,
+
+    #[arg(long, value_name = "NAME")]
+    /// Save the recommended mem and walltime as [preset.NAME], to submit with --preset NAME
+    preset: Option<String>,
+
+    #[arg(long)]
+    /// Also the jobs of the others sharing the history
+    all_users: bool,
+}
+
+#[derive(Args)]
+#[command(after_help = "Example:\n    qsub2 -q \"$(qsub2 pick queue)\" 'make -j8'")]
+struct PickArgs {
+    what: PickTarget,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PickTarget {
+    Queue,
+    Template,
+}
+
+#[derive(Args)]
+struct SelfUpdateArgs {
+    #[arg(long)]
+    /// Only say whether a newer release exists
+    check: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+    /// Address and port to listen on
+    listen: String,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "listen")]
+    /// Listen on a Unix socket instead; with socket = PATH in the configuration, qsub2 submits through it
+    socket: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "socket")]
+    /// Write the token that requests must send as Authorization: Bearer here [~/.local/share/qsub2/serve.token]
+    token_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct AccountingArgs {
+    #[arg(long, value_name = "PROJECT")]
+    /// Only the jobs charged to PROJECT (qsub -P or -A)
+    project: Option<String>,
+
+    #[arg(long, value_parser = accounting::parse_month)]
+    /// Only the jobs that ended in this month, e.g. 2025-06
+    month: Option<String>,
+
+    #[arg(long, value_enum)]
+    /// Print the rows as CSV or TSV instead of a table
+    format: Option<ExportFormat>,
+
+    #[arg(long)]
+    /// Also the jobs of the others sharing the history
+    all_users: bool,
+}
+
+#[derive(Args)]
+struct DigestArgs {
+    #[arg(long, default_value = "24h", value_parser = parse_duration)]
+    /// The jobs that finished this far back
+    since: Duration,
+
+    #[arg(long, value_name = "ADDRESS")]
+    /// Mail the digest to ADDRESS instead of printing it
+    email: Option<String>,
+}
+
+#[derive(Args)]
+#[command(
+    after_help = "Example:\n    qsub2 daemon --watch ./incoming -- --template align.sh 'bwa mem ref.fa {file} > {stem}.sam'"
+)]
+struct DaemonArgs {
+    #[arg(long, value_name = "DIR")]
+    /// Directory to watch
+    watch: PathBuf,
+
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    /// Polling interval; a file is submitted once its size is unchanged for one interval
+    interval: Duration,
+
+    #[arg(long)]
+    /// Also submit the files that are already there at startup
+    existing: bool,
+
+    #[arg(last = true, required = true, value_name = "JOB OPTIONS")]
+    /// Options and command for the jobs, as for a --per-file --submit run
+    job: Vec<String>,
+}
+
+#[derive(Args)]
+#[command(
+    after_help = "Example:\n    qsub2 schedule --every 1d --at 02:00 -- -n nightly-qc -o qc.sh 'make qc'"
+)]
+struct ScheduleArgs {
+    #[arg(long, value_parser = parse_duration)]
+    /// How often to submit, e.g. 1d or 6h
+    every: Duration,
+
+    #[arg(long, value_name = "HH:MM", value_parser = schedule::parse_at)]
+    /// Time of day the schedule starts from [now]
+    at: Option<chrono::NaiveTime>,
+
+    #[arg(long)]
+    /// Print a crontab line instead of staying in the foreground
+    crontab: bool,
+
+    #[arg(last = true, required = true, value_name = "JOB OPTIONS")]
+    /// Options and command for the job, as for a --submit run
+    job: Vec<String>,
+}
+
+#[derive(Args)]
+struct RerunArgs {
+    #[arg(long, conflicts_with = "job_id")]
+    /// The most recent submission
+    last: bool,
+
+    #[arg(required_unless_present_any = ["last", "group"])]
+    /// Job ID from the history
+    job_id: Option<String>,
+
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["job_id", "last", "edit"])]
+    /// Every job of a group, submitted again from its recorded settings
+    group: Option<String>,
+
+    #[arg(long, requires = "group")]
+    /// With --group, only the jobs that exited with a non-zero status
+    only_failed: bool,
+
+    #[arg(long)]
+    /// Edit the job's resolved settings in $EDITOR before submitting
+    edit: bool,
+
+    #[arg(long, value_name = "[TYPE:]JOBID", value_delimiter = ',', value_parser = parse_dependency, conflicts_with = "group")]
+    /// Depend on these jobs instead of the recorded dependencies
+    depend: Vec<Dependency>,
+}
+
+#[derive(Args)]
+struct RestartArgs {
+    /// Job ID from the history
+    job_id: String,
+}
+
+#[derive(Args)]
+struct InteractiveArgs {
+    #[arg(short = '@', long, default_value_t = 1)]
+    /// CPU number
+    ncpus: u32,
+
+    #[arg(short = 'm', long, value_parser = parse_mem)]
+    /// Memory, e.g. 16gb [server default]
+    mem: Option<String>,
+
+    #[arg(short, long, value_parser = parse_walltime_arg)]
+    /// Walltime, e.g. 2h or 02:00:00 [server default]
+    walltime: Option<String>,
+
+    #[arg(short, long)]
+    /// Queue [server default]
+    queue: Option<String>,
+
+    #[arg(short, long)]
+    /// Session name
+    name: Option<String>,
+
+    #[arg(long)]
+    /// Forward X11 so GUI programs can open windows (qsub -X)
+    x11: bool,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Placement {
+    Scatter,
+    Pack,
+    Free,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum Backup {
+    #[default]
+    None,
+    Numbered,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Tsv,
+}
+
+/// Name of the user running qsub2.
+fn current_user() -> String {
+    ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .unwrap_or_default()
+}
+
+/// Parse durations such as `90`, `30s`, `5m`, `2h`, `1d` or `1h30m`; bare numbers are seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("invalid duration unit '{}' in '{}'", c, s)),
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration '{}'", s))?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() || s.is_empty() {
+        return Err(format!("invalid duration '{}'", s));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+fn parse_mem(s: &str) -> Result<String, String> {
+    match pbs::parse_size(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err(format!("'{}' is not a size such as 16gb", s)),
+    }
+}
+
+fn parse_select(s: &str) -> Result<String, String> {
+    select::parse(s).map(|chunks| select::format(&chunks))
+}
+
+fn parse_chunk(s: &str) -> Result<String, String> {
+    select::parse_chunk(s).map(|chunk| chunk.to_string())
+}
+
+/// `-k` values: any of `o`, `e` and `d`, or `n` on its own.
+fn parse_keep(s: &str) -> Result<String, String> {
+    let valid = s == "n" || (!s.is_empty() && s.chars().all(|c| "oed".contains(c)));
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!("'{}' must be a combination of o, e and d, or n", s))
+    }
+}
+
+/// A word without spaces or commas, e.g. `exp42`.
+fn parse_label(s: &str) -> Result<String, String> {
+    if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c == ',') {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "'{}' is not a single word; leave out spaces and commas",
+            s
+        ))
+    }
+}
+
+/// Octal modes such as `027` or `0027`.
+fn parse_umask(s: &str) -> Result<String, String> {
+    if (3..=4).contains(&s.len()) && s.chars().all(|c| ('0'..='7').contains(&c)) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("'{}' is not an octal umask such as 0027", s))
+    }
+}
+
+/// A `key=value` resource for the select chunk.
+fn parse_constraint(s: &str) -> Result<String, String> {
+    match s.split_once('=') {
+        Some((key, value))
+            if !key.is_empty() && !value.is_empty() && !s.contains([':', '+', ' ']) =>
+        {
+            Ok(s.to_string())
+        }
+        _ => Err(format!("'{}' is not a constraint such as cputype=rome", s)),
+    }
+}
+
+/// Walltimes as `[[DD:]HH:]MM:SS` or as durations such as `2h`.
+fn parse_walltime_arg(s: &str) -> Result<String, String> {
+    if s.contains(':') {
+        return match pbs::parse_walltime(s) {
+            Some(_) => Ok(s.to_string()),
+            None => Err(format!("'{}' is not [[DD:]HH:]MM:SS", s)),
+        };
+    }
+    parse_duration(s).map(|d| pbs::format_walltime(d.as_secs()))
+}
+
+const DEPENDENCY_TYPES: &[&str] = &[
+    "after",
+    "afterok",
+    "afternotok",
+    "afterany",
+    "afterokarray",
+    "afternotokarray",
+    "afteranyarray",
+    "before",
+    "beforeok",
+    "beforenotok",
+    "beforeany",
+    "on",
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Dependency {
+    kind: String,
+    targets: Vec<String>,
+}
+
+/// Parse `TYPE:JOBID[:JOBID...]`; a bare job ID means `afterok`.
+fn parse_dependency(s: &str) -> Result<Dependency, String> {
+    let (kind, targets) = match s.split_once(':') {
+        Some((kind, targets)) => (kind, targets),
+        None => ("afterok", s),
+    };
+    if !DEPENDENCY_TYPES.contains(&kind) {
+        return Err(format!(
+            "unknown dependency type '{}' (expected one of: {})",
+            kind,
+            DEPENDENCY_TYPES.join(", ")
+        ));
+    }
+    let targets: Vec<String> = targets
+        .split(':')
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect();
+    if targets.is_empty() {
+        return Err(format!("no job ID given for '{}'", kind));
+    }
+    Ok(Dependency {
+        kind: kind.to_string(),
+        targets,
+    })
+}
+
+/// Array jobs have IDs like `1234[].server` and need the `*array` dependency types.
+fn is_array_job(job_id: &str) -> bool {
+    job_id.contains("[]")
+}
+
+fn depend_directive(dependencies: &[Dependency]) -> Option<String> {
+    let mut groups: Vec<(String, Vec<&str>)> = Vec::new();
+    for dependency in dependencies {
+        for target in &dependency.targets {
+            let kind = match dependency.kind.as_str() {
+                "afterok" | "afternotok" | "afterany" if is_array_job(target) => {
+                    format!("{}array", dependency.kind)
+                }
+                kind => kind.to_string(),
+            };
+            match groups.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, targets)) => targets.push(target),
+                None => groups.push((kind, vec![target])),
+            }
+        }
+    }
+
+    if groups.is_empty() {
+        return None;
+    }
+    let depend: Vec<String> = groups
+        .iter()
+        .map(|(kind, targets)| format!("{}:{}", kind, targets.join(":")))
+        .collect();
+    Some(format!("#PBS -W depend={}", depend.join(",")))
+}
+
+/// `-l place=scatter:excl`; `None` when neither `--place` nor `--exclusive` is given.
+fn place_directive(place: Option<Placement>, exclusive: bool) -> Option<String> {
+    let arrangement = place.map(|p| match p {
+        Placement::Scatter => "scatter",
+        Placement::Pack => "pack",
+        Placement::Free => "free",
+    });
+    let place: Vec<&str> = arrangement
+        .into_iter()
+        .chain(exclusive.then_some("excl"))
+        .collect();
+    if place.is_empty() {
+        return None;
+    }
+    Some(format!("#PBS -l place={}", place.join(":")))
+}
+
+/// Directives that have no dedicated placeholder in the template.
+fn extra_directives(spec: &JobSpec) -> Vec<String> {
+    let mut directives = Vec::new();
+    if let Some(depend) = depend_directive(&spec.depend) {
+        directives.push(depend);
+    }
+    if let Some(place) = place_directive(spec.place, spec.exclusive) {
+        directives.push(place);
+    }
+    if spec.notify_on_failure {
+        directives.push("#PBS -m a".to_string());
+    }
+    if let Some(ref keep) = spec.keep {
+        directives.push(format!("#PBS -k {}", keep));
+    }
+    if let Some(ref group) = spec.group {
+        directives.push(format!("#PBS -W group_list={}", group));
+    }
+    if let Some(ref umask) = spec.umask {
+        directives.push(format!("#PBS -W umask={}", umask));
+    }
+    if let Some(ref reservation) = spec.reservation {
+        directives.push(format!("#PBS -W x=advres:{}", reservation));
+    }
+    match spec.rerunnable {
+        Some(true) => directives.push("#PBS -r y".to_string()),
+        Some(false) => directives.push("#PBS -r n".to_string()),
+        None => {}
+    }
+    if let Some(directive) = spec.checkpoint.as_ref().and_then(|c| c.directive()) {
+        directives.push(directive);
+    }
+    if let Some(ref array) = spec.array {
+        directives.push(format!("#PBS -J 1-{}", array.size));
+    }
+    // A directory makes PBS name the files after the job ID.
+    if let Some(dir) = spec.log_dir() {
+        directives.push(format!("#PBS -o {}/", dir.display()));
+        directives.push(format!("#PBS -e {}/", dir.display()));
+    }
+    directives
+}
+
+/// With `--backup numbered`, move an existing `path` to the first free
+/// `path.N` unless it already holds `script`.
+fn backup_script(mode: Backup, path: &Path, script: &str) -> error::Result<()> {
+    if mode == Backup::None {
+        return Ok(());
+    }
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == script => return Ok(()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        // not text, but still worth keeping
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+        Err(e) => return Err(Error::Render(path.to_path_buf(), e)),
+    }
+    let numbered = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+    let backup = (1..)
+        .map(numbered)
+        .find(|p| !p.exists())
+        .expect("some backup number is free");
+    log::info!(
+        "Keeping the previous {} as {}",
+        path.display(),
+        backup.display()
+    );
+    fs::rename(path, &backup).map_err(|e| Error::Render(path.to_path_buf(), e))
+}
+
+/// Stop a script with `--lint` errors before it is written.
+fn lint_script(script: &str, name: &str) -> error::Result<()> {
+    match lint::report(&lint::check(script), name) {
+        0 => Ok(()),
+        errors => Err(Error::LintFailed(errors)),
+    }
+}
+
+fn write_env_snapshot(cli: &Cli, spec: &JobSpec) -> error::Result<()> {
+    let Some(ref path) = spec.env_snapshot else {
+        return Ok(());
+    };
+    let saved = snapshot::write(path, &cli.config.snapshot_env)
+        .map_err(|e| Error::Io("saving the environment", e))?;
+    log::info!(
+        "Saved {} environment variables to {}",
+        saved,
+        path.display()
+    );
+    Ok(())
+}
+
+/// PBS cannot deliver the output to a `--log-dir` that does not exist.
+fn create_log_dir(spec: &JobSpec) -> error::Result<()> {
+    match spec.log_dir() {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| Error::Io("creating the log directory", e))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Put the extra directives at `{directives}`, or after the last `#PBS` line
+/// for templates that do not have the placeholder.
+fn insert_directives(script: &str, directives: &[String]) -> String {
+    let block: String = directives.iter().map(|d| format!("{}\n", d)).collect();
+
+    if script.contains("{directives}") {
+        return script
+            .replace("{directives}\n", &block)
+            .replace("{directives}", block.trim_end());
+    }
+    if block.is_empty() {
+        return script.to_string();
+    }
+
+    let lines: Vec<&str> = script.split_inclusive('\n').collect();
+    let position = match lines.iter().rposition(|l| l.starts_with("#PBS")) {
+        Some(i) => i + 1,
+        None if lines.first().is_some_and(|l| l.starts_with("#!")) => 1,
+        None => 0,
+    };
+    let mut rendered: String = lines[..position].concat();
+    if !rendered.is_empty() && !rendered.ends_with('\n') {
+        rendered.push('\n');
+    }
+    rendered.push_str(&block);
+    rendered.push_str(&lines[position..].concat());
+    rendered
+}
+
+/// `it's` -> `'it'\''s'`
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Commands with quotes, `$`, backslashes, backticks or newlines are run
+/// through `eval` on a single-quoted copy, so they reach the shell exactly as
+/// given whatever surrounds `{command}` in the template. Simple commands and
+/// `--raw-command` are pasted as they are.
+fn render_command(command: &str, raw: bool, dialect: Dialect) -> String {
+    if raw || !command.contains(['\'', '"', '$', '`', '\\', '\n']) {
+        return command.to_string();
+    }
+    format!("eval {}", dialect.quote(command))
+}
+
+/// `-m a` only covers jobs the server aborts; this mails the owner when the body exits non-zero.
+const FAILURE_MAIL: &str = r#"if [ $status -ne 0 ]; then echo "Job $PBS_JOBID ($PBS_JOBNAME) exited with status $status" | mail -s "PBS job $PBS_JOBID failed" "$PBS_O_LOGNAME"; fi"#;
+
+/// Shell setup that goes before the command: `--umask`, an EXIT trap for
+/// `--epilogue` and `--notify-on-failure`, then the `--prologue`.
+fn render_preamble(spec: &JobSpec) -> String {
+    let dialect = spec.dialect;
+    let mut preamble = String::new();
+    if !spec.tags.is_empty() {
+        preamble.push_str(&format!("# qsub2 tags: {}\n", spec.tags.join(" ")));
+    }
+    if let Some(ref group) = spec.job_group {
+        preamble.push_str(&format!("# qsub2 group: {}\n", group));
+    }
+    if let Some(ref commit) = spec.git_commit {
+        preamble.push_str(&format!("# Submitted from git commit {}\n", commit));
+    }
+    if let Some(ref requeue) = spec.requeue {
+        let attempt = spec.resumed + 1;
+        preamble.push_str(&format!(
+            "# Attempt {} of {}; qsub2 submits the job again if it is preempted.\n",
+            attempt,
+            requeue.max + 1
+        ));
+        preamble.push_str(&dialect.export(&[
+            ("QSUB2_ATTEMPT", attempt.to_string()),
+            ("QSUB2_RESUMED", u8::from(spec.resumed > 0).to_string()),
+        ]));
+        if spec.resumed < requeue.max {
+            preamble.push_str(
+                &dialect.requeue_trap(&shell_quote(&requeue.program.display().to_string())),
+            );
+        }
+    }
+    if let Some(ref warning) = spec.walltime_warning {
+        let walltime = pbs::parse_walltime(&spec.walltime).unwrap_or_default();
+        preamble.push_str(&warning.trap(walltime, spec.checkpoint.as_ref()));
+    }
+    if let Some(ref snapshot) = spec.env_snapshot {
+        preamble
+            .push_str("# The environment qsub2 ran in; source it to reproduce the submission.\n");
+        preamble.push_str(&dialect.export(&[(
+            "QSUB2_ENV_SNAPSHOT",
+            shell_quote(&snapshot.display().to_string()),
+        )]));
+    }
+    if !spec.env.is_empty() {
+        let variables: Vec<(&str, String)> = spec
+            .env
+            .iter()
+            .map(|(name, value)| (name.as_str(), dialect.quote(value)))
+            .collect();
+        preamble.push_str("# From --env-file.\n");
+        preamble.push_str(&dialect.export(&variables));
+    }
+    // The directive only covers the output files PBS writes; the body needs its own.
+    if let Some(ref umask) = spec.umask {
+        preamble.push_str(&format!("umask {}\n", umask));
+    }
+    let mut on_exit = Vec::new();
+    // bash rejects a function with an empty body
+    let epilogue = spec.epilogue.as_ref().filter(|e| !e.trim().is_empty());
+    // other dialects run these after the command, see `render_steps`
+    if let Some(epilogue) = epilogue.filter(|_| dialect == Dialect::Bash) {
+        preamble.push_str(&format!("qsub2_epilogue() {{\n{}\n}}\n", epilogue));
+        on_exit.push("qsub2_epilogue");
+    }
+    if spec.notify_on_failure && dialect == Dialect::Bash {
+        on_exit.push(FAILURE_MAIL);
+    }
+    // A trap keeps the job's exit status, whatever the epilogue returns.
+    if !on_exit.is_empty() {
+        preamble.push_str(&format!("trap 'status=$?; {}' EXIT\n", on_exit.join("; ")));
+    }
+    if let Some(ref array) = spec.array {
+        preamble.push_str(&array::lookup(array));
+    }
+    if let Some(segment) = spec.segment {
+        preamble.push_str(&format!(
+            "# Segment {} of {}, submitted with --segments.\n",
+            segment.index, segment.count
+        ));
+        preamble.push_str(&dialect.export(&[
+            ("QSUB2_SEGMENT", segment.index.to_string()),
+            ("QSUB2_SEGMENTS", segment.count.to_string()),
+        ]));
+    }
+    if let Some(ref prologue) = spec.prologue {
+        preamble.push_str(prologue);
+        preamble.push('\n');
+    }
+    for snippet in &spec.prepend {
+        preamble.push_str(snippet);
+        preamble.push('\n');
+    }
+    preamble
+}
+
+/// The command followed by any `--then` steps: chained with `&&`, or with
+/// `--continue-on-error` all run and the job exits with the last failure.
+fn render_steps(spec: &JobSpec) -> String {
+    let dialect = spec.dialect;
+    let steps: Vec<String> = std::iter::once(&spec.command)
+        .chain(&spec.then)
+        .map(|step| render_command(step, spec.raw_command, dialect))
+        .collect();
+    let body = if steps.len() == 1 {
+        steps[0].clone()
+    } else if !spec.continue_on_error {
+        steps.join(dialect.and())
+    } else {
+        let mut body = dialect.set_status("0") + "\n";
+        for step in steps {
+            body.push_str(&dialect.keep_failure(&step));
+        }
+        body.push_str(&dialect.exit());
+        body
+    };
+    let body = match spec.checkpoint {
+        Some(ref checkpoint) => checkpoint.wrap(&body),
+        None => body,
+    };
+    let body = match spec.walltime_warning {
+        Some(ref warning) => warning.wrap(&body),
+        None => body,
+    };
+    let mut after = spec.append.clone();
+    // without an EXIT trap these run last, where the status is kept anyway
+    if dialect != Dialect::Bash {
+        after.extend(
+            spec.epilogue
+                .iter()
+                .filter(|e| !e.trim().is_empty())
+                .cloned(),
+        );
+        if spec.notify_on_failure {
+            after.push(dialect.failure_mail());
+        }
+    }
+    if after.is_empty() {
+        return render_preamble(spec) + &body;
+    }
+    // The job exits with the command's status, not that of the last line appended.
+    let exit = dialect.exit();
+    let body = match body.strip_suffix(&format!("\n{}", exit)) {
+        Some(steps) => format!("{}\n", steps),
+        None => format!("{}\n{}\n", body, dialect.save_status()),
+    };
+    render_preamble(spec) + &body + &after.join("\n") + "\n" + &exit
+}
+
+fn default_script_path(cli: &Cli, job_name: &str) -> PathBuf {
+    let format = match cli.name_from_hash {
+        true => naming::HASH_FORMAT,
+        false => cli.name_format.as_deref().unwrap_or(naming::DEFAULT_FORMAT),
+    };
+    let name = naming::script_name(format, job_name, Local::now());
+    match cli.config.script_dir() {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+/// Create a new script next to [`default_script_path`], adding `_1`, `_2`, ...
+/// when another qsub2 got there first. The file is only readable by its owner.
+/// A name with `{hash}` is the same for the same script, which is then kept.
+fn create_script_file(cli: &Cli, job_name: &str, script: &str) -> error::Result<PathBuf> {
+    let path = default_script_path(cli, job_name);
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| Error::Render(path.clone(), e))?;
+    }
+    if naming::has_hash(&path) {
+        let path = naming::fill_in_hash(&path, &history::fingerprint(script, &cli.files));
+        write_hashed_script(&path, script)?;
+        return Ok(path);
+    }
+    let stem = path.with_extension("");
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    for attempt in 0.. {
+        let candidate = match attempt {
+            0 => path.clone(),
+            n => PathBuf::from(format!("{}_{}{}", stem.display(), n, extension)),
+        };
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&candidate) {
+            Ok(mut file) => {
+                return file
+                    .write_all(script.as_bytes())
+                    .map(|()| candidate.clone())
+                    .map_err(|e| Error::Render(candidate, e));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::Render(candidate, e)),
+        }
+    }
+    unreachable!("the attempts never run out")
+}
+
+/// Write a script named after its hash, unless it is already there.
+fn write_hashed_script(path: &Path, script: &str) -> error::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == script) {
+        log::info!("{} is unchanged", path.display());
+        return Ok(());
+    }
+    fs::write(path, script).map_err(|e| Error::Render(path.to_path_buf(), e))
+}
+
+/// `{hash}` in a file written before the script is rendered: a hash of the
+/// job's settings and inputs.
+fn settings_hash(cli: &Cli, spec: &JobSpec) -> String {
+    history::fingerprint(&serde_json::to_string(spec).unwrap_or_default(), &cli.files)
+}
+
+fn render_job_script(spec: &JobSpec) -> error::Result<String> {
+    if let Some(feature) = spec.dialect.unsupported(spec) {
+        return Err(Error::Usage(format!(
+            "{} is only written for bash scripts, not --script-dialect {}",
+            feature,
+            spec.dialect.name()
+        )));
+    }
+    let template_content = if let Some(ref template_path) = spec.template {
+        encoding::read(template_path).map_err(|e| Error::Template(template_path.clone(), e))?
+    } else {
+        spec.dialect.template().into() // Use a built-in default template as a fallback
+    };
+    let template_content =
+        expr::render(&template_content, &expr::spec_vars(spec)).map_err(|e| {
+            let template = spec
+                .template
+                .as_ref()
+                .map_or("the built-in template".into(), |t| {
+                    format!("template {}", t.display())
+                });
+            Error::Expression(format!("{}, {}", template, e))
+        })?;
+
+    let mut mem = spec
+        .mem
+        .as_deref()
+        .map_or(String::new(), |m| format!(":mem={}", m));
+    let mut constraints: String = spec.constraints.iter().map(|c| format!(":{}", c)).collect();
+    // Older templates have no {constraints}; the chunk still ends at {mem}.
+    if !template_content.contains("{constraints}") && !constraints.is_empty() {
+        if !template_content.contains("{mem}") {
+            log::warn!("the template has no {{constraints}} or {{mem}}; ignoring --constraint");
+        }
+        mem.push_str(&constraints);
+        constraints.clear();
+    }
+
+    let job_script = template_content
+        .replace("{name}", &spec.name)
+        .replace("{ncpus}", &format!(":ncpus={}", spec.ncpus))
+        .replace("{mem}", &mem)
+        .replace("{constraints}", &constraints)
+        .replace("{queue}", &spec.queue)
+        .replace("{walltime}", &spec.walltime);
+    let mut directives = extra_directives(spec);
+    let job_script = match spec.select {
+        Some(ref select) => {
+            let directive = format!("#PBS -l select={}", select);
+            if job_script.lines().any(|l| l.starts_with("#PBS -l select=")) {
+                job_script
+                    .split_inclusive('\n')
+                    .map(|line| match line.strip_prefix("#PBS -l select=") {
+                        Some(old) => {
+                            let ending = &old[old.trim_end().len()..];
+                            format!("{}{}", directive, ending)
+                        }
+                        None => line.to_string(),
+                    })
+                    .collect()
+            } else {
+                directives.insert(0, directive);
+                job_script
+            }
+        }
+        None => job_script,
+    };
+    let template_lines: Vec<&str> = template_content
+        .lines()
+        .filter(|l| l.starts_with("#PBS") && !l.contains('{'))
+        .collect();
+    let job_script = directives::merge(
+        &insert_directives(&job_script, &directives),
+        &template_lines,
+    )
+    .map_err(Error::Usage)?;
+    // The command goes in last so placeholder-like text in it stays as typed.
+    let job_script = job_script.replace("{command}", &render_steps(spec));
+
+    for directive in job_script.lines().filter(|l| l.starts_with("#PBS")) {
+        log::debug!("directive: {}", directive);
+    }
+    Ok(job_script)
+}
+
+fn generate_job_script(spec: &JobSpec, outfile: &Path, backup: Backup) -> error::Result<()> {
+    let job_script = render_job_script(spec)?;
+    create_log_dir(spec)?;
+    backup_script(backup, outfile, &job_script)?;
+    File::create(outfile)
+        .and_then(|mut file| file.write_all(job_script.as_bytes()))
+        .map_err(|e| Error::Render(outfile.to_path_buf(), e))?;
+
+    Ok(())
+}
+
+/// Where a setting shown by `--show-defaults` came from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    BuiltIn,
+    /// A key in the configuration file.
+    Config(String),
+    /// Derived from the command.
+    Command,
+    Env(&'static str),
+    Flag(&'static str),
+}
+
+impl Source {
+    /// `--mem-rule` or `$QSUB2_MEM_RULE`, depending on which one set the option.
+    fn option(cli: &Cli, id: &str, flag: &'static str, env: &'static str) -> Self {
+        if cli.from_env.iter().any(|i| i == id) {
+            Source::Env(env)
+        } else {
+            Source::Flag(flag)
+        }
+    }
+
+    /// A command line option, a `[default]` key, or neither.
+    fn pick<T>(
+        given: &Option<T>,
+        flag: &'static str,
+        default: &Option<T>,
+        key: &'static str,
+    ) -> Self {
+        match (given, default) {
+            (Some(_), _) => Source::Flag(flag),
+            (None, Some(_)) => Source::Config(key.to_string()),
+            (None, None) => Source::BuiltIn,
+        }
+    }
+
+    /// `config ~/.config/qsub2/config.toml:3`
+    fn describe(&self) -> String {
+        match self {
+            Source::BuiltIn => "built-in".to_string(),
+            Source::Command => "from the command".to_string(),
+            Source::Config(key) => match config::default_path() {
+                Some(path) => match config::line_of(&path, key) {
+                    Some(line) => format!("config {}:{}", path.display(), line),
+                    None => format!("config {}", path.display()),
+                },
+                None => "config".to_string(),
+            },
+            Source::Env(var) => format!("${}", var),
+            Source::Flag(flag) => flag.to_string(),
+        }
+    }
+}
+
+/// Every setting of a submission with its effective value.
+fn effective_settings(cli: &Cli, spec: &JobSpec) -> Vec<(&'static str, String, Source)> {
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| "(none)".to_string());
+    let defaults = &cli.config.defaults;
+    let (scheduler, scheduler_source) = if cli.no_scheduler {
+        (
+            format!(
+                "mock ({})",
+                or_none(mock::directory().map(|d| d.display().to_string()))
+            ),
+            Source::option(cli, "no_scheduler", "--no-scheduler", "QSUB2_MOCK"),
+        )
+    } else {
+        let qsub = cli
+            .qsub_bin
+            .as_deref()
+            .or(cli.config.qsub_path.as_deref())
+            .unwrap_or(Path::new("qsub"))
+            .display()
+            .to_string();
+        let qsub_source = Source::pick(
+            &cli.qsub_bin,
+            "--qsub-bin",
+            &cli.config.qsub_path,
+            "qsub_path",
+        );
+        (qsub, qsub_source)
+    };
+    let scaled = |given: bool, rule: bool, id, flag, env| {
+        (!given && rule).then(|| Source::option(cli, id, flag, env))
+    };
+    let queue_defaults = cli.config.queues.get(&spec.queue);
+    let by_queue = |given: bool, set: fn(&config::QueueDefaults) -> bool, key: &str| {
+        (!given && queue_defaults.is_some_and(set))
+            .then(|| Source::Config(format!("queue.{}.{}", spec.queue, key)))
+    };
+    let mem_source = scaled(
+        cli.mem.is_some(),
+        cli.mem_rule.is_some(),
+        "mem_rule",
+        "--mem-rule",
+        "QSUB2_MEM_RULE",
+    )
+    .or_else(|| by_queue(cli.mem.is_some(), |d| d.mem.is_some(), "mem"))
+    .unwrap_or_else(|| Source::pick(&cli.mem, "--mem", &defaults.mem, "default.mem"));
+    let walltime_source = scaled(
+        cli.walltime.is_some(),
+        cli.walltime_rule.is_some(),
+        "walltime_rule",
+        "--walltime-rule",
+        "QSUB2_WALLTIME_RULE",
+    )
+    .or_else(|| by_queue(cli.walltime.is_some(), |d| d.walltime.is_some(), "walltime"))
+    .unwrap_or_else(|| {
+        Source::pick(
+            &cli.walltime,
+            "--walltime",
+            &defaults.walltime,
+            "default.walltime",
+        )
+    });
+    let from_env = |var| {
+        if std::env::var_os(var).is_some() {
+            Source::Env(var)
+        } else {
+            Source::BuiltIn
+        }
+    };
+    vec![
+        (
+            "name",
+            spec.name.clone(),
+            match Source::pick(&cli.name, "--name", &defaults.name, "default.name") {
+                Source::BuiltIn if spec.name != "job" => Source::Command,
+                source => source,
+            },
+        ),
+        (
+            "ncpus",
+            spec.ncpus.to_string(),
+            by_queue(cli.ncpus.is_some(), |d| d.ncpus.is_some(), "ncpus").unwrap_or_else(|| {
+                Source::pick(&cli.ncpus, "--ncpus", &defaults.ncpus, "default.ncpus")
+            }),
+        ),
+        (
+            "mem",
+            spec.mem
+                .clone()
+                .unwrap_or_else(|| "(server default)".to_string()),
+            mem_source,
+        ),
+        (
+            "queue",
+            spec.queue.clone(),
+            match routing::route(&cli.config.routes, spec) {
+                Some(_) if cli.queue.is_none() => Source::Config("routes".to_string()),
+                _ => Source::pick(&cli.queue, "--queue", &defaults.queue, "default.queue"),
+            },
+        ),
+        ("walltime", spec.walltime.clone(), walltime_source),
+        (
+            "template",
+            spec.template
+                .as_ref()
+                .map_or("(built-in)".to_string(), |t| t.display().to_string()),
+            by_queue(cli.template.is_some(), |d| d.template.is_some(), "template").unwrap_or_else(
+                || {
+                    Source::pick(
+                        &cli.template,
+                        "--template",
+                        &defaults.template,
+                        "default.template",
+                    )
+                },
+            ),
+        ),
+        (
+            "log_dir",
+            or_none(spec.log_dir().map(|d| d.display().to_string())),
+            Source::pick(
+                &cli.log_dir,
+                "--log-dir",
+                &defaults.log_dir,
+                "default.log_dir",
+            ),
+        ),
+        (
+            "script_dialect",
+            spec.dialect.name().to_string(),
+            Source::pick(
+                &cli.script_dialect,
+                "--script-dialect",
+                &defaults.script_dialect,
+                "default.script_dialect",
+            ),
+        ),
+        ("scheduler", scheduler, scheduler_source),
+        (
+            "submit_args",
+            or_none(Some(cli.config.submit_args.join(" ")).filter(|a| !a.is_empty())),
+            if cli.config.submit_args.is_empty() {
+                Source::BuiltIn
+            } else {
+                Source::Config("submit_args".to_string())
+            },
+        ),
+        (
+            "config",
+            or_none(config::default_path().map(|p| p.display().to_string())),
+            from_env("QSUB2_CONFIG"),
+        ),
+        (
+            "history",
+            or_none(history::default_path().map(|p| p.display().to_string())),
+            match from_env("QSUB2_HISTORY") {
+                Source::BuiltIn if cli.config.history.is_some() => {
+                    Source::Config("history".to_string())
+                }
+                source => source,
+            },
+        ),
+    ]
+}
+
+/// With `-v`, each setting is followed by where it came from.
+fn show_defaults(cli: &Cli, spec: &JobSpec) {
+    for (setting, value, source) in effective_settings(cli, spec) {
+        if cli.verbose > 0 {
+            println!("{:<12} {:<32} ({})", setting, value, source.describe());
+        } else {
+            println!("{:<12} {}", setting, value);
+        }
+    }
+}
+
+/// `ncpus 4  mem 16gb  walltime 12:00:00  queue batch`
+fn print_resources(spec: &JobSpec) {
+    let label = |name: &str| {
+        format!(
+            "{}{}{}",
+            style::LABEL.render(),
+            name,
+            style::LABEL.render_reset()
+        )
+    };
+    if let Some(ref select) = spec.select {
+        anstream::println!(
+            "{} {}  {} {}  {} {}",
+            label("select"),
+            select,
+            label("walltime"),
+            spec.walltime,
+            label("queue"),
+            spec.queue
+        );
+        return;
+    }
+    anstream::println!(
+        "{} {}  {} {}  {} {}  {} {}",
+        label("ncpus"),
+        spec.ncpus,
+        label("mem"),
+        spec.mem.as_deref().unwrap_or("default"),
+        label("walltime"),
+        spec.walltime,
+        label("queue"),
+        spec.queue
+    );
+}
+
+/// Submit the script file, or `script` itself when there is none, from
+/// `workdir` (the job's `$PBS_O_WORKDIR`) with qsub or the scheduler plugin,
+/// trying again after transient errors, and return the job ID.
+fn submit_job(
+    cli: &Cli,
+    script: &str,
+    outfile: Option<&Path>,
+    workdir: Option<&Path>,
+) -> error::Result<String> {
+    if cli.no_scheduler {
+        log::debug!(
+            "mock submission of {}",
+            outfile.map_or("standard input".into(), |o| o.display().to_string())
+        );
+        return mock::submit(script, outfile)
+            .map_err(|e| Error::Io("recording mock submission", e));
+    }
+    let retries = cli.config.submit_retries.unwrap_or(SUBMIT_RETRIES);
+    let mut delay = Duration::from_secs(2);
+    for attempt in 1.. {
+        let submitted = match scheduler::plugin() {
+            Some(plugin) => plugin.submit(script, outfile, workdir),
+            None => pbs(cli).submit(script, outfile, workdir),
+        };
+        match submitted {
+            Err(Error::QsubRejected(message))
+                if attempt <= retries && pbs::is_transient(&message) =>
+            {
+                log::warn!(
+                    "{}; trying again in {}s (retry {} of {})",
+                    message,
+                    delay.as_secs(),
+                    attempt,
+                    retries
+                );
+                interrupt::sleep(delay);
+                if interrupt::requested() {
+                    return Err(Error::Interrupted("nothing was submitted".into()));
+                }
+                delay = (delay * 2).min(MAX_SUBMIT_DELAY);
+            }
+            submitted => return submitted,
+        }
+    }
+    unreachable!("the attempts never run out")
+}
+
+/// How many times a submission that failed for a reason that passes is
+/// tried again, waiting twice as long each time up to `MAX_SUBMIT_DELAY`.
+/// A server that timed out may have queued the job anyway, so a retried job
+/// can end up there twice; `submit_retries = 0` where that matters.
+const SUBMIT_RETRIES: u32 = 3;
+const MAX_SUBMIT_DELAY: Duration = Duration::from_secs(60);
+
+/// `qsub` as configured.
+fn pbs(cli: &Cli) -> scheduler::Pbs {
+    scheduler::Pbs {
+        qsub: cli.qsub_bin.clone().or(cli.config.qsub_path.clone()),
+        args: cli.config.submit_args.clone(),
+        own_group: cli.attached,
+    }
+}
+
+/// `--show-command`: what [`submit_job`] would run for the script at
+/// `outfile`, from the directory it would run in.
+fn submit_command(cli: &Cli, outfile: Option<&Path>) -> String {
+    if cli.no_scheduler {
+        return "# --no-scheduler: the submission is recorded, no command is run".to_string();
+    }
+    let (words, stdin) = match scheduler::plugin() {
+        Some(plugin) => (
+            vec![plugin.program.display().to_string()],
+            "a submit request as JSON",
+        ),
+        None => (pbs(cli).command_line(outfile), "the script"),
+    };
+    let mut line = words
+        .iter()
+        .map(|word| batch::shell_word(word))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if let Ok(dir) = std::env::current_dir() {
+        line = format!(
+            "cd {} && {}",
+            batch::shell_word(&dir.display().to_string()),
+            line
+        );
+    }
+    if outfile.is_none() || scheduler::plugin().is_some() {
+        line.push_str(&format!("  # {} on standard input", stdin));
+    }
+    line
+}
+
+/// Poll the scheduler until the jobs have finished. Returns `false` on timeout.
+fn wait_for_jobs(args: &WaitArgs) -> io::Result<bool> {
+    let start = Instant::now();
+    let mut finished: HashSet<&str> = HashSet::new();
+
+    loop {
+        for job_id in &args.job_ids {
+            if finished.contains(job_id.as_str()) {
+                continue;
+            }
+            let done = scheduler::job_status(job_id)?.is_none_or(|a| pbs::is_finished(&a));
+            if done {
+                finished.insert(job_id);
+            }
+        }
+
+        let condition_met = if args.any {
+            !finished.is_empty()
+        } else {
+            finished.len() == args.job_ids.len()
+        };
+        if condition_met {
+            return Ok(true);
+        }
+
+        let mut interval = args.interval;
+        if let Some(timeout) = args.timeout {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Ok(false);
+            }
+            interval = interval.min(timeout - elapsed);
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn show_logs(args: &LogsArgs) -> io::Result<()> {
+    let path = logs::locate(&args.job_id, args.stderr)?;
+    if args.follow {
+        return logs::follow(&path, || {
+            Ok(scheduler::job_status(&args.job_id)?.is_none_or(|a| pbs::is_finished(&a)))
+        });
+    }
+    let content = fs::read(&path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "{}: {} (PBS may only copy it there when the job ends)",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    io::stdout().write_all(&content)
+}
+
+fn watch_job(args: &WatchArgs) -> io::Result<()> {
+    let mut last_state = String::new();
+    let mut report = |attributes: Option<pbs::JobAttributes>| -> bool {
+        let state = attributes
+            .as_ref()
+            .and_then(|a| a.get("job_state"))
+            .map_or("F", String::as_str)
+            .to_string();
+        if state != last_state {
+            println!(
+                "[{}] {} is {}",
+                Local::now().format("%H:%M:%S"),
+                args.job_id,
+                pbs::state_name(&state)
+            );
+            last_state = state;
+        }
+        attributes.is_none_or(|a| pbs::is_finished(&a))
+    };
+
+    loop {
+        let attributes = scheduler::job_status(&args.job_id)?;
+        let running = attributes
+            .as_ref()
+            .is_some_and(|a| a.get("job_state").is_some_and(|s| s == "R"));
+        if report(attributes) {
+            return Ok(());
+        }
+        if running {
+            break;
+        }
+        thread::sleep(args.interval);
+    }
+
+    let path = logs::locate(&args.job_id, false)?;
+    let finished = || Ok(report(scheduler::job_status(&args.job_id)?));
+    match args.peek {
+        Some(ref peek) if !path.exists() => logs::follow_command(peek, &args.job_id, finished),
+        _ => logs::follow(&path, finished),
+    }
+}
+
+fn show_nodes(args: &NodesArgs) -> io::Result<()> {
+    let mut nodes = cluster::nodes()?;
+    if let Some(ref queue) = args.queue {
+        nodes.retain(|n| n.serves(queue));
+    }
+    let request = (args.ncpus.is_some() || args.mem.is_some() || args.ngpus.is_some()).then(|| {
+        nodes::Request {
+            ncpus: args.ncpus.unwrap_or(1),
+            mem: args.mem,
+            ngpus: args.ngpus.unwrap_or(0),
+        }
+    });
+    if args.classes {
+        print!("{}", nodes::class_table(&nodes, request.as_ref()));
+    } else {
+        print!("{}", nodes::table(&nodes, request.as_ref()));
+    }
+    if let Some(ref request) = request {
+        println!("\n{}", nodes::summary(&nodes, request));
+    }
+    Ok(())
+}
+
+fn show_top() -> io::Result<()> {
+    let summaries = cluster::summarize(&cluster::queues()?, &cluster::nodes()?);
+    println!(
+        "{:<16} {:>8} {:>8} {:>15} {:>21}",
+        "QUEUE", "RUNNING", "QUEUED", "FREE CPUS", "FREE MEM"
+    );
+    for s in summaries {
+        println!(
+            "{:<16} {:>8} {:>8} {:>15} {:>21}",
+            s.name,
+            s.running,
+            s.queued,
+            format!("{}/{}", s.free_ncpus, s.ncpus),
+            format!(
+                "{}/{}",
+                pbs::format_size(s.free_mem),
+                pbs::format_size(s.mem)
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// How long the cached cluster snapshot is used: `cluster_cache_ttl`, or five minutes.
+fn cache_ttl(cli: &Cli) -> error::Result<Duration> {
+    match cli.config.cluster_cache_ttl {
+        Some(ref ttl) => {
+            parse_duration(ttl).map_err(|e| Error::Usage(format!("cluster_cache_ttl: {}", e)))
+        }
+        None => Ok(discovery::DEFAULT_TTL),
+    }
+}
+
+fn cluster_snapshot(cli: &Cli) -> error::Result<discovery::Snapshot> {
+    discovery::get(cache_ttl(cli)?).map_err(|e| Error::Io("querying the queues", e))
+}
+
+/// Warn about what the cached cluster snapshot says the server would refuse;
+/// without a fresh one the server is not asked.
+fn check_against_cluster(cli: &Cli, spec: &JobSpec) -> error::Result<()> {
+    let Some(snapshot) = discovery::cached(cache_ttl(cli)?) else {
+        return Ok(());
+    };
+    for problem in snapshot.problems(spec) {
+        log::warn!("{}", problem);
+    }
+    Ok(())
+}
+
+/// The queues that accept the job, the one expected to start it first at the front.
+fn queue_candidates(cli: &Cli, spec: &JobSpec) -> error::Result<Vec<cluster::Candidate>> {
+    let snapshot = cluster_snapshot(cli)?;
+    let walltime = pbs::parse_walltime(&spec.walltime).unwrap_or(0);
+    let mem = spec.mem.as_deref().and_then(pbs::parse_size);
+    Ok(cluster::candidates(
+        &snapshot.queues,
+        &snapshot.nodes,
+        spec.ncpus as u64,
+        mem,
+        walltime,
+    ))
+}
+
+/// Without `--queue`, the queue of the first route in the configuration the
+/// job matches; `default.queue` otherwise.
+fn route_queue(cli: &Cli, spec: &mut JobSpec) {
+    if cli.queue.is_some() {
+        return;
+    }
+    if let Some(route) = routing::route(&cli.config.routes, spec) {
+        log::info!("Routed to queue {} by '{}'", route.queue, route.text);
+        spec.queue = route.queue.clone();
+    }
+}
+
+/// The `[queue.NAME]` table of the job's queue, for what neither the command
+/// line nor a scaling rule set.
+fn apply_queue_defaults(cli: &Cli, spec: &mut JobSpec) {
+    let Some(defaults) = cli.config.queues.get(&spec.queue) else {
+        return;
+    };
+    if let (None, Some(ncpus)) = (cli.ncpus, defaults.ncpus) {
+        spec.ncpus = ncpus;
+    }
+    if let (None, None, Some(mem)) = (&cli.mem, &cli.mem_rule, &defaults.mem) {
+        spec.mem = Some(mem.clone());
+    }
+    if let (None, None, Some(walltime)) = (&cli.walltime, &cli.walltime_rule, &defaults.walltime) {
+        spec.walltime = walltime.clone();
+    }
+    if let (None, Some(template)) = (&cli.template, &defaults.template) {
+        spec.template = Some(cli.config.resolve_template(template));
+    }
+}
+
+/// Compute the `{{ ... }}` expressions of mem and walltime, e.g. a
+/// configured `mem = "{{ ncpus * 4 }}gb"`, from the job's other settings.
+fn evaluate_expressions(spec: &mut JobSpec) -> error::Result<()> {
+    let evaluate = |what: &str, value: &str, spec: &JobSpec| {
+        expr::render(value, &expr::spec_vars(spec))
+            .map_err(|e| Error::InvalidResources(format!("{} '{}': {}", what, value, e)))
+    };
+    if expr::has_expressions(&spec.walltime) {
+        spec.walltime = evaluate("walltime", &spec.walltime, spec)?;
+    }
+    if let Some(mem) = spec.mem.clone().filter(|m| expr::has_expressions(m)) {
+        spec.mem = Some(evaluate("mem", &mem, spec)?);
+    }
+    Ok(())
+}
+
+/// Replace `--queue auto` with the queue expected to start the job first.
+fn pick_queue(cli: &Cli, spec: &mut JobSpec) -> error::Result<()> {
+    if spec.queue != "auto" {
+        return Ok(());
+    }
+    let best = queue_candidates(cli, spec)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            Error::InvalidResources(format!(
+                "no enabled queue accepts {} cpus for {}",
+                spec.ncpus, spec.walltime
+            ))
+        })?;
+    log::info!(
+        "Picked queue {} ({})",
+        best.name,
+        if best.starts_now {
+            "the job can start now".to_string()
+        } else {
+            format!("{:.1} queued jobs per running job", best.backlog)
+        }
+    );
+    spec.queue = best.name;
+    Ok(())
+}
+
+fn recommend_queue(cli: &Cli, spec: &JobSpec) -> error::Result<()> {
+    let candidates = queue_candidates(cli, spec)?;
+    if candidates.is_empty() {
+        return Err(Error::InvalidResources(format!(
+            "no enabled queue accepts {} cpus for {}",
+            spec.ncpus, spec.walltime
+        )));
+    }
+    println!(
+        "{:<16} {:>10} {:>8} {:>8}",
+        "QUEUE", "STARTS", "BACKLOG", "PRIORITY"
+    );
+    for candidate in &candidates {
+        println!(
+            "{:<16} {:>10} {:>8.1} {:>8}",
+            candidate.name,
+            if candidate.starts_now { "now" } else { "later" },
+            candidate.backlog,
+            candidate.priority
+        );
+    }
+    Ok(())
+}
+
+/// When the job would likely start in its queue, from the cluster snapshot,
+/// and by how much fewer cpus would start it sooner.
+fn estimate_start(cli: &Cli, spec: &JobSpec) -> error::Result<()> {
+    let snapshot = cluster_snapshot(cli)?;
+    let queue = snapshot
+        .queue(&spec.queue)
+        .ok_or_else(|| Error::InvalidResources(format!("there is no queue {}", spec.queue)))?;
+    let (ncpus, _) = cost::resources(spec).map_err(Error::InvalidResources)?;
+    let mem = spec.mem.as_deref().and_then(pbs::parse_size);
+    let shape = |ncpus: u64, mem: Option<u64>| match mem {
+        Some(mem) => format!("{} cpus and {}", ncpus, pbs::format_size(mem)),
+        None => format!("{} cpus", ncpus),
+    };
+    let delay = estimate::start_delay(&snapshot, queue, ncpus, mem).ok_or_else(|| {
+        Error::InvalidResources(format!(
+            "no node of queue {} has {}",
+            queue.name,
+            shape(ncpus, mem)
+        ))
+    })?;
+    println!(
+        "{} in queue {} would likely start {} (cluster as of {})",
+        shape(ncpus, mem),
+        queue.name,
+        estimate::describe(delay),
+        snapshot.taken_at.format("%H:%M")
+    );
+    for (fewer, mem, sooner) in estimate::alternatives(&snapshot, queue, ncpus, mem) {
+        println!(
+            "  {} would likely start {} sooner than {}",
+            shape(fewer, mem),
+            suggest::short_duration(sooner.div_ceil(1800) * 1800),
+            ncpus
+        );
+    }
+    Ok(())
+}
+
+/// Time elapsed since `time`; zero for times in the future.
+fn age(time: chrono::DateTime<Local>) -> Duration {
+    (Local::now() - time).to_std().unwrap_or_default()
+}
+
+fn history_path() -> io::Result<PathBuf> {
+    history::default_path()
+        .ok_or_else(|| io::Error::other("cannot locate the history file; set QSUB2_HISTORY"))
+}
+
+/// Your submissions in the history.
+fn read_history() -> error::Result<Vec<history::SubmissionRecord>> {
+    let mut records = history_path()
+        .and_then(|path| history::read(&path))
+        .map_err(|e| Error::Io("reading history", e))?;
+    records.retain(history::SubmissionRecord::is_mine);
+    Ok(records)
+}
+
+/// The given jobs, and the unfinished ones in the history with `--tag` and `--group`.
+fn cancel_jobs(args: &CancelArgs) -> error::Result<()> {
+    let mut job_ids = args.job_ids.clone();
+    if args.tag.is_some() || args.group.is_some() {
+        let records = read_history()?;
+        let selected: Vec<&str> = records
+            .iter()
+            .filter(|r| {
+                args.tag
+                    .as_ref()
+                    .is_none_or(|tag| r.spec.tags.contains(tag))
+            })
+            .filter(|r| args.group.is_none() || r.spec.job_group == args.group)
+            .map(|r| r.job_id.as_str())
+            .collect();
+        let unfinished: Vec<String> = selected
+            .iter()
+            .filter(
+                |id| matches!(scheduler::job_status(id), Ok(Some(ref a)) if !pbs::is_finished(a)),
+            )
+            .map(|id| id.to_string())
+            .collect();
+        if unfinished.is_empty() && job_ids.is_empty() {
+            return Err(Error::Usage(format!(
+                "no queued or running jobs match ({} finished)",
+                selected.len()
+            )));
+        }
+        job_ids.extend(unfinished);
+    }
+    let mut seen = HashSet::new();
+    job_ids.retain(|id| seen.insert(id.clone()));
+
+    let mut failed = 0;
+    for job_id in &job_ids {
+        if let Err(e) = history::mark_cancelled(job_id) {
+            log::warn!("{} may be requeued as preempted: {}", job_id, e);
+        }
+        match scheduler::cancel(job_id) {
+            Ok(()) => println!("Cancelled {}", job_id),
+            Err(e) => {
+                log::error!("{}: {}", job_id, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        let message = format!(
+            "{} of {} jobs could not be cancelled",
+            failed,
+            job_ids.len()
+        );
+        return Err(Error::Io("cancelling jobs", io::Error::other(message)));
+    }
+    Ok(())
+}
+
+/// `qsub2 select`: list the matching live jobs, then act on them once confirmed.
+fn select_jobs(cli: &Cli, args: &SelectArgs) -> error::Result<()> {
+    let filter = bulk::Filter {
+        name: args.name.clone(),
+        states: args.state.clone(),
+        queue: args.queue.clone(),
+        user: match args.all_users {
+            true => None,
+            false => Some(args.user.clone().unwrap_or_else(current_user)),
+        },
+    };
+    let jobs = bulk::live_jobs(&filter).map_err(|e| Error::Io("selecting jobs", e))?;
+    let action = match (args.cancel, args.hold, args.release) {
+        (true, _, _) => Some(bulk::Action::Cancel),
+        (_, true, _) => Some(bulk::Action::Hold),
+        (_, _, true) => Some(bulk::Action::Release),
+        _ => None,
+    };
+    if jobs.is_empty() {
+        if !cli.quiet {
+            eprintln!("No live jobs match.");
+        }
+        return Ok(());
+    }
+    if cli.quiet && action.is_none() {
+        for (job_id, _) in &jobs {
+            println!("{}", job_id);
+        }
+        return Ok(());
+    }
+    if !cli.quiet {
+        println!(
+            "{:<20} {:<16} {:<10} {:<10} OWNER",
+            "JOB ID", "NAME", "STATE", "QUEUE"
+        );
+        for (job_id, attributes) in &jobs {
+            let get = |key: &str| attributes.get(key).map_or("-", String::as_str);
+            println!(
+                "{:<20} {:<16} {:<10} {:<10} {}",
+                job_id,
+                get("Job_Name"),
+                pbs::state_name(get("job_state")),
+                get("queue"),
+                get("Job_Owner").split('@').next().unwrap_or_default()
+            );
+        }
+    }
+    let Some(action) = action else {
+        return Ok(());
+    };
+    let question = format!("{} these {} jobs?", action.verb(), jobs.len());
+    if !args.yes && !preview::confirm(&question).map_err(|e| Error::Io("reading the answer", e))? {
+        eprintln!("Nothing was changed.");
+        return Ok(());
+    }
+    let mut failed = 0;
+    for (job_id, _) in &jobs {
+        match action.apply(job_id) {
+            Ok(()) if cli.quiet => println!("{}", job_id),
+            Ok(()) => println!("{} {}", action.done(), job_id),
+            Err(e) => {
+                log::error!("{}: {}", job_id, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        let message = format!("{} of {} jobs failed", failed, jobs.len());
+        return Err(Error::Io("changing jobs", io::Error::other(message)));
+    }
+    Ok(())
+}
+
+/// The state of each job of a group, and how many are in each state.
+/// With `--quiet`, one `JOBID<TAB>STATE<TAB>EXIT STATUS` line per job, for scripts.
+fn show_status(cli: &Cli, args: &StatusArgs) -> error::Result<()> {
+    let records = read_history()?;
+    let mut jobs: Vec<(String, String)> = args
+        .job_ids
+        .iter()
+        .map(|id| {
+            let name = records.iter().rev().find(|r| &r.job_id == id);
+            (
+                id.clone(),
+                name.map_or("-".to_string(), |r| r.spec.name.clone()),
+            )
+        })
+        .collect();
+    if let Some(ref group) = args.group {
+        let members = history::latest_in_group(&records, group);
+        if members.is_empty() {
+            return Err(Error::Usage(format!(
+                "no jobs in the history belong to group {}",
+                group
+            )));
+        }
+        jobs.extend(
+            members
+                .iter()
+                .map(|r| (r.job_id.clone(), r.spec.name.clone())),
+        );
+    }
+
+    if cli.quiet {
+        for (job_id, _) in &jobs {
+            let state = history::resolve_state(job_id);
+            let exit_status = state.exit_status.unwrap_or_default();
+            println!("{}\t{}\t{}", job_id, state.state, exit_status);
+        }
+        return Ok(());
+    }
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    let mut failed = 0;
+    let mut requeued = 0;
+    println!("{:<20} {:<16} STATE", "JOB ID", "NAME");
+    for (job_id, name) in &jobs {
+        let state = history::resolve_state(job_id);
+        failed += usize::from(state.failed());
+        requeued += usize::from(state.requeued());
+        match counts.iter_mut().find(|(s, _)| *s == state.state) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((state.state.clone(), 1)),
+        }
+        println!("{:<20} {:<16} {}", job_id, name, state);
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(state, count)| format!("{} {}", count, state))
+        .collect();
+    println!(
+        "\n{} jobs: {}, {} failed",
+        jobs.len(),
+        counts.join(", "),
+        failed
+    );
+    // not failures: the server runs them again by itself
+    if requeued > 0 {
+        println!(
+            "{} requeued by the server, e.g. after a node failure",
+            requeued
+        );
+    }
+    Ok(())
+}
+
+fn export_field(value: &str, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv if value.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        ExportFormat::Csv => value.to_string(),
+        ExportFormat::Tsv => value.replace(['\t', '\n', '\r'], " "),
+    }
+}
+
+fn list_history(args: &ListArgs) -> io::Result<()> {
+    let mut records = history::read(&history_path()?)?;
+    if !args.all_users {
+        records.retain(history::SubmissionRecord::is_mine);
+    }
+    if let Some(since) = args.since {
+        records.retain(|r| age(r.submitted_at) <= since);
+    }
+    if let Some(ref tag) = args.tag {
+        records.retain(|r| r.spec.tags.contains(tag));
+    }
+    if let Some(limit) = args.limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+
+    let Some(format) = args.export else {
+        let user = |user: &str| match args.all_users {
+            true => format!("{:<12} ", user),
+            false => String::new(),
+        };
+        println!(
+            "{:<20} {:<16} {:<10} {:<19} {}STATE",
+            "JOB ID",
+            "NAME",
+            "QUEUE",
+            "SUBMITTED",
+            user("USER")
+        );
+        for record in &records {
+            let state = history::resolve_state(&record.job_id);
+            println!(
+                "{:<20} {:<16} {:<10} {:<19} {}{}",
+                record.job_id,
+                record.spec.name,
+                record.spec.queue,
+                record.submitted_at.format("%Y-%m-%d %H:%M:%S"),
+                user(record.user.as_deref().unwrap_or("-")),
+                state
+            );
+        }
+        return Ok(());
+    };
+
+    let separator = match format {
+        ExportFormat::Csv => ",",
+        ExportFormat::Tsv => "\t",
+    };
+    let header = [
+        "job_id",
+        "name",
+        "queue",
+        "ncpus",
+        "mem",
+        "walltime",
+        "submitted_at",
+        "state",
+        "exit_status",
+        "script",
+        "command",
+        "tags",
+        "requeues",
+        "user",
+    ];
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", header.join(separator))?;
+    for record in &records {
+        let state = history::resolve_state(&record.job_id);
+        let row = [
+            record.job_id.clone(),
+            record.spec.name.clone(),
+            record.spec.queue.clone(),
+            record.spec.ncpus.to_string(),
+            record.spec.mem.clone().unwrap_or_default(),
+            record.spec.walltime.clone(),
+            record.submitted_at.to_rfc3339(),
+            state.state,
+            state.exit_status.unwrap_or_default(),
+            record.script.display().to_string(),
+            record.spec.command.clone(),
+            record.spec.tags.join(" "),
+            state.requeues.to_string(),
+            record.user.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = row.iter().map(|v| export_field(v, format)).collect();
+        writeln!(stdout, "{}", row.join(separator))?;
+    }
+    Ok(())
+}
+
+fn show_report(args: &ReportArgs) -> io::Result<()> {
+    let attributes = report::usage_attributes(&args.job_id)?;
+    let name = attributes.get("Job_Name").map_or("", String::as_str);
+    println!("Job {} {}", args.job_id, name);
+    println!(
+        "{:<10} {:>12} {:>12} {:>11}",
+        "RESOURCE", "REQUESTED", "USED", "EFFICIENCY"
+    );
+    for line in report::lines(&attributes) {
+        println!(
+            "{:<10} {:>12} {:>12} {:>11}",
+            line.resource,
+            line.requested,
+            line.used,
+            line.efficiency
+                .map_or("-".to_string(), |e| format!("{:.0}%", e))
+        );
+    }
+    if let Some(exit_status) = attributes.get("Exit_status") {
+        println!("Exit status: {}", exit_status);
+    }
+    Ok(())
+}
+
+fn show_stats(args: &StatsArgs) -> io::Result<()> {
+    let mut records = history::read(&history_path()?)?;
+    if !args.all_users {
+        records.retain(history::SubmissionRecord::is_mine);
+    }
+    if let Some(since) = args.since {
+        records.retain(|r| age(r.submitted_at) <= since);
+    }
+    let progress = batch::progress_bar(records.len(), "looking up");
+    let mut by_queue: Vec<(String, Vec<Option<stats::Usage>>)> = Vec::new();
+    for record in &records {
+        progress.inc(1);
+        let usage = stats::lookup(record);
+        match by_queue.iter_mut().find(|(q, _)| *q == record.spec.queue) {
+            Some((_, jobs)) => jobs.push(usage),
+            None => by_queue.push((record.spec.queue.clone(), vec![usage])),
+        }
+    }
+    progress.finish_and_clear();
+    by_queue.sort_by(|a, b| a.0.cmp(&b.0));
+
+    println!(
+        "{:<10} {:>5} {:>7} {:>8} {:>8} {:>12} {:>11} {:>14}",
+        "QUEUE",
+        "JOBS",
+        "FAILED",
+        "REQUEUED",
+        "SUCCESS",
+        "MEDIAN WAIT",
+        "CORE-HOURS",
+        "WALLTIME USED"
+    );
+    let print = |queue: &str, summary: &stats::Summary| {
+        let percent = |p: Option<f64>| p.map_or("-".to_string(), |p| format!("{:.0}%", p));
+        println!(
+            "{:<10} {:>5} {:>7} {:>8} {:>8} {:>12} {:>11.1} {:>14}",
+            queue,
+            summary.jobs,
+            summary.failed,
+            summary.requeued,
+            percent(summary.success_rate()),
+            summary
+                .median_wait
+                .map_or("-".to_string(), suggest::short_duration),
+            summary.core_hours,
+            percent(summary.median_walltime_used)
+        );
+    };
+    for (queue, jobs) in &by_queue {
+        print(queue, &stats::summarize(jobs));
+    }
+    if by_queue.len() > 1 {
+        print(
+            "all",
+            &stats::summarize(by_queue.iter().flat_map(|(_, jobs)| jobs)),
+        );
+    }
+    let unknown = by_queue
+        .iter()
+        .flat_map(|(_, jobs)| jobs)
+        .filter(|u| u.is_none())
+        .count();
+    if unknown > 0 {
+        log::info!(
+            "{} of {} jobs have no scheduler or accounting records and are only counted",
+            unknown,
+            records.len()
+        );
+    }
+    Ok(())
+}
+
+fn show_efficiency(args: &EfficiencyArgs) -> error::Result<()> {
+    let mut records = history_path()
+        .and_then(|path| history::read(&path))
+        .map_err(|e| Error::Io("reading history", e))?;
+    if !args.all_users {
+        records.retain(history::SubmissionRecord::is_mine);
+    }
+    if let Some(ref group) = args.group {
+        records.retain(|r| r.spec.job_group.as_ref() == Some(group));
+    }
+    if let Some(since) = args.since {
+        records.retain(|r| age(r.submitted_at) <= since);
+    }
+    if let Some(ref class) = args.class {
+        records.retain(|r| efficiency::class(&r.spec) == *class);
+    }
+    let progress = batch::progress_bar(records.len(), "looking up");
+    let mut by_class: std::collections::BTreeMap<String, Vec<efficiency::Run>> = Default::default();
+    for record in &records {
+        progress.inc(1);
+        if let Some(run) = efficiency::lookup(record) {
+            by_class
+                .entry(efficiency::class(&record.spec))
+                .or_default()
+                .push(run);
+        }
+    }
+    progress.finish_and_clear();
+    let runs: usize = by_class.values().map(Vec::len).sum();
+    if runs < records.len() {
+        log::info!(
+            "{} of {} jobs have no usage in the scheduler or accounting records and are left out",
+            records.len() - runs,
+            records.len()
+        );
+    }
+    if by_class.is_empty() {
+        return Err(Error::Usage("no finished jobs of the history match".into()));
+    }
+
+    println!(
+        "{:<16} {:>5} {:>9} {:>9} {:>9} {:>10} {:>10} {:>10}",
+        "CLASS", "RUNS", "MEM REQ", "MEM P95", "MEM PEAK", "WALL REQ", "WALL P95", "WALL PEAK"
+    );
+    let size = |s: Option<u64>| s.map_or("-".to_string(), pbs::format_size);
+    let duration = |d: Option<u64>| d.map_or("-".to_string(), suggest::short_duration);
+    let summaries: Vec<(&String, efficiency::Summary)> = by_class
+        .iter()
+        .map(|(class, runs)| (class, efficiency::summarize(runs)))
+        .collect();
+    for (class, summary) in &summaries {
+        println!(
+            "{:<16} {:>5} {:>9} {:>9} {:>9} {:>10} {:>10} {:>10}",
+            class,
+            summary.runs,
+            size(summary.requested_mem),
+            size(summary.p95_mem),
+            size(summary.peak_mem),
+            duration(summary.requested_walltime),
+            duration(summary.p95_walltime),
+            duration(summary.peak_walltime)
+        );
+    }
+    for (class, summary) in &summaries {
+        let advice = summary.advice();
+        if !advice.is_empty() {
+            println!("{}: {}", class, advice.join("; "));
+        }
+    }
+
+    let Some(ref preset) = args.preset else {
+        return Ok(());
+    };
+    let [(_, summary)] = summaries.as_slice() else {
+        let classes: Vec<&str> = summaries.iter().map(|(c, _)| c.as_str()).collect();
+        return Err(Error::Usage(format!(
+            "--preset needs the jobs of one class, not {}; choose one with --class",
+            classes.join(", ")
+        )));
+    };
+    let path = config::default_path()
+        .ok_or_else(|| Error::Usage("cannot locate the configuration; set QSUB2_CONFIG".into()))?;
+    let (mem, walltime) = summary.recommended();
+    for (key, value) in [("mem", mem), ("walltime", walltime)] {
+        if let Some(value) = value {
+            // quoted, so that 06:00:00 is not read as a TOML time
+            let value = toml::Value::String(value).to_string();
+            config::set(&path, &format!("preset.{}.{}", preset, key), &value)?;
+        }
+    }
+    log::info!(
+        "Saved the recommendation as [preset.{}] in {}; submit with --preset {}",
+        preset,
+        path.display(),
+        preset
+    );
+    Ok(())
+}
+
+fn show_accounting(args: &AccountingArgs) -> io::Result<()> {
+    let mut records = history::read(&history_path()?)?;
+    if !args.all_users {
+        records.retain(history::SubmissionRecord::is_mine);
+    }
+    if let Some(ref month) = args.month {
+        // a job cannot end before it was submitted
+        records.retain(|r| r.submitted_at.format("%Y-%m").to_string() <= *month);
+    }
+    let progress = batch::progress_bar(records.len(), "looking up");
+    let mut charges = Vec::new();
+    for record in &records {
+        progress.inc(1);
+        charges.extend(accounting::lookup(record));
+    }
+    progress.finish_and_clear();
+    charges.retain(|c| {
+        args.project.as_ref().is_none_or(|p| c.project == *p)
+            && args.month.as_ref().is_none_or(|m| c.month == *m)
+    });
+    let totals = accounting::totals(&charges);
+
+    let Some(format) = args.format else {
+        println!(
+            "{:<16} {:<7} {:>6} {:>7} {:>11} {:>10}",
+            "PROJECT", "MONTH", "JOBS", "FAILED", "CORE-HOURS", "GPU-HOURS"
+        );
+        let print = |total: &accounting::Total| {
+            println!(
+                "{:<16} {:<7} {:>6} {:>7} {:>11.1} {:>10.1}",
+                total.project,
+                total.month,
+                total.jobs,
+                total.failed,
+                total.core_hours,
+                total.gpu_hours
+            );
+        };
+        totals.iter().for_each(print);
+        if totals.len() > 1 {
+            print(&accounting::Total {
+                project: "all".to_string(),
+                month: String::new(),
+                jobs: totals.iter().map(|t| t.jobs).sum(),
+                failed: totals.iter().map(|t| t.failed).sum(),
+                core_hours: totals.iter().map(|t| t.core_hours).sum(),
+                gpu_hours: totals.iter().map(|t| t.gpu_hours).sum(),
+            });
+        }
+        return Ok(());
+    };
+
+    let separator = match format {
+        ExportFormat::Csv => ",",
+        ExportFormat::Tsv => "\t",
+    };
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "{}",
+        [
+            "project",
+            "month",
+            "jobs",
+            "failed",
+            "core_hours",
+            "gpu_hours"
+        ]
+        .join(separator)
+    )?;
+    for total in &totals {
+        let row = [
+            export_field(&total.project, format),
+            total.month.clone(),
+            total.jobs.to_string(),
+            total.failed.to_string(),
+            format!("{:.2}", total.core_hours),
+            format!("{:.2}", total.gpu_hours),
+        ];
+        writeln!(stdout, "{}", row.join(separator))?;
+    }
+    Ok(())
+}
+
+fn show_digest(args: &DigestArgs) -> io::Result<()> {
+    let mut records = history::read(&history_path()?)?;
+    records.retain(|r| r.is_mine() && age(r.submitted_at) <= args.since + digest::LOOKBACK);
+    let progress = batch::progress_bar(records.len(), "looking up");
+    let mut jobs = Vec::new();
+    for record in &records {
+        progress.inc(1);
+        let attributes = match report::usage_attributes(&record.job_id) {
+            Ok(attributes) => Some(attributes),
+            Err(_) => scheduler::job_status_with_history(&record.job_id)
+                .ok()
+                .flatten(),
+        };
+        let Some(job) = attributes.and_then(|a| digest::Finished::from_attributes(&a, record))
+        else {
+            continue;
+        };
+        if age(job.ended.unwrap_or(record.submitted_at)) <= args.since {
+            jobs.push(job);
+        }
+    }
+    progress.finish_and_clear();
+    if jobs.is_empty() {
+        log::info!(
+            "No jobs finished in the last {}",
+            suggest::short_duration(args.since.as_secs())
+        );
+        return Ok(());
+    }
+    let subject = digest::subject(&jobs);
+    let body = digest::body(&mut jobs, args.since);
+    match &args.email {
+        Some(address) => {
+            digest::mail(address, &subject, &body)?;
+            log::info!("Mailed the digest to {}", address);
+        }
+        None => print!("{}\n\n{}", subject, body),
+    }
+    Ok(())
+}
+
+/// `qsub2 <flags> <job options> <files>`, leaving out flags the job options already have.
+fn job_argv(flags: &[&str], job: &[String], files: &[PathBuf]) -> Vec<String> {
+    let mut argv = vec!["qsub2".to_string()];
+    for flag in flags {
+        let short = if *flag == "--submit" { "-s" } else { flag };
+        if !job.iter().any(|a| a == flag || a == short) {
+            argv.push(flag.to_string());
+        }
+    }
+    argv.extend(job.iter().cloned());
+    argv.extend(files.iter().map(|f| f.display().to_string()));
+    argv
+}
+
+/// Open `path` in `$VISUAL` / `$EDITOR` (default `vi`) and wait for it to close.
+fn open_in_editor(path: &Path) -> error::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let status = Command::new(words.next().unwrap_or("vi"))
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| Error::Io("running the editor", e))?;
+    if !status.success() {
+        return Err(Error::Usage(format!("the editor exited with {}", status)));
+    }
+    Ok(())
+}
+
+fn run_config_command(cli: &Cli, command: &ConfigCommand) -> error::Result<()> {
+    let path = config::default_path()
+        .ok_or_else(|| Error::Usage("cannot locate the configuration; set QSUB2_CONFIG".into()))?;
+    match command {
+        ConfigCommand::Show => {
+            println!("# {}", path.display());
+            if let Some(project) = std::env::current_dir()
+                .ok()
+                .and_then(|dir| config::find_project(&dir))
+            {
+                println!("# {}", project.display());
+            }
+            print!("{}", toml::to_string(&cli.config).unwrap_or_default());
+        }
+        ConfigCommand::Get { key } => match cli.config.get(key) {
+            Some(value) => println!("{}", value),
+            None => return Err(Error::Usage(format!("{} is not set", key))),
+        },
+        ConfigCommand::Set { key, value } => config::set(&path, key, value)?,
+        ConfigCommand::Edit => {
+            open_in_editor(&path)?;
+            config::Config::load(&path)?;
+        }
+        ConfigCommand::Validate { file } => {
+            let path = file.as_ref().unwrap_or(&path);
+            if !path.exists() {
+                return Err(Error::Config(path.clone(), "no such file".into()));
+            }
+            config::Config::load(path)?;
+            println!("{}: ok", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn write_nextflow_config(cli: &Cli, args: &NextflowArgs) -> error::Result<()> {
+    let mut argv = job_argv(&[], &args.job, &[]);
+    // Nextflow brings the command of each task
+    argv.push("nextflow".to_string());
+    let mut job_cli = Cli::try_parse_from(&argv).map_err(|e| Error::Usage(e.to_string()))?;
+    job_cli.config = cli.config.clone();
+    let mut spec = JobSpec::from_cli(&job_cli);
+    evaluate_expressions(&mut spec)?;
+    spec.validate()?;
+    let config = integrate::nextflow(&spec, &cli.config.submit_args, &args.executor);
+    match args.output {
+        Some(ref path) => fs::write(path, config).map_err(|e| Error::Render(path.clone(), e)),
+        None => io::stdout()
+            .write_all(config.as_bytes())
+            .map_err(|e| Error::Io("writing the configuration", e)),
+    }
+}
+
+fn export_pipeline(args: &PipelineExportArgs) -> error::Result<()> {
+    let records = read_history()?;
+    let jobs = pipeline::jobs(&records, &args.group);
+    if jobs.is_empty() {
+        return Err(Error::Usage(format!(
+            "no jobs in the history belong to group {}",
+            args.group
+        )));
+    }
+    let content = match args.format {
+        PipelineFormat::Makefile => pipeline::makefile(&jobs, &args.group),
+    };
+    match args.output {
+        Some(ref path) => fs::write(path, content).map_err(|e| Error::Render(path.clone(), e)),
+        None => io::stdout()
+            .write_all(content.as_bytes())
+            .map_err(|e| Error::Io("writing the Makefile", e)),
+    }
+}
+
+fn validate_specs(files: &[PathBuf]) -> error::Result<()> {
+    let mut invalid = 0;
+    for file in files {
+        let result = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|content| schema::read_spec(&content))
+            .and_then(|spec| spec.validate().map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => println!("{}: ok", file.display()),
+            Err(message) => {
+                log::error!("{}: {}", file.display(), message);
+                invalid += 1;
+            }
+        }
+    }
+    if invalid > 0 {
+        return Err(Error::InvalidResources(format!(
+            "{} of {} job specs are invalid",
+            invalid,
+            files.len()
+        )));
+    }
+    Ok(())
+}
+
+/// How many jobs `--submit` sends at once without asking.
+const CONFIRM_OVER: usize = 100;
+
+/// Before submitting more jobs than `confirm_over` at once, show what they
+/// ask for together and go on only if the answer is yes; `r` shows the
+/// scripts one at a time first. There are `subjobs` jobs (an array's) of
+/// each of `specs`, whose scripts `script` gives by index.
+fn confirm_bulk(
+    cli: &Cli,
+    specs: &[&JobSpec],
+    subjobs: usize,
+    script: impl Fn(usize) -> error::Result<String>,
+) -> error::Result<bool> {
+    let count = specs.len() * subjobs;
+    let limit = cli.config.confirm_over.unwrap_or(CONFIRM_OVER);
+    if cli.yes || count <= limit {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::Usage(format!(
+            "{} jobs are more than {} at once; submit them with --yes, or raise confirm_over",
+            count, limit
+        )));
+    }
+    let mut cpus = 0;
+    let mut core_hours = 0.0;
+    let mut queues: Vec<&str> = Vec::new();
+    for spec in specs {
+        let cores = cost::resources(spec).map_or(spec.ncpus as u64, |(cores, _)| cores);
+        let hours = pbs::parse_walltime(&spec.walltime).unwrap_or(0) as f64 / 3600.0;
+        cpus += cores * subjobs as u64;
+        core_hours += cores as f64 * hours * subjobs as f64;
+        if !queues.contains(&spec.queue.as_str()) {
+            queues.push(&spec.queue);
+        }
+    }
+    eprintln!(
+        "About to submit {} jobs to {} {}: {} cpus for up to {:.0} core-hours.",
+        count,
+        if queues.len() == 1 { "queue" } else { "queues" },
+        queues.join(", "),
+        cpus,
+        core_hours
+    );
+    let answer = |e| Error::Io("reading the answer", e);
+    loop {
+        match preview::ask("Submit them? [y/N, r to review the scripts]")
+            .map_err(answer)?
+            .as_str()
+        {
+            "y" | "yes" => return Ok(true),
+            "r" | "review" => {}
+            _ => return Ok(false),
+        }
+        for index in 0..specs.len() {
+            preview::print(&script(index)?);
+            let next = format!("Next script ({} of {})?", index + 2, specs.len());
+            if index + 1 == specs.len() || !preview::confirm(&next).map_err(answer)? {
+                break;
+            }
+        }
+    }
+}
+
+/// After Ctrl-C, cancel the job if asked to (or if the user says so), so
+/// trying things out does not leave jobs running for days.
+fn interrupted(job_id: &str, cancel: bool) -> Error {
+    eprintln!();
+    let cancel = cancel
+        || (io::stdin().is_terminal()
+            && preview::confirm(&format!("Cancel {}?", job_id)).unwrap_or(false));
+    if !cancel {
+        return Error::Interrupted(format!(
+            "{} is still queued or running; qsub2 cancel {} stops it",
+            job_id, job_id
+        ));
+    }
+    if let Err(e) = history::mark_cancelled(job_id) {
+        log::warn!("{} may be requeued as preempted: {}", job_id, e);
+    }
+    match scheduler::cancel(job_id) {
+        Ok(()) => Error::Interrupted(format!("cancelled {}", job_id)),
+        Err(e) => Error::Interrupted(format!("{} could not be cancelled: {}", job_id, e)),
+    }
+}
+
+/// `qsub2 run`: submit, report the state on stderr until the job starts,
+/// then copy its stdout and stderr to ours and take over its exit status.
+fn run_attached(cli: &Cli, args: &RunArgs) -> error::Result<()> {
+    let argv = job_argv(&["--submit"], &args.job, &[]);
+    let mut job_cli = Cli::try_parse_from(&argv).unwrap_or_else(|e| e.exit());
+    if job_cli.per_file {
+        return Err(Error::Usage(
+            "qsub2 run submits a single job; leave out --per-file".into(),
+        ));
+    }
+    job_cli.invocation = argv;
+    job_cli.config = cli.config.clone();
+    job_cli.quiet = true;
+    job_cli.attached = true;
+    // Caught before submitting: a Ctrl-C while qsub runs would otherwise end
+    // qsub2 between the submission and the job ID, leaving the job unwatched.
+    // qsub itself runs in its own process group, which the terminal's
+    // Ctrl-C does not reach (`attached`).
+    interrupt::catch().map_err(|e| Error::Io("setting up Ctrl-C", e))?;
+    run(&job_cli)?;
+    let job_id = job_cli
+        .submitted
+        .get()
+        .cloned()
+        .ok_or_else(|| Error::Usage("no job was submitted".into()))?;
+    let stopped = || interrupted(&job_id, args.cancel_on_interrupt);
+    if interrupt::requested() {
+        return Err(stopped());
+    }
+
+    let query = |e: io::Error| match e.kind() {
+        io::ErrorKind::Interrupted => stopped(),
+        _ => Error::Io("following the job", e),
+    };
+    let mut last_state = String::new();
+    let mut report = |attributes: &Option<pbs::JobAttributes>| {
+        let state = attributes
+            .as_ref()
+            .and_then(|a| a.get("job_state"))
+            .map_or("F", String::as_str)
+            .to_string();
+        if state != last_state {
+            log::info!("{} is {}", job_id, pbs::state_name(&state));
+            last_state = state;
+        }
+        attributes.as_ref().is_none_or(pbs::is_finished)
+    };
+    loop {
+        let attributes = scheduler::job_status(&job_id).map_err(query)?;
+        let started = attributes.as_ref().is_some_and(|a| {
+            a.get("job_state")
+                .is_some_and(|s| s != "Q" && s != "H" && s != "W")
+        });
+        if report(&attributes) || started {
+            break;
+        }
+        interrupt::sleep(args.interval);
+        if interrupt::requested() {
+            return Err(stopped());
+        }
+    }
+
+    let stdout = logs::locate(&job_id, false).map_err(query)?;
+    let stderr = logs::locate(&job_id, true).map_err(query)?;
+    let finished = || Ok(report(&scheduler::job_status(&job_id)?));
+    match args.peek {
+        Some(ref peek) if !stdout.exists() => logs::follow_command(peek, &job_id, finished),
+        // joined streams (-j oe) share one file
+        _ if stdout == stderr => logs::follow(&stdout, finished),
+        _ => logs::follow_all(&[(&stdout, false), (&stderr, true)], finished),
+    }
+    .map_err(query)?;
+
+    let exit_status = scheduler::job_status_with_history(&job_id)
+        .ok()
+        .flatten()
+        .or_else(|| report::usage_attributes(&job_id).ok())
+        .and_then(|a| a.get("Exit_status")?.parse::<i32>().ok());
+    match exit_status {
+        Some(0) => Ok(()),
+        Some(status) => Err(Error::JobFailed(status)),
+        None => {
+            log::warn!("the exit status of {} is not known", job_id);
+            Ok(())
+        }
+    }
+}
+
+/// Parse and run one submission on behalf of `daemon` or `schedule`.
+fn run_job(cli: &Cli, argv: Vec<String>) {
+    let mut job_cli = match Cli::try_parse_from(&argv) {
+        Ok(job_cli) => job_cli,
+        Err(e) => return log::error!("{}", e),
+    };
+    job_cli.invocation = argv;
+    job_cli.config = cli.config.clone();
+    job_cli.quiet |= cli.quiet;
+    if let Err(e) = run(&job_cli) {
+        log::error!("{}", e);
+    }
+}
+
+const DAEMON_FLAGS: &[&str] = &["--per-file", "--submit"];
+/// A recurring job is by design a duplicate of the last one.
+const SCHEDULE_FLAGS: &[&str] = &["--submit", "--allow-duplicate"];
+
+fn run_daemon(cli: &Cli, args: &DaemonArgs) -> error::Result<()> {
+    // Catch mistakes in the job options now rather than at the first file.
+    let probe = job_argv(DAEMON_FLAGS, &args.job, &[PathBuf::from("FILE")]);
+    if let Err(e) = Cli::try_parse_from(probe) {
+        e.exit();
+    }
+    daemon::watch(&args.watch, args.interval, args.existing, |files| {
+        run_job(cli, job_argv(DAEMON_FLAGS, &args.job, &files))
+    })
+    .map_err(|e| Error::Io("watching the directory", e))
+}
+
+fn self_update(args: &SelfUpdateArgs) -> io::Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = selfupdate::latest()?;
+    if !selfupdate::is_newer(release.version(), current) {
+        log::info!("qsub2 {} is the latest release", current);
+        return Ok(());
+    }
+    if args.check {
+        println!(
+            "qsub2 {} is available (this is {}); run qsub2 self-update",
+            release.version(),
+            current
+        );
+        return Ok(());
+    }
+    let exe = std::env::current_exe()?;
+    selfupdate::install(&release, &exe)?;
+    log::info!(
+        "Updated {} from {} to {}",
+        exe.display(),
+        current,
+        release.version()
+    );
+    Ok(())
+}
+
+fn run_serve(cli: &Cli, args: &ServeArgs) -> error::Result<()> {
+    history::keep_open();
+    if let Some(ref socket) = args.socket {
+        return serve::serve_socket(socket, |request| api_response(cli, request))
+            .map_err(|e| Error::Io("serving the API", e));
+    }
+    let token_file = match args.token_file {
+        Some(ref path) => path.clone(),
+        None => history::data_dir()
+            .ok_or_else(|| Error::Usage("cannot locate the data directory; set HOME".into()))?
+            .join("serve.token"),
+    };
+    let token = serve::new_token()
+        .and_then(|token| serve::write_token(&token_file, &token).map(|()| token))
+        .map_err(|e| Error::Io("writing the token", e))?;
+    eprintln!(
+        "Send Authorization: Bearer $(cat {}) with each request",
+        token_file.display()
+    );
+    serve::serve(&args.listen, &token, |request| api_response(cli, request))
+        .map_err(|e| Error::Io("serving the API", e))
+}
+
+/// Refuse a `template` sent to the API that is not in the template
+/// directory, so that the API does not read any file its owner can.
+fn check_api_template(cli: &Cli, template: &Path) -> error::Result<()> {
+    let inside = cli
+        .config
+        .template_dir()
+        .and_then(|dir| dir.canonicalize().ok())
+        .zip(template.canonicalize().ok())
+        .is_some_and(|(dir, template)| template.starts_with(dir));
+    match inside {
+        true => Ok(()),
+        false => Err(Error::Usage(format!(
+            "template {} is not in the template directory",
+            template.display()
+        ))),
+    }
+}
+
+/// A job spec sent to the API, checked as `qsub2 spec validate` does; what
+/// it leaves out is what `qsub2` would use without options.
+fn api_spec(cli: &Cli, body: &str) -> error::Result<JobSpec> {
+    let given: serde_json::Value =
+        serde_json::from_str(body).map_err(|e| Error::Usage(e.to_string()))?;
+    let serde_json::Value::Object(given) = given else {
+        return Err(Error::Usage("a job spec is a JSON object".to_string()));
+    };
+    let template_given = given.get("template").is_some_and(|t| !t.is_null());
+    let mut spec = serde_json::to_value(JobSpec::from_cli(cli)).unwrap_or_default();
+    if let Some(spec) = spec.as_object_mut() {
+        spec.extend(given);
+    }
+    let mut spec = schema::read_spec(&spec.to_string()).map_err(Error::Usage)?;
+    spec.template = spec.template.map(|t| cli.config.resolve_template(&t));
+    if let (true, Some(template)) = (template_given, &spec.template) {
+        check_api_template(cli, template)?;
+    }
+    evaluate_expressions(&mut spec)?;
+    spec.validate()?;
+    check_against_cluster(cli, &spec)?;
+    Ok(spec)
+}
+
+fn api_status(error: &Error) -> u16 {
+    match error {
+        Error::Usage(_) => 400,
+        Error::Duplicate(_) => 409,
+        Error::QsubMissing(_) | Error::QsubRejected(_) => 502,
+        Error::Io(..) => 500,
+        _ => 422,
+    }
+}
+
+fn api_response(cli: &Cli, request: &serve::Request) -> serve::Response {
+    use serde_json::json;
+    let endpoint = match serve::endpoint(request) {
+        Ok(endpoint) => endpoint,
+        Err(response) => return response,
+    };
+    let result = match endpoint {
+        serve::Endpoint::Render => api_spec(cli, &request.body)
+            .and_then(|spec| render_job_script(&spec))
+            .map(|script| json!({ "script": script })),
+        serve::Endpoint::Submit => api_spec(cli, &request.body)
+            .and_then(|spec| {
+                let script = render_job_script(&spec)?;
+                submit_and_record(cli, &spec, &script, None, &[], Local::now())
+            })
+            .map(|job_id| json!({ "job_id": job_id })),
+        serve::Endpoint::Scripts => serde_json::from_str(&request.body)
+            .map_err(|e| Error::Usage(e.to_string()))
+            .and_then(|mut submission: ScriptSubmission| {
+                submission.user = current_user();
+                submit_script(cli, &submission)
+            })
+            .map(|record| json!(record)),
+        serve::Endpoint::Jobs => read_history().map(|records| {
+            records
+                .iter()
+                .map(|r| {
+                    json!({
+                        "job_id": r.job_id,
+                        "name": r.spec.name,
+                        "queue": r.spec.queue,
+                        "submitted_at": r.submitted_at.to_rfc3339(),
+                    })
+                })
+                .collect()
+        }),
+        serve::Endpoint::Job(job_id) => {
+            let state = history::resolve_state(job_id);
+            Ok(json!({
+                "job_id": job_id,
+                "state": state.state,
+                "exit_status": state.exit_status,
+                "requeues": state.requeues,
+            }))
+        }
+        serve::Endpoint::Cancel(job_id) => {
+            if let Err(e) = history::mark_cancelled(job_id) {
+                log::warn!("{} may be requeued as preempted: {}", job_id, e);
+            }
+            scheduler::cancel(job_id)
+                .map(|()| json!({ "job_id": job_id, "cancelled": true }))
+                .map_err(|e| Error::QsubRejected(e.to_string()))
+        }
+    };
+    match result {
+        Ok(value) => serve::Response::json(200, &value),
+        Err(e) => serve::Response::error(api_status(&e), &e.to_string()),
+    }
+}
+
+fn run_schedule(cli: &Cli, args: &ScheduleArgs) -> error::Result<()> {
+    let argv = job_argv(SCHEDULE_FLAGS, &args.job, &[]);
+    if let Err(e) = Cli::try_parse_from(&argv) {
+        e.exit();
+    }
+    if args.crontab {
+        let timing = schedule::crontab_timing(args.at, args.every).map_err(Error::Usage)?;
+        let exe = std::env::current_exe().map_err(|e| Error::Io("locating qsub2", e))?;
+        let cwd = std::env::current_dir().map_err(|e| Error::Io("reading the directory", e))?;
+        let command: Vec<String> = std::iter::once(exe.display().to_string())
+            .chain(argv[1..].iter().cloned())
+            .map(|a| shell_quote(&a))
+            .collect();
+        let line = format!(
+            "cd {} && {}",
+            shell_quote(&cwd.display().to_string()),
+            command.join(" ")
+        );
+        // cron turns an unescaped % into a newline
+        println!("{} {}", timing, line.replace('%', "\\%"));
+        return Ok(());
+    }
+    loop {
+        let next = schedule::next_run(Local::now(), args.at, args.every);
+        log::info!("Next submission at {}", next.format("%Y-%m-%d %H:%M"));
+        thread::sleep(time_until(next));
+        run_job(cli, argv.clone());
+    }
+}
+
+/// Time left until `time`; zero for times in the past.
+fn time_until(time: chrono::DateTime<Local>) -> Duration {
+    (time - Local::now()).to_std().unwrap_or_default()
+}
+
+/// The `qsub -I` arguments for an interactive session.
+fn interactive_args(args: &InteractiveArgs) -> Vec<String> {
+    let mut chunk = select::SelectChunk::new(1).ncpus(args.ncpus);
+    if let Some(ref mem) = args.mem {
+        chunk = chunk.mem(mem);
+    }
+    let mut qsub_args = vec![
+        "-I".to_string(),
+        "-l".to_string(),
+        format!("select={}", chunk),
+    ];
+    if args.x11 {
+        qsub_args.push("-X".to_string());
+    }
+    if let Some(ref walltime) = args.walltime {
+        qsub_args.extend(["-l".to_string(), format!("walltime={}", walltime)]);
+    }
+    if let Some(ref queue) = args.queue {
+        qsub_args.extend(["-q".to_string(), queue.clone()]);
+    }
+    if let Some(ref name) = args.name {
+        qsub_args.extend(["-N".to_string(), name.clone()]);
+    }
+    qsub_args
+}
+
+/// Hand the terminal over to `qsub -I`; on Unix qsub2 is replaced by it.
+fn run_interactive(cli: &Cli, args: &InteractiveArgs) -> error::Result<()> {
+    let qsub = cli.config.qsub_path.as_deref().unwrap_or(Path::new("qsub"));
+    let qsub_args = interactive_args(args);
+    log::debug!(
+        "running: {} {}",
+        qsub.display(),
+        cli.config
+            .submit_args
+            .iter()
+            .chain(&qsub_args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let mut command = Command::new(qsub);
+    command.args(&cli.config.submit_args).args(&qsub_args);
+    let to_error = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => Error::QsubMissing(e),
+        _ => Error::Io("running qsub", e),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(to_error(command.exec()))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command.status().map_err(to_error)?;
+        if !status.success() {
+            return Err(Error::QsubRejected(status.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Size mem and walltime from `--mem-rule` / `--walltime-rule` unless they were given explicitly.
+fn apply_scaling(cli: &Cli, spec: &mut JobSpec, files: &[PathBuf]) -> error::Result<()> {
+    let scales_mem = cli.mem.is_none() && cli.mem_rule.is_some();
+    let scales_walltime = cli.walltime.is_none() && cli.walltime_rule.is_some();
+    if !scales_mem && !scales_walltime {
+        return Ok(());
+    }
+    let mut sizes = Vec::new();
+    for file in files {
+        sizes.push(
+            fs::metadata(file)
+                .map_err(|e| {
+                    let e = io::Error::new(e.kind(), format!("{}: {}", file.display(), e));
+                    Error::Io("reading the input files", e)
+                })?
+                .len(),
+        );
+    }
+    // each subjob of an --array has one of the files
+    let input_bytes = match cli.array {
+        true => sizes.into_iter().max().unwrap_or(0),
+        false => sizes.into_iter().sum(),
+    };
+    if let (None, Some(rule)) = (&cli.mem, &cli.mem_rule) {
+        spec.mem = Some(scaling::mem_request(rule.evaluate(input_bytes)));
+    }
+    if let (None, Some(rule)) = (&cli.walltime, &cli.walltime_rule) {
+        spec.walltime = scaling::walltime_request(rule.evaluate(input_bytes));
+    }
+    Ok(())
+}
+
+/// Print what similar jobs used before and, with `--auto-size`, request that instead.
+fn apply_past_usage(cli: &Cli, spec: &mut JobSpec) {
+    let Some(records) = history::default_path().and_then(|p| history::read(&p).ok()) else {
+        return;
+    };
+    let usage = suggest::past_usage(&records, spec);
+    if usage.runs == 0 {
+        return;
+    }
+    log::info!("Hint: {}", suggest::hint(&usage, spec));
+    if cli.auto_size {
+        let (mem, walltime) = suggest::suggested(&usage);
+        if mem.is_some() {
+            spec.mem = mem;
+        }
+        if let Some(walltime) = walltime {
+            spec.walltime = walltime;
+        }
+        log::info!(
+            "Auto-size: requesting mem={} walltime={}",
+            spec.mem.as_deref().unwrap_or("default"),
+            spec.walltime
+        );
+    }
+}
+
+/// With `--preflight`, check the working directory and `dirs` before submitting.
+fn run_preflight(cli: &Cli, dirs: Vec<PathBuf>) -> error::Result<()> {
+    let min_free = match (cli.min_free, &cli.config.min_free_space) {
+        (Some(min_free), _) => Some(min_free),
+        (None, Some(size)) => Some(
+            inputs::parse_min_size(size)
+                .map_err(|e| Error::Usage(format!("min_free_space: {}", e)))?,
+        ),
+        (None, None) => None,
+    };
+    if !(cli.preflight || cli.config.preflight || cli.min_free.is_some()) {
+        return Ok(());
+    }
+    let workdir =
+        std::env::current_dir().map_err(|e| Error::Io("finding the working directory", e))?;
+    let dirs: Vec<PathBuf> = std::iter::once(workdir).chain(dirs).collect();
+    preflight::check(&dirs, min_free).map_err(Error::Preflight)
+}
+
+/// The estimate for `--cost`, after refusing jobs over `--max-cost` or `cost.max`.
+fn check_cost(cli: &Cli, spec: &JobSpec) -> error::Result<Option<String>> {
+    let rates = &cli.config.cost;
+    let max_cost = cli.max_cost.or(rates.max);
+    if !cli.cost && max_cost.is_none() {
+        return Ok(None);
+    }
+    let estimate = cost::estimate(spec, rates).map_err(Error::Usage)?;
+    let description = format!("{} ({})", cost::amount(estimate.cost, rates), estimate);
+    if let Some(max_cost) = max_cost.filter(|&max| estimate.cost > max) {
+        return Err(Error::TooExpensive(format!(
+            "{} can cost up to {}, more than the limit of {}",
+            spec.name,
+            description,
+            cost::amount(max_cost, rates)
+        )));
+    }
+    Ok(Some(description))
+}
+
+/// ` by alice` for a job someone else submitted to a shared history.
+fn submitted_by(records: &[history::SubmissionRecord], job_id: &str) -> String {
+    records
+        .iter()
+        .rev()
+        .find(|r| r.job_id == job_id)
+        .filter(|r| !r.is_mine())
+        .and_then(|r| r.user.as_ref())
+        .map_or(String::new(), |user| format!(" by {}", user))
+}
+
+/// Refuse (or with `--allow-duplicate`, warn about) resubmitting a job identical
+/// to one that is still queued or was submitted recently.
+fn check_duplicate(fingerprint: &str, allow_duplicate: bool) -> error::Result<()> {
+    let message = |records: &[history::SubmissionRecord]| {
+        Some(match history::find_duplicate(records, fingerprint)? {
+            history::Duplicate::Active(job_id) => format!(
+                "an identical job is already queued or running as {}{}",
+                job_id,
+                submitted_by(records, &job_id)
+            ),
+            history::Duplicate::Recent(job_id) => format!(
+                "an identical job was submitted recently as {}{}",
+                job_id,
+                submitted_by(records, &job_id)
+            ),
+        })
+    };
+    // the daemon keeps thousands of records, which are not copied for this
+    let message = history::default_path().and_then(|p| history::with_records(&p, message).ok());
+    let Some(message) = message.flatten() else {
+        return Ok(());
+    };
+    if allow_duplicate {
+        log::warn!("{}; submitting anyway", message);
+        Ok(())
+    } else {
+        Err(Error::Duplicate(format!(
+            "{}. Use --allow-duplicate to submit it again.",
+            message
+        )))
+    }
+}
+
+/// Submit the rendered script and add it to the history. Without a script
+/// file (`--no-script`) the history keeps the script content instead.
+fn submit_and_record(
+    cli: &Cli,
+    spec: &JobSpec,
+    script: &str,
+    outfile: Option<&Path>,
+    files: &[PathBuf],
+    generated_at: chrono::DateTime<Local>,
+) -> error::Result<String> {
+    let submission = ScriptSubmission {
+        spec: spec.clone(),
+        script: script.to_string(),
+        outfile: outfile.map(Path::to_path_buf),
+        files: files.to_vec(),
+        generated_at,
+        invocation: cli.invocation.clone(),
+        workdir: std::env::current_dir().ok(),
+        user: current_user(),
+        allow_duplicate: cli.allow_duplicate,
+    };
+    let record = match submit_through_daemon(cli, &submission) {
+        Some(record) => record?,
+        None => submit_script(cli, &submission)?,
+    };
+    let job_id = record.job_id.clone();
+    if let Some(ref log) = cli.submission_log {
+        if let Err(e) = history::append(log, &record) {
+            log::error!("writing submission log: {}", e);
+        }
+    }
+    if let (Some(script), true) = (outfile, cli.sidecar || cli.config.sidecar) {
+        let sidecar = sidecar::Sidecar {
+            script,
+            spec,
+            files,
+            generated_at,
+            job_id: Some(&job_id),
+            submitted_at: Some(record.submitted_at),
+        };
+        if let Err(e) = sidecar.write() {
+            log::warn!(
+                "could not add the job ID to {}: {}",
+                sidecar::path(script).display(),
+                e
+            );
+        }
+    }
+    if let Some(ref hook) = cli.config.post_submit {
+        let input = serde_json::to_string(&record).unwrap_or_default();
+        match hooks::run("post_submit", hook, &input) {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("post_submit hook failed for {}: {}", job_id, status),
+            Err(e) => log::warn!("{}", e),
+        }
+    }
+    Ok(job_id)
+}
+
+/// Generate (and with `--submit`, submit) one job per input file. A failing file
+/// does not stop the others; they are listed at the end.
+fn run_per_file(cli: &Cli, spec: &JobSpec) -> error::Result<()> {
+    let mut spec = spec.clone();
+    if spec.job_group.is_none() {
+        spec.job_group = Some(format!("batch-{}", Local::now().format("%Y%m%d-%H%M%S")));
+    }
+    let spec = &spec;
+    let overrides = match cli.resources {
+        Some(ref path) => Some(
+            batch::Overrides::read(path).map_err(|e| Error::Io("reading the resource table", e))?,
+        ),
+        None => None,
+    };
+    let script_base = cli
+        .outfile
+        .clone()
+        .unwrap_or_else(|| default_script_path(cli, &spec.name));
+    let mut inputs = match cli.paired {
+        Some(ref markers) => batch::parse_markers(markers)
+            .and_then(|markers| batch::pair(&cli.files, &markers))
+            .map_err(Error::Usage)?,
+        None => cli
+            .files
+            .iter()
+            .map(|f| (f.clone(), vec![f.clone()]))
+            .collect(),
+    };
+    // the job may not start where qsub2 ran, so the command gets absolute paths
+    for (_, files) in &mut inputs {
+        for file in files.iter_mut() {
+            if let Ok(absolute) = std::path::absolute(&*file) {
+                *file = absolute;
+            }
+        }
+    }
+    let jobs = batch::per_file(spec, &inputs, &script_base, cli.output_pattern.as_deref())
+        .map_err(Error::Usage)?;
+    let skip_existing = cli
+        .skip_existing
+        .as_ref()
+        .map(|pattern| match cli.output_pattern {
+            Some(ref output) => pattern.replace("{output}", output),
+            None => pattern.clone(),
+        });
+
+    let progress = batch::progress_bar(jobs.len(), "generating");
+    let mut generated = Vec::new();
+    let mut failures: Vec<(PathBuf, String)> = Vec::new();
+    let mut skipped = 0;
+    for mut job in jobs {
+        progress.inc(1);
+        if let Some(ref pattern) = skip_existing {
+            if let Some(output) = batch::up_to_date_output(pattern, &job.file, &job.files) {
+                progress.suspend(|| {
+                    log::info!(
+                        "Skipping {}: {} is up to date",
+                        job.file.display(),
+                        output.display()
+                    )
+                });
+                skipped += 1;
+                continue;
+            }
+        }
+        if let Err(e) = apply_scaling(cli, &mut job.spec, &job.files) {
+            failures.push((job.file, e.to_string()));
+            continue;
+        }
+        if let Some(row) = overrides.as_ref().and_then(|o| o.get(&job.file)) {
+            row.apply(&mut job.spec);
+        }
+        let hashed = naming::has_hash(&job.outfile);
+        if hashed {
+            match render_job_script(&job.spec) {
+                Ok(script) => {
+                    let hash = history::fingerprint(&script, &job.files);
+                    job.outfile = naming::fill_in_hash(&job.outfile, &hash);
+                }
+                Err(e) => {
+                    failures.push((job.file, e.to_string()));
+                    continue;
+                }
+            }
+        }
+        let generated_at = Local::now();
+        if let Err(e) = job
+            .spec
+            .validate()
+            .and_then(|()| check_cost(cli, &job.spec).map(|_| ()))
+            .and_then(|()| {
+                if !cli.lint {
+                    return Ok(());
+                }
+                let script = render_job_script(&job.spec)?;
+                progress.suspend(|| lint_script(&script, &job.outfile.to_string_lossy()))
+            })
+            .and_then(|()| match hashed {
+                true => create_log_dir(&job.spec).and_then(|()| {
+                    write_hashed_script(&job.outfile, &render_job_script(&job.spec)?)
+                }),
+                false => generate_job_script(&job.spec, &job.outfile, cli.backup),
+            })
+            .and_then(|()| write_sidecar(cli, &job.outfile, &job.spec, &job.files, generated_at))
+        {
+            failures.push((job.file, e.to_string()));
+            continue;
+        }
+        log::debug!(
+            "{}: {}",
+            job.file.display(),
+            serde_json::to_string(&job.spec).unwrap_or_default()
+        );
+        generated.push((job, generated_at));
+    }
+    progress.finish_and_clear();
+
+    if cli.submit && !generated.is_empty() {
+        let mut dirs = Vec::new();
+        for (job, _) in &generated {
+            dirs.extend(job.spec.log_dir());
+            if let Some(ref pattern) = cli.output_pattern {
+                let output = batch::output_path(pattern, &job.file);
+                dirs.extend(output.parent().map(Path::to_path_buf));
+            }
+        }
+        run_preflight(cli, dirs)?;
+        let specs: Vec<&JobSpec> = generated.iter().map(|(job, _)| &job.spec).collect();
+        let script = |index: usize| {
+            let path = &generated[index].0.outfile;
+            fs::read_to_string(path).map_err(|e| Error::Render(path.clone(), e))
+        };
+        if !confirm_bulk(cli, &specs, 1, script)? {
+            eprintln!("Not submitted; the scripts are kept.");
+            return Ok(());
+        }
+    }
+    let results = if cli.submit {
+        submit_generated(cli, &generated)
+    } else {
+        generated.iter().map(|_| None).collect()
+    };
+
+    if !cli.quiet {
+        println!("{:<24} {:<32} JOB ID", "FILE", "SCRIPT");
+    }
+    for ((job, _), result) in generated.iter().zip(&results) {
+        let job_id = match result {
+            Some(Ok(job_id)) => job_id.clone(),
+            Some(Err(e)) => {
+                failures.push((job.file.clone(), e.to_string()));
+                "failed".to_string()
+            }
+            None if cli.submit => {
+                failures.push((job.file.clone(), "not submitted".to_string()));
+                "not submitted".to_string()
+            }
+            None => "-".to_string(),
+        };
+        if !cli.quiet {
+            let job_style = match result {
+                Some(Ok(_)) => style::JOB_ID,
+                Some(Err(_)) | None if cli.submit => style::ERROR,
+                _ => anstyle::Style::new(),
+            };
+            anstream::println!(
+                "{:<24} {:<32} {}{}{}",
+                job.file.display(),
+                job.outfile.display(),
+                job_style.render(),
+                job_id,
+                job_style.render_reset()
+            );
+        } else if let Some(Ok(job_id)) = result {
+            println!("{}", job_id);
+        }
+    }
+    let job_ids: Vec<String> = results
+        .iter()
+        .filter_map(|r| r.as_ref()?.as_ref().ok().cloned())
+        .collect();
+    if cli.notify_when_done && !job_ids.is_empty() {
+        match submit_summary(cli, spec, &job_ids) {
+            Ok(job_id) => log::info!("Summary job {} mails you when the batch ends", job_id),
+            Err(e) => log::error!("submitting the summary job: {}", e),
+        }
+    }
+    let submitted = job_ids.len();
+    log::info!(
+        "{} generated, {} submitted, {} failed, {} skipped",
+        generated.len(),
+        submitted,
+        failures.len(),
+        skipped
+    );
+    if let (true, Some(group)) = (submitted > 0, &spec.job_group) {
+        log::info!("Group {}: see qsub2 status --group {}", group, group);
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    eprintln!("{} of {} files failed:", failures.len(), inputs.len());
+    for (file, error) in &failures {
+        eprintln!("  {}: {}", file.display(), error);
+    }
+    let retry: Vec<String> = failures
+        .iter()
+        .flat_map(
+            |(file, _)| match inputs.iter().find(|(named, _)| named == file) {
+                Some((_, files)) => files.clone(),
+                None => vec![file.clone()],
+            },
+        )
+        .map(|file| file.display().to_string())
+        .collect();
+    eprintln!(
+        "To retry, rerun the same command with only these files: {}",
+        retry.join(" ")
+    );
+    Err(Error::BatchFailed(failures.len()))
+}
+
+/// Submit the generated per-file scripts, honouring `--max-queued`, `--delay`/`--rate` and `--parallel`.
+/// With `--max-parallel N` they go one at a time, each made to depend on the
+/// job N places before it, so the scheduler never runs more than N of them.
+fn submit_generated(
+    cli: &Cli,
+    generated: &[(batch::Job, chrono::DateTime<Local>)],
+) -> Vec<Option<error::Result<String>>> {
+    let progress = batch::progress_bar(generated.len(), "submitting");
+    let failed = AtomicUsize::new(0);
+    let user = current_user();
+    let pace = cli.delay.or(cli.rate);
+    let mut last_submission: Option<Instant> = None;
+    let before_each = || {
+        if let Some(max_queued) = cli.max_queued {
+            let waiting = |active| {
+                progress.suspend(|| {
+                    log::info!(
+                        "{} {} queued or running (limit {}); waiting before the next submission",
+                        active,
+                        if active == 1 { "job" } else { "jobs" },
+                        max_queued
+                    )
+                })
+            };
+            if let Err(e) = batch::wait_for_room(&user, max_queued as usize, waiting) {
+                progress.suspend(|| log::error!("checking the queue: {}", e));
+                return false;
+            }
+        }
+        if let (Some(pace), Some(last)) = (pace, last_submission) {
+            thread::sleep(pace.saturating_sub(last.elapsed()));
+        }
+        last_submission = Some(Instant::now());
+        true
+    };
+    let job_ids: std::sync::Mutex<Vec<Option<String>>> =
+        std::sync::Mutex::new(vec![None; generated.len()]);
+    let indexed: Vec<(usize, &(batch::Job, chrono::DateTime<Local>))> =
+        generated.iter().enumerate().collect();
+    let workers = match cli.max_parallel {
+        Some(_) => 1,
+        None => cli.parallel,
+    };
+    let results = batch::submit_all(
+        &indexed,
+        workers,
+        before_each,
+        |&(index, (job, generated_at))| {
+            let window = cli.max_parallel.map_or(usize::MAX, |n| n as usize);
+            let after = index
+                .checked_sub(window)
+                .map(|before| job_ids.lock().unwrap()[before].clone());
+            let chained = match after {
+                // PBS refuses a dependency on a job it no longer has or that has ended
+                Some(Some(before)) if scheduler::has_ended(&before) => Ok(None),
+                Some(Some(before)) => chain_behind(job, &before).map(Some),
+                Some(None) => {
+                    progress.suspend(|| {
+                        log::warn!(
+                            "{} does not wait for another job: the one before it was not submitted",
+                            job.file.display()
+                        )
+                    });
+                    Ok(None)
+                }
+                None => Ok(None),
+            };
+            let result = chained.and_then(|spec| {
+                let script = fs::read_to_string(&job.outfile)
+                    .map_err(|e| Error::Render(job.outfile.clone(), e))?;
+                submit_and_record(
+                    cli,
+                    spec.as_ref().unwrap_or(&job.spec),
+                    &script,
+                    Some(&job.outfile),
+                    &job.files,
+                    *generated_at,
+                )
+            });
+            if let Ok(ref job_id) = result {
+                job_ids.lock().unwrap()[index] = Some(job_id.clone());
+            }
+            if result.is_err() {
+                let failed = failed.fetch_add(1, Ordering::Relaxed) + 1;
+                progress.set_message(format!("submitting, {} failed", failed));
+            }
+            progress.inc(1);
+            result
+        },
+    );
+    progress.finish_and_clear();
+    results
+}
+
+/// `--notify-when-done`: submit the summary job of the batch `job_ids`. It
+/// waits only for the jobs still on the server, at most
+/// [`batch::DEPEND_CHUNK`] at a time: the rest go to small jobs it waits for
+/// in a chain, since one dependency list holding the whole of a large batch
+/// is longer than the server takes.
+fn submit_summary(cli: &Cli, spec: &JobSpec, job_ids: &[String]) -> error::Result<String> {
+    let submit = |job: JobSpec| {
+        let script = render_job_script(&job)?;
+        submit_and_record(cli, &job, &script, None, &[], Local::now())
+    };
+    let unfinished: Vec<String> = job_ids
+        .iter()
+        .filter(|id| !scheduler::has_ended(id))
+        .cloned()
+        .collect();
+    let mut chunks: Vec<&[String]> = unfinished.chunks(batch::DEPEND_CHUNK).collect();
+    let last = chunks.pop().unwrap_or_default();
+    let mut before: Option<String> = None;
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let targets = before.into_iter().chain(chunk.iter().cloned()).collect();
+        before = Some(submit(batch::gate_job(spec, i + 1, targets))?);
+    }
+    let targets = before.into_iter().chain(last.iter().cloned()).collect();
+    submit(batch::summary_job(spec, job_ids, targets))
+}
+
+/// `--max-parallel`: make `job` wait for the job `before`, still on the
+/// server, to end, writing its script again with the extra dependency. That
+/// changes its content, which is why `--name-from-hash` cannot be combined
+/// with `--max-parallel`.
+fn chain_behind(job: &batch::Job, before: &str) -> error::Result<JobSpec> {
+    let mut spec = job.spec.clone();
+    spec.depend.push(Dependency {
+        kind: "afterany".to_string(),
+        targets: vec![before.to_string()],
+    });
+    let script = render_job_script(&spec)?;
+    fs::write(&job.outfile, script).map_err(|e| Error::Render(job.outfile.clone(), e))?;
+    Ok(spec)
+}
+
+/// The walltime of one `--segments` job: `--segment-walltime`, or the limit
+/// of the job's queue.
+fn segment_limit(cli: &Cli, spec: &JobSpec) -> error::Result<u64> {
+    if let Some(ref walltime) = cli.segment_walltime {
+        return pbs::parse_walltime(walltime)
+            .filter(|&w| w > 0)
+            .ok_or_else(|| Error::Usage(format!("--segment-walltime {} is empty", walltime)));
+    }
+    cluster_snapshot(cli)?
+        .queue(&spec.queue)
+        .and_then(|q| q.max_walltime)
+        .filter(|&w| w > 0)
+        .ok_or_else(|| {
+            Error::Usage(format!(
+                "queue {} has no walltime limit to split at; give --segment-walltime",
+                spec.queue
+            ))
+        })
+}
+
+/// `--segments`: write `run.seg1.sh`, `run.seg2.sh`, ... for `spec`, each
+/// within `limit`, and with `--submit` submit them, each waiting for the one
+/// before it. The `--then` steps run at the end of the last segment.
+fn run_segments(
+    cli: &Cli,
+    spec: &JobSpec,
+    outfile: Option<&Path>,
+    limit: u64,
+) -> error::Result<()> {
+    let restart = cli
+        .restart_command
+        .as_ref()
+        .or(cli.config.restart_command.as_ref())
+        .ok_or_else(|| {
+            Error::Usage(
+                "--segments needs --restart-command (or restart_command in the configuration)"
+                    .to_string(),
+            )
+        })?;
+    let total = pbs::parse_walltime(&spec.walltime).unwrap_or_default();
+    let walltimes = segments::walltimes(total, limit);
+    let base = match outfile {
+        Some(outfile) => outfile.to_path_buf(),
+        None => default_script_path(cli, &spec.name),
+    };
+    let mut spec = spec.clone();
+    if spec.job_group.is_none() {
+        spec.job_group = Some(format!("segments-{}", Local::now().format("%Y%m%d-%H%M%S")));
+    }
+    log::info!(
+        "Splitting the {} job into {} segments of at most {}",
+        spec.walltime,
+        walltimes.len(),
+        pbs::format_walltime(limit)
+    );
+    if cli.submit {
+        run_preflight(cli, spec.log_dir().into_iter().collect())?;
+    }
+    create_log_dir(&spec)?;
+    write_env_snapshot(cli, &spec)?;
+    let count = walltimes.len() as u32;
+    let mut previous: Option<String> = None;
+    for (index, walltime) in (1..).zip(walltimes) {
+        let mut segment = spec.clone();
+        segment.name = format!("{}_seg{}", spec.name, index);
+        segment.walltime = pbs::format_walltime(walltime);
+        segment.segment = Some(spec::Segment { index, count });
+        if index > 1 {
+            segment.command = segments::restart_command(restart, index);
+            segment.raw_command = false;
+        }
+        if index < count {
+            segment.then.clear();
+        }
+        if let Some(before) = previous.take() {
+            segment.depend.push(Dependency {
+                kind: "afterany".to_string(),
+                targets: vec![before],
+            });
+        }
+        let generated_at = Local::now();
+        let script = render_job_script(&segment)?;
+        let outfile = naming::fill_in_hash(
+            &base.with_extension(format!("seg{}.sh", index)),
+            &history::fingerprint(&script, &cli.files),
+        );
+        backup_script(cli.backup, &outfile, &script)?;
+        fs::write(&outfile, &script).map_err(|e| Error::Render(outfile.clone(), e))?;
+        write_sidecar(cli, &outfile, &segment, &cli.files, generated_at)?;
+        if !cli.quiet {
+            println!("Job script generated and saved to: {:?}", outfile);
+        }
+        if !cli.submit {
+            continue;
+        }
+        let job_id = submit_and_record(
+            cli,
+            &segment,
+            &script,
+            Some(&outfile),
+            &cli.files,
+            generated_at,
+        )?;
+        if cli.quiet {
+            println!("{}", job_id);
+        } else {
+            println!("Segment {} submitted with ID: {}", index, job_id);
+        }
+        previous = Some(job_id);
+    }
+    if let (true, Some(group)) = (cli.submit, &spec.job_group) {
+        log::info!("Group {}: see qsub2 status --group {}", group, group);
+    }
+    Ok(())
+}
+
+/// With `--sidecar`, write the metadata of a script that was just generated.
+fn write_sidecar(
+    cli: &Cli,
+    script: &Path,
+    spec: &JobSpec,
+    files: &[PathBuf],
+    generated_at: chrono::DateTime<Local>,
+) -> error::Result<()> {
+    if !(cli.sidecar || cli.config.sidecar) {
+        return Ok(());
+    }
+    sidecar::Sidecar {
+        script,
+        spec,
+        files,
+        generated_at,
+        job_id: None,
+        submitted_at: None,
+    }
+    .write()
+    .map_err(|e| Error::Render(sidecar::path(script), e))
+}
+
+/// A rendered script to submit, and where from; what `qsub2` sends the
+/// daemon of `qsub2 serve --socket`.
+#[derive(Serialize, Deserialize)]
+struct ScriptSubmission {
+    spec: JobSpec,
+    script: String,
+    outfile: Option<PathBuf>,
+    files: Vec<PathBuf>,
+    generated_at: chrono::DateTime<Local>,
+    invocation: Vec<String>,
+    workdir: Option<PathBuf>,
+    /// Not sent: the daemon records its own user, the only one its socket lets in.
+    #[serde(skip)]
+    user: String,
+    allow_duplicate: bool,
+}
+
+/// Check for a duplicate, run the pre_submit hook, submit and add the job to
+/// the history.
+fn submit_script(
+    cli: &Cli,
+    submission: &ScriptSubmission,
+) -> error::Result<history::SubmissionRecord> {
+    let fingerprint = history::fingerprint(&submission.script, &submission.files);
+    check_duplicate(&fingerprint, submission.allow_duplicate)?;
+    if let Some(ref hook) = cli.config.pre_submit {
+        let input = serde_json::to_string(&submission.spec).unwrap_or_default();
+        match hooks::run("pre_submit", hook, &input) {
+            Ok(status) if status.success() => {}
+            Ok(status) => return Err(Error::HookRefused(status.to_string())),
+            Err(e) => return Err(Error::Io("running the pre_submit hook", e)),
+        }
+    }
+    let audit = audit::target(&cli.config);
+    let audit_required = audit::required(&cli.config);
+    if audit_required {
+        let Some(ref target) = audit else {
+            return Err(Error::Usage(
+                "audit_required is set, but no audit_log names where to record the job".into(),
+            ));
+        };
+        audit::check(target).map_err(|e| Error::Io("writing the audit log", e))?;
+    }
+    let outfile = submission.outfile.as_deref();
+    let job_id = submit_job(
+        cli,
+        &submission.script,
+        outfile,
+        submission.workdir.as_deref(),
+    )?;
+    let record = history::SubmissionRecord {
+        job_id,
+        script: outfile.map_or_else(|| PathBuf::from("-"), Path::to_path_buf),
+        spec: submission.spec.clone(),
+        generated_at: submission.generated_at,
+        submitted_at: Local::now(),
+        fingerprint: Some(fingerprint),
+        content: outfile.is_none().then(|| submission.script.clone()),
+        invocation: (!submission.invocation.is_empty()).then(|| submission.invocation.clone()),
+        workdir: submission.workdir.clone(),
+        user: Some(submission.user.clone()),
+    };
+    match history::default_path() {
+        Some(path) => {
+            if let Err(e) = history::append(&path, &record) {
+                log::warn!("could not write history: {}", e);
+            }
+        }
+        None => log::warn!("cannot locate the history file; set QSUB2_HISTORY"),
+    }
+    if let Some(target) = audit {
+        match audit::record(&target, &record) {
+            Ok(()) => {}
+            Err(e) if audit_required => {
+                let job_id = &record.job_id;
+                let cancelled = history::mark_cancelled(job_id)
+                    .and_then(|()| scheduler::cancel(job_id))
+                    .map_or_else(
+                        |e| format!("{} could not be cancelled: {}", job_id, e),
+                        |()| format!("{} was cancelled", job_id),
+                    );
+                let e = io::Error::new(e.kind(), format!("{}; {}", e, cancelled));
+                return Err(Error::Io("writing the audit log", e));
+            }
+            Err(e) => log::error!("writing the audit log: {}", e),
+        }
+    }
+    Ok(record)
+}
+
+/// Hand the submission to the daemon on the configured `socket`; `None` when
+/// there is none, to submit here instead.
+fn submit_through_daemon(
+    cli: &Cli,
+    submission: &ScriptSubmission,
+) -> Option<error::Result<history::SubmissionRecord>> {
+    if cli.no_scheduler || matches!(cli.subcommand, Some(Commands::Serve(_))) {
+        return None;
+    }
+    let socket = cli.config.socket()?;
+    let body = serde_json::to_string(submission).unwrap_or_default();
+    let response = match serve::request(&socket, "POST", "/scripts", &body) {
+        Ok(response) => response,
+        Err(e) => {
+            log::debug!(
+                "no daemon on {} ({}); submitting directly",
+                socket.display(),
+                e
+            );
+            return None;
+        }
+    };
+    let answer: serde_json::Value = serde_json::from_str(&response.body).unwrap_or_default();
+    let message = answer["error"]
+        .as_str()
+        .unwrap_or(&response.body)
+        .to_string();
+    Some(match response.status {
+        200 => serde_json::from_value(answer)
+            .map_err(|e| Error::Io("reading the daemon's answer", e.into())),
+        409 => Err(Error::Duplicate(message)),
+        _ => Err(Error::Io(
+            "submitting through the daemon",
+            io::Error::other(message),
+        )),
+    })
+}
+
+/// Info messages print as they are; warnings and errors get a prefix.
+fn init_logging(cli: &Cli) {
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    let write_style = match cli.color {
+        ColorChoice::Auto => env_logger::WriteStyle::Auto,
+        ColorChoice::Always => env_logger::WriteStyle::Always,
+        ColorChoice::Never => env_logger::WriteStyle::Never,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .write_style(write_style)
+        .format(|buf, record| match record.level() {
+            log::Level::Error => writeln!(
+                buf,
+                "{}Error:{} {}",
+                style::ERROR.render(),
+                style::ERROR.render_reset(),
+                record.args()
+            ),
+            log::Level::Warn => writeln!(
+                buf,
+                "{}Warning:{} {}",
+                style::WARNING.render(),
+                style::WARNING.render_reset(),
+                record.args()
+            ),
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "[{}] {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .parse_default_env()
+        .init();
+}
+
+fn main() {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.invocation = std::env::args().collect();
+    cli.from_env = matches
+        .ids()
+        .filter(|id| {
+            matches.value_source(id.as_str()) == Some(clap::parser::ValueSource::EnvVariable)
+        })
+        .map(|id| id.to_string())
+        .collect();
+    match cli.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => anstream::ColorChoice::Always.write_global(),
+        ColorChoice::Never => anstream::ColorChoice::Never.write_global(),
+    }
+    init_logging(&cli);
+    // doctor and config validate report a broken configuration themselves
+    let result = match load_config() {
+        Ok(config) => {
+            if let Some(path) = config.history() {
+                history::configure_path(path);
+            }
+            if let Some(plugin) = config.scheduler_plugin() {
+                scheduler::configure(plugin);
+            }
+            cli.config = config;
+            match cli.preset.clone() {
+                Some(name) => cli.config.apply_preset(&name),
+                None => Ok(()),
+            }
+            .and_then(|()| collect_inputs(&mut cli))
+            .and_then(|()| pick_missing(&mut cli))
+            .and_then(|()| run(&cli))
+        }
+        Err(_)
+            if matches!(
+                cli.subcommand,
+                Some(Commands::Doctor | Commands::Config(ConfigCommand::Validate { .. }))
+            ) =>
+        {
+            run(&cli)
+        }
+        // so a broken file can still be fixed, or read by a newer qsub2
+        Err(e)
+            if matches!(
+                cli.subcommand,
+                Some(Commands::Config(ConfigCommand::Edit) | Commands::SelfUpdate(_))
+            ) =>
+        {
+            log::warn!("{}", e);
+            run(&cli)
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        anstream::eprintln!(
+            "{}Error:{} {}",
+            style::ERROR.render(),
+            style::ERROR.render_reset(),
+            e
+        );
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// A queue or template from the picker.
+fn pick(cli: &Cli, what: PickTarget) -> error::Result<String> {
+    let (name, choices) = match what {
+        PickTarget::Queue => {
+            let snapshot = discovery::cached(cache_ttl(cli)?);
+            ("queue", picker::queues(&cli.config, snapshot.as_ref()))
+        }
+        PickTarget::Template => ("template", picker::templates(&cli.config)),
+    };
+    picker::pick(name, &choices)
+        .map_err(|e| Error::Io("picking from a list", e))?
+        .ok_or_else(|| Error::Usage(format!("no {} was picked", name)))
+}
+
+/// `--queue` and `--template` given without a value, from the picker.
+fn pick_missing(cli: &mut Cli) -> error::Result<()> {
+    if cli.queue.as_deref() == Some("") {
+        cli.queue = Some(pick(cli, PickTarget::Queue)?);
+    }
+    if cli
+        .template
+        .as_ref()
+        .is_some_and(|t| t.as_os_str().is_empty())
+    {
+        cli.template = Some(PathBuf::from(pick(cli, PickTarget::Template)?));
+    }
+    Ok(())
+}
+
+/// Add the files of `--files0-from`, `--glob` and `--recursive` to the input
+/// files, in that order after those on the command line; a file found twice
+/// keeps its first place. `--ext`, `--min-size` and `--newer-than` then pick
+/// from them all, and those left must exist unless `--allow-missing-inputs`.
+fn collect_inputs(cli: &mut Cli) -> error::Result<()> {
+    let mut files = Vec::new();
+    if let Some(ref path) = cli.files0_from {
+        let stdin = Path::new("-");
+        if path == stdin && cli.command_file.as_deref() == Some(stdin) {
+            return Err(Error::Usage(
+                "--files0-from and --command-file cannot both read stdin".to_string(),
+            ));
+        }
+        files.extend(inputs::read_files0(path).map_err(|e| {
+            let e = io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+            Error::Io("reading the file list", e)
+        })?);
+    }
+    for pattern in &cli.glob {
+        let found = inputs::glob(pattern);
+        if found.is_empty() {
+            log::warn!("No files match {}", pattern);
+        }
+        files.extend(found);
+    }
+    for dir in &cli.recursive {
+        if !dir.is_dir() {
+            return Err(Error::Usage(format!(
+                "{} is not a directory",
+                dir.display()
+            )));
+        }
+        files.extend(inputs::recursive(dir));
+    }
+    let mut seen: HashSet<PathBuf> = cli.files.iter().cloned().collect();
+    files.retain(|file| seen.insert(file.clone()));
+    cli.files.extend(files);
+    let filter = inputs::Filter {
+        extensions: cli.ext.clone(),
+        min_size: cli.min_size,
+        newer_than: cli.newer_than,
+    };
+    if !filter.is_empty() {
+        let before = cli.files.len();
+        cli.files.retain(|file| filter.matches(file));
+        log::info!(
+            "{} of {} input files pass the filters",
+            cli.files.len(),
+            before
+        );
+    }
+    if !cli.allow_missing_inputs {
+        let missing: Vec<String> = cli
+            .files
+            .iter()
+            .filter(|file| !file.exists())
+            .map(|file| file.display().to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Usage(format!(
+                "no such input file: {} (--allow-missing-inputs if an earlier job makes it)",
+                missing.join(", ")
+            )));
+        }
+    }
+    let searched = cli.files0_from.is_some()
+        || !cli.glob.is_empty()
+        || !cli.recursive.is_empty()
+        || !filter.is_empty();
+    if cli.files.is_empty() && searched {
+        return Err(Error::Usage("no input files were found".to_string()));
+    }
+    Ok(())
+}
+
+/// The job body for `--command-file`, or a `--prologue`/`--epilogue`, pasted into the script as it is.
+fn read_script_file(path: &Path, doing: &'static str) -> error::Result<String> {
+    let content = if path == Path::new("-") {
+        io::read_to_string(io::stdin())
+    } else {
+        encoding::read(path)
+    };
+    content.map(|c| c.trim_end().to_string()).map_err(|e| {
+        let e = io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        Error::Io(doing, e)
+    })
+}
+
+/// `--prepend`/`--append` values: the contents of those naming a file, the others as they are.
+fn read_snippets(values: &[String]) -> error::Result<Vec<String>> {
+    values
+        .iter()
+        .map(|value| match Path::new(value).is_file() {
+            true => read_script_file(Path::new(value), "reading a snippet"),
+            false => Ok(value.clone()),
+        })
+        .collect()
+}
+
+fn load_config() -> error::Result<config::Config> {
+    let config = match config::default_path() {
+        Some(path) => {
+            log::debug!("configuration: {}", path.display());
+            config::Config::load(&path)?
+        }
+        None => config::Config::default(),
+    };
+    match std::env::current_dir()
+        .ok()
+        .and_then(|dir| config::find_project(&dir))
+    {
+        Some(path) => {
+            log::debug!("project configuration: {}", path.display());
+            config.with_project(&path)
+        }
+        None => Ok(config),
+    }
+}
+
+fn run(cli: &Cli) -> error::Result<()> {
+    match cli.subcommand {
+        Some(Commands::Wait(ref args)) => {
+            return match wait_for_jobs(args) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(Error::Timeout),
+                Err(e) => Err(Error::Io("waiting for jobs", e)),
+            };
+        }
+        Some(Commands::Logs(ref args)) => {
+            return show_logs(args).map_err(|e| Error::Io("showing logs", e));
+        }
+        Some(Commands::Watch(ref args)) => {
+            return watch_job(args).map_err(|e| Error::Io("watching job", e));
+        }
+        Some(Commands::Run(ref args)) => {
+            return run_attached(cli, args);
+        }
+        Some(Commands::New) => {
+            return wizard::new_job(cli);
+        }
+        Some(Commands::Pick(ref args)) => {
+            println!("{}", pick(cli, args.what)?);
+            return Ok(());
+        }
+        Some(Commands::Doctor) => {
+            return match doctor::run(cli) {
+                0 => Ok(()),
+                failures => Err(Error::ChecksFailed(failures)),
+            };
+        }
+        Some(Commands::Nodes(ref args)) => {
+            return show_nodes(args).map_err(|e| Error::Io("reading the nodes", e));
+        }
+        Some(Commands::Top) => {
+            return show_top().map_err(|e| Error::Io("reading cluster state", e));
+        }
+        Some(Commands::Exporter(ref args)) => {
+            return exporter::serve(&args.listen).map_err(|e| Error::Io("running exporter", e));
+        }
+        Some(Commands::List(ref args)) => {
+            return list_history(args).map_err(|e| Error::Io("listing history", e));
+        }
+        Some(Commands::Report(ref args)) => {
+            return show_report(args).map_err(|e| Error::Io("reading resource usage", e));
+        }
+        Some(Commands::Stats(ref args)) => {
+            return show_stats(args).map_err(|e| Error::Io("summarizing the history", e));
+        }
+        Some(Commands::Efficiency(ref args)) => {
+            return show_efficiency(args);
+        }
+        Some(Commands::Accounting(ref args)) => {
+            return show_accounting(args).map_err(|e| Error::Io("summarizing the accounting", e));
+        }
+        Some(Commands::Digest(ref args)) => {
+            return show_digest(args).map_err(|e| Error::Io("writing the digest", e));
+        }
+        Some(Commands::Config(ref command)) => {
+            return run_config_command(cli, command);
+        }
+        Some(Commands::Spec(SpecCommand::Validate { ref files })) => {
+            return validate_specs(files);
+        }
+        Some(Commands::Cluster(ref command)) => {
+            let snapshot = match command {
+                ClusterCommand::Discover => discovery::discover(),
+                ClusterCommand::Show => discovery::get(cache_ttl(cli)?),
+            }
+            .map_err(|e| Error::Io("querying the queues", e))?;
+            print!("{}", discovery::describe(&snapshot));
+            return Ok(());
+        }
+        Some(Commands::Pipeline(PipelineCommand::Export(ref args))) => {
+            return export_pipeline(args);
+        }
+        Some(Commands::Integrate(IntegrateCommand::Snakemake(ref args))) => {
+            let program = std::env::current_exe()
+                .map_err(|e| Error::Io("locating the qsub2 executable", e))?;
+            integrate::snakemake(&args.out, &program, &args.job)
+                .map_err(|e| Error::Render(args.out.clone(), e))?;
+            log::info!(
+                "Wrote a Snakemake profile to {}; run snakemake --profile {}",
+                args.out.display(),
+                args.out.display()
+            );
+            return Ok(());
+        }
+        Some(Commands::Integrate(IntegrateCommand::Nextflow(ref args))) => {
+            return write_nextflow_config(cli, args);
+        }
+        Some(Commands::Rerun(ref args)) => {
+            return rerun::rerun(cli, args);
+        }
+        Some(Commands::Restart(ref args)) => {
+            return rerun::restart(cli, args);
+        }
+        Some(Commands::Requeue(ref args)) => {
+            return rerun::requeue(cli, args);
+        }
+        Some(Commands::Cancel(ref args)) => {
+            return cancel_jobs(args);
+        }
+        Some(Commands::Select(ref args)) => {
+            return select_jobs(cli, args);
+        }
+        Some(Commands::Status(ref args)) => {
+            return show_status(cli, args);
+        }
+        Some(Commands::Schedule(ref args)) => {
+            return run_schedule(cli, args);
+        }
+        Some(Commands::Daemon(ref args)) => {
+            return run_daemon(cli, args);
+        }
+        Some(Commands::Serve(ref args)) => {
+            return run_serve(cli, args);
+        }
+        Some(Commands::SelfUpdate(ref args)) => {
+            return self_update(args).map_err(|e| Error::Io("updating qsub2", e));
+        }
+        Some(Commands::Interactive(ref args)) => {
+            return run_interactive(cli, args);
+        }
+        Some(Commands::Monitor(ref args)) => {
+            let user = args.user.clone().unwrap_or_else(current_user);
+            return monitor::run(&user, args.interval).map_err(|e| Error::Io("running monitor", e));
+        }
+        None => {}
+    }
+
+    let mut spec = JobSpec::from_cli(cli);
+    let uses_output = std::iter::once(&spec.command)
+        .chain(&spec.then)
+        .chain(&cli.skip_existing)
+        .any(|s| s.contains("{output}"));
+    if uses_output && cli.output_pattern.is_none() {
+        return Err(Error::Usage(
+            "{output} needs --output-pattern, e.g. --output-pattern '{stem}.bam'".to_string(),
+        ));
+    }
+    if let Some(ref path) = cli.command_file {
+        spec.command = read_script_file(path, "reading the command file")?;
+        spec.raw_command = true;
+    }
+    if let Some(ref path) = cli.prologue {
+        spec.prologue = Some(read_script_file(path, "reading the prologue")?);
+    }
+    if let Some(ref path) = cli.epilogue {
+        spec.epilogue = Some(read_script_file(path, "reading the epilogue")?);
+    }
+    for path in &cli.env_file {
+        let variables = envfile::read(path, &cli.config.env_file)
+            .map_err(|e| Error::Io("reading the env file", e))?;
+        spec.env.extend(variables);
+    }
+    spec.prepend = read_snippets(&cli.prepend)?;
+    spec.append = read_snippets(&cli.append)?;
+    // --per-file fills in the placeholders for each file
+    let outfile = match cli.outfile {
+        Some(ref outfile) if !cli.per_file => Some(
+            naming::fill_in_path(outfile, cli.files.first().map(PathBuf::as_path), 1)
+                .map_err(Error::Usage)?,
+        ),
+        _ => cli.outfile.clone(),
+    };
+    if !cli.per_file {
+        spec.name = naming::fill_in(&spec.name, cli.files.first().map(PathBuf::as_path), 1)
+            .map_err(Error::Usage)?;
+    }
+    if cli.show_defaults {
+        if !cli.per_file {
+            apply_scaling(cli, &mut spec, &cli.files)?;
+        }
+        evaluate_expressions(&mut spec)?;
+        route_queue(cli, &mut spec);
+        apply_queue_defaults(cli, &mut spec);
+        evaluate_expressions(&mut spec)?;
+        show_defaults(cli, &spec);
+        return Ok(());
+    }
+    if cli.array {
+        let path = match outfile {
+            Some(ref outfile) => array::manifest_path(outfile),
+            None => array::manifest_path(&default_script_path(cli, &spec.name)),
+        };
+        let path = naming::fill_in_hash(&path, &settings_hash(cli, &spec));
+        let plan = array::plan(
+            &path,
+            &cli.files,
+            cli.output_pattern.as_deref(),
+            cli.param_table.as_deref(),
+        )
+        .map_err(Error::Usage)?;
+        if let Some(ref pattern) = cli.output_pattern {
+            array::fill_in(pattern, &plan).map_err(Error::Usage)?;
+        }
+        spec.command = array::command(&spec.command, &plan).map_err(Error::Usage)?;
+        spec.then = spec
+            .then
+            .iter()
+            .map(|step| array::fill_in(step, &plan))
+            .collect::<Result<_, _>>()
+            .map_err(Error::Usage)?;
+        spec.array = Some(plan);
+    }
+    if cli.snapshot_env {
+        let path = match outfile {
+            Some(ref outfile) if !cli.per_file => outfile.with_extension("env"),
+            _ => default_script_path(cli, &spec.name).with_extension("env"),
+        };
+        let path = naming::fill_in_hash(&path, &settings_hash(cli, &spec));
+        spec.env_snapshot =
+            Some(std::path::absolute(&path).map_err(|e| Error::Io("saving the environment", e))?);
+    }
+    if let Some(max) = cli.requeue_on_preempt {
+        let program = std::env::current_exe().map_err(|e| Error::Io("locating qsub2", e))?;
+        spec.requeue = Some(spec::Requeue { max, program });
+    }
+    if cli.require_clean_git || cli.config.require_clean_git {
+        match git::clean_head() {
+            Ok(commit) => spec.git_commit = Some(commit),
+            Err(message) if cli.submit || cli.preview => return Err(Error::UncleanGit(message)),
+            Err(message) => log::warn!("{}", message),
+        }
+    }
+    if cli.recommend_queue {
+        return recommend_queue(cli, &spec);
+    }
+    if cli.per_file {
+        evaluate_expressions(&mut spec)?;
+        route_queue(cli, &mut spec);
+        pick_queue(cli, &mut spec)?;
+        apply_queue_defaults(cli, &mut spec);
+        evaluate_expressions(&mut spec)?;
+        check_against_cluster(cli, &spec)?;
+        write_env_snapshot(cli, &spec)?;
+        return run_per_file(cli, &spec);
+    }
+    apply_scaling(cli, &mut spec, &cli.files)?;
+    evaluate_expressions(&mut spec)?;
+    route_queue(cli, &mut spec);
+    pick_queue(cli, &mut spec)?;
+    apply_queue_defaults(cli, &mut spec);
+    evaluate_expressions(&mut spec)?;
+    apply_past_usage(cli, &mut spec);
+    spec.validate()?;
+    check_against_cluster(cli, &spec)?;
+    if cli.estimate_start {
+        return estimate_start(cli, &spec);
+    }
+    if let Some(cost) = check_cost(cli, &spec)? {
+        if cli.cost {
+            println!("Maximum cost: {}", cost);
+        }
+    }
+    log::debug!(
+        "job settings: {}",
+        serde_json::to_string(&spec).unwrap_or_default()
+    );
+
+    if cli.segments {
+        let limit = segment_limit(cli, &spec)?;
+        match pbs::parse_walltime(&spec.walltime) {
+            Some(total) if total > limit => {
+                return run_segments(cli, &spec, outfile.as_deref(), limit);
+            }
+            _ => log::info!(
+                "The job fits in one segment of {}",
+                pbs::format_walltime(limit)
+            ),
+        }
+    }
+
+    let generated_at = Local::now();
+    let script = render_job_script(&spec)?;
+    if cli.lint {
+        let name = outfile
+            .as_ref()
+            .map_or("script".into(), |o| o.display().to_string());
+        lint_script(&script, &name)?;
+    }
+    if let Some(ref old) = cli.diff_against {
+        let old_script =
+            fs::read_to_string(old).map_err(|e| Error::Io("reading the script to compare", e))?;
+        let name = old.display().to_string();
+        match diff::unified(&name, &old_script, "(rendered)", &script) {
+            Some(diff) => diff::print(&diff),
+            None if !cli.quiet => eprintln!("No differences from {}", name),
+            None => {}
+        }
+        return Ok(());
+    }
+    if cli.show_command {
+        let outfile = (!cli.no_script).then(|| {
+            let outfile = outfile
+                .clone()
+                .unwrap_or_else(|| default_script_path(cli, &spec.name));
+            naming::fill_in_hash(&outfile, &history::fingerprint(&script, &cli.files))
+        });
+        println!("{}", submit_command(cli, outfile.as_deref()));
+        return Ok(());
+    }
+    if cli.submit || cli.preview {
+        let mut dirs: Vec<PathBuf> = spec.log_dir().into_iter().collect();
+        if let Some(ref pattern) = cli.output_pattern {
+            for file in &cli.files {
+                let output = batch::output_path(pattern, file);
+                dirs.extend(output.parent().map(Path::to_path_buf));
+            }
+        }
+        run_preflight(cli, dirs)?;
+    }
+    if let (true, Some(array)) = (cli.submit, &spec.array) {
+        if !confirm_bulk(cli, &[&spec], array.size, |_| Ok(script.clone()))? {
+            eprintln!("Not submitted.");
+            return Ok(());
+        }
+    }
+    if cli.preview {
+        preview::print(&script);
+        if !preview::confirm("Submit?").map_err(|e| Error::Io("reading the answer", e))? {
+            eprintln!("Not submitted.");
+            return Ok(());
+        }
+    }
+    create_log_dir(&spec)?;
+    write_env_snapshot(cli, &spec)?;
+    if let Some(ref array) = spec.array {
+        array::write_manifest(array, &cli.files)
+            .map_err(|e| Error::Io("writing the file list", e))?;
+    }
+    // Without -o the script gets a timestamped name, which is then what is submitted.
+    let outfile = if cli.no_script {
+        None
+    } else {
+        let outfile = match outfile {
+            Some(ref outfile) if naming::has_hash(outfile) => {
+                let outfile =
+                    naming::fill_in_hash(outfile, &history::fingerprint(&script, &cli.files));
+                write_hashed_script(&outfile, &script)?;
+                outfile
+            }
+            Some(ref outfile) => {
+                backup_script(cli.backup, outfile, &script)?;
+                fs::write(outfile, &script).map_err(|e| Error::Render(outfile.clone(), e))?;
+                outfile.clone()
+            }
+            None => create_script_file(cli, &spec.name, &script)?,
+        };
+        write_sidecar(cli, &outfile, &spec, &cli.files, generated_at)?;
+        if !cli.quiet {
+            println!("Job script generated and saved to: {:?}", outfile);
+        }
+        Some(outfile)
+    };
+    if !cli.quiet {
+        print_resources(&spec);
+    }
+
+    if cli.local {
+        let status = local::run(&spec, &script, outfile.as_deref(), cli.local_limits)
+            .map_err(|e| Error::Io("running the script", e))?;
+        return match status.code() {
+            Some(0) => Ok(()),
+            Some(code) => Err(Error::JobFailed(code)),
+            #[cfg(unix)]
+            None => Err(Error::JobFailed(
+                256 + std::os::unix::process::ExitStatusExt::signal(&status).unwrap_or(0),
+            )),
+            #[cfg(not(unix))]
+            None => Err(Error::JobFailed(1)),
+        };
+    }
+    if cli.submit || cli.preview {
+        let job_id = submit_and_record(
+            cli,
+            &spec,
+            &script,
+            outfile.as_deref(),
+            &cli.files,
+            generated_at,
+        )?;
+        let _ = cli.submitted.set(job_id.clone());
+        if cli.attached {
+            log::info!("Job submitted with ID: {}", job_id);
+        } else if cli.quiet {
+            println!("{}", job_id);
+        } else {
+            anstream::println!(
+                "Job submitted with ID: {}{}{}",
+                style::JOB_ID.render(),
+                job_id,
+                style::JOB_ID.render_reset()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_job_script_with_defaults() {
+        let cli = Cli {
+            command: Some("echo Hello, world!".into()),
+            outfile: Some(PathBuf::from("test_output.sh")),
+            ..Default::default()
+        };
+
+        let result = generate_job_script(
+            &JobSpec::from_cli(&cli),
+            Path::new("test_output.sh"),
+            Backup::None,
+        );
+        assert!(result.is_ok());
+
+        let expected_content = include_str!("../test/fixtures/expected_default_script.sh"); // Assume this contains the expected default script
+        let generated_content = fs::read_to_string("test_output.sh").unwrap();
+        assert_eq!(generated_content, expected_content);
+
+        fs::remove_file("test_output.sh").unwrap();
+    }
+
+    #[test]
+    fn test_template_brace_expansion_is_not_an_expression() {
+        let template = std::env::temp_dir().join(format!("qsub2-braces-{}.sh", std::process::id()));
+        fs::write(
+            &template,
+            "#!/bin/bash\n#PBS -l ncpus={{ ncpus * 2 }}\nfor f in {{a,b},c}; do gzip $f; done\n{command}\n",
+        )
+        .unwrap();
+        let mut spec = JobSpec {
+            ncpus: 4,
+            command: "true".into(),
+            template: Some(template.clone()),
+            ..Default::default()
+        };
+        let script = render_job_script(&spec).unwrap();
+        assert!(script.contains("#PBS -l ncpus=8\nfor f in {{a,b},c}; do gzip $f; done\n"));
+
+        fs::write(&template, "#!/bin/bash\n{command}\necho {{ ncpu }}\n").unwrap();
+        spec.template = Some(template.clone());
+        let error = render_job_script(&spec).unwrap_err();
+        assert_eq!(error.exit_code(), 17);
+        assert!(error
+            .to_string()
+            .ends_with(", line 3: {{ ncpu }}: unknown name 'ncpu'"));
+        fs::remove_file(&template).unwrap();
+    }
+
+    #[test]
+    fn test_depend_directive_uses_array_form_for_array_jobs() {
+        let dependencies: Vec<Dependency> = ["123.pbs", "456[].pbs", "afterany:124.pbs:125.pbs"]
+            .iter()
+            .map(|s| parse_dependency(s).unwrap())
+            .collect();
+        assert_eq!(
+            depend_directive(&dependencies).as_deref(),
+            Some("#PBS -W depend=afterok:123.pbs,afterokarray:456[].pbs,afterany:124.pbs:125.pbs")
+        );
+        assert_eq!(depend_directive(&[]), None);
+    }
+
+    #[test]
+    fn test_constraints_go_into_the_select_chunk() {
+        let cli = Cli {
+            command: Some("true".into()),
+            mem: Some("8gb".into()),
+            constraint: vec!["cputype=rome".into(), "host=node042".into()],
+            ..Default::default()
+        };
+        let script = render_job_script(&JobSpec::from_cli(&cli)).unwrap();
+        assert!(script.contains("#PBS -l select=1:ncpus=1:mem=8gb:cputype=rome:host=node042\n"));
+        assert!(parse_constraint("cputype").is_err());
+        assert!(parse_constraint("host=a:ncpus=2").is_err());
+    }
+
+    #[test]
+    fn test_select_replaces_the_template_chunk() {
+        let cli = Cli {
+            command: Some("true".into()),
+            select: Some(parse_select("2:ncpus=64:mem=200gb+ncpus=8").unwrap()),
+            ..Default::default()
+        };
+        let script = render_job_script(&JobSpec::from_cli(&cli)).unwrap();
+        assert!(script.contains("\n#PBS -l select=2:ncpus=64:mem=200gb+1:ncpus=8\n#PBS -q batch\n"));
+        assert!(!script.contains("ncpus=1"));
+    }
+
+    #[test]
+    fn test_requeue_trap_until_the_last_attempt() {
+        let mut spec = JobSpec::from_cli(&Cli {
+            command: Some("true".into()),
+            ..Default::default()
+        });
+        spec.requeue = Some(spec::Requeue {
+            max: 1,
+            program: PathBuf::from("/opt/qsub2"),
+        });
+        let script = render_job_script(&spec).unwrap();
+        assert!(script.contains("export QSUB2_ATTEMPT=1 QSUB2_RESUMED=0\n"));
+        assert!(script.contains("'/opt/qsub2' requeue \"$PBS_JOBID\""));
+        spec.resumed = 1;
+        let script = render_job_script(&spec).unwrap();
+        assert!(script.contains("export QSUB2_ATTEMPT=2 QSUB2_RESUMED=1\n"));
+        assert!(!script.contains("trap"));
+    }
+
+    #[test]
+    fn test_place_directive() {
+        assert_eq!(place_directive(None, false), None);
+        assert_eq!(
+            place_directive(Some(Placement::Scatter), true).as_deref(),
+            Some("#PBS -l place=scatter:excl")
+        );
+        assert_eq!(
+            place_directive(None, true).as_deref(),
+            Some("#PBS -l place=excl")
+        );
+    }
+
+    #[test]
+    fn test_log_dir_directives() {
+        let spec = JobSpec {
+            name: "my align".into(),
+            log_dir: Some(PathBuf::from("logs")),
+            ..Default::default()
+        };
+        let dir = Path::new("logs").join("my_align");
+        assert_eq!(
+            extra_directives(&spec),
+            [
+                format!("#PBS -o {}/", dir.display()),
+                format!("#PBS -e {}/", dir.display())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backup_script_numbers_old_versions() {
+        let dir = std::env::temp_dir().join(format!("qsub2-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("job.sh");
+        fs::write(&path, "v1").unwrap();
+        backup_script(Backup::Numbered, &path, "v1").unwrap();
+        assert!(!dir.join("job.sh.1").exists());
+        backup_script(Backup::Numbered, &path, "v2").unwrap();
+        fs::write(&path, "v2").unwrap();
+        backup_script(Backup::Numbered, &path, "v3").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("job.sh.1")).unwrap(), "v1");
+        assert_eq!(fs::read_to_string(dir.join("job.sh.2")).unwrap(), "v2");
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_field_quotes_csv() {
+        assert_eq!(export_field("plain", ExportFormat::Csv), "plain");
+        assert_eq!(
+            export_field("echo \"a,b\"", ExportFormat::Csv),
+            "\"echo \"\"a,b\"\"\""
+        );
+        assert_eq!(export_field("a\tb\nc", ExportFormat::Tsv), "a b c");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("2x").is_err());
+        assert!(parse_duration("5m3").is_err());
+    }
+
+    #[test]
+    fn test_parse_dependency_rejects_unknown_types() {
+        assert!(parse_dependency("afterwards:123").is_err());
+        assert!(parse_dependency("afterany:").is_err());
+        assert_eq!(
+            parse_dependency("before:7.pbs"),
+            Ok(Dependency {
+                kind: "before".into(),
+                targets: vec!["7.pbs".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_command_quotes_special_characters() {
+        assert_eq!(
+            render_command("echo Hello, world!", false, Dialect::Bash),
+            "echo Hello, world!"
+        );
+        assert_eq!(
+            render_command("echo \"it's $HOME\"", false, Dialect::Bash),
+            "eval 'echo \"it'\\''s $HOME\"'"
+        );
+        assert_eq!(
+            render_command("echo $HOME", true, Dialect::Bash),
+            "echo $HOME"
+        );
+    }
+
+    #[test]
+    fn test_render_steps() {
+        let cli = Cli {
+            command: Some("make".into()),
+            then: vec!["make test".into(), "echo \"done\"".into()],
+            ..Default::default()
+        };
+        let mut spec = JobSpec::from_cli(&cli);
+        assert_eq!(
+            render_steps(&spec),
+            "make &&\nmake test &&\neval 'echo \"done\"'"
+        );
+        spec.continue_on_error = true;
+        assert_eq!(
+            render_steps(&spec),
+            "status=0\nmake || status=$?\nmake test || status=$?\neval 'echo \"done\"' || status=$?\nexit $status"
+        );
+        spec.umask = Some("0027".into());
+        assert!(render_steps(&spec).starts_with("umask 0027\nstatus=0\n"));
+        spec.epilogue = Some("rm -rf $TMPDIR/work".into());
+        spec.prologue = Some("module load bwa".into());
+        assert!(render_steps(&spec).starts_with(
+            "umask 0027\nqsub2_epilogue() {\nrm -rf $TMPDIR/work\n}\ntrap 'status=$?; qsub2_epilogue' EXIT\nmodule load bwa\nstatus=0\n"
+        ));
+        spec.append = vec!["date".into()];
+        assert!(render_steps(&spec).ends_with("|| status=$?\ndate\nexit $status"));
+        spec.continue_on_error = false;
+        spec.prepend = vec!["ulimit -c 0".into()];
+        assert!(render_steps(&spec).ends_with(
+            "module load bwa\nulimit -c 0\nmake &&\nmake test &&\neval 'echo \"done\"'\nstatus=$?\ndate\nexit $status"
+        ));
+        spec.dialect = Dialect::Csh;
+        spec.notify_on_failure = true;
+        let script = render_steps(&spec);
+        assert!(script.starts_with("umask 0027\nmodule load bwa\n"));
+        assert!(script.ends_with(
+            "make && \\\nmake test && \\\neval 'echo \"done\"'\nset qsub2_status = $status\ndate\nrm -rf $TMPDIR/work\nif ($qsub2_status != 0) then\n    echo \"Job $PBS_JOBID ($PBS_JOBNAME) exited with status $qsub2_status\" | mail -s \"PBS job $PBS_JOBID failed\" \"$PBS_O_LOGNAME\"\nendif\nexit $qsub2_status"
+        ));
+    }
+
+    #[test]
+    fn test_interactive_args() {
+        let args = InteractiveArgs {
+            ncpus: 4,
+            mem: Some("16gb".into()),
+            walltime: Some(parse_walltime_arg("2h").unwrap()),
+            queue: None,
+            name: None,
+            x11: true,
+        };
+        assert_eq!(
+            interactive_args(&args),
+            [
+                "-I",
+                "-l",
+                "select=1:ncpus=4:mem=16gb",
+                "-X",
+                "-l",
+                "walltime=02:00:00"
+            ]
+        );
+        assert!(parse_walltime_arg("2:xx").is_err());
+    }
+
+    #[test]
+    fn test_insert_directives_without_placeholder() {
+        let script = "#!/bin/bash\n#PBS -N job\n\necho hi\n";
+        let directives = vec!["#PBS -W depend=afterok:1".to_string()];
+        assert_eq!(
+            insert_directives(script, &directives),
+            "#!/bin/bash\n#PBS -N job\n#PBS -W depend=afterok:1\n\necho hi\n"
+        );
+    }
+
+    #[test]
+    fn test_confirm_bulk_below_the_limit() {
+        let mut cli = Cli::try_parse_from(["qsub2", "make"]).unwrap();
+        let spec = JobSpec::from_cli(&cli);
+        let specs = vec![&spec; CONFIRM_OVER];
+        assert!(confirm_bulk(&cli, &specs, 1, |_| unreachable!()).unwrap());
+        cli.yes = true;
+        assert!(confirm_bulk(&cli, &specs, 10, |_| unreachable!()).unwrap());
     }
 }
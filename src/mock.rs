@@ -0,0 +1,67 @@
+//! A stand-in for `qsub` (`--no-scheduler` / `QSUB2_MOCK=1`) for tests, demos,
+//! and machines without PBS. Each submission copies the script to the mock
+//! directory as `<n>.mock.sh`, appends the call to `calls.log`, and returns `<n>.mock`.
+
+use crate::history;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// `$QSUB2_MOCK_DIR`, or `mock` next to the history (`~/.local/share/qsub2/mock`).
+pub fn directory() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("QSUB2_MOCK_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    Some(history::data_dir()?.join("mock"))
+}
+
+/// Record a submission of `content`, read from the script file if there is one
+/// or from standard input otherwise.
+pub fn submit(content: &str, script: Option<&Path>) -> io::Result<String> {
+    let dir = directory().ok_or_else(|| io::Error::other("cannot locate the mock directory"))?;
+    fs::create_dir_all(&dir)?;
+
+    // Claiming the next free number with `create_new` keeps parallel submissions apart.
+    let mut number = next_number(&dir)?;
+    let job_id = loop {
+        let job_id = format!("{}.mock", number);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(format!("{}.sh", job_id)))
+        {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes())?;
+                break job_id;
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("calls.log"))?;
+    match script {
+        Some(script) => writeln!(log, "{}\tqsub {}", job_id, script.display())?,
+        None => writeln!(log, "{}\tqsub", job_id)?,
+    }
+    Ok(job_id)
+}
+
+fn next_number(dir: &Path) -> io::Result<u64> {
+    let mut highest = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some(number) = name
+            .to_str()
+            .and_then(|n| n.strip_suffix(".mock.sh"))
+            .and_then(|n| n.parse().ok())
+        {
+            highest = highest.max(number);
+        }
+    }
+    Ok(highest + 1)
+}
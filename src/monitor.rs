@@ -0,0 +1,233 @@
+//! Interactive job monitor (`qsub2 monitor`).
+
+use crate::logs;
+use crate::pbs::{self, JobAttributes};
+use crate::scheduler;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Clear, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 20;
+const LOG_LINES: usize = 200;
+
+enum Mode {
+    Normal,
+    ConfirmCancel(String),
+    Logs(String, String),
+}
+
+struct Monitor {
+    user: String,
+    jobs: Vec<(String, JobAttributes)>,
+    table: TableState,
+    mode: Mode,
+    message: String,
+}
+
+pub fn run(user: &str, refresh: Duration) -> io::Result<()> {
+    let mut monitor = Monitor {
+        user: user.to_string(),
+        jobs: Vec::new(),
+        table: TableState::default().with_selected(0),
+        mode: Mode::Normal,
+        message: String::new(),
+    };
+    monitor.reload()?;
+    ratatui::run(|terminal| monitor.event_loop(terminal, refresh))
+}
+
+impl Monitor {
+    fn reload(&mut self) -> io::Result<()> {
+        self.jobs = pbs::user_jobs(&self.user)?;
+        if self.table.selected().is_some_and(|i| i >= self.jobs.len()) {
+            self.table.select(Some(self.jobs.len().saturating_sub(1)));
+        }
+        Ok(())
+    }
+
+    fn selected(&self) -> Option<&(String, JobAttributes)> {
+        self.jobs.get(self.table.selected()?)
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal, refresh: Duration) -> io::Result<()> {
+        let mut last_reload = Instant::now();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let timeout = refresh.saturating_sub(last_reload.elapsed());
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle_key(key.code)? {
+                        return Ok(());
+                    }
+                }
+            }
+            if last_reload.elapsed() >= refresh {
+                if let Err(e) = self.reload() {
+                    self.message = e.to_string();
+                }
+                last_reload = Instant::now();
+            }
+        }
+    }
+
+    /// Returns `false` when the monitor should exit.
+    fn handle_key(&mut self, key: KeyCode) -> io::Result<bool> {
+        match &self.mode {
+            Mode::ConfirmCancel(job_id) => {
+                if key == KeyCode::Char('y') {
+                    self.message = match scheduler::cancel(job_id) {
+                        Ok(()) => format!("Cancelled {}", job_id),
+                        Err(e) => e.to_string(),
+                    };
+                    self.reload()?;
+                }
+                self.mode = Mode::Normal;
+            }
+            Mode::Logs(..) => self.mode = Mode::Normal,
+            Mode::Normal => match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Char('c') => {
+                    if let Some((job_id, _)) = self.selected() {
+                        self.mode = Mode::ConfirmCancel(job_id.clone());
+                    }
+                }
+                KeyCode::Char('l') => {
+                    if let Some((job_id, _)) = self.selected() {
+                        let job_id = job_id.clone();
+                        let content = logs::locate(&job_id, false)
+                            .and_then(fs::read_to_string)
+                            .unwrap_or_else(|e| e.to_string());
+                        self.mode = Mode::Logs(job_id, tail(&content, LOG_LINES));
+                    }
+                }
+                KeyCode::Char('r') => {
+                    if let Some((job_id, attributes)) = self.selected() {
+                        self.message = match pbs::resubmit(attributes) {
+                            Ok(new_id) => format!("Resubmitted {} as {}", job_id, new_id),
+                            Err(e) => e.to_string(),
+                        };
+                        self.reload()?;
+                    }
+                }
+                KeyCode::Char('g') => self.reload()?,
+                _ => {}
+            },
+        }
+        Ok(true)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+        let rows = self.jobs.iter().map(|(job_id, attributes)| {
+            let get = |key: &str| attributes.get(key).cloned().unwrap_or_default();
+            let mut state = pbs::state_name(&get("job_state")).to_string();
+            if pbs::requeues(attributes) > 0 {
+                state.push_str(" (requeued)");
+            }
+            Row::new(vec![
+                job_id.clone(),
+                get("Job_Name"),
+                get("queue"),
+                state,
+                walltime_bar(
+                    attributes.get("resources_used.walltime"),
+                    attributes.get("Resource_List.walltime"),
+                ),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(20),
+                Constraint::Length(16),
+                Constraint::Length(10),
+                Constraint::Length(18),
+                Constraint::Min(BAR_WIDTH as u16 + 6),
+            ],
+        )
+        .header(
+            Row::new(vec!["Job ID", "Name", "Queue", "State", "Walltime"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(format!(" qsub2 monitor: {} ", self.user)));
+        frame.render_stateful_widget(table, main, &mut self.table);
+
+        let help = match &self.mode {
+            Mode::ConfirmCancel(job_id) => format!("Cancel {}? (y/n)", job_id),
+            _ if !self.message.is_empty() => self.message.clone(),
+            _ => "q quit  ↑/↓ select  c cancel  l logs  r resubmit  g refresh".to_string(),
+        };
+        frame.render_widget(Line::from(help), footer);
+
+        if let Mode::Logs(job_id, content) = &self.mode {
+            let area = centered(main, 90, 80);
+            frame.render_widget(Clear, area);
+            frame.render_widget(
+                Paragraph::new(content.as_str())
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title(format!(" {} (any key to close) ", job_id))),
+                area,
+            );
+        }
+    }
+}
+
+/// `[██████░░░░] 60%` of the requested walltime used so far.
+fn walltime_bar(used: Option<&String>, requested: Option<&String>) -> String {
+    let used = used.and_then(|w| pbs::parse_walltime(w)).unwrap_or(0);
+    let Some(requested) = requested.and_then(|w| pbs::parse_walltime(w)) else {
+        return String::new();
+    };
+    let ratio = if requested == 0 {
+        1.0
+    } else {
+        (used as f64 / requested as f64).min(1.0)
+    };
+    let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {:>3.0}%",
+        "█".repeat(filled),
+        "░".repeat(BAR_WIDTH - filled),
+        ratio * 100.0
+    )
+}
+
+fn tail(content: &str, lines: usize) -> String {
+    let all: Vec<&str> = content.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+fn centered(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = area.height * percent_y / 100;
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walltime_bar() {
+        let bar = walltime_bar(Some(&"00:30:00".into()), Some(&"01:00:00".into()));
+        assert_eq!(bar, format!("[{}{}]  50%", "█".repeat(10), "░".repeat(10)));
+        assert_eq!(walltime_bar(None, None), "");
+    }
+}
@@ -0,0 +1,256 @@
+//! Names for the scripts written when `--outfile` is not given, and for jobs
+//! without `--name`.
+//!
+//! The default `job_{name}_{id}.sh` gives e.g. `job_align_01JA2X3Y4ZK7P9QM.sh`.
+//! `{id}` starts with the time in milliseconds and ends in random characters,
+//! like a ULID, so names sort by creation time and two submissions made in the
+//! same instant still get different files.
+
+use crate::batch;
+use crate::expr;
+use chrono::{DateTime, Local};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FORMAT: &str = "job_{name}_{id}.sh";
+
+/// `--name-from-hash`: the same job gets the same script.
+pub const HASH_FORMAT: &str = "job_{name}_{hash}.sh";
+
+/// Older PBS versions and Torque cut job names at 15 characters.
+const MAX_JOB_NAME: usize = 15;
+
+/// Crockford's base 32, which leaves out I, L, O and U.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Fill in `{name}` (the job name), `{timestamp}` (`YYYYmmddHHMMSS`),
+/// `{random}` (6 random characters) and `{id}` (the time followed by the random part).
+/// `{hash}` is left for [`fill_in_hash`] once the script is rendered.
+pub fn script_name(format: &str, name: &str, now: DateTime<Local>) -> String {
+    let random = encode(random_bits(), 6);
+    let id = encode(now.timestamp_millis().max(0) as u64, 10) + &random;
+    format
+        .replace("{name}", &sanitize(name))
+        .replace("{timestamp}", &now.format("%Y%m%d%H%M%S").to_string())
+        .replace("{random}", &random)
+        .replace("{id}", &id)
+}
+
+/// Whether a script name waits for its `{hash}`.
+pub fn has_hash(path: &Path) -> bool {
+    path.to_string_lossy().contains("{hash}")
+}
+
+/// Fill in `{hash}`, the [`crate::history::fingerprint`] of the script and
+/// its inputs, which the duplicate check compares too.
+pub fn fill_in_hash(path: &Path, hash: &str) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().replace("{hash}", hash))
+}
+
+/// Whether `pattern` uses any of the placeholders [`fill_in`] knows.
+pub fn has_placeholders(pattern: &str) -> bool {
+    expr::has_expressions(pattern)
+        || [
+            "{basename}",
+            "{stem}",
+            "{ext}",
+            "{dir}",
+            "{abs}",
+            "{index}",
+            "{date}",
+        ]
+        .iter()
+        .any(|p| pattern.contains(p))
+}
+
+/// For `data/sample1.fq.gz`: `{basename}` (`sample1.fq.gz`), `{stem}`
+/// (`sample1.fq`), `{ext}` (`gz`), `{dir}` (`data`, or `.`) and `{abs}`, the
+/// absolute path.
+pub fn file_placeholders(file: &Path) -> [(&'static str, String); 5] {
+    let lossy = |s: Option<&std::ffi::OsStr>| {
+        s.map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.display().to_string(),
+        _ => ".".to_string(),
+    };
+    let abs = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+    [
+        ("{basename}", lossy(file.file_name())),
+        ("{stem}", batch::stem(file)),
+        ("{ext}", lossy(file.extension())),
+        ("{dir}", dir),
+        ("{abs}", abs.display().to_string()),
+    ]
+}
+
+/// Fill in the [`file_placeholders`] of the input `file`, its 1-based
+/// `{index}` among the inputs, and today's `{date}` (`YYYYmmdd`), in a
+/// `--name` or `--outfile`; after its `{{ ... }}` expressions, which see
+/// [`expr::file_vars`].
+pub fn fill_in(pattern: &str, file: Option<&Path>, index: usize) -> Result<String, String> {
+    if !has_placeholders(pattern) {
+        return Ok(pattern.to_string());
+    }
+    let mut pattern = expr::render(pattern, &expr::file_vars(file, index))?;
+    for (placeholder, value) in file.map(file_placeholders).unwrap_or_default() {
+        pattern = pattern.replace(placeholder, &value);
+    }
+    Ok(pattern
+        .replace("{index}", &index.to_string())
+        .replace("{date}", &Local::now().format("%Y%m%d").to_string()))
+}
+
+/// [`fill_in`] for a path.
+pub fn fill_in_path(pattern: &Path, file: Option<&Path>, index: usize) -> Result<PathBuf, String> {
+    fill_in(&pattern.to_string_lossy(), file, index).map(PathBuf::from)
+}
+
+/// A job name from the command's executable and first argument, e.g.
+/// `bwa mem ref.fa a.fq` -> `bwa_mem`, `python3 /opt/qc/run.py` -> `python3_run`.
+/// Leading `VAR=value` words, options and `{file}` placeholders are skipped.
+pub fn job_name(command: &str) -> Option<String> {
+    let mut words = command
+        .split_whitespace()
+        .skip_while(|w| w.contains('=') && !w.starts_with('-'));
+    let executable = words.next().map(file_stem)?;
+    let argument = words
+        .next()
+        .filter(|w| !w.starts_with('-') && !w.contains('{'))
+        .map(file_stem)
+        .filter(|a| !a.is_empty());
+    let name = match argument {
+        Some(argument) => format!("{}_{}", executable, argument),
+        None => executable,
+    };
+    // PBS wants the name to start with a letter.
+    let name: String = name
+        .trim_start_matches(|c: char| !c.is_ascii_alphabetic())
+        .chars()
+        .take(MAX_JOB_NAME)
+        .collect();
+    let name = name.trim_end_matches(['_', '-', '.']);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// `/opt/qc/run.py` -> `run`, without quotes or characters PBS does not allow.
+fn file_stem(word: &str) -> String {
+    let word = word.rsplit('/').next().unwrap_or(word);
+    let word = match word.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => word,
+    };
+    word.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .collect()
+}
+
+/// Keep letters, digits, `.`, `-` and `_`, so a job name never adds a directory.
+pub fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The last `len` base 32 digits of `value`.
+fn encode(mut value: u64, len: usize) -> String {
+    let mut digits = vec![b'0'; len];
+    for digit in digits.iter_mut().rev() {
+        *digit = ALPHABET[(value % 32) as usize];
+        value /= 32;
+    }
+    String::from_utf8(digits).expect("the alphabet is ASCII")
+}
+
+/// `RandomState` is seeded randomly for each process.
+fn random_bits() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos()),
+    );
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_script_name() {
+        let now = Local.with_ymd_and_hms(2026, 10, 14, 4, 30, 0).unwrap();
+        let name = script_name(DEFAULT_FORMAT, "my align/2", now);
+        assert!(name.starts_with("job_my_align_2_"), "{}", name);
+        assert_eq!(name.len(), "job_my_align_2_".len() + 16 + ".sh".len());
+        assert_ne!(name, script_name(DEFAULT_FORMAT, "my align/2", now));
+        assert_eq!(
+            script_name("{name}-{timestamp}.sh", "qc", now),
+            "qc-20261014043000.sh"
+        );
+        assert_eq!(encode(33, 3), "011");
+    }
+
+    #[test]
+    fn test_fill_in() {
+        let file = Path::new("data/sample1.fq.gz");
+        assert_eq!(
+            fill_in("qc_{stem}_{index}", Some(file), 3).unwrap(),
+            "qc_sample1.fq_3"
+        );
+        assert_eq!(
+            fill_in_path(Path::new("{basename}.sh"), Some(file), 1).unwrap(),
+            Path::new("sample1.fq.gz.sh")
+        );
+        assert_eq!(
+            fill_in("run_{date}", None, 1).unwrap(),
+            format!("run_{}", Local::now().format("%Y%m%d"))
+        );
+        assert_eq!(fill_in("plain {x}", Some(file), 1).unwrap(), "plain {x}");
+        assert_eq!(
+            fill_in("{{ upper(stem) }}_{index}", Some(file), 2).unwrap(),
+            "SAMPLE1.FQ_2"
+        );
+        assert_eq!(
+            fill_in("{dir}/{stem}.{ext}", Some(file), 1).unwrap(),
+            "data/sample1.fq.gz"
+        );
+        assert_eq!(fill_in("{dir}", Some(Path::new("a.fq")), 1).unwrap(), ".");
+        let hashed = Path::new("jobs/job_qc_{hash}.sh");
+        assert!(has_hash(hashed));
+        assert_eq!(
+            fill_in_hash(hashed, "5f639ac11f75221f"),
+            Path::new("jobs/job_qc_5f639ac11f75221f.sh")
+        );
+    }
+
+    #[test]
+    fn test_job_name() {
+        assert_eq!(job_name("bwa mem ref.fa a.fq").as_deref(), Some("bwa_mem"));
+        assert_eq!(
+            job_name("python3 /opt/qc/run.py --fast").as_deref(),
+            Some("python3_run")
+        );
+        assert_eq!(
+            job_name("OMP_NUM_THREADS=4 ./align.sh -t 4").as_deref(),
+            Some("align")
+        );
+        assert_eq!(job_name("gzip {file}").as_deref(), Some("gzip"));
+        assert_eq!(
+            job_name("samtools_wrapper_v2 sort").as_deref(),
+            Some("samtools_wrappe")
+        );
+        assert_eq!(job_name("'echo' \"hi\"").as_deref(), Some("echo_hi"));
+        assert_eq!(job_name("./123"), None);
+        assert_eq!(job_name(""), None);
+    }
+}
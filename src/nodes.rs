@@ -0,0 +1,191 @@
+//! `qsub2 nodes`: the free cpus, memory and GPUs of each node, or of each
+//! class of alike nodes. Given a request such as `-@ 64`, it also says which
+//! nodes could start it now, which once their jobs end, and which never.
+
+use crate::cluster::{self, Node};
+use crate::pbs;
+
+/// The resources of one chunk a job asks for.
+#[derive(Debug, Default)]
+pub struct Request {
+    pub ncpus: u64,
+    pub mem: Option<u64>,
+    pub ngpus: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fit {
+    Now,
+    Later,
+    Never,
+}
+
+impl Fit {
+    fn label(self) -> &'static str {
+        match self {
+            Fit::Now => "now",
+            Fit::Later => "later",
+            Fit::Never => "-",
+        }
+    }
+}
+
+/// Whether `node` has room for `request` now, has it once its jobs end, or
+/// is too small or out of service.
+pub fn fit(node: &Node, request: &Request) -> Fit {
+    let fits = |ncpus, mem, ngpus| {
+        ncpus >= request.ncpus
+            && request.mem.is_none_or(|m| node.mem == 0 || mem >= m)
+            && ngpus >= request.ngpus
+    };
+    if node.is_unavailable() || !fits(node.ncpus, node.mem, node.ngpus) {
+        Fit::Never
+    } else if fits(node.free_ncpus(), node.free_mem(), node.free_ngpus()) {
+        Fit::Now
+    } else {
+        Fit::Later
+    }
+}
+
+/// `48/64`; `-` for a resource the node does not report.
+fn free_of(free: u64, total: u64) -> String {
+    match total {
+        0 => "-".to_string(),
+        _ => format!("{}/{}", free, total),
+    }
+}
+
+fn free_mem_of(free: u64, total: u64) -> String {
+    match total {
+        0 => "-".to_string(),
+        _ => format!("{}/{}", pbs::format_size(free), pbs::format_size(total)),
+    }
+}
+
+/// One line per node.
+pub fn table(nodes: &[Node], request: Option<&Request>) -> String {
+    let header = format!(
+        "{:<16} {:<12} {:>9} {:>17} {:>7}  {:<10}{}",
+        "NODE",
+        "STATE",
+        "FREE CPUS",
+        "FREE MEM",
+        "GPUS",
+        "QUEUE",
+        if request.is_some() { " FITS" } else { "" }
+    );
+    let mut text = format!("{}\n", header.trim_end());
+    for node in nodes {
+        // nothing is free on a node that cannot start jobs
+        let up = u64::from(!node.is_unavailable());
+        let line = format!(
+            "{:<16} {:<12} {:>9} {:>17} {:>7}  {:<10}{}",
+            node.name,
+            node.state,
+            free_of(up * node.free_ncpus(), node.ncpus),
+            free_mem_of(up * node.free_mem(), node.mem),
+            free_of(up * node.free_ngpus(), node.ngpus),
+            node.queue.as_deref().unwrap_or("any"),
+            request.map_or(String::new(), |r| format!(" {}", fit(node, r).label())),
+        );
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// One line per class of alike nodes, with the free resources of those in service.
+pub fn class_table(nodes: &[Node], request: Option<&Request>) -> String {
+    let mut text = format!(
+        "{:>5} {:>3} {:>6} {:>10} {:>5}  {:<10} {:>9} {:>10} {:>9}{}\n",
+        "NODES",
+        "UP",
+        "CPUS",
+        "MEM",
+        "GPUS",
+        "QUEUE",
+        "FREE CPUS",
+        "FREE MEM",
+        "FREE GPUS",
+        if request.is_some() { " FIT NOW" } else { "" }
+    );
+    for class in cluster::classes(nodes) {
+        let up: Vec<&&Node> = class.iter().filter(|n| !n.is_unavailable()).collect();
+        let first = class[0];
+        text.push_str(&format!(
+            "{:>5} {:>3} {:>6} {:>10} {:>5}  {:<10} {:>9} {:>10} {:>9}{}\n",
+            class.len(),
+            up.len(),
+            first.ncpus,
+            match first.mem {
+                0 => "-".to_string(),
+                mem => pbs::format_size(mem),
+            },
+            first.ngpus,
+            first.queue.as_deref().unwrap_or("any"),
+            up.iter().map(|n| n.free_ncpus()).sum::<u64>(),
+            pbs::format_size(up.iter().map(|n| n.free_mem()).sum()),
+            up.iter().map(|n| n.free_ngpus()).sum::<u64>(),
+            request.map_or(String::new(), |r| format!(
+                " {:>7}",
+                class.iter().filter(|n| fit(n, r) == Fit::Now).count()
+            )),
+        ));
+    }
+    text
+}
+
+/// `64 cpus: 1 node could start it now, 3 once their jobs end, 2 never`
+pub fn summary(nodes: &[Node], request: &Request) -> String {
+    let count = |wanted: Fit| nodes.iter().filter(|n| fit(n, request) == wanted).count();
+    let mut asked = vec![format!("{} cpus", request.ncpus)];
+    if let Some(mem) = request.mem {
+        asked.push(pbs::format_size(mem));
+    }
+    if request.ngpus > 0 {
+        asked.push(format!("{} GPUs", request.ngpus));
+    }
+    let now = count(Fit::Now);
+    format!(
+        "{}: {} {} could start it now, {} once their jobs end, {} never",
+        asked.join(", "),
+        now,
+        if now == 1 { "node" } else { "nodes" },
+        count(Fit::Later),
+        count(Fit::Never)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let node = |state: &str, assigned_ncpus| Node {
+            name: "cpu01".into(),
+            state: state.into(),
+            ncpus: 64,
+            assigned_ncpus,
+            mem: 256 << 30,
+            ..Node::default()
+        };
+        let request = Request {
+            ncpus: 48,
+            mem: Some(100 << 30),
+            ngpus: 0,
+        };
+        assert_eq!(fit(&node("free", 0), &request), Fit::Now);
+        assert_eq!(fit(&node("job-busy", 32), &request), Fit::Later);
+        assert_eq!(fit(&node("down", 0), &request), Fit::Never);
+        let gpus = Request {
+            ngpus: 1,
+            ..request
+        };
+        assert_eq!(fit(&node("free", 0), &gpus), Fit::Never);
+        assert_eq!(
+            summary(&[node("free", 0), node("job-busy", 60)], &gpus),
+            "48 cpus, 100gb, 1 GPUs: 0 nodes could start it now, 0 once their jobs end, 2 never"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Queries against the PBS server.
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use std::collections::HashMap;
+use std::io;
+use std::process::{Command, Output};
+
+/// Attributes of one job as printed by `qstat -f`.
+pub type JobAttributes = HashMap<String, String>;
+
+/// Parse `qstat -f`, `qstat -Qf` or `pbsnodes -a` output into `(name, attributes)` pairs.
+/// Each record starts with an unindented header line (`Job Id: 123.server`,
+/// `Queue: workq`, or a bare node name). Long values are wrapped onto
+/// tab-indented continuation lines, which are joined back.
+pub fn parse_full(output: &str) -> Vec<(String, JobAttributes)> {
+    let mut records: Vec<(String, JobAttributes)> = Vec::new();
+    let mut last_key: Option<String> = None;
+
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with([' ', '\t']) {
+            let name = ["Job Id:", "Queue:", "Server:"]
+                .iter()
+                .find_map(|prefix| line.strip_prefix(prefix))
+                .unwrap_or(line);
+            records.push((name.trim().to_string(), JobAttributes::new()));
+            last_key = None;
+        } else if line.starts_with('\t') {
+            if let (Some((_, attributes)), Some(key)) = (records.last_mut(), &last_key) {
+                if let Some(value) = attributes.get_mut(key) {
+                    value.push_str(line.trim());
+                }
+            }
+        } else if let Some((key, value)) = line.trim().split_once(" = ") {
+            if let Some((_, attributes)) = records.last_mut() {
+                attributes.insert(key.to_string(), value.to_string());
+                last_key = Some(key.to_string());
+            }
+        }
+    }
+    records
+}
+
+/// Run a PBS client command and return its stdout, failing on a non-zero exit status.
+pub fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    log::trace!("running: {} {}", program, args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Look up a job with `qstat -f`. Returns `None` once the server no longer reports it.
+pub fn job_status(job_id: &str) -> io::Result<Option<JobAttributes>> {
+    query_job(&["-f", job_id])
+}
+
+/// Like [`job_status`], but also sees finished jobs on servers that keep job history (`-x`).
+pub fn job_status_with_history(job_id: &str) -> io::Result<Option<JobAttributes>> {
+    match query_job(&["-f", "-x", job_id]) {
+        Ok(Some(attributes)) => Ok(Some(attributes)),
+        _ => job_status(job_id),
+    }
+}
+
+/// Every job the server currently reports.
+pub fn all_jobs() -> io::Result<Vec<(String, JobAttributes)>> {
+    Ok(parse_full(&run("qstat", &["-f"])?))
+}
+
+/// Parse timestamps such as `qtime = Wed Oct 14 04:00:00 2026`.
+pub fn parse_time(time: &str) -> Option<DateTime<Local>> {
+    let naive = NaiveDateTime::parse_from_str(time.trim(), "%a %b %e %H:%M:%S %Y").ok()?;
+    naive.and_local_timezone(Local).single()
+}
+
+/// All jobs of `user` that the server still knows about.
+pub fn user_jobs(user: &str) -> io::Result<Vec<(String, JobAttributes)>> {
+    selected_jobs(&["-u", user])
+}
+
+/// The jobs `qselect` picks with `args`, e.g. `-u ana -s Q`.
+pub fn selected_jobs(args: &[&str]) -> io::Result<Vec<(String, JobAttributes)>> {
+    let stdout = run("qselect", args)?;
+    let job_ids: Vec<&str> = stdout.split_whitespace().collect();
+    if job_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = vec!["-f"];
+    args.extend(job_ids);
+    let output = qstat(&args)?;
+    Ok(parse_full(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Delete a job with `qdel`.
+pub fn cancel(job_id: &str) -> io::Result<()> {
+    run("qdel", &[job_id]).map(|_| ())
+}
+
+/// Keep a queued job from starting with `qhold`.
+pub fn hold(job_id: &str) -> io::Result<()> {
+    run("qhold", &[job_id]).map(|_| ())
+}
+
+/// Let a held job start again with `qrls`.
+pub fn release(job_id: &str) -> io::Result<()> {
+    run("qrls", &[job_id]).map(|_| ())
+}
+
+/// Submit a job again with the arguments recorded by the server
+/// (`Submit_arguments`), from its original working directory. Returns the new job ID.
+pub fn resubmit(attributes: &JobAttributes) -> io::Result<String> {
+    let submit_arguments = attributes
+        .get("Submit_arguments")
+        .ok_or_else(|| io::Error::other("the server did not record the submit arguments"))?;
+    let mut qsub = Command::new("qsub");
+    qsub.args(submit_arguments.split_whitespace());
+    if let Some(workdir) = variable(attributes, "PBS_O_WORKDIR") {
+        qsub.current_dir(workdir);
+    }
+    let output = qsub
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run qsub: {}", e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("qsub failed: {}", stderr.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Look up a variable in the job's `Variable_List` (`NAME=value,NAME=value`).
+pub fn variable<'a>(attributes: &'a JobAttributes, name: &str) -> Option<&'a str> {
+    attributes
+        .get("Variable_List")?
+        .split(',')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Parse PBS sizes such as `16gb`, `512mb` or `263768680kb` into bytes.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().to_lowercase();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number: u64 = number.parse().ok()?;
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "kb" | "k" => 1 << 10,
+        "mb" | "m" => 1 << 20,
+        "gb" | "g" => 1 << 30,
+        "tb" | "t" => 1 << 40,
+        _ => return None,
+    };
+    Some(number * multiplier)
+}
+
+/// Format a byte count the way PBS writes sizes, e.g. `1.5gb`.
+pub fn format_size(bytes: u64) -> String {
+    let units = [
+        ("tb", 1u64 << 40),
+        ("gb", 1 << 30),
+        ("mb", 1 << 20),
+        ("kb", 1 << 10),
+    ];
+    for (unit, size) in units {
+        if bytes >= size {
+            let value = bytes as f64 / size as f64;
+            return if value.fract() == 0.0 {
+                format!("{}{}", value, unit)
+            } else {
+                format!("{:.1}{}", value, unit)
+            };
+        }
+    }
+    format!("{}b", bytes)
+}
+
+/// Parse `[[DD:]HH:]MM:SS` walltimes into seconds.
+pub fn parse_walltime(walltime: &str) -> Option<u64> {
+    let fields: Vec<u64> = walltime
+        .split(':')
+        .map(|f| f.parse().ok())
+        .collect::<Option<_>>()?;
+    let (days, rest) = match fields.len() {
+        4 => (fields[0], &fields[1..]),
+        1..=3 => (0, &fields[..]),
+        _ => return None,
+    };
+    let seconds = rest.iter().fold(0, |acc, f| acc * 60 + f);
+    Some(days * 24 * 60 * 60 + seconds)
+}
+
+/// Format seconds as `HH:MM:SS`.
+pub fn format_walltime(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn query_job(args: &[&str]) -> io::Result<Option<JobAttributes>> {
+    let output = qstat(args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if is_gone(&stderr) {
+            return Ok(None);
+        }
+        return Err(io::Error::other(format!("qstat failed: {}", stderr.trim())));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_full(&stdout)
+        .into_iter()
+        .next()
+        .map(|(_, attributes)| attributes))
+}
+
+fn qstat(args: &[&str]) -> io::Result<Output> {
+    log::trace!("running: qstat {}", args.join(" "));
+    Command::new("qstat")
+        .args(args)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run qstat: {}", e)))
+}
+
+fn is_gone(qstat_stderr: &str) -> bool {
+    let message = qstat_stderr.to_lowercase();
+    message.contains("unknown job") || message.contains("has finished")
+}
+
+/// Human-readable name for a `job_state` letter.
+pub fn state_name(state: &str) -> &str {
+    match state {
+        "Q" => "queued",
+        "R" => "running",
+        "H" => "held",
+        "E" => "exiting",
+        "W" => "waiting",
+        "T" => "moving",
+        "S" | "U" => "suspended",
+        "B" => "running (array)",
+        "X" => "finished (subjob)",
+        "F" | "C" => "finished",
+        other => other,
+    }
+}
+
+/// Completed (Torque) or finished (PBS Pro, with job history enabled).
+pub fn is_finished(attributes: &JobAttributes) -> bool {
+    matches!(
+        attributes.get("job_state").map(String::as_str),
+        Some("C") | Some("F")
+    )
+}
+
+/// Exit statuses the server gives a job it is running again itself, e.g.
+/// after its node failed: JOB_EXEC_RETRY and JOB_EXEC_RERUN.
+pub fn is_requeue_status(exit_status: &str) -> bool {
+    matches!(exit_status, "-3" | "-11")
+}
+
+/// Whether qsub failed for a reason that passes, such as the server being
+/// restarted or too busy to answer, rather than because of the job.
+pub fn is_transient(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "connection refused",
+        "cannot connect to server",
+        "server busy",
+        "pbs_server busy",
+        "timed out",
+        "timeout",
+        "try again",
+        "temporarily unavailable",
+        "end of file",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// How many times the server started the job again after the first run;
+/// `run_count` on PBS Pro, `start_count` on Torque.
+pub fn requeues(attributes: &JobAttributes) -> u32 {
+    attributes
+        .get("run_count")
+        .or_else(|| attributes.get("start_count"))
+        .and_then(|n| n.parse::<u32>().ok())
+        .map_or(0, |n| n.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_size() {
+        assert_eq!(parse_size("16gb"), Some(16 << 30));
+        assert_eq!(parse_size("263768680kb"), Some(263768680 << 10));
+        assert_eq!(parse_size("5 parsecs"), None);
+        assert_eq!(format_size(16 << 30), "16gb");
+        assert_eq!(format_size(1536 << 20), "1.5gb");
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(
+            "exit status: 1: qsub: cannot connect to server pbs01 (errno=111)"
+        ));
+        assert!(is_transient("exit status: 1: qsub: Server Busy"));
+        assert!(is_transient("exit status: 1: Request timed out"));
+        assert!(!is_transient(
+            "exit status: 1: qsub: Unknown queue MSG=cannot locate queue"
+        ));
+        assert!(!is_transient(
+            "exit status: 1: qsub: Job exceeds queue resource limits"
+        ));
+    }
+
+    #[test]
+    fn test_requeues() {
+        let attributes = |pairs: &[(&str, &str)]| -> JobAttributes {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(requeues(&attributes(&[])), 0);
+        assert_eq!(requeues(&attributes(&[("run_count", "1")])), 0);
+        assert_eq!(requeues(&attributes(&[("run_count", "3")])), 2);
+        assert_eq!(requeues(&attributes(&[("start_count", "2")])), 1);
+        assert!(is_requeue_status("-11"));
+        assert!(!is_requeue_status("1"));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let time = parse_time("Wed Oct 14 04:00:00 2026").unwrap();
+        assert_eq!(
+            time.format("%Y-%m-%d %H:%M").to_string(),
+            "2026-10-14 04:00"
+        );
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_walltime() {
+        assert_eq!(parse_walltime("01:30:00"), Some(5400));
+        assert_eq!(parse_walltime("30:00:00:00"), Some(30 * 24 * 60 * 60));
+        assert_eq!(parse_walltime("45"), Some(45));
+        assert_eq!(parse_walltime("1h"), None);
+        assert_eq!(format_walltime(90061), "25:01:01");
+    }
+
+    #[test]
+    fn test_parse_full_reads_node_and_queue_records() {
+        let nodes = parse_full("node01\n     state = free\n     resources_available.ncpus = 64\n\nnode02\n     state = down\n");
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].0, "node01");
+        assert_eq!(nodes[0].1["resources_available.ncpus"], "64");
+        let queues = parse_full("Queue: workq\n    queue_type = Execution\n");
+        assert_eq!(queues[0].0, "workq");
+    }
+
+    #[test]
+    fn test_parse_full_joins_continuation_lines() {
+        let output = "Job Id: 123.server\n    Job_Name = align\n    job_state = R\n    Output_Path = node01:/home/user/very/lo\n\tng/path/align.o123\n\nJob Id: 124.server\n    job_state = Q\n";
+        let jobs = parse_full(output);
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].0, "123.server");
+        assert_eq!(jobs[0].1["Job_Name"], "align");
+        assert_eq!(
+            jobs[0].1["Output_Path"],
+            "node01:/home/user/very/long/path/align.o123"
+        );
+        assert_eq!(jobs[1].1["job_state"], "Q");
+    }
+}
@@ -0,0 +1,258 @@
+//! A fuzzy picker for `--queue` and `--template` given without a value, and
+//! for `qsub2 pick`: type to narrow the list, ↑/↓ to move, Enter to take.
+//!
+//! The queues are those of the cached `qsub2 cluster` snapshot and the
+//! `[queue.NAME]` tables of the configuration; the templates are the files
+//! of the template directory. The picker draws on stderr, so that
+//! `$(qsub2 pick queue)` gets only the answer.
+
+use crate::config::Config;
+use crate::discovery::Snapshot;
+use crate::pbs;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use std::fs;
+use std::io::{self, IsTerminal};
+
+/// Something to pick, with what to show beside it.
+#[derive(Debug, PartialEq)]
+pub struct Choice {
+    pub value: String,
+    pub detail: String,
+}
+
+/// The cached queues with their limits, then those only the configuration names.
+pub fn queues(config: &Config, snapshot: Option<&Snapshot>) -> Vec<Choice> {
+    let mut choices: Vec<Choice> = snapshot
+        .map(|s| s.queues.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|queue| {
+            let mut detail = Vec::new();
+            if !(queue.enabled && queue.started) {
+                detail.push("closed".to_string());
+            }
+            if let Some(max) = queue.max_walltime {
+                detail.push(format!("walltime {}", pbs::format_walltime(max)));
+            }
+            if let Some(max) = queue.max_ncpus {
+                detail.push(format!("{} cpus", max));
+            }
+            Choice {
+                value: queue.name.clone(),
+                detail: detail.join(", "),
+            }
+        })
+        .collect();
+    for name in config.queues.keys() {
+        match choices.iter_mut().find(|c| &c.value == name) {
+            Some(choice) if choice.detail.is_empty() => choice.detail = "configured".into(),
+            Some(_) => {}
+            None => choices.push(Choice {
+                value: name.clone(),
+                detail: "configured".into(),
+            }),
+        }
+    }
+    choices
+}
+
+/// The files of the template directory, by name.
+pub fn templates(config: &Config) -> Vec<Choice> {
+    let Some(dir) = config.template_dir() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    paths.retain(|p| p.is_file());
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| Choice {
+            value: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            detail: path.display().to_string(),
+        })
+        .collect()
+}
+
+/// How well `query` matches `candidate`, or `None` unless its letters appear
+/// in `candidate` in order. Runs of letters and letters starting a word count
+/// for more, gaps against.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let mut score = 0;
+    let mut next = 0;
+    for letter in query.chars().filter(|c| !c.is_whitespace()) {
+        let letter = letter.to_ascii_lowercase();
+        let found = (next..candidate.len()).find(|&i| candidate[i] == letter)?;
+        score += 1;
+        if found > 0 && found == next {
+            score += 4;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        if next > 0 {
+            score -= (found - next) as i64;
+        }
+        next = found + 1;
+    }
+    Some(score)
+}
+
+/// The indices of the `choices` that match `query`, best and then shortest first.
+fn ranked(choices: &[Choice], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(usize, i64)> = choices
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| Some((i, score(query, &c.value)?)))
+        .collect();
+    matches.sort_by_key(|&(i, score)| (std::cmp::Reverse(score), choices[i].value.len()));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+struct Picker<'a> {
+    what: &'a str,
+    choices: &'a [Choice],
+    query: String,
+    shown: Vec<usize>,
+    table: TableState,
+}
+
+impl Picker<'_> {
+    fn update(&mut self) {
+        self.shown = ranked(self.choices, &self.query);
+        self.table.select((!self.shown.is_empty()).then_some(0));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [prompt, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(frame.area());
+        frame.render_widget(Line::from(format!("{}> {}", self.what, self.query)), prompt);
+        let rows = self.shown.iter().map(|&i| {
+            let choice = &self.choices[i];
+            Row::new(vec![choice.value.clone(), choice.detail.clone()])
+        });
+        let width = self
+            .choices
+            .iter()
+            .map(|c| c.value.len())
+            .max()
+            .unwrap_or(0);
+        let table = Table::new(
+            rows,
+            [Constraint::Length(width as u16 + 2), Constraint::Min(0)],
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(format!(
+            " {}/{}  Enter pick  Esc cancel ",
+            self.shown.len(),
+            self.choices.len()
+        )));
+        frame.render_stateful_widget(table, list, &mut self.table);
+    }
+
+    /// `Some` once a choice is taken or given up on.
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Option<Option<String>> {
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Esc => return Some(None),
+            KeyCode::Char('c') if control => return Some(None),
+            KeyCode::Enter => {
+                let index = self.shown.get(self.table.selected()?)?;
+                return Some(Some(self.choices[*index].value.clone()));
+            }
+            KeyCode::Down => self.table.select_next(),
+            KeyCode::Char('n') if control => self.table.select_next(),
+            KeyCode::Up => self.table.select_previous(),
+            KeyCode::Char('p') if control => self.table.select_previous(),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update();
+            }
+            KeyCode::Char(c) if !control => {
+                self.query.push(c);
+                self.update();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Let the user pick one of `choices`, a `what` such as `queue`; `None` when
+/// they give up.
+pub fn pick(what: &str, choices: &[Choice]) -> io::Result<Option<String>> {
+    if choices.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("there is no {} to pick from", what),
+        ));
+    }
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(io::Error::other(format!(
+            "a terminal is needed to pick a {}; name it instead",
+            what
+        )));
+    }
+    let mut picker = Picker {
+        what,
+        choices,
+        query: String::new(),
+        shown: Vec::new(),
+        table: TableState::default(),
+    };
+    picker.update();
+
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let picked = Terminal::new(CrosstermBackend::new(io::stderr())).and_then(|mut terminal| loop {
+        terminal.draw(|frame| picker.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                if let Some(picked) = picker.handle_key(key.code, key.modifiers) {
+                    break Ok(picked);
+                }
+            }
+        }
+    });
+    disable_raw_mode()?;
+    execute!(io::stderr(), LeaveAlternateScreen)?;
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_and_rank() {
+        assert!(score("gpu", "gpu_long").unwrap() > score("gpu", "bigmem_pub").unwrap());
+        assert_eq!(score("xyz", "gpu_long"), None);
+        assert_eq!(score("", "batch"), Some(0));
+        let choices: Vec<Choice> = ["batch", "gpu_short", "gpu", "long"]
+            .into_iter()
+            .map(|value| Choice {
+                value: value.into(),
+                detail: String::new(),
+            })
+            .collect();
+        assert_eq!(ranked(&choices, "gpu"), [2, 1]);
+        assert_eq!(ranked(&choices, "lo"), [3]);
+        assert_eq!(ranked(&choices, "b"), [0]);
+    }
+}
@@ -0,0 +1,165 @@
+//! `qsub2 pipeline export`: the jobs of a group, and the jobs submitted with
+//! `--depend` on them, as a Makefile whose targets submit them again in
+//! dependency order.
+//!
+//! Each target runs `qsub2 rerun` on a recorded job and keeps the new job ID
+//! in `NAME.jobid`. A target that depends on others passes their new IDs
+//! with `--depend`, so make only has to order the submissions; the scheduler
+//! still does the waiting.
+
+use crate::history::{self, SubmissionRecord};
+use crate::shell_quote;
+use std::collections::HashMap;
+
+/// The latest jobs of `group`, followed by every later submission that
+/// depends on one of the jobs collected so far.
+pub fn jobs<'a>(records: &'a [SubmissionRecord], group: &str) -> Vec<&'a SubmissionRecord> {
+    let mut jobs = history::latest_in_group(records, group);
+    for record in records {
+        let in_graph = |id: &String| jobs.iter().any(|j| &j.job_id == id);
+        if in_graph(&record.job_id) {
+            continue;
+        }
+        let depends = record
+            .spec
+            .depend
+            .iter()
+            .any(|d| d.targets.iter().any(in_graph));
+        if depends {
+            jobs.push(record);
+        }
+    }
+    jobs
+}
+
+/// `align_a.jobid`, made unique with a number when names repeat.
+fn target_names(jobs: &[&SubmissionRecord]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    jobs.iter()
+        .map(|job| {
+            let name: String = job
+                .spec
+                .name
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' => c,
+                    _ => '_',
+                })
+                .collect();
+            let count = seen.entry(name.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => format!("{}.jobid", name),
+                n => format!("{}-{}.jobid", name, n),
+            }
+        })
+        .collect()
+}
+
+pub fn makefile(jobs: &[&SubmissionRecord], group: &str) -> String {
+    let targets = target_names(jobs);
+    let target_of: HashMap<&str, &str> = jobs
+        .iter()
+        .zip(&targets)
+        .map(|(job, target)| (job.job_id.as_str(), target.as_str()))
+        .collect();
+
+    let mut makefile = format!(
+        "# The jobs of group {} and the jobs that depend on them; `make -j` submits\n\
+         # them again. Each target keeps the new job ID: delete it, or make clean,\n\
+         # to submit that job once more.\n\
+         QSUB2 ?= qsub2\n\n\
+         .DELETE_ON_ERROR:\n\
+         .PHONY: all clean\n\n\
+         all: {}\n",
+        group,
+        targets.join(" ")
+    );
+    for (job, target) in jobs.iter().zip(&targets) {
+        let mut prerequisites = Vec::new();
+        let mut depend = Vec::new();
+        for dependency in &job.spec.depend {
+            let ids: Vec<String> = dependency
+                .targets
+                .iter()
+                .map(|id| match target_of.get(id.as_str()) {
+                    Some(prerequisite) => {
+                        prerequisites.push(*prerequisite);
+                        format!("$$(cat {})", prerequisite)
+                    }
+                    // outside the graph, so it is not submitted again
+                    None => id.clone(),
+                })
+                .collect();
+            depend.push(format!("{}:{}", dependency.kind, ids.join(":")));
+        }
+        makefile.push_str(&format!("\n{}:", target));
+        for prerequisite in &prerequisites {
+            makefile.push_str(&format!(" {}", prerequisite));
+        }
+        makefile.push_str(&format!(
+            "\n\t$(QSUB2) rerun --quiet {}",
+            shell_quote(&job.job_id)
+        ));
+        if !prerequisites.is_empty() {
+            makefile.push_str(&format!(" --depend \"{}\"", depend.join(",")));
+        }
+        makefile.push_str(" > $@\n");
+    }
+    makefile.push_str(&format!("\nclean:\n\trm -f {}\n", targets.join(" ")));
+    makefile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::JobSpec;
+    use crate::Dependency;
+    use chrono::Local;
+    use std::path::PathBuf;
+
+    fn record(job_id: &str, name: &str, group: Option<&str>, depend: &[&str]) -> SubmissionRecord {
+        SubmissionRecord {
+            job_id: job_id.to_string(),
+            script: PathBuf::from(format!("{}.sh", job_id)),
+            spec: JobSpec {
+                name: name.to_string(),
+                job_group: group.map(String::from),
+                depend: (!depend.is_empty())
+                    .then(|| Dependency {
+                        kind: "afterany".to_string(),
+                        targets: depend.iter().map(|d| d.to_string()).collect(),
+                    })
+                    .into_iter()
+                    .collect(),
+                ..JobSpec::default()
+            },
+            generated_at: Local::now(),
+            submitted_at: Local::now(),
+            fingerprint: None,
+            content: None,
+            invocation: None,
+            workdir: None,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn test_makefile() {
+        let records = [
+            record("1.pbs", "align", Some("g"), &[]),
+            record("2.pbs", "align", Some("g"), &[]),
+            record("3.pbs", "other", None, &[]),
+            record("4.pbs", "merge", None, &["1.pbs", "2.pbs", "0.pbs"]),
+        ];
+        let jobs = jobs(&records, "g");
+        assert_eq!(jobs.len(), 3);
+        let makefile = makefile(&jobs, "g");
+        assert!(makefile.contains("all: align.jobid align-2.jobid merge.jobid\n"));
+        assert!(makefile.contains("\nalign.jobid:\n\t$(QSUB2) rerun --quiet '1.pbs' > $@\n"));
+        assert!(makefile.contains(
+            "\nmerge.jobid: align.jobid align-2.jobid\n\t$(QSUB2) rerun --quiet '4.pbs' \
+             --depend \"afterany:$$(cat align.jobid):$$(cat align-2.jobid):0.pbs\" > $@\n"
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+//! `--preflight`: before submitting, check that the directories a job writes
+//! to are writable and have enough free space, so a long job does not fail
+//! at its last write.
+//!
+//! Free space is what `df` reports for the filesystem; a per-user quota that
+//! the filesystem does not show there is not seen.
+
+use crate::pbs;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// `dir`, or for one the job has yet to create, the closest ancestor that exists.
+fn existing(dir: &Path) -> PathBuf {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .find(|d| d.is_dir())
+        .unwrap_or(Path::new("/"))
+        .to_path_buf()
+}
+
+/// Whether a file can be created in `dir`; the test file is removed again.
+fn writable(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".qsub2-preflight-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// The free bytes and mount point of the filesystem `dir` is on, from `df -Pk`.
+fn free_space(dir: &Path) -> Option<(u64, String)> {
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let fields: Vec<&str> = stdout.lines().nth(1)?.split_whitespace().collect();
+    let available: u64 = fields.get(3)?.parse().ok()?;
+    Some((available * 1024, fields[5..].join(" ")))
+}
+
+/// Check every directory in `dirs`, and with `min_free` in bytes the free space
+/// of each filesystem they are on. Returns what is wrong, one problem a line.
+pub fn check(dirs: &[PathBuf], min_free: Option<u64>) -> Result<(), String> {
+    let mut problems = Vec::new();
+    let mut checked = HashSet::new();
+    let mut mounts = HashSet::new();
+    for dir in dirs {
+        let dir = existing(dir);
+        if !checked.insert(dir.clone()) {
+            continue;
+        }
+        if let Err(e) = writable(&dir) {
+            problems.push(format!("{} is not writable: {}", dir.display(), e));
+        }
+        let Some(min_free) = min_free else {
+            continue;
+        };
+        let Some((free, mount)) = free_space(&dir) else {
+            log::warn!("Cannot tell the free space of {}", dir.display());
+            continue;
+        };
+        if mounts.insert(mount.clone()) && free < min_free {
+            problems.push(format!(
+                "{} has {} free, less than the {} asked for",
+                mount,
+                pbs::format_size(free),
+                pbs::format_size(min_free)
+            ));
+        }
+    }
+    log::debug!("Preflight: checked {} directories", checked.len());
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(problems.join("\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = std::env::temp_dir();
+        assert_eq!(existing(&dir.join("not/made/yet")), dir);
+        assert!(check(&[dir.join("not/made/yet")], None).is_ok());
+        let e = check(std::slice::from_ref(&dir), Some(u64::MAX)).unwrap_err();
+        assert!(e.contains("free, less than"), "{}", e);
+    }
+}
@@ -0,0 +1,179 @@
+//! `--preview`: show the rendered script with its directives summarized in a
+//! box and simple shell highlighting, then ask before submitting.
+
+use crate::style;
+use anstyle::Style;
+use std::io::{self, BufRead, Write};
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "return", "exit", "export", "local", "set", "cd", "source", "trap",
+];
+
+/// The directive box followed by the highlighted script.
+pub fn print(script: &str) {
+    print_directives(script);
+    for line in script.lines() {
+        let mut rendered = String::new();
+        for (style, text) in highlight(line) {
+            match style {
+                Some(style) => rendered.push_str(&format!(
+                    "{}{}{}",
+                    style.render(),
+                    text,
+                    style.render_reset()
+                )),
+                None => rendered.push_str(text),
+            }
+        }
+        anstream::println!("{}", rendered);
+    }
+}
+
+/// ```text
+/// ┌ PBS directives ─────────┐
+/// │ -N align                │
+/// │ -l select=1:ncpus=4     │
+/// └─────────────────────────┘
+/// ```
+fn print_directives(script: &str) {
+    let directives: Vec<&str> = script
+        .lines()
+        .filter_map(|l| l.strip_prefix("#PBS "))
+        .map(str::trim)
+        .collect();
+    if directives.is_empty() {
+        return;
+    }
+    let title = " PBS directives ";
+    let width = directives
+        .iter()
+        .map(|d| d.chars().count() + 2)
+        .chain([title.len() + 1])
+        .max()
+        .unwrap_or_default();
+    anstream::println!(
+        "┌{}{}{}{}┐",
+        style::LABEL.render(),
+        title,
+        style::LABEL.render_reset(),
+        "─".repeat(width - title.len())
+    );
+    for directive in directives {
+        println!("│ {:<w$} │", directive, w = width - 2);
+    }
+    println!("└{}┘", "─".repeat(width));
+}
+
+/// Split a line into pieces to color: `#PBS` lines, comments, quoted strings,
+/// `$variables` and keywords. Good enough for job scripts, not a shell parser.
+fn highlight(line: &str) -> Vec<(Option<Style>, &str)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("#PBS") {
+        return vec![(Some(style::DIRECTIVE), line)];
+    }
+    let mut pieces = Vec::new();
+    let bytes = line.as_bytes();
+    let mut plain = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let at_word_start = i == 0 || bytes[i - 1].is_ascii_whitespace() || bytes[i - 1] == b';';
+        let end = match bytes[i] {
+            b'#' if at_word_start => Some((style::COMMENT, line.len())),
+            quote @ (b'\'' | b'"') => {
+                let close = line[i + 1..]
+                    .find(quote as char)
+                    .map_or(line.len(), |j| i + j + 2);
+                Some((style::STRING, close))
+            }
+            b'$' if line[i + 1..].starts_with('{') => {
+                let close = line[i..].find('}').map_or(line.len(), |j| i + j + 1);
+                Some((style::VARIABLE, close))
+            }
+            b'$' => {
+                let length = line[i + 1..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(line.len() - i - 1);
+                // $?, $# and friends are one character
+                let special = line[i + 1..].starts_with(['?', '#', '@', '*', '!', '$', '-']);
+                let length = if length == 0 && special { 1 } else { length };
+                Some((style::VARIABLE, i + 1 + length))
+            }
+            c if at_word_start && c.is_ascii_alphabetic() => {
+                let word_end = line[i..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .map_or(line.len(), |j| i + j);
+                KEYWORDS
+                    .contains(&&line[i..word_end])
+                    .then_some((style::KEYWORD, word_end))
+            }
+            _ => None,
+        };
+        match end {
+            Some((style, end)) => {
+                if plain < i {
+                    pieces.push((None, &line[plain..i]));
+                }
+                pieces.push((Some(style), &line[i..end]));
+                i = end;
+                plain = end;
+            }
+            None => i += 1,
+        }
+    }
+    if plain < line.len() {
+        pieces.push((None, &line[plain..]));
+    }
+    pieces
+}
+
+/// Ask `question` on stderr; the answer, trimmed and in lower case.
+pub fn ask(question: &str) -> io::Result<String> {
+    eprint!("{} ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_ascii_lowercase())
+}
+
+/// Ask a yes/no `question` on stderr; anything but `y` or `yes` is a no.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    let answer = ask(&format!("{} [y/N]", question))?;
+    Ok(matches!(answer.as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let pieces = highlight("if [ -n \"$x\" ]; then echo $HOME # done");
+        assert_eq!(
+            pieces,
+            [
+                (Some(style::KEYWORD), "if"),
+                (None, " [ -n "),
+                (Some(style::STRING), "\"$x\""),
+                (None, " ]; "),
+                (Some(style::KEYWORD), "then"),
+                (None, " echo "),
+                (Some(style::VARIABLE), "$HOME"),
+                (None, " "),
+                (Some(style::COMMENT), "# done"),
+            ]
+        );
+        assert_eq!(
+            highlight("#PBS -N x"),
+            [(Some(style::DIRECTIVE), "#PBS -N x")]
+        );
+        assert_eq!(
+            highlight("exit $?"),
+            [
+                (Some(style::KEYWORD), "exit"),
+                (None, " "),
+                (Some(style::VARIABLE), "$?"),
+            ]
+        );
+    }
+}
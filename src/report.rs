@@ -0,0 +1,110 @@
+//! Requested vs. used resources of a finished job (`qsub2 report`).
+
+use crate::pbs::{self, JobAttributes};
+use crate::scheduler;
+use std::io;
+
+/// Attributes of the job from `qstat -fx`, or from the accounting records
+/// printed by `tracejob` once the server has forgotten the job.
+pub fn usage_attributes(job_id: &str) -> io::Result<JobAttributes> {
+    if let Some(attributes) = scheduler::job_status_with_history(job_id)? {
+        if attributes.keys().any(|k| k.starts_with("resources_used.")) {
+            return Ok(attributes);
+        }
+    }
+    let output = pbs::run("tracejob", &[job_id])?;
+    let attributes = parse_tracejob(&output);
+    if attributes.is_empty() {
+        return Err(io::Error::other(format!(
+            "no resource usage recorded for job {}",
+            job_id
+        )));
+    }
+    Ok(attributes)
+}
+
+/// Collect `Resource_List.*`, `resources_used.*` and `Exit_status` tokens from tracejob output.
+fn parse_tracejob(output: &str) -> JobAttributes {
+    output
+        .split_whitespace()
+        .filter_map(|token| token.split_once('='))
+        .filter(|(key, _)| {
+            key.starts_with("Resource_List.")
+                || key.starts_with("resources_used.")
+                || *key == "Exit_status"
+        })
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub resource: &'static str,
+    pub requested: String,
+    pub used: String,
+    /// Percent of the request that was used.
+    pub efficiency: Option<f64>,
+}
+
+pub fn lines(attributes: &JobAttributes) -> Vec<Line> {
+    let get = |key: &str| attributes.get(key).map(String::as_str);
+    let walltime = get("resources_used.walltime").and_then(pbs::parse_walltime);
+    let requested_walltime = get("Resource_List.walltime").and_then(pbs::parse_walltime);
+    let cput = get("resources_used.cput").and_then(pbs::parse_walltime);
+    let ncpus: Option<u64> = get("Resource_List.ncpus").and_then(|n| n.parse().ok());
+    let mem = get("resources_used.mem").and_then(pbs::parse_size);
+    let requested_mem = get("Resource_List.mem").and_then(pbs::parse_size);
+
+    let percent = |used: Option<f64>, requested: Option<f64>| match (used, requested) {
+        (Some(used), Some(requested)) if requested > 0.0 => Some(used / requested * 100.0),
+        _ => None,
+    };
+    // Average number of CPUs kept busy over the run
+    let cpus_used = match (cput, walltime) {
+        (Some(cput), Some(walltime)) if walltime > 0 => Some(cput as f64 / walltime as f64),
+        _ => None,
+    };
+
+    vec![
+        Line {
+            resource: "cpus",
+            requested: ncpus.map_or("-".to_string(), |n| n.to_string()),
+            used: cpus_used.map_or("-".to_string(), |c| format!("{:.1}", c)),
+            efficiency: percent(cpus_used, ncpus.map(|n| n as f64)),
+        },
+        Line {
+            resource: "walltime",
+            requested: requested_walltime.map_or("-".to_string(), pbs::format_walltime),
+            used: walltime.map_or("-".to_string(), pbs::format_walltime),
+            efficiency: percent(
+                walltime.map(|w| w as f64),
+                requested_walltime.map(|w| w as f64),
+            ),
+        },
+        Line {
+            resource: "mem",
+            requested: requested_mem.map_or("-".to_string(), pbs::format_size),
+            used: mem.map_or("-".to_string(), pbs::format_size),
+            efficiency: percent(mem.map(|m| m as f64), requested_mem.map(|m| m as f64)),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_from_tracejob() {
+        let output = "10/14/2026 06:03:04  S    Obit received. momhop:1 serverhop:1 state:4 substate:42\n10/14/2026 06:03:04  A    user=alice Resource_List.ncpus=4 Resource_List.mem=16gb Resource_List.walltime=10:00:00 resources_used.cput=06:00:00 resources_used.mem=4194304kb resources_used.walltime=02:00:00 Exit_status=0\n";
+        let attributes = parse_tracejob(output);
+        assert_eq!(attributes["Exit_status"], "0");
+        let lines = lines(&attributes);
+        assert_eq!(lines[0].used, "3.0");
+        assert_eq!(lines[0].efficiency, Some(75.0));
+        assert_eq!(lines[1].requested, "10:00:00");
+        assert_eq!(lines[1].efficiency, Some(20.0));
+        assert_eq!(lines[2].used, "4gb");
+        assert_eq!(lines[2].efficiency, Some(25.0));
+    }
+}
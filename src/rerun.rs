@@ -0,0 +1,241 @@
+//! Submit a job from the history again (`qsub2 rerun`).
+//!
+//! By default the recorded command line is replayed in the directory it was
+//! run from. With `--edit`, or for records without a command line, the
+//! resolved job spec is submitted instead, after opening it in `$EDITOR`.
+
+use crate::error::{Error, Result};
+use crate::history::{self, SubmissionRecord};
+use crate::pbs;
+use crate::spec::JobSpec;
+use crate::{
+    open_in_editor, render_job_script, run, submit_and_record, Cli, RerunArgs, RestartArgs,
+};
+use chrono::Local;
+use clap::Parser;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn rerun(cli: &Cli, args: &RerunArgs) -> Result<()> {
+    let path = history::default_path()
+        .ok_or_else(|| Error::Usage("cannot locate the history file; set QSUB2_HISTORY".into()))?;
+    let mut records = history::read(&path).map_err(|e| Error::Io("reading history", e))?;
+    if let Some(ref group) = args.group {
+        records.retain(SubmissionRecord::is_mine);
+        return rerun_group(cli, &records, group, args.only_failed);
+    }
+    let record = match args.job_id {
+        Some(ref job_id) => records.iter().rev().find(|r| &r.job_id == job_id),
+        None => records.iter().rev().find(|r| r.is_mine()),
+    }
+    .ok_or_else(|| Error::Usage("no such submission in the history".into()))?;
+
+    if let Some(ref workdir) = record.workdir {
+        env::set_current_dir(workdir)
+            .map_err(|e| Error::Io("changing to the submission directory", e))?;
+    }
+    let mut spec = record.spec.clone();
+    if !args.depend.is_empty() {
+        spec.depend = args.depend.clone();
+    }
+    // The command line of a --per-file job (or one to change) would submit more than this job.
+    let whole_batch = |invocation: &[String]| {
+        Cli::try_parse_from(invocation).is_ok_and(|c| c.per_file) || !args.depend.is_empty()
+    };
+    match record.invocation {
+        Some(ref invocation) if !args.edit && !whole_batch(invocation) => replay(cli, invocation),
+        _ if args.edit => resubmit_spec(cli, record, edit_spec(&spec)?),
+        _ => resubmit_spec(cli, record, spec),
+    }
+}
+
+/// Submit the jobs of a group again. The recorded command line of a
+/// `--per-file` job covers the whole batch, so each job's spec is used instead.
+fn rerun_group(
+    cli: &Cli,
+    records: &[SubmissionRecord],
+    group: &str,
+    only_failed: bool,
+) -> Result<()> {
+    let members = history::latest_in_group(records, group);
+    if members.is_empty() {
+        return Err(Error::Usage(format!(
+            "no jobs in the history belong to group {}",
+            group
+        )));
+    }
+    let selected: Vec<&SubmissionRecord> = members
+        .into_iter()
+        .filter(|r| !only_failed || history::resolve_state(&r.job_id).failed())
+        .collect();
+    if selected.is_empty() {
+        log::info!("No jobs of group {} failed", group);
+        return Ok(());
+    }
+    let start = env::current_dir().map_err(|e| Error::Io("reading the current directory", e))?;
+    let mut failed = 0;
+    for record in &selected {
+        let workdir = record.workdir.as_deref().unwrap_or(&start);
+        let result = env::set_current_dir(workdir)
+            .map_err(|e| Error::Io("changing to the submission directory", e))
+            .and_then(|()| resubmit_spec(cli, record, record.spec.clone()));
+        if let Err(e) = result {
+            log::error!("{}: {}", record.job_id, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        let message = format!(
+            "{} of {} jobs could not be submitted",
+            failed,
+            selected.len()
+        );
+        return Err(Error::Io("submitting the group", io::Error::other(message)));
+    }
+    Ok(())
+}
+
+fn replay(cli: &Cli, invocation: &[String]) -> Result<()> {
+    log::info!("Replaying: {}", invocation.join(" "));
+    let mut job_cli = Cli::try_parse_from(invocation).map_err(|e| Error::Usage(e.to_string()))?;
+    job_cli.config = cli.config.clone();
+    job_cli.invocation = invocation.to_vec();
+    job_cli.quiet |= cli.quiet;
+    // Resubmitting the same job is the point here.
+    job_cli.allow_duplicate = true;
+    run(&job_cli)
+}
+
+/// `qsub2 restart`: go on from where a checkpointed job stopped.
+pub fn restart(cli: &Cli, args: &RestartArgs) -> Result<()> {
+    let path = history::default_path()
+        .ok_or_else(|| Error::Usage("cannot locate the history file; set QSUB2_HISTORY".into()))?;
+    let records = history::read(&path).map_err(|e| Error::Io("reading history", e))?;
+    let record = records
+        .iter()
+        .rev()
+        .find(|r| r.job_id == args.job_id)
+        .ok_or_else(|| Error::Usage("no such submission in the history".into()))?;
+    let Some(ref checkpoint) = record.spec.checkpoint else {
+        return Err(Error::Usage(format!(
+            "{} was not submitted with --checkpoint",
+            record.job_id
+        )));
+    };
+    if !checkpoint.dmtcp {
+        // The server keeps the image with the job, so only it can restart it.
+        pbs::run("qrerun", &[&record.job_id]).map_err(|e| Error::Io("running qrerun", e))?;
+        println!("{}", record.job_id);
+        return Ok(());
+    }
+
+    if let Some(ref workdir) = record.workdir {
+        env::set_current_dir(workdir)
+            .map_err(|e| Error::Io("changing to the submission directory", e))?;
+    }
+    let images = fs::read_dir(checkpoint.dir())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().ends_with(".dmtcp"))
+                .count()
+        })
+        .unwrap_or(0);
+    if images == 0 {
+        return Err(Error::Usage(format!(
+            "no checkpoint images in {}; the job may not have reached its first checkpoint",
+            checkpoint.dir().display()
+        )));
+    }
+    let mut spec = record.spec.clone();
+    if let Some(ref mut checkpoint) = spec.checkpoint {
+        checkpoint.restart = true;
+    }
+    resubmit_spec(cli, record, spec)
+}
+
+/// `qsub2 requeue`, run by the job's SIGTERM trap: submit the next attempt
+/// unless the job was cancelled or has used up its attempts.
+pub fn requeue(cli: &Cli, args: &RestartArgs) -> Result<()> {
+    let path = history::default_path()
+        .ok_or_else(|| Error::Usage("cannot locate the history file; set QSUB2_HISTORY".into()))?;
+    let records = history::read(&path).map_err(|e| Error::Io("reading history", e))?;
+    let record = records
+        .iter()
+        .rev()
+        .find(|r| r.job_id == args.job_id)
+        .ok_or_else(|| Error::Usage("no such submission in the history".into()))?;
+    let Some(ref requeue) = record.spec.requeue else {
+        return Err(Error::Usage(format!(
+            "{} was not submitted with --requeue-on-preempt",
+            record.job_id
+        )));
+    };
+    if history::was_cancelled(&record.job_id) {
+        log::info!("{} was cancelled; not requeueing it", record.job_id);
+        return Ok(());
+    }
+    if record.spec.resumed >= requeue.max {
+        log::warn!(
+            "{} was preempted after {} requeues; giving up",
+            record.job_id,
+            requeue.max
+        );
+        return Ok(());
+    }
+    if let Some(ref workdir) = record.workdir {
+        env::set_current_dir(workdir)
+            .map_err(|e| Error::Io("changing to the submission directory", e))?;
+    }
+    let mut spec = record.spec.clone();
+    spec.resumed += 1;
+    log::info!(
+        "{} was preempted; submitting attempt {} of {}",
+        record.job_id,
+        spec.resumed + 1,
+        requeue.max + 1
+    );
+    resubmit_spec(cli, record, spec)
+}
+
+fn resubmit_spec(cli: &Cli, record: &SubmissionRecord, spec: JobSpec) -> Result<()> {
+    spec.validate()?;
+    let generated_at = Local::now();
+    let script = render_job_script(&spec)?;
+    let outfile = (record.script != Path::new("-")).then_some(record.script.as_path());
+    if let Some(outfile) = outfile {
+        fs::write(outfile, &script).map_err(|e| Error::Render(outfile.to_path_buf(), e))?;
+    }
+    let job_cli = Cli {
+        config: cli.config.clone(),
+        quiet: cli.quiet,
+        no_scheduler: cli.no_scheduler,
+        submission_log: cli.submission_log.clone(),
+        allow_duplicate: true,
+        ..Default::default()
+    };
+    let job_id = submit_and_record(&job_cli, &spec, &script, outfile, &[], generated_at)?;
+    println!("{}", job_id);
+    Ok(())
+}
+
+/// Open the spec as JSON in `$VISUAL` / `$EDITOR` and read it back.
+fn edit_spec(spec: &JobSpec) -> Result<JobSpec> {
+    let path: PathBuf = env::temp_dir().join(format!("qsub2-rerun-{}.json", std::process::id()));
+    let json = serde_json::to_string_pretty(spec).unwrap_or_default();
+    fs::write(&path, json + "\n").map_err(|e| Error::Io("writing the spec to edit", e))?;
+
+    let edited = open_in_editor(&path).and_then(|()| {
+        fs::read_to_string(&path).map_err(|e| Error::Io("reading the edited spec", e))
+    });
+    let _ = fs::remove_file(&path);
+    let edited = edited?;
+    serde_json::from_str(&edited).map_err(|e| {
+        Error::Io(
+            "reading the edited spec",
+            io::Error::new(io::ErrorKind::InvalidData, e),
+        )
+    })
+}
@@ -0,0 +1,384 @@
+//! `routes` in the configuration: which queue a job goes to when `--queue`
+//! is not given, so a lab can keep its submission policy in one shared file.
+//!
+//! A route is `CONDITION [and CONDITION...] -> QUEUE`; the first route whose
+//! conditions all hold wins, and without one `default.queue` applies.
+//! A condition compares a property of the job with a value:
+//!
+//! ```text
+//! walltime <= 1h -> express
+//! ngpus > 0 -> gpu
+//! name ~ "^qc_" and ncpus <= 4 -> short
+//! ```
+//!
+//! `ncpus`, `ngpus`, `mem` and `walltime` take `<`, `<=`, `>`, `>=`, `==`
+//! and `!=`; `name` and `command` take `==`, `!=`, and `~` with a regular
+//! expression of `^ $ . [...] * + ?` and `\` escapes.
+
+use crate::pbs;
+use crate::spec::JobSpec;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Property {
+    Name,
+    Command,
+    Ncpus,
+    Ngpus,
+    Mem,
+    Walltime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Matches,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(u64),
+    Text(String),
+    Pattern(Regex),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    property: Property,
+    op: Op,
+    value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Route {
+    /// As written in the configuration.
+    pub text: String,
+    conditions: Vec<Condition>,
+    pub queue: String,
+}
+
+const OPS: [(&str, Op); 7] = [
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("~", Op::Matches),
+];
+
+fn parse_condition(s: &str) -> Result<Condition, String> {
+    let (at, symbol, op) = OPS
+        .iter()
+        .filter_map(|&(symbol, op)| s.find(symbol).map(|at| (at, symbol, op)))
+        .min_by_key(|&(at, symbol, _)| (at, std::cmp::Reverse(symbol.len())))
+        .ok_or_else(|| format!("'{}' has no comparison such as <= or ~", s))?;
+    let name = s[..at].trim();
+    let value = s[at + symbol.len()..].trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    let property = match name {
+        "name" => Property::Name,
+        "command" => Property::Command,
+        "ncpus" => Property::Ncpus,
+        "ngpus" => Property::Ngpus,
+        "mem" => Property::Mem,
+        "walltime" => Property::Walltime,
+        _ => {
+            return Err(format!(
+                "unknown property '{}', expected name, command, ncpus, ngpus, mem or walltime",
+                name
+            ))
+        }
+    };
+    let value = match property {
+        Property::Name | Property::Command => match op {
+            Op::Matches => Value::Pattern(Regex::new(value)?),
+            Op::Eq | Op::Ne => Value::Text(value.to_string()),
+            _ => return Err(format!("{} takes ==, != or ~, not {}", name, symbol)),
+        },
+        _ if op == Op::Matches => return Err(format!("{} is a number; ~ is for text", name)),
+        Property::Ncpus | Property::Ngpus => Value::Number(
+            value
+                .parse()
+                .map_err(|_| format!("{} takes a number, not '{}'", name, value))?,
+        ),
+        Property::Mem => Value::Number(
+            pbs::parse_size(value)
+                .ok_or_else(|| format!("mem {} is not a size such as 16gb", value))?,
+        ),
+        Property::Walltime => Value::Number(
+            crate::parse_walltime_arg(value)
+                .ok()
+                .and_then(|w| pbs::parse_walltime(&w))
+                .ok_or_else(|| format!("walltime {} is not a duration such as 1h", value))?,
+        ),
+    };
+    Ok(Condition {
+        property,
+        op,
+        value,
+    })
+}
+
+impl TryFrom<String> for Route {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let (conditions, queue) = text
+            .rsplit_once("->")
+            .ok_or_else(|| format!("route '{}' has no -> QUEUE", text))?;
+        let queue = queue.trim();
+        if queue.is_empty() {
+            return Err(format!("route '{}' has no queue after ->", text));
+        }
+        let conditions = conditions
+            .split(" and ")
+            .map(parse_condition)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("route '{}': {}", text, e))?;
+        Ok(Route {
+            queue: queue.to_string(),
+            conditions,
+            text,
+        })
+    }
+}
+
+impl From<Route> for String {
+    fn from(route: Route) -> String {
+        route.text
+    }
+}
+
+impl Condition {
+    fn holds(&self, spec: &JobSpec) -> bool {
+        let number = match self.property {
+            Property::Name => return self.holds_for_text(&spec.name),
+            Property::Command => return self.holds_for_text(&spec.command),
+            Property::Ncpus => crate::cost::resources(spec).ok().map(|(cores, _)| cores),
+            Property::Ngpus => crate::cost::resources(spec).ok().map(|(_, gpus)| gpus),
+            Property::Mem => spec.mem.as_deref().and_then(pbs::parse_size),
+            Property::Walltime => pbs::parse_walltime(&spec.walltime),
+        };
+        let (Some(number), Value::Number(value)) = (number, &self.value) else {
+            return false;
+        };
+        match self.op {
+            Op::Lt => number < *value,
+            Op::Le => number <= *value,
+            Op::Gt => number > *value,
+            Op::Ge => number >= *value,
+            Op::Eq => number == *value,
+            Op::Ne => number != *value,
+            Op::Matches => false,
+        }
+    }
+
+    fn holds_for_text(&self, text: &str) -> bool {
+        match (&self.value, self.op) {
+            (Value::Pattern(regex), _) => regex.is_match(text),
+            (Value::Text(value), Op::Eq) => text == value,
+            (Value::Text(value), Op::Ne) => text != value,
+            _ => false,
+        }
+    }
+}
+
+impl Route {
+    fn matches(&self, spec: &JobSpec) -> bool {
+        self.conditions.iter().all(|c| c.holds(spec))
+    }
+}
+
+/// The first of `routes` that `spec` matches.
+pub fn route<'a>(routes: &'a [Route], spec: &JobSpec) -> Option<&'a Route> {
+    routes.iter().find(|r| r.matches(spec))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Atom {
+    Any,
+    Char(char),
+    /// `[a-z_]`, or with `[^...]` any character but those.
+    Class(bool, Vec<(char, char)>),
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            Atom::Char(expected) => c == *expected,
+            Atom::Class(negated, ranges) => {
+                ranges.iter().any(|&(from, to)| (from..=to).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Repeat {
+    Once,
+    Optional,
+    Any,
+    Some,
+}
+
+/// The small part of regular expressions that routes need.
+#[derive(Clone, Debug, PartialEq)]
+struct Regex {
+    anchored_start: bool,
+    anchored_end: bool,
+    items: Vec<(Atom, Repeat)>,
+}
+
+impl Regex {
+    fn new(pattern: &str) -> Result<Self, String> {
+        let mut chars = pattern.chars().peekable();
+        let anchored_start = chars.next_if_eq(&'^').is_some();
+        let mut anchored_end = false;
+        let mut items: Vec<(Atom, Repeat)> = Vec::new();
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '$' if chars.peek().is_none() => {
+                    anchored_end = true;
+                    continue;
+                }
+                '*' | '+' | '?' => {
+                    let Some(last) = items.last_mut().filter(|(_, r)| *r == Repeat::Once) else {
+                        return Err(format!("nothing to repeat with {} in {}", c, pattern));
+                    };
+                    last.1 = match c {
+                        '*' => Repeat::Any,
+                        '+' => Repeat::Some,
+                        _ => Repeat::Optional,
+                    };
+                    continue;
+                }
+                '.' => Atom::Any,
+                '\\' => Atom::Char(
+                    chars
+                        .next()
+                        .ok_or_else(|| format!("{} ends with \\", pattern))?,
+                ),
+                '[' => {
+                    let negated = chars.next_if_eq(&'^').is_some();
+                    let mut ranges = Vec::new();
+                    loop {
+                        let from = match chars.next() {
+                            Some(']') if !ranges.is_empty() => break,
+                            Some(c) => c,
+                            None => return Err(format!("[ is not closed in {}", pattern)),
+                        };
+                        let mut ahead = chars.clone();
+                        let to = match (ahead.next(), ahead.next()) {
+                            (Some('-'), Some(to)) if to != ']' => {
+                                chars.nth(1);
+                                to
+                            }
+                            _ => from,
+                        };
+                        ranges.push((from, to));
+                    }
+                    Atom::Class(negated, ranges)
+                }
+                '(' | ')' | '|' | '{' | '}' => {
+                    return Err(format!("{} is not supported in {}", c, pattern))
+                }
+                c => Atom::Char(c),
+            };
+            items.push((atom, Repeat::Once));
+        }
+        Ok(Regex {
+            anchored_start,
+            anchored_end,
+            items,
+        })
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        if self.anchored_start {
+            return self.match_here(&self.items, &text);
+        }
+        (0..=text.len()).any(|i| self.match_here(&self.items, &text[i..]))
+    }
+
+    fn match_here(&self, items: &[(Atom, Repeat)], text: &[char]) -> bool {
+        let Some(((atom, repeat), rest)) = items.split_first() else {
+            return !self.anchored_end || text.is_empty();
+        };
+        let (min, max) = match repeat {
+            Repeat::Once => (1, 1),
+            Repeat::Optional => (0, 1),
+            Repeat::Any => (0, usize::MAX),
+            Repeat::Some => (1, usize::MAX),
+        };
+        let available = text
+            .iter()
+            .take(max)
+            .take_while(|&&c| atom.matches(c))
+            .count();
+        (min..=available)
+            .rev()
+            .any(|n| self.match_here(rest, &text[n..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex() {
+        let matches = |p: &str, t: &str| Regex::new(p).unwrap().is_match(t);
+        assert!(matches("^qc_", "qc_sample1"));
+        assert!(!matches("^qc_", "run_qc_1"));
+        assert!(matches("_R[12]$", "s1_R2"));
+        assert!(matches("^a.*b+c?$", "axxbb"));
+        assert!(!matches("^[^0-9]+$", "abc1"));
+        assert!(matches("\\.bam$", "x.bam"));
+        assert!(Regex::new("(a|b)").is_err());
+        assert!(Regex::new("*a").is_err());
+    }
+
+    #[test]
+    fn test_route() {
+        let routes: Vec<Route> = [
+            "ngpus > 0 -> gpu",
+            "name ~ \"^qc_\" and ncpus <= 4 -> short",
+            "walltime <= 1h -> express",
+        ]
+        .iter()
+        .map(|r| Route::try_from(r.to_string()).unwrap())
+        .collect();
+        let spec = |name: &str, ncpus, walltime: &str| JobSpec {
+            name: name.into(),
+            ncpus,
+            walltime: walltime.into(),
+            ..JobSpec::default()
+        };
+        let queue = |spec: &JobSpec| route(&routes, spec).map(|r| r.queue.as_str());
+        assert_eq!(queue(&spec("qc_a", 2, "10:00:00")), Some("short"));
+        assert_eq!(queue(&spec("qc_a", 8, "00:30:00")), Some("express"));
+        assert_eq!(queue(&spec("align", 8, "10:00:00")), None);
+        let gpu = JobSpec {
+            constraints: vec!["ngpus=1".into()],
+            ..spec("qc_a", 2, "10:00:00")
+        };
+        assert_eq!(queue(&gpu), Some("gpu"));
+
+        assert!(Route::try_from("ncpus > 4".to_string()).is_err());
+        assert!(Route::try_from("cpus > 4 -> big".to_string()).is_err());
+        assert!(Route::try_from("mem < 1gb and walltime < x -> small".to_string()).is_err());
+        assert!(Route::try_from("ncpus ~ 4 -> big".to_string()).is_err());
+    }
+}
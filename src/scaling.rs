@@ -0,0 +1,110 @@
+//! Resource requests that grow with the size of the input files,
+//! e.g. `--mem-rule "2gb + 0.5gb/gb"` or `--walltime-rule "1h + 20m/gb"`.
+
+const GB: f64 = (1u64 << 30) as f64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Bytes,
+    Seconds,
+}
+
+/// `base + per_gb * input size in GB`, both in bytes or seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    base: f64,
+    per_gb: f64,
+}
+
+impl Rule {
+    pub fn evaluate(&self, input_bytes: u64) -> f64 {
+        self.base + self.per_gb * input_bytes as f64 / GB
+    }
+}
+
+pub fn parse_mem_rule(s: &str) -> Result<Rule, String> {
+    parse_rule(s, Unit::Bytes)
+}
+
+pub fn parse_walltime_rule(s: &str) -> Result<Rule, String> {
+    parse_rule(s, Unit::Seconds)
+}
+
+/// Terms separated by `+`; a term ending in `/gb` (or `per gb`) is scaled by the input size.
+fn parse_rule(s: &str, unit: Unit) -> Result<Rule, String> {
+    let mut rule = Rule {
+        base: 0.0,
+        per_gb: 0.0,
+    };
+    for term in s.split('+') {
+        let term = term.trim().to_lowercase();
+        let term = term.strip_suffix("of input").unwrap_or(&term).trim_end();
+        match term
+            .strip_suffix("/gb")
+            .or_else(|| term.strip_suffix(" per gb"))
+        {
+            Some(amount) => rule.per_gb += parse_amount(amount.trim(), unit, s)?,
+            None => rule.base += parse_amount(term, unit, s)?,
+        }
+    }
+    Ok(rule)
+}
+
+/// `0.5gb`, `512mb`, `1.5h`, `20m`
+fn parse_amount(amount: &str, unit: Unit, rule: &str) -> Result<f64, String> {
+    let split = amount
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(amount.len());
+    let (number, suffix) = amount.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid amount '{}' in rule '{}'", amount, rule))?;
+    let multiplier = match (unit, suffix.trim()) {
+        (Unit::Bytes, "b") => 1.0,
+        (Unit::Bytes, "kb") => (1u64 << 10) as f64,
+        (Unit::Bytes, "mb") => (1u64 << 20) as f64,
+        (Unit::Bytes, "gb") => GB,
+        (Unit::Bytes, "tb") => (1u64 << 40) as f64,
+        (Unit::Seconds, "s") => 1.0,
+        (Unit::Seconds, "m") => 60.0,
+        (Unit::Seconds, "h") => 60.0 * 60.0,
+        (Unit::Seconds, "d") => 24.0 * 60.0 * 60.0,
+        _ => return Err(format!("invalid unit in '{}' in rule '{}'", amount, rule)),
+    };
+    Ok(number * multiplier)
+}
+
+/// Round up to whole megabytes, written in gigabytes when that is exact.
+pub fn mem_request(bytes: f64) -> String {
+    let mb = (bytes / (1u64 << 20) as f64).ceil().max(1.0) as u64;
+    if mb.is_multiple_of(1024) {
+        format!("{}gb", mb / 1024)
+    } else {
+        format!("{}mb", mb)
+    }
+}
+
+/// Round up to whole minutes.
+pub fn walltime_request(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).ceil().max(1.0) as u64;
+    crate::pbs::format_walltime(minutes * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let mem = parse_mem_rule("2gb + 0.5gb per GB of input").unwrap();
+        assert_eq!(mem, parse_mem_rule("2gb+0.5gb/gb").unwrap());
+        assert_eq!(mem_request(mem.evaluate(0)), "2gb");
+        assert_eq!(mem_request(mem.evaluate(3 << 29)), "2816mb");
+
+        let walltime = parse_walltime_rule("1h + 20m/gb").unwrap();
+        assert_eq!(walltime_request(walltime.evaluate(3 << 30)), "02:00:00");
+
+        assert!(parse_mem_rule("2h").is_err());
+        assert!(parse_walltime_rule("lots").is_err());
+    }
+}
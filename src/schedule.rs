@@ -0,0 +1,97 @@
+//! Recurring submissions (`qsub2 schedule`), either by staying in the
+//! foreground or as a crontab entry.
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use std::time::Duration;
+
+/// Parse `HH:MM`.
+pub fn parse_at(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| format!("'{}' is not a time such as 02:00", s))
+}
+
+/// The first time after `now` on the grid that starts at today's `at` (or at
+/// `now` itself without one) and repeats `every`.
+pub fn next_run(now: DateTime<Local>, at: Option<NaiveTime>, every: Duration) -> DateTime<Local> {
+    let Some(at) = at else {
+        return now + every;
+    };
+    let start = Local
+        .from_local_datetime(&now.date_naive().and_time(at))
+        .earliest()
+        .unwrap_or(now);
+    if start > now {
+        return start;
+    }
+    let every = every.as_secs().max(1);
+    let elapsed = (now - start).num_seconds().max(0) as u64;
+    start + Duration::from_secs((elapsed / every + 1) * every)
+}
+
+/// The five time fields of a crontab line. Cron can only express intervals
+/// that divide an hour or a day evenly, or exactly one day.
+pub fn crontab_timing(at: Option<NaiveTime>, every: Duration) -> Result<String, String> {
+    use chrono::Timelike;
+    let (hour, minute) = at.map_or((0, 0), |t| (t.hour(), t.minute()));
+    let seconds = every.as_secs();
+    let unsupported = || {
+        Err(format!(
+            "cron cannot run every {}s; use 1d, a divisor of 24h, or a divisor of 60m",
+            seconds
+        ))
+    };
+    match seconds {
+        86400 => Ok(format!("{} {} * * *", minute, hour)),
+        s if s.is_multiple_of(3600) && 86400 % s == 0 => {
+            let step = s / 3600;
+            Ok(format!(
+                "{} {}-23/{} * * *",
+                minute,
+                hour % step as u32,
+                step
+            ))
+        }
+        s if s.is_multiple_of(60) && 3600 % s == 0 => {
+            let step = s / 60;
+            Ok(format!("{}-59/{} * * * *", minute % step as u32, step))
+        }
+        _ => unsupported(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_run() {
+        let now = Local.with_ymd_and_hms(2026, 10, 14, 4, 30, 0).unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let at = |s| Some(parse_at(s).unwrap());
+        assert_eq!(
+            next_run(now, at("02:00"), day),
+            Local.with_ymd_and_hms(2026, 10, 15, 2, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_run(now, at("06:00"), day),
+            Local.with_ymd_and_hms(2026, 10, 14, 6, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_run(now, at("02:00"), Duration::from_secs(3 * 60 * 60)),
+            Local.with_ymd_and_hms(2026, 10, 14, 5, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_crontab_timing() {
+        let at = Some(parse_at("02:30").unwrap());
+        let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+        assert_eq!(crontab_timing(at, hours(24)).unwrap(), "30 2 * * *");
+        assert_eq!(crontab_timing(at, hours(6)).unwrap(), "30 2-23/6 * * *");
+        assert_eq!(
+            crontab_timing(None, Duration::from_secs(15 * 60)).unwrap(),
+            "0-59/15 * * * *"
+        );
+        assert!(crontab_timing(at, hours(5)).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Where jobs are submitted, looked up and cancelled. PBS is built in; a
+//! site with another scheduler (OAR, Flux, an in-house wrapper) adds it as a
+//! plugin, an executable that gets one JSON request on stdin and answers
+//! with one JSON object on stdout:
+//!
+//! ```text
+//! {"op": "submit", "script": "#!/bin/bash\n...", "path": "run.sh", "workdir": "/home/me/x"}
+//!     -> {"job_id": "4242"}
+//! {"op": "status", "job_id": "4242"}
+//!     -> {"job": {"job_state": "R", "Exit_status": "0", ...}}, or {"job": null} once forgotten
+//! {"op": "cancel", "job_id": "4242"}
+//!     -> {}
+//! ```
+//!
+//! `path` is null when the script was not written to a file. A plugin runs
+//! in the job's working directory for `submit`. It reports failure by exiting
+//! non-zero or answering `{"error": "..."}`. Job attributes take the names
+//! `qstat -f` gives them (`job_state`, `Exit_status`,
+//! `resources_used.walltime`, ...), since the rest of qsub2 reads those.
+//!
+//! `scheduler = "oar"` in the configuration picks the plugin named in its
+//! `[schedulers]` table, or else `qsub2-scheduler-oar` on `PATH`. The
+//! commands that need more of PBS than this (`qsub2 interactive`, `cluster`,
+//! `nodes`, `select`, resubmitting in `monitor`) still talk to PBS.
+
+use crate::error::{Error, Result};
+use crate::pbs::{self, JobAttributes};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+pub trait Scheduler {
+    /// Submit `script`, written to `path` unless it goes to the scheduler's
+    /// standard input, from `workdir`. The job ID.
+    fn submit(&self, script: &str, path: Option<&Path>, workdir: Option<&Path>) -> Result<String>;
+
+    /// The job's attributes, or `None` once the scheduler no longer reports it.
+    fn status(&self, job_id: &str) -> io::Result<Option<JobAttributes>>;
+
+    /// Like [`Scheduler::status`], but also for finished jobs the scheduler
+    /// still keeps.
+    fn status_with_history(&self, job_id: &str) -> io::Result<Option<JobAttributes>> {
+        self.status(job_id)
+    }
+
+    fn cancel(&self, job_id: &str) -> io::Result<()>;
+}
+
+/// `qsub`, `qstat` and `qdel`.
+pub struct Pbs {
+    /// The `qsub` to run instead of the one on `PATH`.
+    pub qsub: Option<PathBuf>,
+    /// Arguments passed to `qsub` before the script.
+    pub args: Vec<String>,
+    /// Run `qsub` in a process group of its own, out of reach of the Ctrl-C
+    /// that `qsub2 run` catches, so that it always gets to print the job ID.
+    pub own_group: bool,
+}
+
+impl Pbs {
+    fn program(&self) -> &Path {
+        self.qsub.as_deref().unwrap_or(Path::new("qsub"))
+    }
+
+    /// The `qsub` command line that submits the script at `path`, or the one
+    /// on its standard input.
+    pub fn command_line(&self, path: Option<&Path>) -> Vec<String> {
+        std::iter::once(self.program().display().to_string())
+            .chain(self.args.iter().cloned())
+            .chain(path.map(|p| p.display().to_string()))
+            .collect()
+    }
+}
+
+impl Scheduler for Pbs {
+    /// Run `qsub` in `workdir`, which becomes the job's `$PBS_O_WORKDIR`.
+    fn submit(&self, script: &str, path: Option<&Path>, workdir: Option<&Path>) -> Result<String> {
+        log::debug!("running: {}", self.command_line(path).join(" "));
+        let mut qsub = Command::new(self.program());
+        qsub.args(&self.args);
+        #[cfg(unix)]
+        if self.own_group {
+            use std::os::unix::process::CommandExt;
+            qsub.process_group(0);
+        }
+        if let Some(workdir) = workdir {
+            qsub.current_dir(workdir);
+        }
+        let spawned = match path {
+            Some(path) => qsub.arg(path).output(),
+            None => qsub
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .and_then(|mut child| {
+                    // qsub reads the whole script before it answers, so this cannot deadlock.
+                    if let Some(mut stdin) = child.stdin.take() {
+                        stdin.write_all(script.as_bytes())?;
+                    }
+                    child.wait_with_output()
+                }),
+        };
+        let output = spawned.map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::QsubMissing(e),
+            _ => Error::Io("running qsub", e),
+        })?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::QsubRejected(format!(
+                "{}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn status(&self, job_id: &str) -> io::Result<Option<JobAttributes>> {
+        pbs::job_status(job_id)
+    }
+
+    fn status_with_history(&self, job_id: &str) -> io::Result<Option<JobAttributes>> {
+        pbs::job_status_with_history(job_id)
+    }
+
+    fn cancel(&self, job_id: &str) -> io::Result<()> {
+        pbs::cancel(job_id)
+    }
+}
+
+/// An external executable speaking the protocol above.
+#[derive(Debug, PartialEq)]
+pub struct Plugin {
+    pub name: String,
+    pub program: PathBuf,
+}
+
+impl Plugin {
+    /// `qsub2-scheduler-NAME`, the program a plugin not in `[schedulers]` is.
+    pub fn default_program(name: &str) -> PathBuf {
+        PathBuf::from(format!("qsub2-scheduler-{}", name))
+    }
+
+    /// Send `request` and read the answer.
+    fn call(&self, request: &Value, workdir: Option<&Path>) -> io::Result<Value> {
+        log::trace!("running: {} <<< {}", self.program.display(), request);
+        let mut command = Command::new(&self.program);
+        if let Some(workdir) = workdir {
+            command.current_dir(workdir);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "scheduler plugin {} ({}): {}",
+                        self.name,
+                        self.program.display(),
+                        e
+                    ),
+                )
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(request.to_string().as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        let answer: serde_json::Result<Value> =
+            serde_json::from_str(String::from_utf8_lossy(&output.stdout).trim());
+        if let Some(message) = answer.as_ref().ok().and_then(reported_error) {
+            return Err(io::Error::other(format!("{}: {}", self.name, message)));
+        }
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                self.name, output.status
+            )));
+        }
+        answer.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: the answer is not JSON: {}", self.name, e),
+            )
+        })
+    }
+}
+
+/// The `error` of a plugin's answer.
+fn reported_error(answer: &Value) -> Option<String> {
+    match answer.get("error")? {
+        Value::Null => None,
+        Value::String(message) => Some(message.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// `{"job": {...}}` as attributes; numbers and booleans become their text.
+fn parse_job(answer: &Value) -> io::Result<Option<JobAttributes>> {
+    match answer.get("job") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Object(job)) => Ok(Some(
+            job.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        )),
+        Some(other) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("\"job\" is not an object: {}", other),
+        )),
+    }
+}
+
+impl Scheduler for Plugin {
+    fn submit(&self, script: &str, path: Option<&Path>, workdir: Option<&Path>) -> Result<String> {
+        let request = json!({
+            "op": "submit",
+            "script": script,
+            "path": path,
+            "workdir": workdir,
+        });
+        let answer = self.call(&request, workdir).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::QsubMissing(e),
+            _ => Error::QsubRejected(e.to_string()),
+        })?;
+        match answer.get("job_id").and_then(Value::as_str) {
+            Some(job_id) if !job_id.trim().is_empty() => Ok(job_id.trim().to_string()),
+            _ => Err(Error::QsubRejected(format!(
+                "{} answered without a job_id: {}",
+                self.name, answer
+            ))),
+        }
+    }
+
+    fn status(&self, job_id: &str) -> io::Result<Option<JobAttributes>> {
+        parse_job(&self.call(&json!({ "op": "status", "job_id": job_id }), None)?)
+    }
+
+    fn cancel(&self, job_id: &str) -> io::Result<()> {
+        self.call(&json!({ "op": "cancel", "job_id": job_id }), None)
+            .map(|_| ())
+    }
+}
+
+static PLUGIN: OnceLock<Plugin> = OnceLock::new();
+
+/// Use `plugin` in place of PBS from now on.
+pub fn configure(plugin: Plugin) {
+    let _ = PLUGIN.set(plugin);
+}
+
+/// The plugin set by [`configure`], if any.
+pub fn plugin() -> Option<&'static Plugin> {
+    PLUGIN.get()
+}
+
+/// The configured plugin, or PBS as configured for queries.
+pub fn active() -> &'static dyn Scheduler {
+    static PBS: Pbs = Pbs {
+        qsub: None,
+        args: Vec::new(),
+        own_group: false,
+    };
+    match PLUGIN.get() {
+        Some(plugin) => plugin,
+        None => &PBS,
+    }
+}
+
+/// See [`Scheduler::status`].
+pub fn job_status(job_id: &str) -> io::Result<Option<JobAttributes>> {
+    active().status(job_id)
+}
+
+/// Whether the server says `job_id` has finished or no longer has it, so
+/// that a dependency on it would be refused. Not when it cannot be asked.
+pub fn has_ended(job_id: &str) -> bool {
+    job_status(job_id).is_ok_and(|a| a.as_ref().is_none_or(crate::pbs::is_finished))
+}
+
+/// See [`Scheduler::status_with_history`].
+pub fn job_status_with_history(job_id: &str) -> io::Result<Option<JobAttributes>> {
+    active().status_with_history(job_id)
+}
+
+/// See [`Scheduler::cancel`].
+pub fn cancel(job_id: &str) -> io::Result<()> {
+    active().cancel(job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_answers() {
+        assert_eq!(
+            reported_error(&json!({ "error": "no such queue" })),
+            Some("no such queue".to_string())
+        );
+        assert_eq!(
+            reported_error(&json!({ "job_id": "1", "error": null })),
+            None
+        );
+        let job = parse_job(&json!({
+            "job": { "job_state": "R", "Exit_status": 0, "queue": null }
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(job.get("job_state").map(String::as_str), Some("R"));
+        assert_eq!(job.get("Exit_status").map(String::as_str), Some("0"));
+        assert!(!job.contains_key("queue"));
+        assert_eq!(parse_job(&json!({ "job": null })).unwrap(), None);
+        let pbs = Pbs {
+            qsub: None,
+            args: vec!["-P".into(), "P123".into()],
+            own_group: false,
+        };
+        assert_eq!(
+            pbs.command_line(Some(Path::new("run.sh"))),
+            ["qsub", "-P", "P123", "run.sh"]
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let program =
+                std::env::temp_dir().join(format!("qsub2-scheduler-test-{}", std::process::id()));
+            std::fs::write(
+                &program,
+                "#!/bin/sh\ncat > /dev/null\necho '{\"job_id\": \"7.flux\"}'\n",
+            )
+            .unwrap();
+            std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let plugin = Plugin {
+                name: "flux".into(),
+                program: program.clone(),
+            };
+            assert_eq!(plugin.submit("echo hi", None, None).unwrap(), "7.flux");
+            std::fs::remove_file(&program).unwrap();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Checking the configuration and job spec files (`config validate`,
+//! `spec validate`).
+//!
+//! The schemas are the serde types themselves, `Config` and `JobSpec`, so
+//! they cannot drift from what qsub2 reads. This module turns their errors
+//! into one line with the position, and for a misspelt key names the
+//! closest one that exists.
+
+use crate::spec::JobSpec;
+use serde::Deserialize;
+
+/// Levenshtein distance between two keys.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The key in `known` that `key` is most likely a typo of.
+pub fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (distance(key, k), *k))
+        .min()
+        .filter(|(d, _)| *d <= (key.len() / 3).max(1))
+        .map(|(_, k)| k)
+}
+
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// `unknown key 'walltme' at line 7, did you mean 'walltime'?`
+fn unknown_key(key: &str, line: Option<usize>, known: &[&str]) -> String {
+    let mut message = format!("unknown key '{}'", key);
+    if let Some(line) = line {
+        message.push_str(&format!(" at line {}", line));
+    }
+    match closest(key, known) {
+        Some(known) => message.push_str(&format!(", did you mean '{}'?", known)),
+        None if !known.is_empty() => {
+            message.push_str(&format!("; expected one of {}", known.join(", ")))
+        }
+        None => {}
+    }
+    message
+}
+
+/// An error from reading `content` as the configuration, on one line.
+pub fn toml_error(e: &toml::de::Error, content: &str) -> String {
+    let line = e.span().map(|span| line_at(content, span.start));
+    let message = e.message();
+    if message.starts_with("unknown field `") {
+        // unknown field `walltme`, expected one of `name`, `ncpus`, ...
+        let names: Vec<&str> = message.split('`').skip(1).step_by(2).collect();
+        if let Some((key, known)) = names.split_first() {
+            return unknown_key(key, line, known);
+        }
+    }
+    match line {
+        Some(line) => format!("{} at line {}", message, line),
+        None => message.to_string(),
+    }
+}
+
+/// A sidecar file or history record, which keeps the spec under `spec`.
+#[derive(Deserialize)]
+struct Record {
+    spec: JobSpec,
+}
+
+/// Read a job spec file: a bare spec as `rerun --edit` shows it, or a
+/// sidecar or history record with one under `spec`. Unknown keys are only
+/// looked for at the top of the spec; older records must keep loading.
+pub fn read_spec(content: &str) -> Result<JobSpec, String> {
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let nested = value.get("spec").filter(|s| s.is_object());
+    let object = nested
+        .unwrap_or(&value)
+        .as_object()
+        .ok_or("a job spec is a JSON object")?;
+    let known = serde_json::to_value(JobSpec::default()).unwrap_or_default();
+    let known: Vec<&str> = known
+        .as_object()
+        .map(|o| o.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    if let Some(key) = object.keys().find(|k| !known.contains(&k.as_str())) {
+        let line = content
+            .find(&format!("\"{}\"", key))
+            .map(|i| line_at(content, i));
+        return Err(unknown_key(key, line, &known));
+    }
+    // from the text rather than the value, so type errors keep their position
+    match nested {
+        Some(_) => serde_json::from_str::<Record>(content).map(|r| r.spec),
+        None => serde_json::from_str(content),
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_error_suggests_the_key() {
+        #[derive(Debug, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[allow(dead_code)]
+        struct Defaults {
+            queue: Option<String>,
+            walltime: Option<String>,
+        }
+        let content = "queue = \"long\"\nwalltme = \"1:00:00\"\n";
+        let e = toml::from_str::<Defaults>(content).unwrap_err();
+        assert_eq!(
+            toml_error(&e, content),
+            "unknown key 'walltme' at line 2, did you mean 'walltime'?"
+        );
+        assert_eq!(closest("xyz", &["queue", "walltime"]), None);
+    }
+
+    #[test]
+    fn test_read_spec() {
+        let spec = serde_json::to_string_pretty(&JobSpec::default()).unwrap();
+        assert!(read_spec(&spec).is_ok());
+        let record = format!("{{\"job_id\": \"1.server\", \"spec\": {}}}", spec);
+        assert!(read_spec(&record).is_ok());
+
+        let typo = spec.replace("\"walltime\"", "\"walltme\"");
+        let e = read_spec(&typo).unwrap_err();
+        assert!(
+            e.starts_with("unknown key 'walltme' at line ") && e.ends_with("'walltime'?"),
+            "{}",
+            e
+        );
+        let e = read_spec(&spec.replace("\"ncpus\": 0", "\"ncpus\": \"four\"")).unwrap_err();
+        assert!(e.contains("at line 3"), "{}", e);
+    }
+}
@@ -0,0 +1,41 @@
+//! `--segments`: a job longer than the queue allows, run as a chain of jobs
+//! that each fit its walltime limit.
+//!
+//! The first segment runs the command; each later one waits for the one
+//! before it to end (`afterany`, as a segment is usually killed at its
+//! limit) and runs the restart command, which goes on from the state the
+//! command saved. Saving that state is up to the command.
+
+/// The walltimes of the segments of a `total`-second job: the limit for all
+/// but the last, which gets what is left.
+pub fn walltimes(total: u64, limit: u64) -> Vec<u64> {
+    let mut walltimes = vec![limit; total.div_ceil(limit) as usize];
+    if let Some(last) = walltimes.last_mut() {
+        *last = total - limit * (total.div_ceil(limit) - 1);
+    }
+    walltimes
+}
+
+/// The restart command for segment `index`, with `{segment}` and
+/// `{previous}` as its number and the one before.
+pub fn restart_command(template: &str, index: u32) -> String {
+    template
+        .replace("{segment}", &index.to_string())
+        .replace("{previous}", &(index - 1).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walltimes() {
+        assert_eq!(walltimes(10 * 3600, 4 * 3600), [14400, 14400, 7200]);
+        assert_eq!(walltimes(8 * 3600, 4 * 3600), [14400, 14400]);
+        assert_eq!(walltimes(60, 3600), [60]);
+        assert_eq!(
+            restart_command("sim --resume state.{previous} --save state.{segment}", 3),
+            "sim --resume state.2 --save state.3"
+        );
+    }
+}
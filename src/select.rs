@@ -0,0 +1,160 @@
+//! PBS select statements such as `2:ncpus=64:mem=200gb+1:ncpus=8`.
+
+use std::fmt;
+
+/// One `N:key=value:...` chunk of a select statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectChunk {
+    count: u32,
+    resources: Vec<(String, String)>,
+}
+
+impl SelectChunk {
+    pub fn new(count: u32) -> Self {
+        SelectChunk {
+            count,
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn ncpus(self, ncpus: u32) -> Self {
+        self.resource("ncpus", ncpus.to_string())
+    }
+
+    pub fn mem(self, mem: &str) -> Self {
+        self.resource("mem", mem)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The value of a resource in this chunk, e.g. `get("ngpus")`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.resources
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Any other chunk-level resource, e.g. `ngpus` or `cputype`.
+    pub fn resource(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.resources.push((key.to_string(), value.into()));
+        self
+    }
+}
+
+impl fmt::Display for SelectChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.count)?;
+        for (key, value) in &self.resources {
+            write!(f, ":{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Chunks joined with `+`.
+pub fn format(chunks: &[SelectChunk]) -> String {
+    chunks
+        .iter()
+        .map(SelectChunk::to_string)
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Parse a select statement; a chunk without a leading count means one chunk.
+pub fn parse(s: &str) -> Result<Vec<SelectChunk>, String> {
+    let s = s.strip_prefix("select=").unwrap_or(s);
+    let mut chunks = Vec::new();
+    for chunk in s.split('+') {
+        let mut fields = chunk.split(':').peekable();
+        let count = match fields.peek().and_then(|f| f.parse().ok()) {
+            Some(count) => {
+                fields.next();
+                count
+            }
+            None => 1,
+        };
+        if count == 0 {
+            return Err(format!("chunk '{}' asks for 0 chunks", chunk));
+        }
+        let mut parsed = SelectChunk::new(count);
+        for field in fields {
+            match field.split_once('=') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+                    parsed = parsed.resource(key, value);
+                }
+                _ => {
+                    return Err(format!(
+                        "'{}' in '{}' is not a resource such as ncpus=8",
+                        field, s
+                    ))
+                }
+            }
+        }
+        if parsed.resources.is_empty() {
+            return Err(format!("chunk '{}' requests no resources", chunk));
+        }
+        chunks.push(parsed);
+    }
+    Ok(chunks)
+}
+
+/// One `--chunk` such as `count=4:ncpus=16`; without `count`, one chunk.
+pub fn parse_chunk(s: &str) -> Result<SelectChunk, String> {
+    let mut chunk = SelectChunk::new(1);
+    for field in s.split(':') {
+        match field.split_once('=') {
+            Some(("count", count)) => match count.parse() {
+                Ok(count) if count > 0 => chunk.count = count,
+                _ => return Err(format!("'{}' is not a number of chunks", count)),
+            },
+            Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+                chunk = chunk.resource(key, value);
+            }
+            _ => {
+                return Err(format!(
+                    "'{}' in '{}' is not a resource such as ncpus=8",
+                    field, s
+                ))
+            }
+        }
+    }
+    if chunk.resources.is_empty() {
+        return Err(format!("chunk '{}' requests no resources", s));
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let chunks = parse("2:ncpus=64:mem=200gb+ncpus=8").unwrap();
+        assert_eq!(
+            chunks,
+            [
+                SelectChunk::new(2).ncpus(64).mem("200gb"),
+                SelectChunk::new(1).ncpus(8)
+            ]
+        );
+        assert_eq!(format(&chunks), "2:ncpus=64:mem=200gb+1:ncpus=8");
+        assert!(parse("2").is_err());
+        assert!(parse("0:ncpus=4").is_err());
+        assert!(parse("1:ncpus").is_err());
+
+        assert_eq!(
+            parse_chunk("count=4:ncpus=16"),
+            Ok(SelectChunk::new(4).ncpus(16))
+        );
+        assert_eq!(
+            parse_chunk("ncpus=1:mem=500gb"),
+            Ok(SelectChunk::new(1).ncpus(1).mem("500gb"))
+        );
+        assert!(parse_chunk("count=0:ncpus=4").is_err());
+        assert!(parse_chunk("count=4").is_err());
+    }
+}
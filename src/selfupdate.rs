@@ -0,0 +1,276 @@
+//! `qsub2 self-update`: replace the running executable with the latest
+//! GitHub release, for the many who keep a single binary in `~/bin`.
+//!
+//! Each release carries `qsub2-TARGET.tar.gz` and a `.sha256` file for it
+//! (see `.github/workflows/release.yml`). The archive is fetched with `curl`,
+//! which honours `https_proxy`, checked with `sha256sum` and unpacked with
+//! `tar`. Only once its checksum matches is the new binary put next to the old one and renamed
+//! over it, so a failed update leaves the old one working.
+
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const LATEST: &str = "https://api.github.com/repos/kojix2/qsub2/releases/latest";
+
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    /// `v0.3.0` as `0.3.0`.
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    /// The archive and checksum asset for the first of `targets` that has both.
+    fn assets_for(&self, targets: &[&str]) -> Option<(&Asset, &Asset)> {
+        targets.iter().find_map(|target| {
+            let stem = format!("qsub2-{}", target);
+            let archive = self
+                .assets
+                .iter()
+                .find(|a| a.name == format!("{}.tar.gz", stem))?;
+            let checksum = self
+                .assets
+                .iter()
+                .find(|a| a.name.starts_with(&stem) && a.name.ends_with(".sha256"))?;
+            Some((archive, checksum))
+        })
+    }
+}
+
+/// The release targets that run here, the static one first.
+pub fn targets() -> &'static [&'static str] {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => &["x86_64-unknown-linux-musl", "x86_64-unknown-linux-gnu"],
+        ("linux", "aarch64") => &["aarch64-unknown-linux-gnu"],
+        ("macos", "x86_64") => &["universal-apple-darwin", "x86_64-apple-darwin"],
+        ("macos", "aarch64") => &["universal-apple-darwin", "aarch64-apple-darwin"],
+        _ => &[],
+    }
+}
+
+/// Whether `latest` is a later `MAJOR.MINOR.PATCH` than `current`.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    numbers(latest) > numbers(current)
+}
+
+/// The checksum for `name` in the output of `sha256sum`, or the lone hash
+/// of a file that has only that.
+fn checksum_for(text: &str, name: &str) -> Option<String> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let matching = text.lines().find_map(|line| {
+        let (hash, file) = line.split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then_some(hash)
+    });
+    let hash = match matching {
+        Some(hash) => hash,
+        None => match (lines.next(), lines.next()) {
+            (Some(only), None) => only.split_whitespace().next()?,
+            _ => return None,
+        },
+    };
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+/// SHA-256 of the file at `path`, in hex, from `sha256sum`, or `shasum`
+/// where coreutils is missing (macOS).
+fn sha256(path: &Path) -> io::Result<String> {
+    let output = match Command::new("sha256sum").arg(path).output() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Command::new("shasum")
+            .args(["-a", "256"])
+            .arg(path)
+            .output(),
+        output => output,
+    }
+    .map_err(|e| io::Error::new(e.kind(), format!("failed to run sha256sum: {}", e)))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next() {
+        Some(hash) if output.status.success() => Ok(hash.to_ascii_lowercase()),
+        _ => Err(io::Error::other(format!(
+            "sha256sum {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// Fetch `url` with `curl`, to `out` or else into memory.
+fn fetch(url: &str, out: Option<&Path>) -> io::Result<Vec<u8>> {
+    let mut curl = Command::new("curl");
+    curl.arg("-fsSL");
+    if url.starts_with("https://api.github.com/") {
+        curl.args(["-H", "Accept: application/vnd.github+json"]);
+    }
+    // Shared login nodes soon exhaust the anonymous rate limit. The token
+    // goes in on standard input, as an argument would show it to `ps`.
+    let token = std::env::var("GITHUB_TOKEN").ok();
+    if token.is_some() {
+        curl.args(["-H", "@-"]);
+    }
+    if let Some(out) = out {
+        curl.arg("-o").arg(out);
+    }
+    let failed = |e: io::Error| io::Error::new(e.kind(), format!("failed to run curl: {}", e));
+    let mut child = curl
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    if let (Some(mut stdin), Some(token)) = (child.stdin.take(), token) {
+        writeln!(stdin, "Authorization: Bearer {}", token)?;
+    }
+    let output = child.wait_with_output().map_err(failed)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "curl {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// The latest release on GitHub.
+pub fn latest() -> io::Result<Release> {
+    let json = fetch(LATEST, None)?;
+    serde_json::from_slice(&json).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("reading the release: {}", e),
+        )
+    })
+}
+
+/// Download `release` for this platform and put it in place of `exe`.
+pub fn install(release: &Release, exe: &Path) -> io::Result<()> {
+    let (archive, checksum) = release.assets_for(targets()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "release {} has no binary for {}-{}",
+                release.tag_name,
+                std::env::consts::ARCH,
+                std::env::consts::OS
+            ),
+        )
+    })?;
+    let dir = exe.parent().unwrap_or(Path::new("."));
+    // in the same directory, so that the rename cannot cross file systems
+    let work = dir.join(format!(".qsub2-update-{}", std::process::id()));
+    fs::create_dir_all(&work)?;
+    let result = (|| {
+        let expected =
+            String::from_utf8_lossy(&fetch(&checksum.browser_download_url, None)?).into_owned();
+        let expected = checksum_for(&expected, &archive.name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no checksum for {} in {}", archive.name, checksum.name),
+            )
+        })?;
+        let path = work.join(&archive.name);
+        fetch(&archive.browser_download_url, Some(&path))?;
+        let actual = sha256(&path)?;
+        if actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has checksum {}, but {} says {}",
+                    archive.name, actual, checksum.name, expected
+                ),
+            ));
+        }
+        let status = Command::new("tar")
+            .arg("-xzf")
+            .arg(&path)
+            .arg("-C")
+            .arg(&work)
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to run tar: {}", e)))?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "tar could not unpack {}",
+                archive.name
+            )));
+        }
+        let binary = work.join("qsub2");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&binary, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&binary, exe)
+    })();
+    let _ = fs::remove_dir_all(&work);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_assets_and_checksum() {
+        let file = std::env::temp_dir().join(format!("qsub2-sha256-{}", std::process::id()));
+        fs::write(&file, [b'a'; 1000]).unwrap();
+        assert_eq!(
+            sha256(&file).unwrap(),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+        fs::remove_file(&file).unwrap();
+        assert!(sha256(&file).is_err());
+        assert!(is_newer("0.10.0", "0.9.3"));
+        assert!(!is_newer("0.3.0", "0.3.0"));
+
+        let release: Release = serde_json::from_str(
+            r#"{"tag_name": "v0.3.0", "assets": [
+                {"name": "qsub2-x86_64-unknown-linux-gnu.tar.gz", "browser_download_url": "a"},
+                {"name": "qsub2-x86_64-unknown-linux-gnu.sha256", "browser_download_url": "b"},
+                {"name": "qsub2-x86_64-unknown-linux-musl.tar.gz", "browser_download_url": "c"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(release.version(), "0.3.0");
+        let (archive, checksum) = release
+            .assets_for(&["x86_64-unknown-linux-musl", "x86_64-unknown-linux-gnu"])
+            .unwrap();
+        assert_eq!(
+            (
+                archive.browser_download_url.as_str(),
+                checksum.name.as_str()
+            ),
+            ("a", "qsub2-x86_64-unknown-linux-gnu.sha256")
+        );
+
+        let hash = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(
+            checksum_for(&format!("{}  qsub2.tar.gz\n", hash), "qsub2.tar.gz"),
+            Some(hash.to_ascii_lowercase())
+        );
+        assert_eq!(
+            checksum_for(&format!("{} *other\n{} x\n", hash, hash), "y"),
+            None
+        );
+    }
+}
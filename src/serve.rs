@@ -0,0 +1,508 @@
+//! `qsub2 serve`: a small HTTP API, so that web portals and notebooks submit
+//! through qsub2's checks and templates rather than calling `qsub` directly.
+//!
+//! Requests and answers are JSON. A job is sent in the format that
+//! `qsub2 spec validate` reads; what it leaves out is taken from the
+//! configuration's `[default]` table and the built-in defaults:
+//!
+//! ```text
+//! POST   /render      job spec -> {"script": "..."}
+//! POST   /submit      job spec -> {"job_id": "..."}
+//! GET    /jobs        -> your submissions in the history
+//! GET    /jobs/ID     -> {"job_id", "state", "exit_status", "requeues"}
+//! DELETE /jobs/ID     -> {"job_id", "cancelled": true}
+//! ```
+//!
+//! Whoever may use the API submits jobs as the user running qsub2, and on a
+//! login node `127.0.0.1` is reachable by every other account. So each
+//! request over TCP has to carry `Authorization: Bearer TOKEN`, where TOKEN
+//! is made up at startup and written to a file only its owner can read
+//! (`--token-file`). A `template` must lie in the template directory.
+//! Requests are answered one at a time, so each has to arrive in full within
+//! [`TIMEOUT`] and with no more than [`MAX_HEADER`] bytes before its body;
+//! a client that sends more slowly, or without end, is cut off.
+//!
+//! With `--socket`, the same API is served on a Unix socket only its owner
+//! may use. When `socket` in the configuration names it, `qsub2` hands each
+//! rendered script to it (`POST /scripts`) instead of checking the history
+//! and running `qsub` itself. That endpoint is not served over TCP: a script
+//! is submitted as it is, without the checks a job spec gets. The daemon
+//! keeps the history in memory, which spares the thousands of jobs of a
+//! workflow from reading it every time.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Bodies larger than this are refused.
+const MAX_BODY: usize = 1 << 20;
+
+/// The request line and headers larger than this are refused.
+pub const MAX_HEADER: usize = 16 << 10;
+
+/// How long a client may take to send its whole request, and to read each
+/// part of the answer.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// The `Authorization` header.
+    pub authorization: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    /// JSON.
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &serde_json::Value) -> Self {
+        Response {
+            status,
+            body: value.to_string(),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Response::json(status, &serde_json::json!({ "error": message }))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Endpoint<'a> {
+    Render,
+    Submit,
+    /// A script rendered by the `qsub2` that sends it.
+    Scripts,
+    Jobs,
+    Job(&'a str),
+    Cancel(&'a str),
+}
+
+/// The endpoint of `request`, or the status to refuse it with.
+pub fn endpoint(request: &Request) -> Result<Endpoint<'_>, Response> {
+    let path = request.path.split('?').next().unwrap_or("");
+    let path = path.trim_end_matches('/');
+    let endpoint = match (request.method.as_str(), path) {
+        ("POST", "/render") => Endpoint::Render,
+        ("POST", "/submit") => Endpoint::Submit,
+        ("POST", "/scripts") => Endpoint::Scripts,
+        ("GET", "/jobs") => Endpoint::Jobs,
+        (method, path) => match path.strip_prefix("/jobs/").filter(|id| !id.contains('/')) {
+            Some(id) if method == "GET" => Endpoint::Job(id),
+            Some(id) if method == "DELETE" => Endpoint::Cancel(id),
+            Some(_) => return Err(Response::error(405, "use GET or DELETE")),
+            None if ["/render", "/submit", "/scripts", "/jobs"].contains(&path) => {
+                return Err(Response::error(405, "method not allowed"))
+            }
+            None => return Err(Response::error(404, &format!("no endpoint {}", path))),
+        },
+    };
+    Ok(endpoint)
+}
+
+/// Read one HTTP/1.1 request.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut head = reader.take(MAX_HEADER as u64);
+    let mut read_line = |line: &mut String| match head.read_line(line)? {
+        0 => Ok(0),
+        _ if !line.ends_with('\n') && head.limit() == 0 => Err(invalid("the header is too large")),
+        read => Ok(read),
+    };
+    let mut line = String::new();
+    if read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("not an HTTP request"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut authorization = None;
+    let mut length = 0;
+    loop {
+        line.clear();
+        if read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("bad Content-Length"))?;
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid("the body is too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("the body is not UTF-8"))?;
+    Ok(Request {
+        method,
+        path,
+        authorization,
+        body,
+    })
+}
+
+/// A new random token, as hex.
+pub fn new_token() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Write `token` to `path`, readable by its owner only.
+pub fn write_token(path: &Path, token: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // a file left by an earlier run keeps its mode otherwise
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options
+        .open(path)?
+        .write_all(format!("{}\n", token).as_bytes())
+}
+
+/// Whether `request` carries `token`, compared in constant time.
+fn authorized(request: &Request, token: &str) -> bool {
+    let Some(given) = request
+        .authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        502 => "Bad Gateway",
+        _ => "Internal Server Error",
+    }
+}
+
+fn write_response(stream: &mut impl Write, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Reads that fail once `deadline` has passed, each given only the time
+/// left by `set_timeout`, the read timeout of the socket under `inner`.
+struct Deadline<R, F> {
+    inner: R,
+    deadline: Instant,
+    set_timeout: F,
+}
+
+impl<R: Read, F: Fn(Duration) -> io::Result<()>> Read for Deadline<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the request took too long",
+            ));
+        }
+        (self.set_timeout)(left)?;
+        self.inner.read(buf)
+    }
+}
+
+fn answer<S>(
+    stream: &S,
+    set_timeout: impl Fn(Duration) -> io::Result<()>,
+    handle: &mut impl FnMut(&Request) -> Response,
+) -> io::Result<()>
+where
+    for<'a> &'a S: Read + Write,
+{
+    let mut reader = BufReader::new(Deadline {
+        inner: stream,
+        deadline: Instant::now() + TIMEOUT,
+        set_timeout,
+    });
+    let response = match read_request(&mut reader) {
+        Ok(request) => {
+            let response = handle(&request);
+            log::info!("{} {} {}", request.method, request.path, response.status);
+            response
+        }
+        // connected only to see whether the daemon runs
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        // a socket timeout is WouldBlock on Unix
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) =>
+        {
+            Response::error(408, "the request took too long")
+        }
+        Err(e) => Response::error(400, &e.to_string()),
+    };
+    write_response(&mut &*stream, &response)
+}
+
+/// Answer requests on `listen` that carry `token` with `handle` until killed.
+pub fn serve(
+    listen: &str,
+    token: &str,
+    mut handle: impl FnMut(&Request) -> Response,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    let address = listener.local_addr()?;
+    if !address.ip().is_loopback() {
+        log::warn!(
+            "anyone who can reach {} and read the token can submit and cancel jobs as you",
+            address
+        );
+    }
+    eprintln!("Listening on http://{}", address);
+    let mut handle = |request: &Request| {
+        if !authorized(request, token) {
+            return Response::error(
+                401,
+                "send Authorization: Bearer and the token of --token-file",
+            );
+        }
+        if endpoint(request) == Ok(Endpoint::Scripts) {
+            return Response::error(403, "POST /scripts is only served on --socket");
+        }
+        handle(request)
+    };
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let set_timeout = |left| stream.set_read_timeout(Some(left));
+                let answered = stream
+                    .set_write_timeout(Some(TIMEOUT))
+                    .and_then(|()| answer(&stream, set_timeout, &mut handle));
+                if let Err(e) = answered {
+                    log::warn!("answering a request: {}", e);
+                }
+            }
+            Err(e) => log::warn!("accepting a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Answer requests on the Unix socket `path` with `handle` until killed.
+#[cfg(unix)]
+pub fn serve_socket(path: &Path, mut handle: impl FnMut(&Request) -> Response) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a daemon already listens on {}", path.display()),
+        ));
+    }
+    // Bound with the umask's mode, the socket would be open to others until
+    // its mode is changed; so it is made in a directory only its owner can
+    // enter, and moved into place (over one a killed daemon left) after.
+    let private = path.with_file_name(format!(".qsub2-serve-{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let listener = UnixListener::bind(&bound)
+        .and_then(|listener| {
+            std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&bound, path)?;
+            Ok(listener)
+        })
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&bound);
+        });
+    std::fs::remove_dir(&private)?;
+    let listener = listener?;
+    eprintln!("Listening on {}", path.display());
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let set_timeout = |left| stream.set_read_timeout(Some(left));
+                let answered = stream
+                    .set_write_timeout(Some(TIMEOUT))
+                    .and_then(|()| answer(&stream, set_timeout, &mut handle));
+                if let Err(e) = answered {
+                    log::warn!("answering a request: {}", e);
+                }
+            }
+            Err(e) => log::warn!("accepting a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve_socket(_: &Path, _: impl FnMut(&Request) -> Response) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets need a Unix system",
+    ))
+}
+
+/// Send `body` to the daemon on the Unix socket `path`; its status and answer.
+#[cfg(unix)]
+pub fn request(path: &Path, method: &str, target: &str, body: &str) -> io::Result<Response> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        target,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    // an answer reads like a request whose first line is `HTTP/1.1 200 OK`
+    let answer = read_request(&mut BufReader::new(stream))?;
+    let status = answer
+        .path
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not an HTTP answer"))?;
+    Ok(Response {
+        status,
+        body: answer.body,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn request(_: &Path, _: &str, _: &str, _: &str) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets need a Unix system",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_endpoint() {
+        let raw = "POST /submit HTTP/1.1\r\nHost: x\r\ncontent-length: 13\r\n\r\n{\"ncpus\": 4}\n";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "POST".into(),
+                path: "/submit".into(),
+                body: "{\"ncpus\": 4}\n".into(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(endpoint(&request), Ok(Endpoint::Submit));
+        assert!(!authorized(&request, "abc"));
+        let raw = "GET /jobs HTTP/1.1\r\nAuthorization: Bearer abc\r\n\r\n";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert!(authorized(&request, "abc"));
+        assert!(!authorized(&request, "abd"));
+        assert!(!authorized(&request, "ab"));
+
+        let request = |method: &str, path: &str| Request {
+            method: method.into(),
+            path: path.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            endpoint(&request("GET", "/jobs/12.server")),
+            Ok(Endpoint::Job("12.server"))
+        );
+        assert_eq!(
+            endpoint(&request("DELETE", "/jobs/12.server/")),
+            Ok(Endpoint::Cancel("12.server"))
+        );
+        assert_eq!(endpoint(&request("GET", "/jobs?x=1")), Ok(Endpoint::Jobs));
+        assert_eq!(
+            endpoint(&request("GET", "/submit")).unwrap_err().status,
+            405
+        );
+        assert_eq!(endpoint(&request("GET", "/nope")).unwrap_err().status, 404);
+
+        let endless = format!("GET /jobs HTTP/1.1\r\nX-Pad: {}", "a".repeat(MAX_HEADER));
+        let error = read_request(&mut endless.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "the header is too large");
+
+        let mut written = Vec::new();
+        write_response(&mut written, &Response::error(404, "x")).unwrap();
+        assert!(String::from_utf8(written)
+            .unwrap()
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_a_trickling_client_runs_out_of_time() {
+        /// A byte at a time, a little too slowly.
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                std::thread::sleep(Duration::from_millis(20));
+                let Some((&first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+        let timeouts = std::cell::RefCell::new(Vec::new());
+        let mut reader = BufReader::new(Deadline {
+            inner: Trickle(b"GET /jobs HTTP/1.1\r\nHost: x\r\n\r\n"),
+            deadline: Instant::now() + Duration::from_millis(200),
+            set_timeout: |left| {
+                timeouts.borrow_mut().push(left);
+                Ok(())
+            },
+        });
+        let error = read_request(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        let timeouts = timeouts.borrow();
+        assert!(timeouts.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(timeouts[0] <= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,53 @@
+//! `--sidecar`: a `<script>.json` file next to each generated script with the
+//! resolved settings of its job, and its job ID once it is submitted, for
+//! other tools to read without parsing `#PBS` lines.
+
+use crate::spec::JobSpec;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct Sidecar<'a> {
+    pub script: &'a Path,
+    pub spec: &'a JobSpec,
+    /// The input files the job was generated for.
+    pub files: &'a [PathBuf],
+    pub generated_at: DateTime<Local>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<DateTime<Local>>,
+}
+
+/// `run.sh` has its metadata in `run.sh.json`.
+pub fn path(script: &Path) -> PathBuf {
+    let mut path = script.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+impl Sidecar<'_> {
+    /// Write (or, after submission, overwrite) the file next to the script.
+    pub fn write(&self) -> io::Result<()> {
+        let mut json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        json.push('\n');
+        fs::write(path(self.script), json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_keeps_the_extension() {
+        assert_eq!(
+            path(Path::new("jobs/run.sh")),
+            Path::new("jobs/run.sh.json")
+        );
+        assert_eq!(path(Path::new("run")), Path::new("run.json"));
+    }
+}
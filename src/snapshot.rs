@@ -0,0 +1,105 @@
+//! `--snapshot-env`: save the submitting shell's environment next to the
+//! script, so a failed job can be rerun by hand with the same settings.
+//!
+//! The file holds one `export NAME='value'` line per variable and can be
+//! sourced. Variables are filtered by the `[snapshot_env]` table of the
+//! configuration; names that look like credentials are left out by default.
+
+use crate::shell_quote;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    /// Patterns such as `CONDA_*` of the variables to keep; empty keeps all.
+    pub allow: Vec<String>,
+    /// Patterns of the variables to leave out, even when allowed.
+    pub deny: Vec<String>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            allow: Vec::new(),
+            deny: ["*TOKEN*", "*SECRET*", "*PASSWORD*", "*PASSWD*", "*_KEY"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn keeps(&self, name: &str) -> bool {
+        let matching = |patterns: &[String]| patterns.iter().any(|p| matches(p, name));
+        (self.allow.is_empty() || matching(&self.allow)) && !matching(&self.deny)
+    }
+}
+
+/// `*` matches any run of characters; everything else matches itself.
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Write the filtered environment to `path`, readable only by its owner.
+/// Returns how many variables were saved.
+pub fn write(path: &Path, filter: &Filter) -> io::Result<usize> {
+    // vars() would panic on a value that is not UTF-8
+    let mut variables: Vec<(String, String)> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| filter.keeps(name))
+        .collect();
+    variables.sort();
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    for (name, value) in &variables {
+        writeln!(file, "export {}={}", name, shell_quote(value))?;
+    }
+    Ok(variables.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert!(matches("CONDA_*", "CONDA_PREFIX"));
+        assert!(matches("*TOKEN*", "GITHUB_TOKEN"));
+        assert!(matches("PATH", "PATH"));
+        assert!(!matches("PATH", "MANPATH"));
+        assert!(!matches("A*B*C", "AXC"));
+
+        let filter = Filter::default();
+        assert!(filter.keeps("PATH"));
+        assert!(!filter.keeps("AWS_SECRET_ACCESS_KEY"));
+        let filter = Filter {
+            allow: vec!["CONDA_*".into(), "PATH".into()],
+            ..Filter::default()
+        };
+        assert!(filter.keeps("CONDA_PREFIX"));
+        assert!(!filter.keeps("HOME"));
+    }
+}
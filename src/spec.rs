@@ -0,0 +1,269 @@
+//! The fully resolved settings of one job.
+
+use crate::checkpoint::Checkpoint;
+use crate::dialect::Dialect;
+use crate::error::{Error, Result};
+use crate::naming;
+use crate::pbs;
+use crate::warning::WalltimeWarning;
+use crate::{Cli, Dependency, Placement};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Resubmit the job when it is preempted, up to `max` times.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Requeue {
+    pub max: u32,
+    /// The qsub2 the job runs to resubmit itself.
+    pub program: PathBuf,
+}
+
+/// `--array`: one subjob per line of `manifest`, or per row of `params`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Array {
+    pub size: usize,
+    /// The file list; `None` for an array over a parameter table only.
+    #[serde(default)]
+    pub manifest: Option<PathBuf>,
+    /// `--output-pattern`, which each subjob fills in for its file.
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub params: Option<Params>,
+}
+
+/// `--param-table`: a tab-separated table whose header names the variables
+/// and whose row N the subjob N reads.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Params {
+    pub table: PathBuf,
+    pub columns: Vec<String>,
+}
+
+/// `--segments`: which segment of how many the job is.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub index: u32,
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct JobSpec {
+    pub name: String,
+    pub ncpus: u32,
+    pub mem: Option<String>,
+    pub queue: String,
+    pub walltime: String,
+    pub command: String,
+    /// `--then` steps run after the command.
+    #[serde(default)]
+    pub then: Vec<String>,
+    #[serde(default)]
+    pub continue_on_error: bool,
+    pub depend: Vec<Dependency>,
+    /// `--select`: the whole select statement, replacing ncpus and mem.
+    #[serde(default)]
+    pub select: Option<String>,
+    /// `--constraint key=value` node attributes for the select chunk.
+    #[serde(default)]
+    pub constraints: Vec<String>,
+    #[serde(default)]
+    pub place: Option<Placement>,
+    #[serde(default)]
+    pub exclusive: bool,
+    /// `--prologue` and `--epilogue` file contents.
+    #[serde(default)]
+    pub prologue: Option<String>,
+    #[serde(default)]
+    pub epilogue: Option<String>,
+    /// `--prepend` and `--append` snippets, in the order given.
+    #[serde(default)]
+    pub prepend: Vec<String>,
+    #[serde(default)]
+    pub append: Vec<String>,
+    /// `--env-file` variables, exported before the prologue.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    #[serde(default)]
+    pub notify_on_failure: bool,
+    /// `--keep`: PBS `-k`, which output streams to write in place.
+    #[serde(default)]
+    pub keep: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub umask: Option<String>,
+    /// `--reservation`: advance reservation to run in.
+    #[serde(default)]
+    pub reservation: Option<String>,
+    /// `--log-dir`: stdout and stderr go to a subdirectory for the job name.
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    /// `--tag` labels for `list --tag` and `cancel --tag`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// `--rerunnable` or `--not-rerunnable`; the server's default otherwise.
+    #[serde(default)]
+    pub rerunnable: Option<bool>,
+    #[serde(default)]
+    pub checkpoint: Option<Checkpoint>,
+    /// `--walltime-warning` and `--on-walltime-warning`.
+    #[serde(default)]
+    pub walltime_warning: Option<WalltimeWarning>,
+    /// `--requeue-on-preempt`.
+    #[serde(default)]
+    pub requeue: Option<Requeue>,
+    /// How many times the job was requeued after a preemption so far.
+    #[serde(default)]
+    pub resumed: u32,
+    /// `--group-name`, or the name given to one `--per-file` batch.
+    #[serde(default)]
+    pub job_group: Option<String>,
+    /// `--require-clean-git`: the commit the job was submitted from.
+    #[serde(default)]
+    pub git_commit: Option<String>,
+    /// `--snapshot-env`: the file with the environment at submission.
+    #[serde(default)]
+    pub env_snapshot: Option<PathBuf>,
+    #[serde(default)]
+    pub array: Option<Array>,
+    #[serde(default)]
+    pub segment: Option<Segment>,
+    pub template: Option<PathBuf>,
+    /// `--raw-command`: paste the command without shell quoting.
+    #[serde(default)]
+    pub raw_command: bool,
+    /// `--script-dialect`: the shell the added lines are written for.
+    #[serde(default)]
+    pub dialect: Dialect,
+}
+
+impl JobSpec {
+    /// Apply the `[default]` table of the configuration, then the built-in defaults,
+    /// to whatever was not given on the command line.
+    pub fn from_cli(cli: &Cli) -> Self {
+        let defaults = &cli.config.defaults;
+        let pick = |given: &Option<String>, default: &Option<String>| {
+            given.clone().or_else(|| default.clone())
+        };
+        JobSpec {
+            name: pick(&cli.name, &defaults.name)
+                .or_else(|| naming::job_name(cli.command.as_deref().unwrap_or_default()))
+                .unwrap_or_else(|| "job".to_string()),
+            ncpus: cli.ncpus.or(defaults.ncpus).unwrap_or(1),
+            mem: pick(&cli.mem, &defaults.mem),
+            queue: pick(&cli.queue, &defaults.queue).unwrap_or_else(|| "batch".to_string()),
+            walltime: pick(&cli.walltime, &defaults.walltime)
+                .unwrap_or_else(|| "30:00:00:00".to_string()),
+            command: cli.command.clone().unwrap_or_default(),
+            then: cli.then.clone(),
+            continue_on_error: cli.continue_on_error,
+            raw_command: cli.raw_command,
+            dialect: cli
+                .script_dialect
+                .or(defaults.script_dialect)
+                .unwrap_or_default(),
+            depend: cli.depend.clone(),
+            select: match cli.chunk.is_empty() {
+                true => cli.select.clone(),
+                false => Some(cli.chunk.join("+")),
+            },
+            constraints: cli.constraint.clone(),
+            place: cli.place,
+            exclusive: cli.exclusive,
+            // read from the files by the caller
+            prologue: None,
+            epilogue: None,
+            prepend: Vec::new(),
+            append: Vec::new(),
+            env: Vec::new(),
+            notify_on_failure: cli.notify_on_failure,
+            keep: cli.keep.clone(),
+            group: cli.group.clone(),
+            umask: cli.umask.clone(),
+            reservation: cli.reservation.clone(),
+            log_dir: cli.log_dir.clone().or_else(|| defaults.log_dir.clone()),
+            tags: cli.tags.clone(),
+            job_group: cli.group_name.clone(),
+            rerunnable: match (cli.rerunnable, cli.not_rerunnable) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            checkpoint: cli.checkpoint.clone(),
+            walltime_warning: cli.walltime_warning.map(|before| WalltimeWarning {
+                before: before.as_secs(),
+                command: cli.on_walltime_warning.clone(),
+            }),
+            requeue: None,
+            resumed: 0,
+            // filled in by the caller
+            git_commit: None,
+            env_snapshot: None,
+            array: None,
+            segment: None,
+            template: cli
+                .template
+                .as_deref()
+                .or(defaults.template.as_deref())
+                .map(|t| cli.config.resolve_template(t)),
+        }
+    }
+
+    /// `logs/align`: where `--log-dir` puts this job's output.
+    pub fn log_dir(&self) -> Option<PathBuf> {
+        Some(self.log_dir.as_ref()?.join(naming::sanitize(&self.name)))
+    }
+
+    /// Catch requests the scheduler would reject before writing anything.
+    pub fn validate(&self) -> Result<()> {
+        if self.ncpus == 0 {
+            return Err(Error::InvalidResources(
+                "ncpus must be at least 1".to_string(),
+            ));
+        }
+        if let Some(ref mem) = self.mem {
+            if pbs::parse_size(mem).is_none() {
+                return Err(Error::InvalidResources(format!(
+                    "mem '{}' is not a size such as 16gb",
+                    mem
+                )));
+            }
+        }
+        let Some(walltime) = pbs::parse_walltime(&self.walltime) else {
+            return Err(Error::InvalidResources(format!(
+                "walltime '{}' is not [[DD:]HH:]MM:SS",
+                self.walltime
+            )));
+        };
+        if let Some(ref warning) = self.walltime_warning {
+            if warning.before >= walltime {
+                return Err(Error::InvalidResources(format!(
+                    "--walltime-warning {} is not shorter than the walltime {}",
+                    pbs::format_walltime(warning.before),
+                    self.walltime
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_rejects_bad_resources() {
+        let cli = Cli {
+            command: Some("true".into()),
+            ..Default::default()
+        };
+        assert!(JobSpec::from_cli(&cli).validate().is_ok());
+        let spec = JobSpec {
+            mem: Some("lots".into()),
+            ..JobSpec::from_cli(&cli)
+        };
+        assert_eq!(spec.validate().unwrap_err().exit_code(), 7);
+    }
+}
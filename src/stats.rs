@@ -0,0 +1,157 @@
+//! Usage summary over the history (`qsub2 stats`).
+//!
+//! Each submission is looked up with `qstat -fx`, or in the accounting
+//! records through `tracejob` when the server no longer has it. Jobs the
+//! scheduler knows nothing about are counted but left out of the figures.
+
+use crate::history::SubmissionRecord;
+use crate::pbs::{self, JobAttributes};
+use crate::report;
+use crate::scheduler;
+
+/// What one finished (or still running) job tells about usage.
+#[derive(Debug, Default, PartialEq)]
+pub struct Usage {
+    pub exit_status: Option<i32>,
+    /// The server ran it again, e.g. after a node failure.
+    pub requeued: bool,
+    /// Seconds from queueing to start.
+    pub wait: Option<u64>,
+    pub core_hours: Option<f64>,
+    /// Percent of the requested walltime that was used.
+    pub walltime_used: Option<f64>,
+}
+
+impl Usage {
+    pub fn from_attributes(attributes: &JobAttributes, record: &SubmissionRecord) -> Self {
+        let get = |key: &str| attributes.get(key).map(String::as_str);
+        let walltime = get("resources_used.walltime").and_then(pbs::parse_walltime);
+        let requested = get("Resource_List.walltime")
+            .and_then(pbs::parse_walltime)
+            .or_else(|| pbs::parse_walltime(&record.spec.walltime));
+        let ncpus = get("Resource_List.ncpus")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(record.spec.ncpus);
+        let queued = get("qtime").and_then(pbs::parse_time);
+        let started = get("stime").and_then(pbs::parse_time);
+        let exit_status = get("Exit_status");
+        Usage {
+            // a requeue status says nothing about how the job itself went
+            exit_status: exit_status
+                .filter(|s| !pbs::is_requeue_status(s))
+                .and_then(|s| s.parse().ok()),
+            requeued: pbs::requeues(attributes) > 0
+                || exit_status.is_some_and(pbs::is_requeue_status),
+            wait: match (queued, started) {
+                (Some(queued), Some(started)) => {
+                    (started - queued).to_std().ok().map(|d| d.as_secs())
+                }
+                _ => None,
+            },
+            core_hours: walltime.map(|w| w as f64 * ncpus as f64 / 3600.0),
+            walltime_used: match (walltime, requested) {
+                (Some(used), Some(requested)) if requested > 0 => {
+                    Some(used as f64 / requested as f64 * 100.0)
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The usage of a submission, or `None` when the scheduler has no record of it.
+pub fn lookup(record: &SubmissionRecord) -> Option<Usage> {
+    // a job that never started has no usage, but may have an exit status
+    let attributes = match report::usage_attributes(&record.job_id) {
+        Ok(attributes) => attributes,
+        Err(_) => scheduler::job_status_with_history(&record.job_id).ok()??,
+    };
+    Some(Usage::from_attributes(&attributes, record))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub jobs: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub requeued: usize,
+    pub median_wait: Option<u64>,
+    pub core_hours: f64,
+    pub median_walltime_used: Option<f64>,
+}
+
+impl Summary {
+    /// Percent of the finished jobs that exited with status 0.
+    pub fn success_rate(&self) -> Option<f64> {
+        let finished = self.succeeded + self.failed;
+        (finished > 0).then(|| self.succeeded as f64 / finished as f64 * 100.0)
+    }
+}
+
+/// Sum up jobs, where `None` is a job without scheduler records.
+pub fn summarize<'a>(jobs: impl IntoIterator<Item = &'a Option<Usage>>) -> Summary {
+    let mut summary = Summary::default();
+    let mut waits = Vec::new();
+    let mut walltime_used = Vec::new();
+    for usage in jobs {
+        summary.jobs += 1;
+        let Some(usage) = usage else {
+            continue;
+        };
+        match usage.exit_status {
+            Some(0) => summary.succeeded += 1,
+            Some(_) => summary.failed += 1,
+            None => {}
+        }
+        summary.requeued += usize::from(usage.requeued);
+        waits.extend(usage.wait);
+        summary.core_hours += usage.core_hours.unwrap_or_default();
+        walltime_used.extend(usage.walltime_used);
+    }
+    summary.median_wait = median(&mut waits);
+    summary.median_walltime_used = median(&mut walltime_used);
+    summary
+}
+
+fn median<T: Copy + PartialOrd>(values: &mut [T]) -> Option<T> {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    values.get(values.len() / 2).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let usage = |exit_status, wait, core_hours, walltime_used| {
+            Some(Usage {
+                exit_status: Some(exit_status),
+                requeued: false,
+                wait: Some(wait),
+                core_hours: Some(core_hours),
+                walltime_used: Some(walltime_used),
+            })
+        };
+        let jobs = [
+            usage(0, 60, 2.0, 10.0),
+            usage(1, 600, 0.5, 50.0),
+            usage(0, 120, 1.5, 20.0),
+            None,
+        ];
+        let summary = summarize(&jobs);
+        assert_eq!(summary.jobs, 4);
+        assert_eq!((summary.succeeded, summary.failed), (2, 1));
+        assert_eq!(summary.median_wait, Some(120));
+        assert_eq!(summary.core_hours, 4.0);
+        assert_eq!(summary.median_walltime_used, Some(20.0));
+        assert_eq!(summarize(&[None]).success_rate(), None);
+
+        let requeued = Some(Usage {
+            requeued: true,
+            ..Usage::default()
+        });
+        let summary = summarize(&[requeued]);
+        assert_eq!((summary.failed, summary.requeued), (0, 1));
+    }
+}
@@ -0,0 +1,20 @@
+//! Terminal styles. Output goes through `anstream`, which drops them when
+//! colors are off (`--color never`, `NO_COLOR`, or not a terminal).
+
+use anstyle::{AnsiColor, Style};
+
+pub const ERROR: Style = AnsiColor::Red.on_default().bold();
+pub const WARNING: Style = AnsiColor::Yellow.on_default().bold();
+pub const JOB_ID: Style = AnsiColor::Green.on_default().bold();
+pub const LABEL: Style = Style::new().bold();
+
+// `--preview`
+pub const DIRECTIVE: Style = AnsiColor::Cyan.on_default();
+pub const COMMENT: Style = AnsiColor::BrightBlack.on_default();
+pub const KEYWORD: Style = AnsiColor::Magenta.on_default().bold();
+pub const STRING: Style = AnsiColor::Yellow.on_default();
+pub const VARIABLE: Style = AnsiColor::Green.on_default();
+
+// `--diff-against`
+pub const REMOVED: Style = AnsiColor::Red.on_default();
+pub const ADDED: Style = AnsiColor::Green.on_default();
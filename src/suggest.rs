@@ -0,0 +1,137 @@
+//! Resource hints from earlier runs of similar jobs.
+
+use crate::history::SubmissionRecord;
+use crate::pbs;
+use crate::scheduler;
+use crate::spec::JobSpec;
+
+/// How many of the most recent similar submissions to look up.
+const RECENT_RUNS: usize = 10;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PastUsage {
+    pub runs: usize,
+    pub max_mem: Option<u64>,
+    pub max_walltime: Option<u64>,
+}
+
+/// Same executable, or the same explicitly chosen job name.
+fn is_similar(previous: &JobSpec, spec: &JobSpec) -> bool {
+    let executable = |s: &JobSpec| s.command.split_whitespace().next().map(String::from);
+    let same_executable =
+        executable(previous).is_some() && executable(previous) == executable(spec);
+    let same_name = spec.name != "job" && previous.name == spec.name;
+    same_executable || same_name
+}
+
+/// Peak memory and walltime of recent similar jobs the server still remembers.
+pub fn past_usage(records: &[SubmissionRecord], spec: &JobSpec) -> PastUsage {
+    let mut usage = PastUsage::default();
+    let similar = records.iter().rev().filter(|r| is_similar(&r.spec, spec));
+    for record in similar.take(RECENT_RUNS) {
+        let Ok(Some(attributes)) = scheduler::job_status_with_history(&record.job_id) else {
+            continue;
+        };
+        let mem = attributes
+            .get("resources_used.mem")
+            .and_then(|m| pbs::parse_size(m));
+        let walltime = attributes
+            .get("resources_used.walltime")
+            .and_then(|w| pbs::parse_walltime(w));
+        if mem.is_none() && walltime.is_none() {
+            continue;
+        }
+        usage.runs += 1;
+        usage.max_mem = usage.max_mem.max(mem);
+        usage.max_walltime = usage.max_walltime.max(walltime);
+    }
+    usage
+}
+
+/// Requests with some headroom over the peak: 20% more memory rounded up to
+/// whole gigabytes, 50% more walltime rounded up to whole hours.
+pub fn suggested(usage: &PastUsage) -> (Option<String>, Option<String>) {
+    const GB: u64 = 1 << 30;
+    const HOUR: u64 = 60 * 60;
+    let mem = usage
+        .max_mem
+        .map(|m| format!("{}gb", (m * 6 / 5).div_ceil(GB).max(1)));
+    let walltime = usage
+        .max_walltime
+        .map(|w| pbs::format_walltime((w * 3 / 2).div_ceil(HOUR).max(1) * HOUR));
+    (mem, walltime)
+}
+
+/// `30d`, `6h`, `1h30m`, `45s`
+pub fn short_duration(seconds: u64) -> String {
+    let units = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+    let mut rest = seconds;
+    let mut text = String::new();
+    for (unit, size) in units {
+        if rest >= size {
+            text.push_str(&format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+    if text.is_empty() {
+        text.push_str("0s");
+    }
+    text
+}
+
+pub fn hint(usage: &PastUsage, spec: &JobSpec) -> String {
+    let used_mem = usage.max_mem.map_or("?".to_string(), pbs::format_size);
+    let used_walltime = usage.max_walltime.map_or("?".to_string(), short_duration);
+    let requested_mem = spec.mem.clone().unwrap_or_else(|| "default".to_string());
+    let requested_walltime =
+        pbs::parse_walltime(&spec.walltime).map_or(spec.walltime.clone(), short_duration);
+    format!(
+        "previous runs used max {} and {} — you requested {}/{}",
+        used_mem, used_walltime, requested_mem, requested_walltime
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, command: &str) -> JobSpec {
+        JobSpec {
+            name: name.into(),
+            ncpus: 1,
+            mem: Some("64gb".into()),
+            queue: "batch".into(),
+            walltime: "30:00:00:00".into(),
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_similar() {
+        let current = spec("job", "bwa mem ref.fa b.fq");
+        assert!(is_similar(&spec("job", "bwa mem ref.fa a.fq"), &current));
+        assert!(!is_similar(&spec("job", "samtools sort a.bam"), &current));
+        assert!(is_similar(
+            &spec("align", "other"),
+            &spec("align", "samtools")
+        ));
+    }
+
+    #[test]
+    fn test_suggested_and_hint() {
+        let usage = PastUsage {
+            runs: 3,
+            max_mem: Some(12 * (1 << 30) + (1 << 29)),
+            max_walltime: Some(6 * 60 * 60),
+        };
+        assert_eq!(
+            suggested(&usage),
+            (Some("15gb".into()), Some("09:00:00".into()))
+        );
+        assert_eq!(
+            hint(&usage, &spec("job", "bwa")),
+            "previous runs used max 12.5gb and 6h — you requested 64gb/30d"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! `--walltime-warning 10m`: give the job a chance to checkpoint or flush its
+//! output before PBS kills it at the end of its walltime.
+//!
+//! PBS Pro has no resource that asks for an early signal (Moab's
+//! `signal=USR1@600` is not understood by its server), so the script sets a
+//! timer of its own that sends itself USR1 that long before the end. bash
+//! only runs a trap between commands, so the body runs in the background
+//! while the script waits for it, and the trap runs as soon as the signal
+//! arrives: `--on-walltime-warning CMD`, else `dmtcp_command --checkpoint`
+//! for a DMTCP job, else `sync`.
+
+use crate::checkpoint::Checkpoint;
+use crate::pbs;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalltimeWarning {
+    /// Seconds before the end of the walltime.
+    pub before: u64,
+    /// What the trap runs.
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+impl WalltimeWarning {
+    /// The trap and the timer, for a job with `walltime` seconds.
+    pub fn trap(&self, walltime: u64, checkpoint: Option<&Checkpoint>) -> String {
+        let command = match self.command {
+            Some(ref command) => command.as_str(),
+            None if checkpoint.is_some_and(|c| c.dmtcp) => "dmtcp_command --checkpoint",
+            None => "sync",
+        };
+        let left = pbs::format_walltime(self.before);
+        format!(
+            "# qsub2 sends the job USR1 {left} before its walltime ends.\n\
+             qsub2_walltime_warning() {{\n\
+             echo \"qsub2: $PBS_JOBID has {left} of walltime left\" >&2\n\
+             {command}\n\
+             }}\n\
+             trap qsub2_walltime_warning USR1\n\
+             (sleep {} && kill -USR1 $$) >/dev/null 2>&1 &\n\
+             qsub2_timer=$!\n",
+            walltime.saturating_sub(self.before),
+        )
+    }
+
+    /// `body` in the background, waited for until it exits, however many
+    /// times the wait is cut short by the trap.
+    pub fn wrap(&self, body: &str) -> String {
+        format!(
+            "{{\n{}\n}} &\n\
+             qsub2_body=$!\n\
+             while wait $qsub2_body; status=$?; kill -0 $qsub2_body 2>/dev/null; do :; done\n\
+             kill $qsub2_timer 2>/dev/null\n\
+             exit $status",
+            body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_and_wrap() {
+        let warning = WalltimeWarning {
+            before: 600,
+            command: None,
+        };
+        let trap = warning.trap(3600, None);
+        assert!(trap.contains("\nsync\n}\ntrap qsub2_walltime_warning USR1\n"));
+        assert!(trap.contains("(sleep 3000 && kill -USR1 $$)"));
+        assert!(trap.contains("has 00:10:00 of walltime left"));
+        let checkpoint = crate::checkpoint::parse("interval=1h,dmtcp").unwrap();
+        assert!(warning
+            .trap(3600, Some(&checkpoint))
+            .contains("\ndmtcp_command --checkpoint\n"));
+        assert!(warning
+            .wrap("make")
+            .starts_with("{\nmake\n} &\nqsub2_body=$!\n"));
+        assert!(warning.wrap("make").ends_with("\nexit $status"));
+    }
+}
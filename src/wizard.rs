@@ -0,0 +1,197 @@
+//! `qsub2 new`: ask for the command, the resources, the queue and the
+//! template one at a time, each with its default and checked as it is
+//! answered, then show the script as `--preview` does and offer to submit.
+//!
+//! What is answered becomes an ordinary qsub2 command line, which is printed
+//! so that the next job can be submitted without the questions.
+
+use crate::discovery;
+use crate::error::{Error, Result};
+use crate::spec::JobSpec;
+use crate::{cache_ttl, parse_walltime_arg, pbs, run, shell_quote, Cli};
+use clap::Parser;
+use std::io::{self, BufRead, Write};
+
+/// Ask `question` until `check` accepts the answer, and take what it makes
+/// of it. `None` for an empty answer, which takes `default`; with no default
+/// an answer is required.
+fn ask(
+    input: &mut impl BufRead,
+    question: &str,
+    default: Option<&str>,
+    check: impl Fn(&str) -> std::result::Result<String, String>,
+) -> io::Result<Option<String>> {
+    loop {
+        match default {
+            Some(default) => eprint!("{} [{}]: ", question, default),
+            None => eprint!("{}: ", question),
+        }
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the questions were not all answered",
+            ));
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            match default {
+                Some(_) => return Ok(None),
+                None => {
+                    eprintln!("  an answer is needed");
+                    continue;
+                }
+            }
+        }
+        match check(answer) {
+            Ok(answer) => return Ok(Some(answer)),
+            Err(message) => eprintln!("  {}", message),
+        }
+    }
+}
+
+/// `ask`, reading standard input.
+fn ask_stdin(
+    question: &str,
+    default: Option<&str>,
+    check: impl Fn(&str) -> std::result::Result<String, String>,
+) -> Result<Option<String>> {
+    ask(&mut io::stdin().lock(), question, default, check)
+        .map_err(|e| Error::Io("reading the answers", e))
+}
+
+/// `word` as typed on a command line: bare unless the shell would split or expand it.
+fn shell_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,%".contains(c));
+    match plain {
+        true => word.to_string(),
+        false => shell_quote(word),
+    }
+}
+
+pub fn new_job(cli: &Cli) -> Result<()> {
+    let command = ask_stdin("Command to run", None, |s| Ok(s.to_string()))?.unwrap_or_default();
+    // the defaults of a job with this command and nothing else
+    let base = {
+        let mut base = Cli::try_parse_from(["qsub2", "--", command.as_str()])
+            .map_err(|e| Error::Usage(e.to_string()))?;
+        base.config = cli.config.clone();
+        JobSpec::from_cli(&base)
+    };
+    let mut flags: Vec<String> = Vec::new();
+    let mut answer = |flag: &str, value: Option<String>| {
+        if let Some(value) = value {
+            flags.extend([flag.to_string(), value]);
+        }
+    };
+
+    answer(
+        "--name",
+        ask_stdin("Job name", Some(&base.name), |s| Ok(s.to_string()))?,
+    );
+    answer(
+        "--ncpus",
+        ask_stdin("CPUs", Some(&base.ncpus.to_string()), |s| {
+            match s.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(n.to_string()),
+                _ => Err(format!("'{}' is not a number of cpus such as 4", s)),
+            }
+        })?,
+    );
+    answer(
+        "--mem",
+        ask_stdin(
+            "Memory",
+            Some(base.mem.as_deref().unwrap_or("the queue's default")),
+            |s| match pbs::parse_size(s) {
+                Some(_) => Ok(s.to_string()),
+                None => Err(format!("'{}' is not a size such as 16gb", s)),
+            },
+        )?,
+    );
+    answer(
+        "--walltime",
+        ask_stdin("Walltime, e.g. 2h or 12:00:00", Some(&base.walltime), |s| {
+            parse_walltime_arg(s)
+        })?,
+    );
+    // a cluster snapshot at hand names the queues, without asking the server
+    let snapshot = discovery::cached(cache_ttl(cli)?);
+    if let Some(ref snapshot) = snapshot {
+        let names: Vec<&str> = snapshot.queues.iter().map(|q| q.name.as_str()).collect();
+        eprintln!("Queues: {}, or auto", names.join(", "));
+    }
+    answer(
+        "--queue",
+        ask_stdin("Queue", Some(&base.queue), |s| match snapshot {
+            Some(ref snapshot) if s != "auto" && snapshot.queue(s).is_none() => {
+                Err(format!("there is no queue {}", s))
+            }
+            _ => Ok(s.to_string()),
+        })?,
+    );
+    let template = base.template.as_ref().map(|t| t.display().to_string());
+    answer(
+        "--template",
+        ask_stdin(
+            "Template",
+            Some(template.as_deref().unwrap_or("built-in")),
+            |s| match cli.config.resolve_template(s.as_ref()).is_file() {
+                true => Ok(s.to_string()),
+                false => Err(format!("there is no template {}", s)),
+            },
+        )?,
+    );
+
+    let mut argv = vec!["qsub2".to_string()];
+    argv.extend(flags);
+    argv.extend(["--".to_string(), command]);
+    eprintln!(
+        "\nThe same job without the questions:\n    {}\n",
+        argv.iter()
+            .map(|word| shell_word(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let mut job_cli = Cli::try_parse_from(&argv).map_err(|e| Error::Usage(e.to_string()))?;
+    job_cli.invocation = argv;
+    job_cli.config = cli.config.clone();
+    job_cli.quiet |= cli.quiet;
+    job_cli.preview = true;
+    run(&job_cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask() {
+        let positive = |s: &str| match s.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(n.to_string()),
+            _ => Err("not a count".to_string()),
+        };
+        let mut input = "zero\n0\n 8 \n".as_bytes();
+        assert_eq!(
+            ask(&mut input, "CPUs", Some("1"), positive).unwrap(),
+            Some("8".to_string())
+        );
+        assert_eq!(
+            ask(&mut "\n".as_bytes(), "CPUs", Some("1"), positive).unwrap(),
+            None
+        );
+        let mut input = "\nbwa mem\n".as_bytes();
+        let any = |s: &str| Ok(s.to_string());
+        assert_eq!(
+            ask(&mut input, "Command", None, any).unwrap(),
+            Some("bwa mem".to_string())
+        );
+        assert!(ask(&mut "".as_bytes(), "Command", None, any).is_err());
+        assert_eq!(shell_word("--mem"), "--mem");
+        assert_eq!(shell_word("bwa mem ref.fa"), "'bwa mem ref.fa'");
+    }
+}
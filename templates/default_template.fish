@@ -0,0 +1,11 @@
+#!/usr/bin/fish
+#PBS -S /usr/bin/fish
+#PBS -N {name}
+#PBS -l select=1{ncpus}{mem}{constraints}
+#PBS -q {queue}
+#PBS -l walltime={walltime}
+{directives}
+
+cd "$PBS_O_WORKDIR"
+
+{command}
@@ -1,9 +1,10 @@
 #!/bin/bash
 #PBS -N {name}
-#PBS -l select=1{ncpus}{mem}
+#PBS -l select=1{ncpus}{mem}{constraints}
 #PBS -q {queue}
 #PBS -l walltime={walltime}
+{directives}
 
-cd $PBS_O_WORKDIR
+cd "$PBS_O_WORKDIR"
 
 {command}
@@ -0,0 +1,575 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("qsub2-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// qsub2 in `dir` with the mock scheduler, as user `ana`, finding the tools
+/// written by [`fake_tool`] first on `PATH`.
+fn mock_qsub2(dir: &Path) -> Command {
+    let path = format!(
+        "{}:{}",
+        dir.join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let mut command = Command::new(env!("CARGO_BIN_EXE_qsub2"));
+    command
+        .current_dir(dir)
+        .env("PATH", path)
+        .env("USER", "ana")
+        .env("QSUB2_MOCK", "1")
+        .env("QSUB2_MOCK_DIR", dir.join("mock"))
+        .env("QSUB2_HISTORY", dir.join("history.jsonl"))
+        .env("QSUB2_CONFIG", dir.join("config.toml"));
+    command
+}
+
+/// The `#PBS -W depend=` lines of the script the mock got as `job_id`.
+fn depend_lines(dir: &Path, job_id: &str) -> Vec<String> {
+    fs::read_to_string(dir.join("mock").join(format!("{}.sh", job_id)))
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("#PBS -W depend="))
+        .map(String::from)
+        .collect()
+}
+
+/// A shell script standing in for the PBS command `name`; it first adds its
+/// arguments to `tools.log`.
+fn fake_tool(dir: &Path, name: &str, body: &str) {
+    let bin = dir.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let log = dir.join("tools.log");
+    let script = format!(
+        "#!/bin/sh\necho \"{} $*\" >> '{}'\n{}\n",
+        name,
+        log.display(),
+        body
+    );
+    let path = bin.join(name);
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_submit_through_mock_scheduler() {
+    let dir = scratch_dir("mock");
+    let output = mock_qsub2(&dir)
+        .args(["-n", "hello", "-o", "hello.sh", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Job submitted with ID: 1.mock"),
+        "{}",
+        stdout
+    );
+
+    let submitted = fs::read_to_string(dir.join("mock").join("1.mock.sh")).unwrap();
+    assert!(submitted.contains("#PBS -N hello"));
+    let calls = fs::read_to_string(dir.join("mock").join("calls.log")).unwrap();
+    assert_eq!(calls, "1.mock\tqsub hello.sh\n");
+    let history = fs::read_to_string(dir.join("history.jsonl")).unwrap();
+    assert!(history.contains("\"job_id\":\"1.mock\""));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_submit_generated_script_without_outfile() {
+    let dir = scratch_dir("auto-named");
+    let output = mock_qsub2(&dir)
+        .args(["--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let script = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .find(|name| name.starts_with("job_echo_hello_"))
+        .expect("a generated script");
+    let calls = fs::read_to_string(dir.join("mock").join("calls.log")).unwrap();
+    assert_eq!(calls, format!("1.mock\tqsub {}\n", script));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_submit_without_script_file() {
+    let dir = scratch_dir("no-script");
+    let output = mock_qsub2(&dir)
+        .args(["--no-script", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut entries: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["history.jsonl", "mock"]);
+    let calls = fs::read_to_string(dir.join("mock").join("calls.log")).unwrap();
+    assert_eq!(calls, "1.mock\tqsub\n");
+    let history = fs::read_to_string(dir.join("history.jsonl")).unwrap();
+    assert!(
+        history.contains("\"content\":\"#!/bin/bash\\n"),
+        "{}",
+        history
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_porcelain_prints_only_the_job_id() {
+    let dir = scratch_dir("porcelain");
+    let output = mock_qsub2(&dir)
+        .args(["--porcelain", "-o", "job.sh", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1.mock\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_max_parallel_chains_each_job_behind_the_one_n_before() {
+    let dir = scratch_dir("max-parallel");
+    for file in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    // b.txt is refused, so d.txt has nothing to wait for.
+    fs::write(
+        dir.join("config.toml"),
+        "pre_submit = \"! grep -q b.txt\"\n",
+    )
+    .unwrap();
+    fake_tool(
+        &dir,
+        "qstat",
+        "for id; do :; done\nprintf 'Job Id: %s\\n    job_state = R\\n' \"$id\"",
+    );
+    let output = mock_qsub2(&dir)
+        .args(["--per-file", "--max-parallel", "2", "--submit", "gzip {}"])
+        .args(["a.txt", "b.txt", "c.txt", "d.txt"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("d.txt does not wait for another job: the one before it was not submitted"),
+        "{}",
+        stderr
+    );
+
+    let depend = |job_id| depend_lines(&dir, job_id);
+    // a.txt and c.txt became 1.mock and 2.mock, d.txt 3.mock.
+    assert!(depend("1.mock").is_empty());
+    assert_eq!(depend("2.mock"), ["#PBS -W depend=afterany:1.mock"]);
+    assert!(depend("3.mock").is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_max_parallel_does_not_wait_for_a_finished_job() {
+    let dir = scratch_dir("max-parallel-finished");
+    for file in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    // 1.mock has already finished; 2.mock is still running.
+    fake_tool(
+        &dir,
+        "qstat",
+        "for id; do :; done\n\
+         case $id in\n\
+         1.mock) printf 'Job Id: %s\\n    job_state = F\\n' \"$id\" ;;\n\
+         *) printf 'Job Id: %s\\n    job_state = R\\n' \"$id\" ;;\n\
+         esac",
+    );
+    let output = mock_qsub2(&dir)
+        .args(["--per-file", "--max-parallel", "1", "--submit", "gzip"])
+        .args(["a.txt", "b.txt", "c.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(depend_lines(&dir, "2.mock").is_empty());
+    assert_eq!(
+        depend_lines(&dir, "3.mock"),
+        ["#PBS -W depend=afterany:2.mock"]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_max_queued_counts_only_unfinished_jobs() {
+    let dir = scratch_dir("max-queued");
+    for file in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    // Every job submitted so far is listed, and reported as finished.
+    let calls = dir.join("mock").join("calls.log");
+    fake_tool(
+        &dir,
+        "qselect",
+        &format!("cut -f1 '{}' 2>/dev/null || true", calls.display()),
+    );
+    fake_tool(
+        &dir,
+        "qstat",
+        "shift\nfor id; do printf 'Job Id: %s\\n    job_state = F\\n' \"$id\"; done",
+    );
+    let output = mock_qsub2(&dir)
+        .args(["--per-file", "--max-queued", "1", "--submit", "gzip"])
+        .args(["a.txt", "b.txt", "c.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(fs::read_to_string(&calls).unwrap().lines().count(), 3);
+    let tools = fs::read_to_string(dir.join("tools.log")).unwrap();
+    assert_eq!(
+        tools,
+        "qselect -u ana\nqselect -u ana\nqstat -f 1.mock\nqselect -u ana\nqstat -f 1.mock 2.mock\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_max_queued_submits_nothing_when_the_queue_cannot_be_read() {
+    let dir = scratch_dir("max-queued-down");
+    fs::write(dir.join("a.txt"), "").unwrap();
+    fake_tool(
+        &dir,
+        "qselect",
+        "echo 'cannot connect to server' >&2\nexit 1",
+    );
+    let output = mock_qsub2(&dir)
+        .args([
+            "--per-file",
+            "--max-queued",
+            "1",
+            "--submit",
+            "gzip",
+            "a.txt",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("checking the queue: qselect failed: cannot connect to server"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("mock").join("calls.log").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_batch_keeps_going_past_a_failed_file() {
+    let dir = scratch_dir("keep-going");
+    for file in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    fs::write(
+        dir.join("config.toml"),
+        "pre_submit = \"! grep -q b.txt\"\n",
+    )
+    .unwrap();
+    let output = mock_qsub2(&dir)
+        .args(["--per-file", "--submit", "gzip", "a.txt", "b.txt", "c.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(10), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|row| row.starts_with("b.txt ") && row.ends_with(" failed")),
+        "{}",
+        stdout
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("3 generated, 2 submitted, 1 failed, 0 skipped"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "To retry, rerun the same command with only these files: {}\n",
+            dir.join("b.txt").display()
+        )),
+        "{}",
+        stderr
+    );
+    let calls = fs::read_to_string(dir.join("mock").join("calls.log")).unwrap();
+    let scripts: Vec<&str> = calls
+        .lines()
+        .map(|l| l.rsplit('_').next().unwrap())
+        .collect();
+    assert_eq!(scripts, ["a.sh", "c.sh"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_show_command_prints_qsub_without_running_it() {
+    let dir = scratch_dir("show-command");
+    fs::write(
+        dir.join("config.toml"),
+        "submit_args = [\"-P\", \"my lab\"]\n",
+    )
+    .unwrap();
+    let output = mock_qsub2(&dir)
+        .env_remove("QSUB2_MOCK")
+        .args(["--qsub-bin", "/opt/pbs/bin/qsub", "--show-command"])
+        .args(["-o", "run.sh", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "cd {} && /opt/pbs/bin/qsub -P 'my lab' run.sh\n",
+            dir.display()
+        )
+    );
+    assert!(!dir.join("run.sh").exists());
+    assert!(!dir.join("history.jsonl").exists());
+
+    let output = mock_qsub2(&dir)
+        .args(["--no-script", "--show-command", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "# --no-scheduler: the submission is recorded, no command is run\n"
+    );
+    assert!(!dir.join("mock").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_status_and_cancel_select_the_group() {
+    let dir = scratch_dir("group");
+    for file in ["a.txt", "b.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    // 1.mock is running; the others have finished.
+    fake_tool(
+        &dir,
+        "qstat",
+        "for id; do :; done\n\
+         case $id in\n\
+         1.mock) printf 'Job Id: %s\\n    job_state = R\\n' \"$id\" ;;\n\
+         *) printf 'Job Id: %s\\n    job_state = F\\n    Exit_status = 0\\n' \"$id\" ;;\n\
+         esac",
+    );
+    fake_tool(&dir, "qdel", "");
+    let run = |args: &[&str]| {
+        let output = mock_qsub2(&dir).args(args).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    run(&[
+        "--per-file",
+        "--group-name",
+        "align",
+        "--submit",
+        "gzip",
+        "a.txt",
+        "b.txt",
+    ]);
+    run(&["--group-name", "qc", "-o", "qc.sh", "--submit", "echo qc"]);
+
+    let status = run(&["status", "--group", "align"]);
+    let jobs: Vec<&str> = status
+        .lines()
+        .skip(1)
+        .map_while(|row| row.split_whitespace().next())
+        .collect();
+    assert_eq!(jobs, ["1.mock", "2.mock"]);
+    assert!(
+        status.contains("2 jobs: 1 running, 1 finished, 0 failed"),
+        "{}",
+        status
+    );
+
+    fs::remove_file(dir.join("tools.log")).unwrap();
+    // only the unfinished job of the group, and only once
+    assert_eq!(
+        run(&["cancel", "1.mock", "--group", "align"]),
+        "Cancelled 1.mock\n"
+    );
+    let tools = fs::read_to_string(dir.join("tools.log")).unwrap();
+    let deleted: Vec<&str> = tools.lines().filter(|l| l.starts_with("qdel")).collect();
+    assert_eq!(deleted, ["qdel 1.mock"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_submit_through_serve_socket() {
+    let dir = scratch_dir("serve-socket");
+    let socket = dir.join("daemon.sock");
+    fs::write(
+        dir.join("config.toml"),
+        format!("socket = '{}'\n", socket.display()),
+    )
+    .unwrap();
+    let mut daemon = mock_qsub2(&dir)
+        .env("QSUB2_HISTORY", dir.join("daemon.jsonl"))
+        .args(["serve", "--socket"])
+        .arg(&socket)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while !socket.exists() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    // Without the mock the client hands the script to the daemon, which
+    // submits it with the mock and keeps the record in its own history.
+    let output = mock_qsub2(&dir)
+        .env_remove("QSUB2_MOCK")
+        .env("USER", "mallory")
+        .args(["-n", "hello", "-o", "hello.sh", "--submit", "echo hello"])
+        .output()
+        .unwrap();
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Job submitted with ID: 1.mock"),
+        "{}",
+        stdout
+    );
+
+    let calls = fs::read_to_string(dir.join("mock").join("calls.log")).unwrap();
+    assert_eq!(calls, "1.mock\tqsub hello.sh\n");
+    let history = fs::read_to_string(dir.join("daemon.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(history.trim()).unwrap();
+    assert_eq!(record["job_id"], "1.mock");
+    assert_eq!(record["spec"]["name"], "hello");
+    assert_eq!(record["workdir"], dir.display().to_string());
+    // the daemon's user, not the one the client claims
+    assert_eq!(record["user"], "ana");
+    assert!(!dir.join("history.jsonl").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_max_queued_waits_without_writing_to_stdout() {
+    let dir = scratch_dir("max-queued-wait");
+    for file in ["a.txt", "b.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let calls = dir.join("mock").join("calls.log");
+    fake_tool(
+        &dir,
+        "qselect",
+        &format!("cut -f1 '{}' 2>/dev/null || true", calls.display()),
+    );
+    // A job is running the first time it is asked about, then finished.
+    let seen = dir.join("seen");
+    fs::create_dir(&seen).unwrap();
+    fake_tool(
+        &dir,
+        "qstat",
+        &format!(
+            "shift\nfor id; do\n\
+             if [ -e '{seen}'/\"$id\" ]; then state=F; else state=R; touch '{seen}'/\"$id\"; fi\n\
+             printf 'Job Id: %s\\n    job_state = %s\\n' \"$id\" \"$state\"\n\
+             done",
+            seen = seen.display()
+        ),
+    );
+    let output = mock_qsub2(&dir)
+        .args([
+            "--porcelain",
+            "--per-file",
+            "--max-queued",
+            "1",
+            "--submit",
+            "gzip",
+        ])
+        .args(["a.txt", "b.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1.mock\n2.mock\n");
+    let tools = fs::read_to_string(dir.join("tools.log")).unwrap();
+    assert_eq!(tools.matches("qstat -f 1.mock").count(), 2, "{}", tools);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_summary_job_waits_for_unfinished_jobs_in_chunks() {
+    let dir = scratch_dir("notify-when-done");
+    let files: Vec<String> = (1..=103).map(|i| format!("f{:03}.txt", i)).collect();
+    for file in &files {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    // 1.mock has already finished; the others are still running.
+    fake_tool(
+        &dir,
+        "qstat",
+        "for id; do :; done\n\
+         case $id in\n\
+         1.mock) printf 'Job Id: %s\\n    job_state = F\\n' \"$id\" ;;\n\
+         *) printf 'Job Id: %s\\n    job_state = R\\n' \"$id\" ;;\n\
+         esac",
+    );
+    let output = mock_qsub2(&dir)
+        .args([
+            "--yes",
+            "--per-file",
+            "--notify-when-done",
+            "--submit",
+            "gzip",
+        ])
+        .args(&files)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // 2.mock to 101.mock fill one list of a job that only waits; the summary
+    // waits for that job and the rest.
+    let waiting: Vec<String> = (2..=101).map(|i| format!("{}.mock", i)).collect();
+    assert_eq!(
+        depend_lines(&dir, "104.mock"),
+        [format!("#PBS -W depend=afterany:{}", waiting.join(":"))]
+    );
+    assert_eq!(
+        depend_lines(&dir, "105.mock"),
+        ["#PBS -W depend=afterany:104.mock:102.mock:103.mock"]
+    );
+    let summary = fs::read_to_string(dir.join("mock").join("105.mock.sh")).unwrap();
+    assert!(summary.contains("#PBS -N gzip-summary"), "{}", summary);
+    assert!(summary.contains("for job in 1.mock 2.mock "), "{}", summary);
+
+    fs::remove_dir_all(&dir).unwrap();
+}